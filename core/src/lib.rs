@@ -7,10 +7,13 @@ extern crate graphql_parser;
 extern crate serde;
 extern crate serde_yaml;
 
+#[cfg(test)]
+extern crate parity_wasm;
+
 mod query;
 mod schema;
 mod subgraph;
 
 pub use query::QueryRunner;
 pub use schema::SchemaProvider;
-pub use subgraph::RuntimeManager;
+pub use subgraph::{IndexingStatusProvider, RuntimeManager};
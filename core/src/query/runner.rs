@@ -2,6 +2,7 @@ use futures::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Mutex;
 
 use graph::prelude::{QueryRunner as QueryRunnerTrait, *};
+use graph::util::metrics;
 use graph_graphql::prelude::*;
 
 /// Common query runner implementation for The Graph.
@@ -39,7 +40,17 @@ where
                 logger: logger.clone(),
                 resolver: StoreResolver::new(&logger, store.clone()),
             };
-            let result = execute(&query, options);
+            let result = {
+                let _timer = metrics::QUERY_EXECUTION_TIME.start_timer();
+                execute(&query, options)
+            };
+
+            metrics::QUERY_EXECUTIONS.inc();
+            for error in result.errors.iter().flat_map(|errors| errors.iter()) {
+                metrics::QUERY_ERRORS
+                    .with_label_values(&[error.kind()])
+                    .inc();
+            }
 
             query
                 .result_sender
@@ -1,6 +1,7 @@
 use futures::sync::mpsc::{channel, Receiver, Sender};
 use std::collections::HashMap;
 
+use graph::components::{backlog_channel, BacklogReceiver, BacklogSender};
 use graph::prelude::{SchemaProvider as SchemaProviderTrait, *};
 
 use graph_graphql::prelude::*;
@@ -8,7 +9,7 @@ use graph_graphql::prelude::*;
 /// Common schema provider implementation for The Graph.
 pub struct SchemaProvider {
     logger: Logger,
-    input: Sender<SchemaEvent>,
+    input: BacklogSender<SchemaEvent>,
     output: Option<Receiver<SchemaProviderEvent>>,
 }
 
@@ -40,7 +41,7 @@ impl SchemaProvider {
         let logger = logger.new(o!("component" => "SchemaProvider"));
 
         // Create a channel for receiving events from the subgraph provider.
-        let (subgraph_sender, subgraph_recv) = channel(100);
+        let (subgraph_sender, subgraph_recv) = backlog_channel("schema_provider", 100);
         // Create a channel for broadcasting changes to the schema.
         let (schema_sender, schema_recv) = channel(100);
 
@@ -61,7 +62,7 @@ impl SchemaProvider {
 
     fn schema_event_handler(
         logger: Logger,
-        input: Receiver<SchemaEvent>,
+        input: BacklogReceiver<SchemaEvent>,
         output: Sender<SchemaProviderEvent>,
     ) -> impl Future<Item = (), Error = ()> {
         let sink_err_logger = logger.clone();
@@ -79,6 +80,10 @@ impl SchemaProvider {
                     SchemaEvent::SchemaRemoved(ref schema) => {
                         input_schemas.remove(&schema.id);
                     }
+                    SchemaEvent::SchemaReplaced(ref old_schema, ref new_schema) => {
+                        input_schemas.remove(&old_schema.id);
+                        input_schemas.insert(new_schema.id.clone(), new_schema.clone());
+                    }
                 };
 
                 // Derive a full-fledged API schema from the first input schema
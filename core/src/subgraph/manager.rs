@@ -1,13 +1,37 @@
-use futures::sync::mpsc::{channel, Receiver, Sender};
+use ethereum_types::{Address, U256};
+use std::str::FromStr;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use graph::components::subgraph::RuntimeHostEvent;
+use graph::components::subgraph::RuntimeManager as RuntimeManagerTrait;
 use graph::components::subgraph::SubgraphProviderEvent;
+use graph::components::{backlog_channel, BacklogReceiver, BacklogSender};
+use graph::data::store::scalar::BigInt;
+use graph::data::subgraph::DataSource;
 use graph::prelude::*;
+use graph::tokio::timer::Interval;
+
+/// ID of the meta subgraph under which the node keeps its own records about
+/// subgraphs, separate from the entities of the subgraphs themselves.
+const META_SUBGRAPH_ID: &str = "subgraphs";
+
+/// Entity type under which data sources created from templates are
+/// persisted in the meta subgraph, so that they can be re-created when the
+/// node restarts.
+const DYNAMIC_DATA_SOURCE_ENTITY: &str = "DynamicEthereumContractDataSource";
+
+/// How often `caught_up` checks whether the hosts of a subgraph have caught up.
+const CAUGHT_UP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The ID of the subgraph of a runtime host, whether the host has caught up
+/// and its status.
+pub type HostStatus = (String, bool, RuntimeHostStatus);
 
 pub struct RuntimeManager {
     logger: Logger,
-    input: Sender<SubgraphProviderEvent>,
+    input: BacklogSender<SubgraphProviderEvent>,
+    host_statuses: Arc<Fn() -> Vec<HostStatus> + Send + Sync>,
 }
 
 impl RuntimeManager where {
@@ -20,29 +44,139 @@ impl RuntimeManager where {
         let logger = logger.new(o!("component" => "RuntimeManager"));
 
         // Create channel for receiving subgraph provider events.
-        let (subgraph_sender, subgraph_receiver) = channel(100);
+        let (subgraph_sender, subgraph_receiver) = backlog_channel("runtime_manager", 100);
+
+        // Hosts are also created from within the event streams of other
+        // hosts (for data sources created from templates), so the list of
+        // hosts is shared
+        let runtime_hosts: Arc<Mutex<Vec<T::Host>>> = Arc::new(Mutex::new(vec![]));
 
         // Handle incoming events from the subgraph provider.
-        Self::handle_subgraph_events(logger.clone(), store, host_builder, subgraph_receiver);
+        Self::handle_subgraph_events(
+            logger.clone(),
+            store,
+            host_builder,
+            runtime_hosts.clone(),
+            subgraph_receiver,
+        );
+
+        // The hosts are of a type that depends on the host builder, so only
+        // their statuses are kept around
+        let host_statuses = move || {
+            runtime_hosts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|host| {
+                    (
+                        host.subgraph_manifest().id.clone(),
+                        host.is_caught_up(),
+                        host.status(),
+                    )
+                })
+                .collect()
+        };
 
         RuntimeManager {
             logger,
             input: subgraph_sender,
+            host_statuses: Arc::new(host_statuses),
         }
     }
 
+    /// Returns the statuses of all runtime hosts.
+    pub fn host_statuses(&self) -> Vec<HostStatus> {
+        (self.host_statuses)()
+    }
+
     /// Handle incoming events from subgraph providers.
     fn handle_subgraph_events<S, T>(
         logger: Logger,
         store: Arc<Mutex<S>>,
-        mut host_builder: T,
-        receiver: Receiver<SubgraphProviderEvent>,
+        host_builder: T,
+        runtime_hosts: Arc<Mutex<Vec<T::Host>>>,
+        receiver: BacklogReceiver<SubgraphProviderEvent>,
+    ) where
+        S: Store + 'static,
+        T: RuntimeHostBuilder,
+    {
+        // The builder is shared for the same reason as the list of hosts
+        let host_builder = Arc::new(Mutex::new(host_builder));
+
+        tokio::spawn(receiver.for_each(move |event| {
+            match event {
+                SubgraphProviderEvent::SubgraphAdded(manifest) => {
+                    info!(logger, "Host mapping runtimes for subgraph";
+                          "location" => &manifest.location);
+
+                    let mut data_sources = manifest.data_sources.clone();
+
+                    // Re-create data sources that were created from
+                    // templates before the node was restarted
+                    if !manifest.templates.is_empty() {
+                        data_sources.extend(Self::load_dynamic_data_sources(
+                            &logger,
+                            &*store.lock().unwrap(),
+                            &manifest,
+                        ));
+                    }
+
+                    // Create a new runtime host for each data source
+                    for data_source in data_sources {
+                        Self::start_runtime_host(
+                            logger.clone(),
+                            store.clone(),
+                            host_builder.clone(),
+                            runtime_hosts.clone(),
+                            manifest.clone(),
+                            data_source,
+                        );
+                    }
+                }
+                SubgraphProviderEvent::SubgraphRemoved(ref manifest) => {
+                    info!(logger, "Stop mapping runtimes for subgraph";
+                          "location" => &manifest.location);
+
+                    // Destroy all runtime hosts for this subgraph; this
+                    // cancels their subscriptions and stops their threads,
+                    // which terminates the hosts' event streams
+                    runtime_hosts
+                        .lock()
+                        .unwrap()
+                        .retain(|host: &T::Host| host.subgraph_manifest() != manifest);
+                }
+            }
+
+            Ok(())
+        }));
+    }
+
+    /// Creates a runtime host for `data_source` and forwards its events to
+    /// the store.
+    fn start_runtime_host<S, T>(
+        logger: Logger,
+        store: Arc<Mutex<S>>,
+        host_builder: Arc<Mutex<T>>,
+        runtime_hosts: Arc<Mutex<Vec<T::Host>>>,
+        manifest: SubgraphManifest,
+        data_source: DataSource,
     ) where
         S: Store + 'static,
         T: RuntimeHostBuilder,
     {
-        // Handles each incoming event from the subgraph.
-        fn handle_event<S: Store + 'static>(store: Arc<Mutex<S>>, event: RuntimeHostEvent) {
+        let mut host = host_builder
+            .lock()
+            .unwrap()
+            .build(manifest.clone(), data_source);
+        let host_events = host.take_event_stream().unwrap();
+
+        // Add the new host to the list of managed runtime hosts
+        runtime_hosts.lock().unwrap().push(host);
+
+        // Forward events from the runtime host to the store; this Tokio task
+        // will terminate when the corresponding subgraph is removed and the
+        // host and its event sender are dropped
+        tokio::spawn(host_events.for_each(move |event| {
             match event {
                 RuntimeHostEvent::EntitySet(store_key, entity, event_source) => {
                     store
@@ -58,48 +192,144 @@ impl RuntimeManager where {
                         .delete(store_key, event_source)
                         .expect("Failed to delete entity from the store");
                 }
-            }
-        }
-
-        let mut runtime_hosts = vec![];
+                RuntimeHostEvent::EntityOperations(operations, event_source) => {
+                    store
+                        .lock()
+                        .unwrap()
+                        .transact(operations, event_source)
+                        .expect("Failed to apply entity operations to the store");
+                }
+                RuntimeHostEvent::DataSourceCreated(data_source, event_source) => {
+                    info!(logger, "Host mapping runtime for data source created from template";
+                          "template" => &data_source.name,
+                          "address" => &data_source.source.address,
+                          "start_block" => data_source.source.start_block);
 
-        tokio::spawn(receiver.for_each(move |event| {
-            match event {
-                SubgraphProviderEvent::SubgraphAdded(manifest) => {
-                    info!(logger, "Host mapping runtimes for subgraph";
-                          "location" => &manifest.location);
+                    // Persist the data source so it survives restarts
+                    let (store_key, entity) =
+                        Self::dynamic_data_source_entity(&manifest, &data_source);
+                    store
+                        .lock()
+                        .unwrap()
+                        .set(store_key, entity, event_source)
+                        .expect("Failed to persist data source in the store");
 
-                    // Create a new runtime host for each data source in the subgraph manifest
-                    let mut new_hosts = manifest
-                        .data_sources
-                        .iter()
-                        .map(|d| host_builder.build(manifest.clone(), d.clone()));
-
-                    // Forward events from the runtime host to the store; this
-                    // Tokio task will terminate when the corresponding subgraph
-                    // is removed and the host and its event sender are dropped
-                    for mut new_host in new_hosts {
-                        let store = store.clone();
-                        tokio::spawn(new_host.take_event_stream().unwrap().for_each(
-                            move |event| {
-                                handle_event(store.clone(), event);
-                                Ok(())
-                            },
-                        ));
-                        // Add the new host to the list of managed runtime hosts
-                        runtime_hosts.push(new_host);
-                    }
-                }
-                SubgraphProviderEvent::SubgraphRemoved(ref manifest) => {
-                    // Destroy all runtime hosts for this subgraph; this will
-                    // also terminate the host's event stream
-                    runtime_hosts.retain(|host| host.subgraph_manifest() != manifest);
+                    Self::start_runtime_host(
+                        logger.clone(),
+                        store.clone(),
+                        host_builder.clone(),
+                        runtime_hosts.clone(),
+                        manifest.clone(),
+                        data_source,
+                    );
                 }
             }
-
             Ok(())
         }));
     }
+
+    /// Returns the store key and entity under which a data source created
+    /// from a template is persisted.
+    fn dynamic_data_source_entity(
+        manifest: &SubgraphManifest,
+        data_source: &DataSource,
+    ) -> (StoreKey, Entity) {
+        let id = format!(
+            "{}-{}-{}",
+            manifest.id, data_source.name, data_source.source.address
+        );
+
+        let mut entity = Entity::new();
+        entity.insert("id".to_string(), Value::String(id.clone()));
+        entity.insert("subgraph".to_string(), Value::String(manifest.id.clone()));
+        entity.insert(
+            "template".to_string(),
+            Value::String(data_source.name.clone()),
+        );
+        entity.insert(
+            "address".to_string(),
+            Value::String(data_source.source.address.clone()),
+        );
+        entity.insert(
+            "startBlock".to_string(),
+            Value::BigInt(BigInt::from(U256::from(data_source.source.start_block))),
+        );
+
+        let store_key = StoreKey {
+            subgraph: META_SUBGRAPH_ID.to_string(),
+            entity: DYNAMIC_DATA_SOURCE_ENTITY.to_string(),
+            id,
+        };
+
+        (store_key, entity)
+    }
+
+    /// Re-creates a data source of `manifest` from the entity it was
+    /// persisted as. Returns `None` if the entity is invalid or its template
+    /// no longer exists.
+    fn dynamic_data_source_from_entity(
+        logger: &Logger,
+        manifest: &SubgraphManifest,
+        entity: &Entity,
+    ) -> Option<DataSource> {
+        let template = match entity.get("template") {
+            Some(Value::String(template)) => template,
+            _ => return None,
+        };
+        let address = match entity.get("address") {
+            Some(Value::String(address)) => Address::from_str(address).ok()?,
+            _ => return None,
+        };
+        let start_block = match entity.get("startBlock") {
+            Some(Value::BigInt(start_block)) => u64::from_str(&start_block.to_string()).ok()?,
+            _ => return None,
+        };
+
+        let data_source = manifest
+            .templates
+            .iter()
+            .find(|t| &t.name == template)
+            .map(|t| t.create_data_source(address, start_block));
+        if data_source.is_none() {
+            warn!(logger, "Data source template no longer exists";
+                  "template" => template);
+        }
+        data_source
+    }
+
+    /// Loads the data sources that were previously created from the
+    /// templates of `manifest`.
+    fn load_dynamic_data_sources<S>(
+        logger: &Logger,
+        store: &S,
+        manifest: &SubgraphManifest,
+    ) -> Vec<DataSource>
+    where
+        S: Store,
+    {
+        let entities = store
+            .find(StoreQuery {
+                subgraph: META_SUBGRAPH_ID.to_string(),
+                entity: DYNAMIC_DATA_SOURCE_ENTITY.to_string(),
+                filter: Some(StoreFilter::Equal(
+                    "subgraph".to_string(),
+                    Value::String(manifest.id.clone()),
+                )),
+                order_by: None,
+                order_direction: None,
+                range: None,
+            })
+            .unwrap_or_else(|_| {
+                error!(logger, "Failed to load data sources created from templates";
+                       "subgraph" => &manifest.id);
+                vec![]
+            });
+
+        entities
+            .iter()
+            .filter_map(|entity| Self::dynamic_data_source_from_entity(logger, manifest, entity))
+            .collect()
+    }
 }
 
 impl EventConsumer<SubgraphProviderEvent> for RuntimeManager {
@@ -111,3 +341,206 @@ impl EventConsumer<SubgraphProviderEvent> for RuntimeManager {
         }))
     }
 }
+
+impl RuntimeManagerTrait for RuntimeManager {
+    fn caught_up(&self, subgraph_id: &str) -> Box<Future<Item = (), Error = ()> + Send> {
+        let host_statuses = self.host_statuses.clone();
+        let subgraph_id = subgraph_id.to_owned();
+
+        // A subgraph has caught up once it has hosts and all of them have
+        // caught up
+        let is_caught_up = move |subgraph_id: &str| {
+            let mut hosts = host_statuses()
+                .into_iter()
+                .filter(|(id, _, _)| id == subgraph_id)
+                .peekable();
+            hosts.peek().is_some() && hosts.all(|(_, caught_up, _)| caught_up)
+        };
+
+        // Check the hosts of the subgraph on the timer of the runtime that
+        // the future runs on, so that waiting doesn't tie up a thread; the
+        // checks stop when the future is dropped
+        Box::new(
+            Interval::new(Instant::now(), CAUGHT_UP_CHECK_INTERVAL)
+                .map_err(|_| ())
+                .skip_while(move |_| Ok(!is_caught_up(&subgraph_id)))
+                .into_future()
+                .map(|_| ())
+                .map_err(|_| ()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::H256;
+    use futures::sync::mpsc::{Receiver, Sender};
+    use graphql_parser;
+    use parity_wasm;
+    use std::collections::BTreeMap;
+
+    use graph::components::store::StoreKey;
+    use graph::data::subgraph::*;
+    use graph::semver::Version;
+    use graph::util::stream::StreamError;
+
+    use super::*;
+
+    /// Store that keeps entities in memory and only supports the queries
+    /// made for dynamic data sources.
+    #[derive(Default)]
+    struct MemoryStore {
+        entities: BTreeMap<StoreKey, Entity>,
+    }
+
+    impl BasicStore for MemoryStore {
+        fn get(&self, key: StoreKey) -> Result<Entity, ()> {
+            self.entities.get(&key).cloned().ok_or(())
+        }
+
+        fn set(&mut self, key: StoreKey, entity: Entity, _: EventSource) -> Result<(), ()> {
+            self.entities.insert(key, entity);
+            Ok(())
+        }
+
+        fn delete(&mut self, key: StoreKey, _: EventSource) -> Result<(), ()> {
+            self.entities.remove(&key);
+            Ok(())
+        }
+
+        fn find(&self, query: StoreQuery) -> Result<Vec<Entity>, ()> {
+            Ok(self
+                .entities
+                .iter()
+                .filter(|(key, _)| key.subgraph == query.subgraph && key.entity == query.entity)
+                .filter(|(_, entity)| match query.filter {
+                    Some(StoreFilter::Equal(ref attribute, ref value)) => {
+                        entity.get(attribute) == Some(value)
+                    }
+                    None => true,
+                    ref filter => panic!("Unsupported filter: {:?}", filter),
+                })
+                .map(|(_, entity)| entity.clone())
+                .collect())
+        }
+    }
+
+    impl Store for MemoryStore {
+        fn schema_provider_event_sink(&mut self) -> Sender<SchemaProviderEvent> {
+            unimplemented!()
+        }
+
+        fn event_stream(&mut self) -> Result<Receiver<StoreEvent>, StreamError> {
+            unimplemented!()
+        }
+
+        fn transact(&mut self, _: Vec<EntityOperation>, _: EventSource) -> Result<(), ()> {
+            unimplemented!()
+        }
+
+        fn count_entities(&self, subgraph: &str) -> Result<u64, ()> {
+            Ok(self
+                .entities
+                .keys()
+                .filter(|key| key.subgraph == subgraph)
+                .count() as u64)
+        }
+    }
+
+    fn test_manifest(id: &str) -> SubgraphManifest {
+        SubgraphManifest {
+            id: String::from(id),
+            location: format!("/ipfs/{}", id),
+            spec_version: Version::new(0, 0, 1),
+            schema: Schema {
+                id: String::from("QmSchema"),
+                document: graphql_parser::parse_schema("type Transfer { id: ID! }").unwrap(),
+            },
+            data_sources: vec![],
+            templates: vec![DataSourceTemplate {
+                kind: String::from("ethereum/contract"),
+                network: None,
+                name: String::from("Template"),
+                source: TemplateSource {
+                    abi: String::from("Token"),
+                },
+                mapping: Mapping {
+                    kind: String::from("ethereum/events"),
+                    api_version: Version::new(0, 0, 2),
+                    language: String::from("wasm/assemblyscript"),
+                    entities: vec![],
+                    abis: vec![],
+                    event_handlers: vec![],
+                    call_handlers: vec![],
+                    block_handlers: vec![],
+                    runtime: parity_wasm::elements::Module::default(),
+                    runtime_hash: H256::zero(),
+                },
+            }],
+        }
+    }
+
+    fn persist<S: Store>(store: &mut S, manifest: &SubgraphManifest, data_source: &DataSource) {
+        let (key, entity) = RuntimeManager::dynamic_data_source_entity(manifest, data_source);
+        store
+            .set(key, entity, EventSource::EthereumBlock(H256::zero()))
+            .expect("Failed to persist data source");
+    }
+
+    #[test]
+    fn dynamic_data_sources_are_kept_out_of_the_subgraph() {
+        let manifest = test_manifest("QmSubgraph");
+        let data_source = manifest.templates[0].create_data_source(
+            Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
+            42,
+        );
+
+        let mut store = MemoryStore::default();
+        persist(&mut store, &manifest, &data_source);
+
+        assert_eq!(store.count_entities("QmSubgraph"), Ok(0));
+        assert_eq!(store.count_entities(META_SUBGRAPH_ID), Ok(1));
+    }
+
+    #[test]
+    fn dynamic_data_sources_are_recreated_after_a_restart() {
+        let logger = Logger::root(slog::Discard, o!());
+        let manifest = test_manifest("QmSubgraph");
+        let first = manifest.templates[0].create_data_source(
+            Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
+            42,
+        );
+        let second = manifest.templates[0].create_data_source(
+            Address::from("0000000000000000000000000000000000000001"),
+            43,
+        );
+
+        let mut store = MemoryStore::default();
+        persist(&mut store, &manifest, &first);
+        persist(&mut store, &manifest, &second);
+
+        // Data sources of other subgraphs are not re-created
+        let other = test_manifest("QmOther");
+        persist(
+            &mut store,
+            &other,
+            &other.templates[0].create_data_source(
+                Address::from("0000000000000000000000000000000000000002"),
+                44,
+            ),
+        );
+
+        let mut data_sources =
+            RuntimeManager::load_dynamic_data_sources(&logger, &store, &manifest);
+        data_sources.sort_by_key(|data_source| data_source.source.start_block);
+        assert_eq!(data_sources, vec![first, second]);
+
+        // Data sources whose template was removed are skipped
+        let mut without_templates = manifest.clone();
+        without_templates.templates = vec![];
+        assert_eq!(
+            RuntimeManager::load_dynamic_data_sources(&logger, &store, &without_templates),
+            vec![]
+        );
+    }
+}
@@ -1,3 +1,5 @@
 mod manager;
+mod status;
 
 pub use self::manager::RuntimeManager;
+pub use self::status::IndexingStatusProvider;
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use graph::components::ethereum::EthereumAdapter;
+use graph::prelude::{IndexingStatusProvider as IndexingStatusProviderTrait, *};
+use graph::util::metrics;
+
+use super::RuntimeManager;
+
+/// Reports how far the subgraphs run by a `RuntimeManager` have been indexed.
+pub struct IndexingStatusProvider<S> {
+    logger: Logger,
+    runtime_manager: Arc<RuntimeManager>,
+    store: Arc<Mutex<S>>,
+    chain_head_block: Arc<Mutex<Option<u64>>>,
+}
+
+impl<S> IndexingStatusProvider<S>
+where
+    S: Store,
+{
+    pub fn new(
+        logger: &Logger,
+        runtime_manager: Arc<RuntimeManager>,
+        store: Arc<Mutex<S>>,
+    ) -> Self {
+        IndexingStatusProvider {
+            logger: logger.new(o!("component" => "IndexingStatusProvider")),
+            runtime_manager,
+            store,
+            chain_head_block: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Keeps track of the chain head by asking `ethereum_adapter` for the
+    /// latest block every `interval`, updating how far each subgraph is
+    /// behind it. Until this is started, the chain head is reported as
+    /// unknown.
+    pub fn start_chain_head_polling<E>(&self, ethereum_adapter: Arc<Mutex<E>>, interval: Duration)
+    where
+        E: EthereumAdapter,
+    {
+        let logger = self.logger.clone();
+        let runtime_manager = self.runtime_manager.clone();
+        let chain_head_block = self.chain_head_block.clone();
+
+        thread::spawn(move || loop {
+            let latest_block = ethereum_adapter.lock().unwrap().latest_block_number();
+            match latest_block.wait() {
+                Ok(number) => {
+                    *chain_head_block.lock().unwrap() = Some(number.as_u64());
+                    update_blocks_behind(&runtime_manager, number.as_u64());
+                }
+                Err(e) => warn!(logger, "Failed to obtain the chain head: {}", e),
+            }
+            thread::sleep(interval);
+        });
+    }
+}
+
+impl<S> IndexingStatusProviderTrait for IndexingStatusProvider<S>
+where
+    S: Store,
+{
+    fn indexing_statuses(&self) -> Vec<SubgraphIndexingStatus> {
+        let chain_head_block = *self.chain_head_block.lock().unwrap();
+
+        group_host_statuses(&self.runtime_manager)
+            .into_iter()
+            .map(|(subgraph_id, (synced, statuses))| {
+                let entity_count = self
+                    .store
+                    .lock()
+                    .unwrap()
+                    .count_entities(&subgraph_id)
+                    .unwrap_or(0);
+                SubgraphIndexingStatus::from_host_statuses(
+                    subgraph_id,
+                    synced,
+                    statuses,
+                    chain_head_block,
+                    entity_count,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Groups the host statuses of `runtime_manager` by subgraph, along with
+/// whether all hosts of the subgraph have caught up.
+fn group_host_statuses(
+    runtime_manager: &RuntimeManager,
+) -> BTreeMap<String, (bool, Vec<RuntimeHostStatus>)> {
+    let mut subgraphs = BTreeMap::new();
+    for (subgraph_id, caught_up, status) in runtime_manager.host_statuses() {
+        let (synced, statuses) = subgraphs
+            .entry(subgraph_id)
+            .or_insert_with(|| (true, vec![]));
+        *synced = *synced && caught_up;
+        statuses.push(status);
+    }
+    subgraphs
+}
+
+/// Updates the metric of how many blocks each subgraph is behind the chain
+/// head.
+fn update_blocks_behind(runtime_manager: &RuntimeManager, chain_head_block: u64) {
+    for (subgraph_id, (synced, statuses)) in group_host_statuses(runtime_manager) {
+        // The entity count doesn't matter here, so the store isn't asked
+        let status = SubgraphIndexingStatus::from_host_statuses(
+            subgraph_id,
+            synced,
+            statuses,
+            Some(chain_head_block),
+            0,
+        );
+        if let Some(blocks_behind) = status.blocks_behind() {
+            metrics::BLOCKS_BEHIND_HEAD
+                .with_label_values(&[status.subgraph.as_str()])
+                .set(blocks_behind as i64);
+        }
+    }
+}
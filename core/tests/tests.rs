@@ -1,4 +1,6 @@
 extern crate ethabi;
+extern crate ethereum_types;
+extern crate failure;
 extern crate futures;
 extern crate graph;
 extern crate graph_core;
@@ -8,14 +10,17 @@ extern crate ipfs_api;
 
 use futures::{Future, Sink};
 use graph::components::ethereum::*;
+use graph::components::link_resolver::{IpfsResolver, IpfsResolverConfig};
 use graph::prelude::*;
 use graph::util::log::logger;
 use graph_core::RuntimeManager;
 use graph_mock::FakeStore;
 use graph_runtime_wasm::RuntimeHostBuilder;
 use ipfs_api::IpfsClient;
+use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
@@ -46,6 +51,49 @@ fn multiple_data_sources_per_subgraph() {
         fn unsubscribe_from_event(&mut self, _subscription_id: String) -> bool {
             unimplemented!()
         }
+
+        fn is_caught_up(&self, _subscription_id: &str) -> bool {
+            unimplemented!()
+        }
+
+        fn latest_block_number(
+            &mut self,
+        ) -> Box<Future<Item = ethereum_types::U256, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_blocks(
+            &mut self,
+            _subscription: EthereumBlockSubscription,
+        ) -> Box<Stream<Item = EthereumBlock, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn events_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+            _events: Vec<ethabi::Event>,
+        ) -> Box<Future<Item = Vec<EthereumEvent>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn calls_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+            _functions: Vec<ethabi::Function>,
+        ) -> Box<Future<Item = Vec<EthereumCall>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn is_called_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+        ) -> Box<Future<Item = bool, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
     }
 
     /// Adds string to IPFS and returns link of the form `/ipfs/`.
@@ -58,7 +106,8 @@ fn multiple_data_sources_per_subgraph() {
 
     // Replace "link to" placeholders in the subgraph manifest with hashes
     // of files just added into a local IPFS daemon on port 5001.
-    let resolver = Arc::new(IpfsClient::default());
+    let logger = logger();
+    let client = IpfsClient::default();
     let mut subgraph_string =
         std::fs::read_to_string("tests/subgraph-two-datasources/two-datasources.yaml").unwrap();
     for file in &[
@@ -68,20 +117,24 @@ fn multiple_data_sources_per_subgraph() {
         "schema.graphql",
     ] {
         let link = add(
-            &resolver,
+            &client,
             read_to_string(format!("tests/subgraph-two-datasources/{}", file)).unwrap(),
         ).wait()
             .unwrap();
         subgraph_string =
             subgraph_string.replace(&format!("link to {}", file), &format!("/ipfs/{}", link));
     }
-    let subgraph_link = add(&resolver, subgraph_string).wait().unwrap();
+    let subgraph_link = add(&client, subgraph_string).wait().unwrap();
 
-    let logger = logger();
+    let resolver = Arc::new(IpfsResolver::new(
+        &logger,
+        client,
+        IpfsResolverConfig::default(),
+    ));
     let eth_adapter = Arc::new(Mutex::new(MockEthereumAdapter {
         received_subscriptions: vec![],
     }));
-    let host_builder = RuntimeHostBuilder::new(&logger, eth_adapter.clone(), resolver.clone());
+    let host_builder = RuntimeHostBuilder::new(&logger, eth_adapter.clone(), resolver.clone(), 1);
 
     let fake_store = Arc::new(Mutex::new(FakeStore));
     let manager = RuntimeManager::new(&logger, fake_store, host_builder);
@@ -114,3 +167,183 @@ fn multiple_data_sources_per_subgraph() {
         }
     }
 }
+
+#[test]
+fn add_and_remove_subgraph_repeatedly() {
+    /// Ethereum adapter whose event streams stay open until they are
+    /// unsubscribed from.
+    #[derive(Default)]
+    struct MockEthereumAdapter {
+        subscriptions: HashMap<String, futures::sync::mpsc::Sender<EthereumEvent>>,
+        live_streams: Arc<AtomicUsize>,
+    }
+
+    /// Decrements the number of live event streams when dropped.
+    struct LiveStream(Arc<AtomicUsize>);
+
+    impl Drop for LiveStream {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl EthereumAdapter for MockEthereumAdapter {
+        fn contract_call(
+            &mut self,
+            _request: EthereumContractCall,
+        ) -> Box<Future<Item = Vec<ethabi::Token>, Error = EthereumContractCallError>> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_event(
+            &mut self,
+            subscription: EthereumEventSubscription,
+        ) -> Box<Stream<Item = EthereumEvent, Error = EthereumSubscriptionError>> {
+            let (sender, receiver) = futures::sync::mpsc::channel(1);
+            self.subscriptions
+                .insert(subscription.subscription_id, sender);
+
+            self.live_streams.fetch_add(1, Ordering::SeqCst);
+            let live_stream = LiveStream(self.live_streams.clone());
+
+            Box::new(receiver.map_err(|()| unreachable!()).map(move |event| {
+                // Keep the guard alive for as long as the stream
+                let _ = &live_stream;
+                event
+            }))
+        }
+
+        fn unsubscribe_from_event(&mut self, subscription_id: String) -> bool {
+            // Dropping the sender ends the stream
+            self.subscriptions.remove(&subscription_id).is_some()
+        }
+
+        fn is_caught_up(&self, _subscription_id: &str) -> bool {
+            false
+        }
+
+        fn latest_block_number(
+            &mut self,
+        ) -> Box<Future<Item = ethereum_types::U256, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_blocks(
+            &mut self,
+            _subscription: EthereumBlockSubscription,
+        ) -> Box<Stream<Item = EthereumBlock, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn events_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+            _events: Vec<ethabi::Event>,
+        ) -> Box<Future<Item = Vec<EthereumEvent>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn calls_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+            _functions: Vec<ethabi::Function>,
+        ) -> Box<Future<Item = Vec<EthereumCall>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn is_called_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: ethereum_types::Address,
+        ) -> Box<Future<Item = bool, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+    }
+
+    /// Resolves the `link to <file>` placeholders of the test subgraph to
+    /// the files next to its manifest.
+    struct FixtureLinkResolver;
+
+    impl LinkResolver for FixtureLinkResolver {
+        fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+            let path = format!(
+                "tests/subgraph-two-datasources/{}",
+                link.link.trim_left_matches("link to ")
+            );
+            Box::new(future::result(
+                std::fs::read(path).map_err(failure::Error::from),
+            ))
+        }
+    }
+
+    /// Waits for up to five seconds for `condition` to become true.
+    fn wait_until(description: &str, condition: impl Fn() -> bool) {
+        let start_time = Instant::now();
+        let max_wait = Duration::from_secs(5);
+        while !condition() {
+            ::std::thread::sleep(Duration::from_millis(10));
+            if Instant::now().duration_since(start_time) > max_wait {
+                panic!("Test failed, timed out waiting until {}", description)
+            }
+        }
+    }
+
+    let logger = logger();
+    let resolver = Arc::new(FixtureLinkResolver);
+    let eth_adapter = Arc::new(Mutex::new(MockEthereumAdapter::default()));
+    let live_streams = eth_adapter.lock().unwrap().live_streams.clone();
+
+    let subgraph = SubgraphManifest::resolve(
+        Link {
+            link: String::from("two-datasources.yaml"),
+        },
+        resolver.clone(),
+    ).wait()
+        .expect("failed to load subgraph");
+
+    // The manager spawns Tokio tasks, so it has to be created on the runtime
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (manager_sender, manager_receiver) = ::std::sync::mpsc::channel();
+    let manager_eth_adapter = eth_adapter.clone();
+    runtime.spawn(future::lazy(move || {
+        let host_builder = RuntimeHostBuilder::new(&logger, manager_eth_adapter, resolver, 2);
+        let fake_store = Arc::new(Mutex::new(FakeStore));
+        manager_sender
+            .send(RuntimeManager::new(&logger, fake_store, host_builder))
+            .unwrap();
+        Ok(())
+    }));
+    let manager = manager_receiver.recv().unwrap();
+
+    for _ in 0..10 {
+        manager
+            .event_sink()
+            .send(SubgraphProviderEvent::SubgraphAdded(subgraph.clone()))
+            .wait()
+            .unwrap();
+
+        // Each of the two data sources subscribes to one event
+        wait_until("both events are subscribed to", || {
+            eth_adapter.lock().unwrap().subscriptions.len() == 2
+                && live_streams.load(Ordering::SeqCst) == 2
+        });
+
+        manager
+            .event_sink()
+            .send(SubgraphProviderEvent::SubgraphRemoved(subgraph.clone()))
+            .wait()
+            .unwrap();
+
+        // Removing the subgraph must cancel all subscriptions, and the WASM
+        // workers must drop the event streams of the removed runtime hosts
+        wait_until("all subscriptions are cancelled", || {
+            eth_adapter.lock().unwrap().subscriptions.is_empty()
+                && live_streams.load(Ordering::SeqCst) == 0
+        });
+    }
+
+    drop(manager);
+    runtime.shutdown_on_idle().wait().unwrap();
+}
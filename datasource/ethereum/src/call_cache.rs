@@ -0,0 +1,153 @@
+use ethereum_types::{Address, H256};
+use graph::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Maximum number of call results kept in memory.
+const MAX_CACHED_CALLS: usize = 10_000;
+
+/// Maximum number of block numbers kept in memory.
+const MAX_CACHED_BLOCK_NUMBERS: usize = 1_000;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct CallKey {
+    block_hash: H256,
+    contract_address: Address,
+    encoded_call: Vec<u8>,
+}
+
+/// A map that forgets its oldest entries once it is full.
+struct BoundedMap<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    insertion_order: VecDeque<K>,
+}
+
+impl<K, V> BoundedMap<K, V>
+where
+    K: Clone + ::std::hash::Hash + Eq,
+    V: Clone,
+{
+    fn new(capacity: usize) -> Self {
+        BoundedMap {
+            capacity,
+            entries: HashMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.entries.insert(key.clone(), value).is_some() {
+            return;
+        }
+
+        self.insertion_order.push_back(key);
+        if self.insertion_order.len() > self.capacity {
+            let oldest = self.insertion_order.pop_front().unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// Cache for the results of contract calls, keyed by block hash, contract
+/// address and ABI-encoded call.
+///
+/// Results are kept in memory and, if a persistent cache is available, also
+/// stored there, so they survive restarts. The cache also remembers the
+/// numbers of recently seen blocks, which `eth_call` needs instead of their
+/// hashes.
+pub struct CallCache {
+    logger: Logger,
+    calls: Mutex<BoundedMap<CallKey, Vec<u8>>>,
+    block_numbers: Mutex<BoundedMap<H256, u64>>,
+    store: Option<Arc<Mutex<EthereumCallCache>>>,
+}
+
+impl CallCache {
+    pub fn new(logger: &Logger, store: Option<Arc<Mutex<EthereumCallCache>>>) -> Self {
+        CallCache {
+            logger: logger.clone(),
+            calls: Mutex::new(BoundedMap::new(MAX_CACHED_CALLS)),
+            block_numbers: Mutex::new(BoundedMap::new(MAX_CACHED_BLOCK_NUMBERS)),
+            store,
+        }
+    }
+
+    /// Looks up the result of a call, first in memory, then in the
+    /// persistent cache.
+    pub fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block_hash: H256,
+    ) -> Option<Vec<u8>> {
+        let key = CallKey {
+            block_hash,
+            contract_address,
+            encoded_call: encoded_call.to_vec(),
+        };
+
+        if let Some(return_value) = self.calls.lock().unwrap().get(&key) {
+            return Some(return_value);
+        }
+
+        let return_value = self.store.as_ref().and_then(|store| {
+            store
+                .lock()
+                .unwrap()
+                .get_call(contract_address, encoded_call, block_hash)
+                .unwrap_or_else(|e| {
+                    warn!(self.logger, "Failed to look up cached contract call";
+                          "error" => format!("{}", e));
+                    None
+                })
+        })?;
+
+        self.calls.lock().unwrap().insert(key, return_value.clone());
+        Some(return_value)
+    }
+
+    /// Caches the result of a call, in memory and in the persistent cache.
+    pub fn set_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block_hash: H256,
+        return_value: &[u8],
+    ) {
+        self.calls.lock().unwrap().insert(
+            CallKey {
+                block_hash,
+                contract_address,
+                encoded_call: encoded_call.to_vec(),
+            },
+            return_value.to_vec(),
+        );
+
+        if let Some(ref store) = self.store {
+            store
+                .lock()
+                .unwrap()
+                .set_call(contract_address, encoded_call, block_hash, return_value)
+                .unwrap_or_else(|e| {
+                    warn!(self.logger, "Failed to cache contract call";
+                          "error" => format!("{}", e));
+                });
+        }
+    }
+
+    pub fn block_number(&self, block_hash: H256) -> Option<u64> {
+        self.block_numbers.lock().unwrap().get(&block_hash)
+    }
+
+    pub fn set_block_number(&self, block_hash: H256, block_number: u64) {
+        self.block_numbers
+            .lock()
+            .unwrap()
+            .insert(block_hash, block_number);
+    }
+}
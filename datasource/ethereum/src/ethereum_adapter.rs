@@ -1,32 +1,102 @@
-use ethabi::{RawLog, Token};
+use ethabi::{self, Event, Function, LogParam, RawLog, Token};
 use ethereum_types::H256;
+use futures::future::{self, Shared};
 use futures::prelude::*;
-use futures::stream::iter_ok;
-use std::sync::Arc;
+use futures::stream::{self, iter_ok};
+use futures::sync::oneshot;
+use serde::de::DeserializeOwned;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use web3;
-use web3::api::CreateFilter;
-use web3::api::{Eth, Web3};
+use web3::api::{BaseFilter, Eth, Web3};
 use web3::helpers::CallResult;
 use web3::types::*;
 
 use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
+use graph::prelude::{debug, kv, o, warn, EthereumCallCache, Logger};
+use graph::serde_json::{self, Value};
+use graph::util::ethereum::{function_selector, function_signature};
+use graph::util::retry::{retry, retry_stream, RetryPolicy, TransientError};
+
+use call_cache::CallCache;
+use pubsub::PubSubTransport;
+use trace::{Trace, TraceFilter};
+
+/// Maximum number of attempts for a contract call. Mappings wait for their
+/// calls on a WASM worker, so calls are never retried forever, whatever the
+/// adapter's retry policy.
+const CONTRACT_CALL_MAX_ATTEMPTS: u32 = 10;
+
+/// Number of past blocks that are requested at once. With a batching
+/// transport, the requests for such a range go out as one JSON-RPC batch.
+const BLOCK_RANGE_SIZE: usize = 50;
+
+/// Number of recent blocks whose hashes are remembered by block
+/// subscriptions, to recognize the blocks that replace them in a reorg.
+const REORG_DEPTH: u64 = 250;
 
 pub struct EthereumAdapterConfig<T: web3::Transport> {
     pub transport: T,
+    /// Policy for retrying requests that fail with transient errors.
+    pub retry_policy: RetryPolicy,
+    /// Persistent cache for the results of contract calls, in addition to
+    /// the in-memory cache.
+    pub call_cache: Option<Arc<Mutex<EthereumCallCache>>>,
 }
 
 pub struct EthereumAdapter<T: web3::Transport> {
+    logger: Logger,
     eth_client: Arc<Web3<T>>,
+    retry_policy: RetryPolicy,
+    call_cache: Arc<CallCache>,
+    /// Active subscriptions, by subscription ID.
+    subscriptions: HashMap<String, Subscription>,
+}
+
+/// An active event or block subscription.
+struct Subscription {
+    /// Dropping the sender cancels the subscription.
+    _cancel: oneshot::Sender<()>,
+    /// Set once the subscription has passed on everything up to the chain
+    /// head at the time it was made.
+    caught_up: Arc<AtomicBool>,
 }
 
 impl<T: web3::Transport> EthereumAdapter<T> {
-    pub fn new(config: EthereumAdapterConfig<T>) -> Self {
+    pub fn new(logger: &Logger, config: EthereumAdapterConfig<T>) -> Self {
+        let logger = logger.new(o!("component" => "EthereumAdapter"));
         EthereumAdapter {
             eth_client: Arc::new(Web3::new(config.transport)),
+            retry_policy: config.retry_policy,
+            call_cache: Arc::new(CallCache::new(&logger, config.call_cache)),
+            logger,
+            subscriptions: HashMap::new(),
         }
     }
 
+    /// Registers a subscription and returns a future that resolves once the
+    /// subscription is cancelled, along with the flag that marks the
+    /// subscription as caught up.
+    fn register_subscription(
+        &mut self,
+        subscription_id: String,
+    ) -> (Shared<oneshot::Receiver<()>>, Arc<AtomicBool>) {
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
+        let caught_up = Arc::new(AtomicBool::new(false));
+        self.subscriptions.insert(
+            subscription_id,
+            Subscription {
+                _cancel: cancel_sender,
+                caught_up: caught_up.clone(),
+            },
+        );
+        (cancel_receiver.shared(), caught_up)
+    }
+
     pub fn block_number(&self) -> CallResult<U256, T::Out> {
         self.eth_client.eth().block_number()
     }
@@ -35,20 +105,183 @@ impl<T: web3::Transport> EthereumAdapter<T> {
         self.eth_client.web3().sha3(Bytes::from(data))
     }
 
-    pub fn event_filter(&self, subscription: EthereumEventSubscription) -> CreateFilter<T, Log> {
+    pub fn event_filter(subscription: &EthereumEventSubscription) -> Filter {
+        Self::logs_filter(
+            subscription.event.signature(),
+            subscription.range.from.clone(),
+            subscription.range.to.clone(),
+        )
+    }
+
+    fn logs_filter(event_signature: H256, from: BlockNumber, to: BlockNumber) -> Filter {
         let filter_builder = FilterBuilder::default();
-        let eth_filter: Filter = filter_builder
-            .from_block(subscription.range.from)
-            .to_block(subscription.range.to)
-            .topics(Some(vec![subscription.event.signature()]), None, None, None)
-            .build();
-        self.eth_client.eth_filter().create_logs_filter(eth_filter)
+        filter_builder
+            .from_block(from)
+            .to_block(to)
+            .topics(Some(vec![event_signature]), None, None, None)
+            .build()
     }
 
     pub fn block(eth: Eth<T>, block_id: BlockId) -> CallResult<Block<H256>, T::Out> {
         eth.block(block_id)
     }
 
+    /// Resolves a block ID into the hash and number of the block. Both are
+    /// `None` for pending blocks.
+    fn resolve_block(
+        eth_client: &Web3<T>,
+        call_cache: &Arc<CallCache>,
+        block_id: BlockId,
+    ) -> Box<Future<Item = (Option<H256>, Option<u64>), Error = EthereumContractCallError>>
+    where
+        T: 'static,
+    {
+        // Calls made by mappings are made against the hash of the block
+        // being processed, typically many times per block, so remember the
+        // number that belongs to the hash
+        if let BlockId::Hash(block_hash) = block_id {
+            if let Some(block_number) = call_cache.block_number(block_hash) {
+                return Box::new(future::ok((Some(block_hash), Some(block_number))));
+            }
+        }
+
+        let call_cache = call_cache.clone();
+        Box::new(
+            Self::block(eth_client.eth(), block_id)
+                .map_err(EthereumContractCallError::from)
+                .map(move |block| {
+                    let block_number = block.number.map(|number| number.as_u64());
+                    if let (Some(block_hash), Some(block_number)) = (block.hash, block_number) {
+                        call_cache.set_block_number(block_hash, block_number);
+                    }
+                    (block.hash, block_number)
+                }),
+        )
+    }
+
+    pub fn block_with_txs(
+        eth: Eth<T>,
+        block_id: BlockId,
+    ) -> CallResult<Block<Transaction>, T::Out> {
+        eth.block_with_txs(block_id)
+    }
+
+    /// Obtains the traces of all calls to `address` in the block with the
+    /// given number.
+    pub fn traces(
+        eth_client: &Web3<T>,
+        block_number: u64,
+        address: Address,
+    ) -> CallResult<Vec<Trace>, T::Out> {
+        let filter = TraceFilter {
+            from_block: BlockNumber::Number(block_number),
+            to_block: BlockNumber::Number(block_number),
+            to_address: vec![address],
+        };
+        CallResult::new(eth_client.transport().execute(
+            "trace_filter",
+            vec![serde_json::to_value(filter).expect("Failed to serialize trace filter")],
+        ))
+    }
+
+    /// Decodes a successful call to one of `functions` from its trace.
+    /// Returns `None` if the trace is not such a call.
+    ///
+    /// Only plain calls are decoded, whether made by a transaction or by
+    /// another contract. `delegatecall` and `callcode` run the contract's
+    /// code on behalf of the caller, with the caller's storage, and
+    /// `staticcall` cannot change any state, so these are skipped.
+    fn decode_call(
+        logger: &Logger,
+        trace: Trace,
+        address: Address,
+        functions: &[(String, Function)],
+    ) -> Option<Result<EthereumCall, ethabi::Error>> {
+        if trace.trace_type != "call" || trace.error.is_some() || trace.action.to != Some(address) {
+            return None;
+        }
+
+        match trace.action.call_type.as_ref().map(String::as_str) {
+            Some("call") => (),
+            call_type => {
+                debug!(logger, "Skipping call that is not a plain call";
+                       "call_type" => call_type.unwrap_or("unknown"),
+                       "transaction" => format!("{:?}", trace.transaction_hash));
+                return None;
+            }
+        }
+
+        let block_hash = trace.block_hash;
+        let block_number = U256::from(trace.block_number);
+        let from = trace.action.from?;
+        let transaction_hash = trace.transaction_hash?;
+        let input = trace.action.input?.0;
+        let output = trace
+            .result?
+            .output
+            .map(|output| output.0)
+            .unwrap_or_default();
+
+        if input.len() < 4 {
+            return None;
+        }
+        let (signature, function) = functions
+            .iter()
+            .find(|(signature, _)| function_selector(signature) == input[..4])?;
+
+        let decode_params = |params: &[ethabi::Param], data: &[u8]| {
+            let kinds: Vec<_> = params.iter().map(|param| param.kind.clone()).collect();
+            ethabi::decode(&kinds, data).map(|tokens| {
+                params
+                    .iter()
+                    .zip(tokens)
+                    .map(|(param, value)| LogParam {
+                        name: param.name.clone(),
+                        value,
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        Some(
+            decode_params(&function.inputs, &input[4..]).and_then(|inputs| {
+                decode_params(&function.outputs, &output).map(|outputs| EthereumCall {
+                    from,
+                    to: address,
+                    block_hash,
+                    block_number,
+                    transaction_hash,
+                    function_signature: signature.clone(),
+                    inputs,
+                    outputs,
+                })
+            }),
+        )
+    }
+
+    /// Converts a web3 block into an `EthereumBlock`. Returns `None` for
+    /// pending blocks, which have no hash or number yet.
+    fn to_ethereum_block(block: Block<Transaction>) -> Option<EthereumBlock> {
+        Some(EthereumBlock {
+            hash: block.hash?,
+            parent_hash: block.parent_hash,
+            number: U256::from(block.number?.as_u64()),
+            timestamp: block.timestamp,
+            author: block.author,
+            gas_used: block.gas_used,
+            gas_limit: block.gas_limit,
+            transactions: block
+                .transactions
+                .into_iter()
+                .map(|transaction| EthereumTransaction {
+                    hash: transaction.hash,
+                    from: transaction.from,
+                    to: transaction.to,
+                })
+                .collect(),
+        })
+    }
+
     fn call(
         eth: Eth<T>,
         contract_address: Address,
@@ -67,41 +300,391 @@ impl<T: web3::Transport> EthereumAdapter<T> {
     }
 }
 
-impl<T: web3::Transport + Send + Sync + 'static> EthereumAdapterTrait for EthereumAdapter<T> {
+impl<T: PubSubTransport + 'static> EthereumAdapter<T> {
+    /// Streams the items that `install_filter` returns along with the filter
+    /// it installs, followed by the changes of that filter.
+    ///
+    /// A node forgets its filters when it restarts, and a provider that took
+    /// over after a failover never knew them, so polling the same filter
+    /// again would fail forever. Whenever polling fails, the filter is
+    /// installed again instead. Once `cancel` resolves, the filter is
+    /// uninstalled and the stream ends.
+    fn poll_filter<I, F, R, S>(
+        logger: &Logger,
+        retry_policy: RetryPolicy,
+        cancel: Shared<oneshot::Receiver<()>>,
+        mut install_filter: F,
+    ) -> impl Stream<Item = I, Error = EthereumSubscriptionError>
+    where
+        I: Clone + DeserializeOwned + 'static,
+        F: FnMut() -> R + 'static,
+        R: Future<Item = (BaseFilter<T, I>, S), Error = web3::error::Error> + 'static,
+        S: Stream<Item = I, Error = web3::error::Error> + 'static,
+    {
+        let installed: Arc<Mutex<Option<BaseFilter<T, I>>>> = Arc::new(Mutex::new(None));
+        let poll_installed = installed.clone();
+        let changes = retry_stream(logger, "eth_getFilterChanges", retry_policy, move || {
+            let installed = poll_installed.clone();
+            install_filter()
+                .map(move |(base_filter, items)| {
+                    let changes = base_filter.clone().stream(Duration::from_millis(2000));
+                    *installed.lock().unwrap() = Some(base_filter);
+                    items.chain(changes)
+                })
+                .flatten_stream()
+                .map_err(FilterError)
+        })
+        .map_err(|e| EthereumSubscriptionError::from(e.0));
+
+        // Once the subscription is cancelled, uninstall the filter before
+        // ending the stream
+        let uninstall_stream = future::lazy(move || {
+            Ok::<_, EthereumSubscriptionError>(installed.lock().unwrap().take())
+        })
+        .into_stream()
+        .filter_map(|base_filter| base_filter)
+        .and_then(|base_filter| base_filter.uninstall().then(|_| Ok(())))
+        .filter_map(|()| None);
+
+        until_cancelled(changes, cancel).chain(uninstall_stream)
+    }
+
+    /// Streams the logs of an event subscription as they are pushed through
+    /// `eth_subscribe("logs")`.
+    ///
+    /// Whenever the subscription is made, initially or again after it
+    /// failed, e.g. because the connection was lost, the logs since the last
+    /// log passed on are fetched first, so that no logs are missed.
+    fn subscribe_to_logs(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        subscription: &EthereumEventSubscription,
+        caught_up: Arc<AtomicBool>,
+    ) -> impl Stream<Item = Log, Error = EthereumSubscriptionError> {
+        let event_signature = subscription.event.signature();
+        let range_from = subscription.range.from.clone();
+        let range_to = subscription.range.to.clone();
+        let to = match range_to {
+            BlockNumber::Number(number) => Some(number),
+            _ => None,
+        };
+
+        // Notifications are only sent for new blocks, so the subscription
+        // takes the filter without its block range
+        let mut filter = serde_json::to_value(Self::event_filter(subscription))
+            .expect("Failed to serialize log filter");
+        if let Some(filter) = filter.as_object_mut() {
+            filter.remove("fromBlock");
+            filter.remove("toBlock");
+        }
+        let params = vec![Value::String("logs".to_owned()), filter];
+
+        // Block number and index of the last log passed on
+        let last_seen: Arc<Mutex<Option<(u64, u64)>>> = Arc::new(Mutex::new(None));
+        let subscribe_last_seen = last_seen.clone();
+
+        retry_stream(logger, "eth_subscribe", retry_policy, move || {
+            let notifications = eth_client
+                .transport()
+                .subscribe(params.clone())
+                .and_then(|log| {
+                    serde_json::from_value::<Log>(log).map_err(|e| {
+                        web3::error::ErrorKind::InvalidResponse(format!("Invalid log: {}", e))
+                            .into()
+                    })
+                });
+
+            let from = subscribe_last_seen
+                .lock()
+                .unwrap()
+                .map_or(range_from.clone(), |(block_number, _)| {
+                    BlockNumber::Number(block_number)
+                });
+            let past_logs = eth_client
+                .eth()
+                .logs(Self::logs_filter(event_signature, from, range_to.clone()))
+                .map(|logs| iter_ok::<_, web3::error::Error>(logs))
+                .flatten_stream();
+            mark_caught_up(past_logs, caught_up.clone()).chain(notifications)
+        })
+        .map_err(EthereumSubscriptionError::from)
+        .filter(move |log| pass_log_once(&last_seen, to, log))
+    }
+
+    /// Streams the logs of an event subscription by polling a logs filter.
+    ///
+    /// Whenever the filter is installed, initially or again after polling it
+    /// failed, the logs since the last log passed on are fetched first, so
+    /// that no logs are missed.
+    fn poll_logs(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        subscription: &EthereumEventSubscription,
+        caught_up: Arc<AtomicBool>,
+        cancel: Shared<oneshot::Receiver<()>>,
+    ) -> impl Stream<Item = Log, Error = EthereumSubscriptionError> {
+        let event_signature = subscription.event.signature();
+        let range_from = subscription.range.from.clone();
+        let range_to = subscription.range.to.clone();
+        let to = match range_to {
+            BlockNumber::Number(number) => Some(number),
+            _ => None,
+        };
+
+        // Block number and index of the last log passed on
+        let last_seen: Arc<Mutex<Option<(u64, u64)>>> = Arc::new(Mutex::new(None));
+        let install_last_seen = last_seen.clone();
+
+        Self::poll_filter(logger, retry_policy, cancel, move || {
+            let from = install_last_seen
+                .lock()
+                .unwrap()
+                .map_or(range_from.clone(), |(block_number, _)| {
+                    BlockNumber::Number(block_number)
+                });
+            let caught_up = caught_up.clone();
+            eth_client
+                .eth_filter()
+                .create_logs_filter(Self::logs_filter(event_signature, from, range_to.clone()))
+                .map(move |base_filter| {
+                    let past_logs = base_filter
+                        .logs()
+                        .map(|logs| iter_ok::<_, web3::error::Error>(logs))
+                        .flatten_stream();
+                    (base_filter, mark_caught_up(past_logs, caught_up))
+                })
+        })
+        .filter(move |log| pass_log_once(&last_seen, to, log))
+    }
+
+    /// Streams the blocks from `next_number` on as their headers are pushed
+    /// through `eth_subscribe("newHeads")`.
+    ///
+    /// Blocks before a new head that were not pushed, e.g. because the
+    /// connection was lost and the subscription had to be made again, are
+    /// fetched along with the new head.
+    fn subscribe_to_new_blocks(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        next_number: u64,
+    ) -> impl Stream<Item = Block<Transaction>, Error = EthereumSubscriptionError> {
+        let subscribe_eth_client = eth_client.clone();
+        let new_heads = retry_stream(logger, "eth_subscribe", retry_policy, move || {
+            subscribe_eth_client
+                .transport()
+                .subscribe(vec![Value::String("newHeads".to_owned())])
+                .and_then(header_number)
+        })
+        .map_err(EthereumSubscriptionError::from);
+
+        Self::blocks_up_to(logger, eth_client, retry_policy, next_number, new_heads)
+    }
+
+    /// Streams the blocks from `next_number` on as new blocks are reported
+    /// by polling a block filter.
+    ///
+    /// Blocks that were not reported, e.g. because the filter had to be
+    /// installed again, are fetched along with the next block that is.
+    fn poll_new_blocks(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        next_number: u64,
+        cancel: Shared<oneshot::Receiver<()>>,
+    ) -> impl Stream<Item = Block<Transaction>, Error = EthereumSubscriptionError> {
+        let filter_eth_client = eth_client.clone();
+        let block_eth_client = eth_client.clone();
+        let block_logger = logger.clone();
+        let new_blocks = Self::poll_filter(logger, retry_policy, cancel, move || {
+            filter_eth_client
+                .eth_filter()
+                .create_blocks_filter()
+                .map(|base_filter| (base_filter, stream::empty()))
+        })
+        .and_then(move |block_hash| {
+            let eth_client = block_eth_client.clone();
+            retry(
+                &block_logger,
+                "eth_getBlockByHash",
+                retry_policy,
+                move || Self::block(eth_client.eth(), BlockId::Hash(block_hash)),
+            )
+            .map_err(EthereumSubscriptionError::from)
+        })
+        .filter_map(|block| block.number.map(|number| number.as_u64()));
+
+        Self::blocks_up_to(logger, eth_client, retry_policy, next_number, new_blocks)
+    }
+
+    /// Streams the blocks from `next_number` on, up to each of the block
+    /// numbers that `heads` produces in turn. Heads that were streamed
+    /// before are streamed again.
+    fn blocks_up_to<S>(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        mut next_number: u64,
+        heads: S,
+    ) -> impl Stream<Item = Block<Transaction>, Error = EthereumSubscriptionError>
+    where
+        S: Stream<Item = u64, Error = EthereumSubscriptionError>,
+    {
+        let logger = logger.clone();
+        heads
+            .map(move |number| {
+                // A head below `next_number` replaces a block in a reorg
+                let numbers = cmp::min(next_number, number)..number + 1;
+                next_number = cmp::max(next_number, number + 1);
+                iter_ok::<_, EthereumSubscriptionError>(numbers)
+            })
+            .flatten()
+            .and_then(move |number| {
+                let eth_client = eth_client.clone();
+                retry(&logger, "eth_getBlockByNumber", retry_policy, move || {
+                    Self::block_with_txs(
+                        eth_client.eth(),
+                        BlockId::Number(BlockNumber::Number(number)),
+                    )
+                })
+                .map_err(EthereumSubscriptionError::from)
+            })
+    }
+
+    /// Returns `block`, preceded by its ancestors that replaced blocks in
+    /// `seen`, oldest first. These are the blocks of the reorg that made
+    /// `block` the new head, if there was one.
+    fn with_replaced_ancestors(
+        logger: &Logger,
+        eth_client: Arc<Web3<T>>,
+        retry_policy: RetryPolicy,
+        seen: Arc<Mutex<BTreeMap<u64, H256>>>,
+        block: EthereumBlock,
+    ) -> impl Future<Item = Vec<EthereumBlock>, Error = EthereumSubscriptionError> {
+        let logger = logger.clone();
+        future::loop_fn(vec![block], move |mut blocks| {
+            let (parent_number, parent_hash) = {
+                let oldest = blocks.last().unwrap();
+                (oldest.number.as_u64().checked_sub(1), oldest.parent_hash)
+            };
+            let replaced = parent_number.map_or(false, |number| {
+                seen.lock()
+                    .unwrap()
+                    .get(&number)
+                    .map_or(false, |hash| *hash != parent_hash)
+            });
+            if !replaced {
+                blocks.reverse();
+                return future::Either::A(future::ok(future::Loop::Break(blocks)));
+            }
+
+            let eth_client = eth_client.clone();
+            future::Either::B(
+                retry(&logger, "eth_getBlockByHash", retry_policy, move || {
+                    Self::block_with_txs(eth_client.eth(), BlockId::Hash(parent_hash))
+                })
+                .map_err(EthereumSubscriptionError::from)
+                .map(move |parent| match Self::to_ethereum_block(parent) {
+                    Some(parent) => {
+                        blocks.push(parent);
+                        future::Loop::Continue(blocks)
+                    }
+                    None => {
+                        blocks.reverse();
+                        future::Loop::Break(blocks)
+                    }
+                }),
+            )
+        })
+    }
+}
+
+impl<T: PubSubTransport + Send + Sync + 'static> EthereumAdapterTrait for EthereumAdapter<T> {
     fn contract_call(
         &mut self,
         call: EthereumContractCall,
     ) -> Box<Future<Item = Vec<Token>, Error = EthereumContractCallError>> {
         // Obtain a handle on the Ethereum client
         let eth_client = self.eth_client.clone();
+        let call_cache = self.call_cache.clone();
 
         // Prepare for the function call, encoding the call parameters according
         // to the ABI
         let call_address = call.address;
         let call_data = call.function.encode_input(&call.args).unwrap();
+        let block_id = call.block_id;
+        let function = call.function;
 
-        Box::new(
-            // Resolve the block ID into a block number
-            Self::block(eth_client.eth(), call.block_id.clone())
+        // Decode the return values according to the ABI
+        let decode_output = move |output: Vec<u8>| {
+            function
+                .decode_output(&output)
                 .map_err(EthereumContractCallError::from)
-                .and_then(move |block| {
-                    // Make the actual function call
-                    Self::call(
-                        eth_client.eth(),
-                        call_address,
-                        Bytes(call_data),
-                        block
-                            .number
-                            .map(|number| number.as_u64())
-                            .map(BlockNumber::Number),
-                    ).map_err(EthereumContractCallError::from)
-                })
-                // Decode the return values according to the ABI
-                .and_then(move |output| {
-                    call.function
-                        .decode_output(&output.0)
-                        .map_err(EthereumContractCallError::from)
-                }),
+        };
+
+        // Calls against a block hash can be answered from the cache without
+        // contacting the node at all
+        if let BlockId::Hash(block_hash) = block_id {
+            if let Some(output) = call_cache.get_call(call_address, &call_data, block_hash) {
+                return Box::new(future::result(decode_output(output)));
+            }
+        }
+
+        let retry_policy = RetryPolicy {
+            max_attempts: Some(
+                self.retry_policy
+                    .max_attempts
+                    .map_or(CONTRACT_CALL_MAX_ATTEMPTS, |max_attempts| {
+                        cmp::min(max_attempts, CONTRACT_CALL_MAX_ATTEMPTS)
+                    }),
+            ),
+            ..self.retry_policy
+        };
+
+        Box::new(
+            retry(&self.logger, "eth_call", retry_policy, move || {
+                let eth_client = eth_client.clone();
+                let call_cache = call_cache.clone();
+                let call_data = call_data.clone();
+
+                // Resolve the block ID into a block number
+                Self::resolve_block(&eth_client, &call_cache, block_id.clone()).and_then(
+                    move |(block_hash, block_number)| -> Box<Future<Item = _, Error = _>> {
+                        if let Some(block_hash) = block_hash {
+                            if let Some(output) =
+                                call_cache.get_call(call_address, &call_data, block_hash)
+                            {
+                                return Box::new(future::ok(output));
+                            }
+                        }
+
+                        // Make the actual function call
+                        Box::new(
+                            Self::call(
+                                eth_client.eth(),
+                                call_address,
+                                Bytes(call_data.clone()),
+                                block_number.map(BlockNumber::Number),
+                            )
+                            .map_err(EthereumContractCallError::from)
+                            .map(move |output| {
+                                if let Some(block_hash) = block_hash {
+                                    call_cache.set_call(
+                                        call_address,
+                                        &call_data,
+                                        block_hash,
+                                        &output.0,
+                                    );
+                                }
+                                output.0
+                            }),
+                        )
+                    },
+                )
+            })
+            .and_then(decode_output),
         )
     }
 
@@ -110,41 +693,439 @@ impl<T: web3::Transport + Send + Sync + 'static> EthereumAdapterTrait for Ethere
         subscription: EthereumEventSubscription,
     ) -> Box<Stream<Item = EthereumEvent, Error = EthereumSubscriptionError>> {
         let event = subscription.event.clone();
+        let (cancel, caught_up) = self.register_subscription(subscription.subscription_id.clone());
+        let eth_client = self.eth_client.clone();
+        let logger = self.logger.clone();
+        let retry_policy = self.retry_policy;
+
+        // Have new logs pushed through `eth_subscribe` if the transport
+        // supports it; otherwise poll a logs filter
+        let logs: Box<Stream<Item = Log, Error = EthereumSubscriptionError>> =
+            if eth_client.transport().supports_pubsub() {
+                Box::new(until_cancelled(
+                    Self::subscribe_to_logs(
+                        &logger,
+                        eth_client,
+                        retry_policy,
+                        &subscription,
+                        caught_up,
+                    ),
+                    cancel,
+                ))
+            } else {
+                Box::new(Self::poll_logs(
+                    &logger,
+                    eth_client,
+                    retry_policy,
+                    &subscription,
+                    caught_up,
+                    cancel,
+                ))
+            };
+
         Box::new(
-            self.event_filter(subscription)
-                .map_err(EthereumSubscriptionError::from)
-                .map(|base_filter| {
-                    let past_logs_stream = base_filter
-                        .logs()
-                        .map_err(EthereumSubscriptionError::from)
-                        .map(|logs_vec| iter_ok::<_, EthereumSubscriptionError>(logs_vec))
-                        .flatten_stream();
-                    let future_logs_stream = base_filter
-                        .stream(Duration::from_millis(2000))
-                        .map_err(EthereumSubscriptionError::from);
-                    past_logs_stream.chain(future_logs_stream)
-                })
-                .flatten_stream()
-                .and_then(move |log| {
-                    event
-                        .parse_log(RawLog {
-                            topics: log.topics.clone(),
-                            data: log.clone().data.0,
-                        })
+            logs.and_then(move |log| {
+                event
+                    .parse_log(RawLog {
+                        topics: log.topics.clone(),
+                        data: log.clone().data.0,
+                    })
+                    .map_err(EthereumSubscriptionError::from)
+                    .map(|log_data| (log, log_data))
+            })
+            .map(move |(log, log_data)| EthereumEvent {
+                address: log.address,
+                event_signature: log.topics[0],
+                block_hash: log.block_hash.unwrap(),
+                block_number: log.block_number.unwrap(),
+                params: log_data.params,
+                removed: log.is_removed(),
+            }),
+        )
+    }
+
+    fn unsubscribe_from_event(&mut self, subscription_id: String) -> bool {
+        // Dropping the sender cancels the subscription
+        self.subscriptions.remove(&subscription_id).is_some()
+    }
+
+    fn is_caught_up(&self, subscription_id: &str) -> bool {
+        self.subscriptions
+            .get(subscription_id)
+            .map_or(false, |subscription| {
+                subscription.caught_up.load(Ordering::SeqCst)
+            })
+    }
+
+    fn latest_block_number(
+        &mut self,
+    ) -> Box<Future<Item = U256, Error = EthereumSubscriptionError>> {
+        Box::new(self.block_number().map_err(EthereumSubscriptionError::from))
+    }
+
+    fn subscribe_to_blocks(
+        &mut self,
+        subscription: EthereumBlockSubscription,
+    ) -> Box<Stream<Item = EthereumBlock, Error = EthereumSubscriptionError>> {
+        let eth_client = self.eth_client.clone();
+        let (cancel, caught_up) = self.register_subscription(subscription.subscription_id.clone());
+        let logger = self.logger.clone();
+        let retry_policy = self.retry_policy;
+        let range = subscription.range;
+        let to = match range.to {
+            BlockNumber::Number(number) => Some(number),
+            _ => None,
+        };
+
+        // Blocks may be reported by both the past and the future block
+        // streams, or more than once by the filter; only pass each block on
+        // once. Blocks that replace blocks passed on before are passed on as
+        // well, so that the blocks of a reorg are processed again
+        let seen = Arc::new(Mutex::new(BTreeMap::new()));
+        let ancestor_logger = self.logger.clone();
+        let ancestor_eth_client = eth_client.clone();
+
+        // Have new blocks pushed through `eth_subscribe` if the transport
+        // supports it; otherwise poll a block filter
+        let pubsub = eth_client.transport().supports_pubsub();
+
+        let head_eth_client = eth_client.clone();
+
+        Box::new(
+            retry(&self.logger, "eth_blockNumber", retry_policy, move || {
+                head_eth_client.eth().block_number()
+            })
+            .map_err(EthereumSubscriptionError::from)
+            .map(move |head| {
+                let head = head.as_u64();
+                let from = match range.from {
+                    BlockNumber::Number(number) => number,
+                    BlockNumber::Earliest => 0,
+                    BlockNumber::Latest | BlockNumber::Pending => head + 1,
+                };
+
+                let past_eth_client = eth_client.clone();
+                let past_logger = logger.clone();
+                // Request past blocks a range at a time rather than one by
+                // one; `buffered` still passes them on in order
+                let past_blocks_stream = iter_ok::<_, EthereumSubscriptionError>(from..=head)
+                    .map(move |number| {
+                        let eth_client = past_eth_client.clone();
+                        retry(
+                            &past_logger,
+                            "eth_getBlockByNumber",
+                            retry_policy,
+                            move || {
+                                Self::block_with_txs(
+                                    eth_client.eth(),
+                                    BlockId::Number(BlockNumber::Number(number)),
+                                )
+                            },
+                        )
                         .map_err(EthereumSubscriptionError::from)
-                        .map(|log_data| (log, log_data))
+                    })
+                    .buffered(BLOCK_RANGE_SIZE);
+
+                let next_number = cmp::max(from, head + 1);
+                let future_blocks_stream: Box<
+                    Stream<Item = Block<Transaction>, Error = EthereumSubscriptionError>,
+                > = if pubsub {
+                    Box::new(until_cancelled(
+                        Self::subscribe_to_new_blocks(
+                            &logger,
+                            eth_client.clone(),
+                            retry_policy,
+                            next_number,
+                        ),
+                        cancel.clone(),
+                    ))
+                } else {
+                    // Polling the filter ends with uninstalling it once the
+                    // subscription is cancelled
+                    Box::new(Self::poll_new_blocks(
+                        &logger,
+                        eth_client.clone(),
+                        retry_policy,
+                        next_number,
+                        cancel.clone(),
+                    ))
+                };
+
+                until_cancelled(mark_caught_up(past_blocks_stream, caught_up), cancel)
+                    .chain(future_blocks_stream)
+            })
+            .flatten_stream()
+            .filter_map(Self::to_ethereum_block)
+            .and_then({
+                let seen = seen.clone();
+                move |block| {
+                    Self::with_replaced_ancestors(
+                        &ancestor_logger,
+                        ancestor_eth_client.clone(),
+                        retry_policy,
+                        seen.clone(),
+                        block,
+                    )
+                }
+            })
+            .map(iter_ok)
+            .flatten()
+            .filter(move |block| pass_block_once(&seen, block))
+            .take_while(move |block| future::ok(to.map_or(true, |to| block.number.as_u64() <= to))),
+        )
+    }
+
+    fn events_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+        events: Vec<Event>,
+    ) -> Box<Future<Item = Vec<EthereumEvent>, Error = EthereumSubscriptionError>> {
+        let block_hash = block.hash;
+        let block_number = block.number.as_u64();
+
+        let eth_filter: Filter = FilterBuilder::default()
+            .from_block(BlockNumber::Number(block_number))
+            .to_block(BlockNumber::Number(block_number))
+            .address(vec![address])
+            .topics(
+                Some(events.iter().map(|event| event.signature()).collect()),
+                None,
+                None,
+                None,
+            )
+            .build();
+
+        let eth_client = self.eth_client.clone();
+
+        Box::new(
+            retry(&self.logger, "eth_getLogs", self.retry_policy, move || {
+                eth_client.eth().logs(eth_filter.clone())
+            })
+            .map_err(EthereumSubscriptionError::from)
+            .and_then(move |logs| {
+                logs.into_iter()
+                    // Ignore logs from blocks that have replaced this
+                    // block in the meantime
+                    .filter(|log| log.block_hash == Some(block_hash))
+                    .filter_map(|log| {
+                        let signature = *log.topics.first()?;
+                        events
+                            .iter()
+                            .find(|event| event.signature() == signature)
+                            .map(|event| (event, log))
+                    })
+                    .map(|(event, log)| {
+                        event
+                            .parse_log(RawLog {
+                                topics: log.topics.clone(),
+                                data: log.data.0.clone(),
+                            })
+                            .map_err(EthereumSubscriptionError::from)
+                            .map(|log_data| EthereumEvent {
+                                address: log.address,
+                                event_signature: log.topics[0],
+                                block_hash,
+                                block_number: U256::from(block_number),
+                                params: log_data.params,
+                                removed: log.is_removed(),
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            }),
+        )
+    }
+
+    fn calls_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+        functions: Vec<Function>,
+    ) -> Box<Future<Item = Vec<EthereumCall>, Error = EthereumSubscriptionError>> {
+        let block_hash = block.hash;
+        let functions: Vec<_> = functions
+            .into_iter()
+            .map(|function| (function_signature(&function), function))
+            .collect();
+
+        let block_number = block.number.as_u64();
+        let eth_client = self.eth_client.clone();
+        let logger = self.logger.clone();
+        let error_logger = self.logger.clone();
+
+        Box::new(
+            retry(&self.logger, "trace_filter", self.retry_policy, move || {
+                Self::traces(&eth_client, block_number, address)
+            })
+            .map_err(move |e| {
+                // There is no way to obtain calls without traces
+                warn!(error_logger, "Failed to obtain traces; call handlers require an \
+                                     Ethereum node that supports `trace_filter`";
+                      "block" => block_number,
+                      "error" => e.to_string());
+                EthereumSubscriptionError::from(e)
+            })
+            .and_then(move |traces| {
+                traces
+                    .into_iter()
+                    // Ignore traces from blocks that have replaced this
+                    // block in the meantime
+                    .filter(|trace| trace.block_hash == block_hash)
+                    .filter_map(|trace| Self::decode_call(&logger, trace, address, &functions))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(EthereumSubscriptionError::from)
+            }),
+        )
+    }
+
+    fn is_called_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+    ) -> Box<Future<Item = bool, Error = EthereumSubscriptionError>> {
+        // Direct calls are visible in the block itself
+        if block.calls_address(&address) {
+            return Box::new(future::ok(true));
+        }
+
+        let block_hash = block.hash;
+        let block_number = block.number.as_u64();
+        let eth_client = self.eth_client.clone();
+        let logger = self.logger.clone();
+
+        Box::new(
+            retry(&self.logger, "trace_filter", self.retry_policy, move || {
+                Self::traces(&eth_client, block_number, address)
+            })
+            .map(move |traces| {
+                traces.iter().any(|trace| {
+                    trace.block_hash == block_hash
+                        && trace.error.is_none()
+                        && trace.action.to == Some(address)
                 })
-                .map(move |(log, log_data)| EthereumEvent {
-                    address: log.address,
-                    event_signature: log.topics[0],
-                    block_hash: log.block_hash.unwrap(),
-                    params: log_data.params,
-                    removed: log.is_removed(),
-                }),
+            })
+            .or_else(move |e| {
+                // Without `trace_filter`, only direct calls can be detected
+                debug!(logger, "Unable to look for internal calls";
+                       "block" => block_number,
+                       "error" => e.to_string());
+                Ok::<_, EthereumSubscriptionError>(false)
+            }),
         )
     }
+}
+
+/// Extracts the block number from a `newHeads` notification.
+fn header_number(header: Value) -> Result<u64, web3::error::Error> {
+    header
+        .get("number")
+        .cloned()
+        .and_then(|number| serde_json::from_value::<U256>(number).ok())
+        .map(|number| number.as_u64())
+        .ok_or_else(|| {
+            web3::error::ErrorKind::InvalidResponse(format!("Invalid block header: {}", header))
+                .into()
+        })
+}
 
-    fn unsubscribe_from_event(&mut self, _unique_id: String) -> bool {
+/// Returns whether `log` is to be passed on, given the block number and
+/// index of the last log passed on in `last_seen`, which is updated. Logs
+/// after block `to` are never passed on.
+fn pass_log_once(last_seen: &Mutex<Option<(u64, u64)>>, to: Option<u64>, log: &Log) -> bool {
+    let number = match (log.block_number, log.log_index) {
+        (Some(block_number), Some(log_index)) => (block_number.as_u64(), log_index.as_u64()),
+        _ => return true,
+    };
+    if to.map_or(false, |to| number.0 > to) {
+        return false;
+    }
+
+    // Logs that were fetched may be fetched or pushed again; only pass each
+    // log on once and in order, unless a reorg removed it, in which case the
+    // logs of its block may come again
+    let mut last_seen = last_seen.lock().unwrap();
+    if log.is_removed() {
+        *last_seen = Some((number.0.saturating_sub(1), u64::max_value()));
+        true
+    } else if last_seen.map_or(true, |last| number > last) {
+        *last_seen = Some(number);
+        true
+    } else {
         false
     }
 }
+
+/// Returns whether `block` is to be passed on, given the hashes of the
+/// recent blocks passed on in `seen`, which are updated. Blocks are passed
+/// on once, unless a reorg replaced them in the meantime.
+fn pass_block_once(seen: &Mutex<BTreeMap<u64, H256>>, block: &EthereumBlock) -> bool {
+    let number = block.number.as_u64();
+    let mut seen = seen.lock().unwrap();
+    if seen.get(&number) == Some(&block.hash) {
+        return false;
+    }
+
+    // Blocks after a replaced block are no longer part of the chain
+    seen.split_off(&(number + 1));
+    seen.insert(number, block.hash);
+    let recent = seen.split_off(&number.saturating_sub(REORG_DEPTH));
+    *seen = recent;
+    true
+}
+
+/// Error from polling a filter.
+///
+/// Nodes report filters they don't know as an RPC error. Such an error is
+/// not transient in general, but here it means that the filter has to be
+/// installed again.
+#[derive(Debug)]
+struct FilterError(web3::error::Error);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TransientError for FilterError {
+    fn is_transient(&self) -> bool {
+        match self.0.kind() {
+            web3::error::ErrorKind::Rpc(e) => e.message.to_lowercase().contains("filter not found"),
+            _ => self.0.is_transient(),
+        }
+    }
+}
+
+/// Passes on the items of `stream` and sets `caught_up` once it has ended.
+fn mark_caught_up<S>(
+    stream: S,
+    caught_up: Arc<AtomicBool>,
+) -> impl Stream<Item = S::Item, Error = S::Error>
+where
+    S: Stream,
+{
+    stream.chain(
+        future::lazy(move || {
+            caught_up.store(true, Ordering::SeqCst);
+            Ok::<Option<S::Item>, S::Error>(None)
+        })
+        .into_stream()
+        .filter_map(|item| item),
+    )
+}
+
+/// Ends `stream` as soon as `cancel` resolves, i.e. when the subscription
+/// that `stream` belongs to is cancelled.
+fn until_cancelled<S>(
+    stream: S,
+    cancel: Shared<oneshot::Receiver<()>>,
+) -> impl Stream<Item = S::Item, Error = S::Error>
+where
+    S: Stream,
+{
+    stream
+        .map(Some)
+        .select(cancel.into_stream().then(|_| Ok(None)))
+        .take_while(|item| future::ok(item.is_some()))
+        .map(|item| item.unwrap())
+}
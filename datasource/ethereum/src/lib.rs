@@ -2,13 +2,31 @@ extern crate ethabi;
 extern crate ethereum_types;
 extern crate futures;
 extern crate graph;
+extern crate hyper;
+extern crate hyper_tls;
 extern crate jsonrpc_core;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate tokio_core;
 
 /// Re-export of the `web3` crate.
 pub extern crate web3;
+extern crate websocket;
 
+mod call_cache;
 mod ethereum_adapter;
+mod providers;
+mod pubsub;
+mod recording;
+mod trace;
 mod transport;
 
 pub use self::ethereum_adapter::{EthereumAdapter, EthereumAdapterConfig};
-pub use self::transport::Transport;
+pub use self::providers::{Provider, ProviderCapabilities, ProviderOptions, ProviderPool};
+pub use self::pubsub::{NotificationStream, PubSubTransport};
+pub use self::recording::{Recorder, Recording, Replayer};
+pub use self::transport::{
+    BatchHttp, BatchOptions, BatchWebSocket, Batching, RawBatchTransport, ReconnectingWebSocket,
+    Transport,
+};
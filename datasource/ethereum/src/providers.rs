@@ -0,0 +1,809 @@
+use futures::future;
+use futures::prelude::*;
+use graph::prelude::*;
+use graph::serde_json::Value;
+use graph::util::metrics;
+use graph::util::retry::TransientError;
+use jsonrpc_core::types::{Call, Error as RpcError, Params};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use web3::error::{Error, ErrorKind};
+use web3::helpers::build_request;
+use web3::{RequestId, Transport};
+
+use pubsub::{self, NotificationStream, PubSubTransport};
+
+/// How long a provider that failed is ejected from the pool, unless a
+/// health check finds it healthy again earlier.
+const EJECTION_DURATION: Duration = Duration::from_secs(30);
+
+/// Number of blocks for which the pool remembers the provider that requests
+/// about them were sent to.
+const MAX_STICKY_BLOCKS: usize = 1_000;
+
+/// Optional capabilities of an Ethereum provider.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    /// The provider keeps the state of all historical blocks.
+    pub archive: bool,
+    /// The provider supports `trace_*` and `debug_trace*` requests.
+    pub traces: bool,
+}
+
+impl ProviderCapabilities {
+    fn supports(&self, required: &ProviderCapabilities) -> bool {
+        (self.archive || !required.archive) && (self.traces || !required.traces)
+    }
+}
+
+/// Options of an Ethereum provider, given on the command line as e.g.
+/// `[weight=2,archive,traces]http://localhost:8545`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderOptions {
+    /// Relative share of the requests sent to the provider.
+    pub weight: u32,
+    pub capabilities: ProviderCapabilities,
+}
+
+impl Default for ProviderOptions {
+    fn default() -> Self {
+        ProviderOptions {
+            weight: 1,
+            capabilities: ProviderCapabilities::default(),
+        }
+    }
+}
+
+impl ProviderOptions {
+    /// Splits an optional `[...]` options prefix off a provider location.
+    pub fn parse(value: &str) -> Result<(ProviderOptions, &str), String> {
+        let mut options = ProviderOptions::default();
+
+        if !value.starts_with('[') {
+            return Ok((options, value));
+        }
+        let end = value
+            .find(']')
+            .ok_or_else(|| format!("Unterminated provider options in `{}`", value))?;
+
+        for option in value[1..end].split(',').map(str::trim) {
+            match option {
+                "" => (),
+                "archive" => options.capabilities.archive = true,
+                "traces" => options.capabilities.traces = true,
+                _ if option.starts_with("weight=") => {
+                    options.weight = option["weight=".len()..]
+                        .parse()
+                        .map_err(|_| format!("Invalid provider weight `{}`", option))?;
+                }
+                _ => return Err(format!("Unknown provider option `{}`", option)),
+            }
+        }
+
+        Ok((options, &value[end + 1..]))
+    }
+}
+
+/// An Ethereum provider in a `ProviderPool`.
+#[derive(Debug)]
+pub struct Provider<T> {
+    /// Name used for logging, e.g. the URL of the provider.
+    pub name: String,
+    pub transport: T,
+    pub options: ProviderOptions,
+}
+
+struct ProviderState<T> {
+    provider: Provider<T>,
+    /// Set while the provider is ejected from the pool after failing.
+    ejected_until: Option<Instant>,
+    /// Used for smooth weighted round-robin selection.
+    current_weight: i64,
+}
+
+impl<T> ProviderState<T> {
+    fn is_healthy(&self) -> bool {
+        self.ejected_until
+            .map_or(true, |ejected_until| Instant::now() >= ejected_until)
+    }
+}
+
+/// A filter installed through the pool.
+struct FilterState {
+    /// Index into `providers` of the provider the filter is installed on.
+    provider: usize,
+    /// ID of the filter on that provider. Once the filter has been
+    /// re-installed on another provider, this differs from the ID that the
+    /// pool handed out for it.
+    provider_filter_id: String,
+    /// The request that installed the filter, for re-installing it.
+    method: String,
+    params: Vec<Value>,
+    /// Number of the latest block that the filter returned logs for.
+    latest_block: Option<u64>,
+    /// Set when a log filter has been re-installed. The next request for
+    /// its changes asks for all of its logs instead, which start after
+    /// `latest_block`, so that no logs are missed.
+    backfill: bool,
+}
+
+struct PoolState<T> {
+    providers: Vec<ProviderState<T>>,
+    /// Filters are local to the provider that installed them, so requests
+    /// for a filter always go to that provider. Maps the filter IDs handed
+    /// out by the pool to the filters.
+    filters: HashMap<String, FilterState>,
+    /// Providers may not have seen the same blocks yet, so requests about a
+    /// block go to the provider that the first request about it went to.
+    /// Maps block numbers and hashes to indices into `providers`.
+    blocks: HashMap<String, usize>,
+    /// Keys of `blocks`, oldest first.
+    block_order: VecDeque<String>,
+}
+
+impl<T> PoolState<T> {
+    /// Remembers that requests about `block` go to the provider `index`.
+    fn remember_block(&mut self, block: String, index: usize) {
+        if self.blocks.insert(block.clone(), index).is_none() {
+            self.block_order.push_back(block);
+        }
+        while self.block_order.len() > MAX_STICKY_BLOCKS {
+            if let Some(oldest) = self.block_order.pop_front() {
+                self.blocks.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// A `web3::Transport` that spreads requests across several Ethereum
+/// providers, weighted by their configured weights.
+///
+/// Requests that need a capability (e.g. traces) only go to providers that
+/// have it, unless no provider has it. Requests about a specific block go
+/// to the same provider as earlier requests about that block, as long as
+/// it is healthy. Requests that fail because a provider cannot be reached
+/// are retried on another provider, and the failed provider is ejected
+/// from the pool until it is healthy again; filters installed on it are
+/// re-installed on another provider.
+#[derive(Clone)]
+pub struct ProviderPool<T> {
+    logger: Logger,
+    state: Arc<Mutex<PoolState<T>>>,
+    next_request_id: Arc<AtomicUsize>,
+}
+
+impl<T> fmt::Debug for ProviderPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_list()
+            .entries(state.providers.iter().map(|state| &state.provider.name))
+            .finish()
+    }
+}
+
+impl<T> ProviderPool<T>
+where
+    T: Transport + Send + 'static,
+    T::Out: 'static,
+{
+    pub fn new(logger: &Logger, providers: Vec<Provider<T>>) -> Self {
+        assert!(
+            !providers.is_empty(),
+            "At least one Ethereum provider is required"
+        );
+
+        ProviderPool {
+            logger: logger.new(o!("component" => "ProviderPool")),
+            state: Arc::new(Mutex::new(PoolState {
+                providers: providers
+                    .into_iter()
+                    .map(|provider| ProviderState {
+                        provider,
+                        ejected_until: None,
+                        current_weight: 0,
+                    })
+                    .collect(),
+                filters: HashMap::new(),
+                blocks: HashMap::new(),
+                block_order: VecDeque::new(),
+            })),
+            next_request_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Checks the health of all providers every `interval` by requesting
+    /// the latest block number from them. Providers that fail the check are
+    /// ejected from the pool, providers that pass it are readmitted.
+    ///
+    /// The checks run on a dedicated thread until the pool is dropped.
+    pub fn start_health_checks(&self, interval: Duration) {
+        let logger = self.logger.clone();
+        let state = Arc::downgrade(&self.state);
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if !Self::check_health(&logger, &state) {
+                break;
+            }
+        });
+    }
+
+    /// Performs one round of health checks. Returns `false` once the pool
+    /// has been dropped.
+    fn check_health(logger: &Logger, state: &Weak<Mutex<PoolState<T>>>) -> bool {
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return false,
+        };
+
+        let checks: Vec<_> = {
+            let state = state.lock().unwrap();
+            state
+                .providers
+                .iter()
+                .map(|provider| {
+                    provider
+                        .provider
+                        .transport
+                        .execute("eth_blockNumber", vec![])
+                })
+                .collect()
+        };
+
+        for (index, check) in checks.into_iter().enumerate() {
+            let result = check.wait();
+            let mut state = state.lock().unwrap();
+            let provider = &mut state.providers[index];
+
+            match result {
+                Ok(_) => {
+                    if provider.ejected_until.take().is_some() {
+                        info!(logger, "Ethereum provider is healthy again";
+                              "provider" => &provider.provider.name);
+                    }
+                }
+                Err(e) => {
+                    warn!(logger, "Ethereum provider failed health check";
+                          "provider" => &provider.provider.name,
+                          "error" => format!("{}", e));
+                    provider.ejected_until = Some(Instant::now() + EJECTION_DURATION);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Selects a provider for a request, skipping the providers in
+    /// `excluded`. The `preferred` provider is selected if it is one of the
+    /// candidates.
+    fn select_provider(
+        state: &mut PoolState<T>,
+        required: &ProviderCapabilities,
+        excluded: &[usize],
+        preferred: Option<usize>,
+    ) -> Option<usize> {
+        // Only consider providers with the required capabilities, unless no
+        // provider has them
+        let capable: Vec<usize> = {
+            let capable: Vec<usize> = (0..state.providers.len())
+                .filter(|i| {
+                    state.providers[*i]
+                        .provider
+                        .options
+                        .capabilities
+                        .supports(required)
+                })
+                .collect();
+            if capable.is_empty() {
+                (0..state.providers.len()).collect()
+            } else {
+                capable
+            }
+        };
+        let candidates: Vec<usize> = capable
+            .into_iter()
+            .filter(|i| !excluded.contains(i))
+            .collect();
+
+        // Prefer healthy providers, but if all of them are ejected, trying
+        // an ejected one is better than failing right away
+        let healthy: Vec<usize> = candidates
+            .iter()
+            .cloned()
+            .filter(|i| state.providers[*i].is_healthy())
+            .collect();
+        let candidates = if healthy.is_empty() {
+            candidates
+        } else {
+            healthy
+        };
+
+        if let Some(preferred) = preferred.filter(|preferred| candidates.contains(preferred)) {
+            return Some(preferred);
+        }
+
+        // Smooth weighted round-robin: every candidate gains its weight, the
+        // one with the highest current weight is picked and pays back the
+        // total weight of all candidates
+        let total_weight: i64 = candidates
+            .iter()
+            .map(|i| state.providers[*i].provider.options.weight as i64)
+            .sum();
+        for i in candidates.iter() {
+            let provider = &mut state.providers[*i];
+            provider.current_weight += provider.provider.options.weight as i64;
+        }
+        let selected = candidates
+            .iter()
+            .cloned()
+            .max_by_key(|i| state.providers[*i].current_weight)?;
+        state.providers[selected].current_weight -= total_weight;
+
+        Some(selected)
+    }
+
+    /// Sends `request` to a provider, preferably the one that requests about
+    /// `block` went to, retrying on other providers if the provider cannot
+    /// be reached. Returns the index of the provider that answered along
+    /// with the response.
+    fn send_to_any(
+        &self,
+        id: RequestId,
+        request: Call,
+        required: ProviderCapabilities,
+        block: Option<String>,
+        excluded: Vec<usize>,
+    ) -> Box<Future<Item = (usize, Value), Error = Error>> {
+        let (index, name, response) = {
+            let mut state = self.state.lock().unwrap();
+            let preferred = block
+                .as_ref()
+                .and_then(|block| state.blocks.get(block).cloned());
+            let index = match Self::select_provider(&mut state, &required, &excluded, preferred) {
+                Some(index) => index,
+                None => {
+                    return Box::new(future::err(
+                        ErrorKind::Transport(String::from(
+                            "No Ethereum provider could handle the request",
+                        ))
+                        .into(),
+                    ))
+                }
+            };
+            let provider = &state.providers[index].provider;
+            (
+                index,
+                provider.name.clone(),
+                provider.transport.send(id, request.clone()),
+            )
+        };
+
+        let pool = self.clone();
+        Box::new(
+            response.then(move |result| -> Box<Future<Item = _, Error = _>> {
+                let e = match result {
+                    Ok(value) => {
+                        // Later requests about the block go to the same provider
+                        if let Some(block) = block {
+                            pool.state.lock().unwrap().remember_block(block, index);
+                        }
+                        return Box::new(future::ok((index, value)));
+                    }
+                    Err(e) => e,
+                };
+
+                metrics::ETHEREUM_RPC_ERRORS
+                    .with_label_values(&[name.as_str()])
+                    .inc();
+
+                if !e.is_transient() {
+                    return Box::new(future::err(e));
+                }
+
+                warn!(pool.logger, "Ethereum provider failed, trying another one";
+                      "provider" => &name,
+                      "error" => format!("{}", e));
+                pool.eject(index);
+
+                let mut excluded = excluded;
+                excluded.push(index);
+                pool.send_to_any(id, request, required, block, excluded)
+            }),
+        )
+    }
+
+    /// Sends `request` to the provider with the given index, without
+    /// retrying on other providers.
+    fn send_to(
+        &self,
+        index: usize,
+        id: RequestId,
+        request: Call,
+    ) -> Box<Future<Item = Value, Error = Error>> {
+        let (name, response) = {
+            let state = self.state.lock().unwrap();
+            let provider = &state.providers[index].provider;
+            (provider.name.clone(), provider.transport.send(id, request))
+        };
+
+        let pool = self.clone();
+        Box::new(response.map_err(move |e| {
+            metrics::ETHEREUM_RPC_ERRORS
+                .with_label_values(&[name.as_str()])
+                .inc();
+            if e.is_transient() {
+                pool.eject(index);
+            }
+            e
+        }))
+    }
+
+    /// Sends a request for the filter with the pool's ID `filter_id` to the
+    /// provider the filter is installed on. If that provider is unhealthy
+    /// or cannot be reached, the filter is re-installed on another provider
+    /// that is not in `excluded`, and the request is sent there.
+    fn send_filter_request(
+        &self,
+        id: RequestId,
+        method: String,
+        filter_id: String,
+        excluded: Vec<usize>,
+    ) -> Box<Future<Item = Value, Error = Error>> {
+        if method == "eth_uninstallFilter" {
+            let filter = self.state.lock().unwrap().filters.remove(&filter_id);
+            return match filter {
+                Some(filter) => {
+                    let request =
+                        build_request(id, &method, vec![Value::String(filter.provider_filter_id)]);
+                    self.send_to(filter.provider, id, request)
+                }
+                None => Box::new(future::ok(Value::Bool(false))),
+            };
+        }
+
+        let (index, provider_filter_id, healthy, backfill) = {
+            let state = self.state.lock().unwrap();
+            let filter = match state.filters.get(&filter_id) {
+                Some(filter) => filter,
+                None => {
+                    return Box::new(future::err(
+                        ErrorKind::Rpc(RpcError::invalid_params("filter not found")).into(),
+                    ))
+                }
+            };
+            (
+                filter.provider,
+                filter.provider_filter_id.clone(),
+                state.providers[filter.provider].is_healthy(),
+                filter.backfill,
+            )
+        };
+
+        if !healthy {
+            let mut excluded = excluded;
+            excluded.push(index);
+            return self.reinstall_filter(id, method, filter_id, excluded);
+        }
+
+        // A re-installed log filter first returns all of its logs
+        let provider_method = if backfill && method == "eth_getFilterChanges" {
+            String::from("eth_getFilterLogs")
+        } else {
+            method.clone()
+        };
+        let request = build_request(
+            id,
+            &provider_method,
+            vec![Value::String(provider_filter_id)],
+        );
+
+        let pool = self.clone();
+        Box::new(self.send_to(index, id, request).then(
+            move |result| -> Box<Future<Item = _, Error = _>> {
+                match result {
+                    Ok(response) => {
+                        let mut state = pool.state.lock().unwrap();
+                        if let Some(filter) = state.filters.get_mut(&filter_id) {
+                            filter.backfill = false;
+                            if let Some(latest_block) = latest_log_block(&response) {
+                                filter.latest_block =
+                                    Some(cmp::max(latest_block, filter.latest_block.unwrap_or(0)));
+                            }
+                        }
+                        Box::new(future::ok(response))
+                    }
+                    Err(ref e) if e.is_transient() => {
+                        let mut excluded = excluded;
+                        excluded.push(index);
+                        pool.reinstall_filter(id, method, filter_id, excluded)
+                    }
+                    Err(e) => Box::new(future::err(e)),
+                }
+            },
+        ))
+    }
+
+    /// Installs the filter with the pool's ID `filter_id` again on a
+    /// provider that is not in `excluded`, and then sends the request for
+    /// the filter there. Log filters are installed again for the blocks
+    /// after the latest block they returned logs for.
+    fn reinstall_filter(
+        &self,
+        id: RequestId,
+        method: String,
+        filter_id: String,
+        excluded: Vec<usize>,
+    ) -> Box<Future<Item = Value, Error = Error>> {
+        let (install_method, install_params) = {
+            let state = self.state.lock().unwrap();
+            let filter = match state.filters.get(&filter_id) {
+                Some(filter) => filter,
+                None => {
+                    return Box::new(future::err(
+                        ErrorKind::Rpc(RpcError::invalid_params("filter not found")).into(),
+                    ))
+                }
+            };
+
+            let mut params = filter.params.clone();
+            if let (Some(latest_block), Some(Value::Object(filter_params))) =
+                (filter.latest_block, params.first_mut())
+            {
+                filter_params.insert(
+                    String::from("fromBlock"),
+                    Value::String(format!("0x{:x}", latest_block + 1)),
+                );
+            }
+            (filter.method.clone(), params)
+        };
+
+        let required = required_capabilities(&install_method, &install_params);
+        let (install_id, install_request) = self.prepare(&install_method, install_params);
+        let is_log_filter = install_method == "eth_newFilter";
+        let install_pool = self.clone();
+        let install_filter_id = filter_id.clone();
+        let pool = self.clone();
+
+        Box::new(
+            self.send_to_any(
+                install_id,
+                install_request,
+                required,
+                None,
+                excluded.clone(),
+            )
+            .and_then(move |(index, response)| {
+                let provider_filter_id = match response.as_str() {
+                    Some(provider_filter_id) => provider_filter_id.to_owned(),
+                    None => {
+                        let message = format!("Invalid filter ID: {}", response);
+                        return Err(ErrorKind::InvalidResponse(message).into());
+                    }
+                };
+
+                let mut state = install_pool.state.lock().unwrap();
+                info!(install_pool.logger, "Installed filter again on another provider";
+                      "filter" => &install_filter_id,
+                      "provider" => &state.providers[index].provider.name);
+                if let Some(filter) = state.filters.get_mut(&install_filter_id) {
+                    filter.provider = index;
+                    filter.provider_filter_id = provider_filter_id;
+                    filter.backfill = is_log_filter;
+                }
+                Ok(())
+            })
+            .and_then(move |()| pool.send_filter_request(id, method, filter_id, excluded)),
+        )
+    }
+
+    fn eject(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.providers[index].ejected_until = Some(Instant::now() + EJECTION_DURATION);
+    }
+}
+
+impl<T> Transport for ProviderPool<T>
+where
+    T: Transport + Send + 'static,
+    T::Out: 'static,
+{
+    type Out = Box<Future<Item = Value, Error = Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_request_id.fetch_add(1, Ordering::SeqCst);
+        (id, build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let (method, params) = match request {
+            Call::MethodCall(ref call) => (call.method.clone(), params_to_vec(&call.params)),
+            _ => (String::new(), vec![]),
+        };
+
+        // Requests for an installed filter must go to the provider that
+        // installed it
+        if method.starts_with("eth_getFilter") || method == "eth_uninstallFilter" {
+            let filter_id = params.first().and_then(Value::as_str).map(str::to_owned);
+            if let Some(filter_id) = filter_id {
+                if self.state.lock().unwrap().filters.contains_key(&filter_id) {
+                    return self.send_filter_request(id, method, filter_id, vec![]);
+                }
+            }
+        }
+
+        let required = required_capabilities(&method, &params);
+        let block = request_block(&method, &params);
+        let installs_filter = method.starts_with("eth_new") && method.ends_with("Filter");
+        let pool = self.clone();
+
+        Box::new(self.send_to_any(id, request, required, block, vec![]).map(
+            move |(index, response)| {
+                let mut state = pool.state.lock().unwrap();
+
+                // Requests about a block that was obtained from a
+                // provider go to that provider
+                for block in response_blocks(&method, &response) {
+                    state.remember_block(block, index);
+                }
+
+                // Remember which provider installed a new filter, and
+                // how, so that it can be installed again elsewhere
+                if installs_filter {
+                    if let Some(filter_id) = response.as_str() {
+                        state.filters.insert(
+                            filter_id.to_owned(),
+                            FilterState {
+                                provider: index,
+                                provider_filter_id: filter_id.to_owned(),
+                                method,
+                                params,
+                                latest_block: None,
+                                backfill: false,
+                            },
+                        );
+                    }
+                }
+                response
+            },
+        ))
+    }
+}
+
+impl<T> PubSubTransport for ProviderPool<T>
+where
+    T: PubSubTransport + Send + 'static,
+    T::Out: 'static,
+{
+    fn supports_pubsub(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .providers
+            .iter()
+            .any(|state| state.provider.transport.supports_pubsub())
+    }
+
+    /// Subscribes through one of the providers that support pub/sub. If the
+    /// subscription fails because the provider cannot be reached, the
+    /// provider is ejected, so that subscribing again picks another one.
+    fn subscribe(&self, params: Vec<Value>) -> NotificationStream {
+        let (index, name, notifications) = {
+            let mut state = self.state.lock().unwrap();
+            let excluded: Vec<usize> = (0..state.providers.len())
+                .filter(|i| !state.providers[*i].provider.transport.supports_pubsub())
+                .collect();
+            let index = match Self::select_provider(
+                &mut state,
+                &ProviderCapabilities::default(),
+                &excluded,
+                None,
+            ) {
+                Some(index) => index,
+                None => return pubsub::unsupported(),
+            };
+            let provider = &state.providers[index].provider;
+            (
+                index,
+                provider.name.clone(),
+                provider.transport.subscribe(params),
+            )
+        };
+
+        let pool = self.clone();
+        Box::new(notifications.map_err(move |e| {
+            if e.is_transient() {
+                warn!(pool.logger, "Ethereum provider subscription failed";
+                      "provider" => &name,
+                      "error" => format!("{}", e));
+                pool.eject(index);
+            }
+            e
+        }))
+    }
+}
+
+/// Capabilities that a provider needs to handle a request.
+fn required_capabilities(method: &str, params: &[Value]) -> ProviderCapabilities {
+    let traces = method.starts_with("trace_") || method.starts_with("debug_trace");
+
+    // State queries for a specific block need an archive node, unless the
+    // block is the latest or pending one
+    let archive = match method {
+        "eth_call"
+        | "eth_getBalance"
+        | "eth_getCode"
+        | "eth_getStorageAt"
+        | "eth_getTransactionCount" => match params.last().and_then(Value::as_str) {
+            Some("latest") | Some("pending") | None => false,
+            Some(_) => true,
+        },
+        _ => false,
+    };
+
+    ProviderCapabilities { archive, traces }
+}
+
+/// The block that a request is about, if any, as a key for `blocks`.
+fn request_block(method: &str, params: &[Value]) -> Option<String> {
+    let block = match method {
+        "eth_getBlockByHash" | "eth_getBlockByNumber" => params.first(),
+        "eth_call"
+        | "eth_getBalance"
+        | "eth_getCode"
+        | "eth_getStorageAt"
+        | "eth_getTransactionCount" => params.last(),
+        "eth_getLogs" | "trace_filter" => params
+            .first()
+            .and_then(|filter| filter.get("blockHash").or_else(|| filter.get("fromBlock"))),
+        _ => None,
+    };
+    block.and_then(block_key)
+}
+
+/// The blocks that a response contains, as keys for `blocks`.
+fn response_blocks(method: &str, response: &Value) -> Vec<String> {
+    match method {
+        "eth_getBlockByHash" | "eth_getBlockByNumber" => ["hash", "number"]
+            .iter()
+            .filter_map(|field| response.get(*field).and_then(block_key))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Normalizes a block number or hash into a key for `blocks`. Returns
+/// `None` for tags like `latest`, which name different blocks over time.
+fn block_key(block: &Value) -> Option<String> {
+    let block = block.as_str()?;
+    if !block.starts_with("0x") {
+        None
+    } else if block.len() == 66 {
+        Some(block.to_lowercase())
+    } else {
+        u64::from_str_radix(&block[2..], 16)
+            .ok()
+            .map(|number| number.to_string())
+    }
+}
+
+/// The number of the latest block among the logs in a filter response.
+fn latest_log_block(response: &Value) -> Option<u64> {
+    response
+        .as_array()?
+        .iter()
+        .filter_map(|log| log.get("blockNumber")?.as_str())
+        .filter_map(|number| u64::from_str_radix(number.trim_left_matches("0x"), 16).ok())
+        .max()
+}
+
+fn params_to_vec(params: &Option<Params>) -> Vec<Value> {
+    match *params {
+        Some(Params::Array(ref values)) => values.clone(),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,96 @@
+use futures::prelude::*;
+use futures::stream;
+use graph::serde_json::{self, Value};
+use web3::api::SubscriptionId;
+use web3::error::{Error, ErrorKind};
+use web3::{DuplexTransport, Transport};
+
+/// Stream of the notifications of an `eth_subscribe` subscription.
+pub type NotificationStream = Box<Stream<Item = Value, Error = Error>>;
+
+/// A `web3::Transport` that may be able to push `eth_subscribe`
+/// notifications, e.g. for new blocks, instead of having them polled.
+pub trait PubSubTransport: Transport {
+    /// Returns `true` if the transport supports `eth_subscribe`. Callers
+    /// fall back to polling filters if it doesn't.
+    fn supports_pubsub(&self) -> bool;
+
+    /// Subscribes to notifications with `eth_subscribe(params)`.
+    ///
+    /// The stream fails if the connection is lost, so that callers can
+    /// subscribe again; dropping the stream cancels the subscription.
+    fn subscribe(&self, params: Vec<Value>) -> NotificationStream;
+}
+
+/// Subscribes to notifications through a transport that supports
+/// `eth_subscribe`.
+pub fn subscribe<T>(transport: &T, params: Vec<Value>) -> NotificationStream
+where
+    T: DuplexTransport + 'static,
+    T::Out: 'static,
+    T::NotificationStream: 'static,
+{
+    let transport = transport.clone();
+
+    Box::new(
+        transport
+            .execute("eth_subscribe", params)
+            .and_then(|id| {
+                serde_json::from_value::<String>(id).map_err(|e| {
+                    ErrorKind::InvalidResponse(format!("Invalid subscription ID: {}", e)).into()
+                })
+            })
+            .map(move |id| {
+                let id = SubscriptionId::from(id);
+                let notifications = transport.subscribe(&id);
+                Subscription {
+                    transport,
+                    id,
+                    notifications,
+                }
+            })
+            .flatten_stream(),
+    )
+}
+
+/// Returns a notification stream that fails right away, for transports that
+/// don't support `eth_subscribe`.
+pub fn unsupported() -> NotificationStream {
+    Box::new(stream::once(Err(ErrorKind::Transport(String::from(
+        "Transport does not support eth_subscribe",
+    ))
+    .into())))
+}
+
+/// An active `eth_subscribe` subscription.
+struct Subscription<T: DuplexTransport> {
+    transport: T,
+    id: SubscriptionId,
+    notifications: T::NotificationStream,
+}
+
+impl<T: DuplexTransport> Stream for Subscription<T> {
+    type Item = Value;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Value>, Error> {
+        match self.notifications.poll()? {
+            // Notifications only end when the connection is closed
+            Async::Ready(None) => Err(ErrorKind::Transport(String::from(
+                "Subscription ended, the connection was closed",
+            ))
+            .into()),
+            result => Ok(result),
+        }
+    }
+}
+
+impl<T: DuplexTransport> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        // The request is sent right away; nobody is interested in the
+        // response, so it is dropped
+        let id = serde_json::to_value(&self.id).unwrap_or(Value::Null);
+        let _ = self.transport.execute("eth_unsubscribe", vec![id]);
+        self.transport.unsubscribe(&self.id);
+    }
+}
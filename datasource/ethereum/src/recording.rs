@@ -0,0 +1,319 @@
+use futures::future;
+use futures::prelude::*;
+use graph::serde_json::{self, Value};
+use jsonrpc_core::types::{Call, Params};
+use jsonrpc_core::Error as RpcError;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use web3::error::{Error, ErrorKind};
+use web3::helpers::build_request;
+use web3::{BatchTransport, RequestId, Transport};
+
+/// A JSON-RPC request and its response, as stored in a fixture file.
+///
+/// Fixture files contain one exchange per line, e.g.
+/// `{"method":"eth_blockNumber","params":[],"response":{"result":"0x10"}}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    params: Vec<Value>,
+    response: Response,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Result(Value),
+    Error(RpcError),
+}
+
+impl Response {
+    /// Converts the result of a request into a recordable response. Errors
+    /// other than RPC errors, e.g. failing to reach the node, depend on the
+    /// circumstances of the recording and are not recorded.
+    fn from_result(result: &Result<Value, Error>) -> Option<Self> {
+        match result {
+            Ok(value) => Some(Response::Result(value.clone())),
+            Err(e) => match e.kind() {
+                ErrorKind::Rpc(rpc_error) => Some(Response::Error(rpc_error.clone())),
+                _ => None,
+            },
+        }
+    }
+
+    fn into_result(self) -> Result<Value, Error> {
+        match self {
+            Response::Result(value) => Ok(value),
+            Response::Error(rpc_error) => Err(ErrorKind::Rpc(rpc_error).into()),
+        }
+    }
+}
+
+/// Extracts the method and parameters of a request.
+fn method_and_params(request: &Call) -> (String, Vec<Value>) {
+    match request {
+        Call::MethodCall(call) => {
+            let params = match call.params {
+                Some(Params::Array(ref values)) => values.clone(),
+                _ => vec![],
+            };
+            (call.method.clone(), params)
+        }
+        _ => (String::new(), vec![]),
+    }
+}
+
+/// Appends the JSON-RPC requests of a transport and their responses to a
+/// fixture file, which a `Replayer` can later serve them from.
+///
+/// Clones share the fixture file, so one recorder can record the requests
+/// of several transports.
+#[derive(Clone)]
+pub struct Recorder {
+    path: String,
+    file: Arc<Mutex<File>>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl Recorder {
+    /// Creates a recorder that appends to the fixture file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        Ok(Recorder {
+            path: path.as_ref().display().to_string(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Records the response to a request, if it can be recorded.
+    fn record(&self, request: &Call, result: &Result<Value, Error>) {
+        let response = match Response::from_result(result) {
+            Some(response) => response,
+            None => return,
+        };
+        let (method, params) = method_and_params(request);
+        let exchange = Exchange {
+            method,
+            params,
+            response,
+        };
+
+        let line = serde_json::to_string(&exchange).expect("Failed to serialize JSON-RPC exchange");
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", line).expect("Failed to write JSON-RPC exchange to fixture file");
+    }
+}
+
+/// A transport that records the requests sent through it, along with their
+/// responses.
+#[derive(Clone, Debug)]
+pub struct Recording<T> {
+    transport: Arc<T>,
+    recorder: Recorder,
+}
+
+impl<T> Recording<T> {
+    pub fn new(transport: T, recorder: Recorder) -> Self {
+        Recording {
+            transport: Arc::new(transport),
+            recorder,
+        }
+    }
+}
+
+impl<T> Transport for Recording<T>
+where
+    T: Transport,
+    T::Out: 'static,
+{
+    type Out = Box<Future<Item = Value, Error = Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.transport
+                .send(id, request.clone())
+                .then(move |result| {
+                    recorder.record(&request, &result);
+                    result
+                }),
+        )
+    }
+}
+
+impl<T> BatchTransport for Recording<T>
+where
+    T: BatchTransport,
+    T::Out: 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = Box<Future<Item = Vec<Result<Value, Error>>, Error = Error> + Send>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let calls: Vec<_> = requests.iter().map(|(_, call)| call.clone()).collect();
+        let recorder = self.recorder.clone();
+
+        Box::new(self.transport.send_batch(requests).map(move |results| {
+            for (request, result) in calls.iter().zip(results.iter()) {
+                recorder.record(request, result);
+            }
+            results
+        }))
+    }
+}
+
+/// Recorded responses to one request.
+#[derive(Debug)]
+struct Replies {
+    method: String,
+    responses: VecDeque<Response>,
+    last: Option<Response>,
+}
+
+impl Replies {
+    /// Returns the next recorded response. Once all of them have been
+    /// served, the last one is served again, except for filter changes,
+    /// which are empty from then on, as if the chain stopped at the end of
+    /// the recording.
+    fn next(&mut self) -> Response {
+        match self.responses.pop_front() {
+            Some(response) => {
+                self.last = Some(response.clone());
+                response
+            }
+            None if self.method == "eth_getFilterChanges" => Response::Result(Value::Array(vec![])),
+            None => self
+                .last
+                .clone()
+                .expect("Replies always contain at least one response"),
+        }
+    }
+}
+
+/// Serves the responses recorded by a `Recorder` without a network.
+///
+/// Responses to identical requests are served in the order in which they
+/// were recorded. Requests that were not recorded fail with an invalid
+/// response error.
+#[derive(Clone)]
+pub struct Replayer {
+    path: String,
+    replies: Arc<Mutex<HashMap<(String, String), Replies>>>,
+    next_request_id: Arc<AtomicUsize>,
+}
+
+impl fmt::Debug for Replayer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Replayer")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl Replayer {
+    /// Loads the recorded responses from the fixture file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path.as_ref())?;
+
+        let mut replies = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let exchange: Exchange = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            replies
+                .entry(Self::key(&exchange.method, &exchange.params))
+                .or_insert_with(|| Replies {
+                    method: exchange.method.clone(),
+                    responses: VecDeque::new(),
+                    last: None,
+                })
+                .responses
+                .push_back(exchange.response);
+        }
+
+        Ok(Replayer {
+            path: path.as_ref().display().to_string(),
+            replies: Arc::new(Mutex::new(replies)),
+            next_request_id: Arc::new(AtomicUsize::new(1)),
+        })
+    }
+
+    fn key(method: &str, params: &[Value]) -> (String, String) {
+        (
+            method.to_owned(),
+            serde_json::to_string(params).expect("Failed to serialize JSON-RPC params"),
+        )
+    }
+
+    /// Serves the next recorded response to a request.
+    fn reply(&self, request: &Call) -> Result<Value, Error> {
+        let (method, params) = method_and_params(request);
+        let mut replies = self.replies.lock().unwrap();
+
+        match replies.get_mut(&Self::key(&method, &params)) {
+            Some(replies) => replies.next().into_result(),
+            None => Err(ErrorKind::InvalidResponse(format!(
+                "No recorded response to `{}` with params {}",
+                method,
+                Value::Array(params)
+            ))
+            .into()),
+        }
+    }
+}
+
+impl Transport for Replayer {
+    type Out = future::FutureResult<Value, Error>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_request_id.fetch_add(1, Ordering::SeqCst);
+        (id, build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        future::result(self.reply(&request))
+    }
+}
+
+impl BatchTransport for Replayer {
+    type Batch = future::FutureResult<Vec<Result<Value, Error>>, Error>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        future::ok(
+            requests
+                .into_iter()
+                .map(|(_, request)| self.reply(&request))
+                .collect(),
+        )
+    }
+}
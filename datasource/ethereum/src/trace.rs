@@ -0,0 +1,41 @@
+use web3::types::{Address, BlockNumber, Bytes, H256};
+
+/// Parameters of a `trace_filter` request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceFilter {
+    pub from_block: BlockNumber,
+    pub to_block: BlockNumber,
+    pub to_address: Vec<Address>,
+}
+
+/// A transaction trace as returned by `trace_filter`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub action: TraceAction,
+    pub block_hash: H256,
+    pub block_number: u64,
+    pub transaction_hash: Option<H256>,
+    pub result: Option<TraceResult>,
+    /// Set if the call failed, e.g. because it was reverted.
+    pub error: Option<String>,
+    #[serde(rename = "type")]
+    pub trace_type: String,
+}
+
+/// The action of a trace. Only the fields of call actions are decoded;
+/// other actions (e.g. contract creations or rewards) leave them empty.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceAction {
+    pub call_type: Option<String>,
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub input: Option<Bytes>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TraceResult {
+    pub output: Option<Bytes>,
+}
@@ -1,19 +1,47 @@
+use futures::future;
 use futures::prelude::*;
-use graph::serde_json::Value;
-use jsonrpc_core::types::Call;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::sync::oneshot;
+use graph::serde_json::{self, Value};
+use graph::util::retry::TransientError;
+use hyper;
+use hyper_tls::HttpsConnector;
+use jsonrpc_core::types::{Call, Id, Output, Request, Response};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_core::reactor::{self, Timeout};
 use web3;
+use web3::error::{Error, ErrorKind};
 use web3::transports::http;
 use web3::transports::ipc;
 use web3::transports::ws;
 use web3::transports::EventLoopHandle;
-use web3::RequestId;
+use web3::{BatchTransport, RequestId, Transport as Web3Transport};
+use websocket::url::Url;
+use websocket::{ClientBuilder, OwnedMessage};
+
+use pubsub::{self, NotificationStream, PubSubTransport};
+use recording::{Recorder, Recording, Replayer};
 
 /// Abstraction over the different web3 transports.
 #[derive(Clone, Debug)]
 pub enum Transport {
     RPC(http::Http),
     IPC(ipc::Ipc),
-    WS(ws::WebSocket),
+    WS(ReconnectingWebSocket),
+    /// JSON-RPC over HTTP whose concurrent requests are coalesced into
+    /// JSON-RPC batches.
+    BatchedRPC(Batching<BatchHttp>),
+    /// JSON-RPC over WebSocket whose concurrent requests are coalesced into
+    /// JSON-RPC batches.
+    BatchedWS(Batching<ReconnectingWebSocket>),
+    /// Another transport whose requests and responses are recorded to a
+    /// fixture file.
+    Recording(Recording<Transport>),
+    /// Responses recorded to a fixture file, served without a network.
+    Replay(Replayer),
 }
 
 impl Transport {
@@ -26,11 +54,22 @@ impl Transport {
 
     /// Creates a WebSocket transport.
     pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
-        ws::WebSocket::new(ws)
+        ReconnectingWebSocket::new(ws)
             .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
             .expect("Failed to connect to Ethereum WS")
     }
 
+    /// Creates a WebSocket transport that sends concurrent requests as
+    /// JSON-RPC batches.
+    pub fn new_batched_ws(ws: &str, options: BatchOptions) -> (EventLoopHandle, Self) {
+        ReconnectingWebSocket::new_batched(ws)
+            .map(|(event_loop, transport)| {
+                let batching = Batching::new(transport, options, event_loop.remote());
+                (event_loop, Transport::BatchedWS(batching))
+            })
+            .expect("Failed to connect to Ethereum WS")
+    }
+
     /// Creates a JSON-RPC over HTTP transport.
     ///
     /// Note: JSON-RPC over HTTP doesn't always support subscribing to new
@@ -38,7 +77,32 @@ impl Transport {
     pub fn new_rpc(rpc: &str) -> (EventLoopHandle, Self) {
         http::Http::new(rpc)
             .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
-            .expect("Failed to connect to Ethereum WS")
+            .expect("Failed to connect to Ethereum RPC")
+    }
+
+    /// Creates a JSON-RPC over HTTP transport that sends concurrent requests
+    /// as JSON-RPC batches.
+    pub fn new_batched_rpc(rpc: &str, options: BatchOptions) -> (EventLoopHandle, Self) {
+        let url = rpc.to_owned();
+        EventLoopHandle::spawn(move |handle| BatchHttp::with_event_loop(&url, handle))
+            .map(|(event_loop, transport)| {
+                let batching = Batching::new(transport, options, event_loop.remote());
+                (event_loop, Transport::BatchedRPC(batching))
+            })
+            .expect("Failed to connect to Ethereum RPC")
+    }
+
+    /// Wraps the transport so that its requests and responses are recorded.
+    pub fn recorded(self, recorder: Recorder) -> Self {
+        Transport::Recording(Recording::new(self, recorder))
+    }
+
+    /// Creates a transport that serves the responses recorded in the
+    /// fixture file at `path`.
+    pub fn new_replay(path: &str) -> Self {
+        Replayer::load(path)
+            .map(Transport::Replay)
+            .unwrap_or_else(|e| panic!("Failed to load Ethereum fixture file {}: {}", path, e))
     }
 }
 
@@ -50,6 +114,10 @@ impl web3::Transport for Transport {
             Transport::RPC(http) => http.prepare(method, params),
             Transport::IPC(ipc) => ipc.prepare(method, params),
             Transport::WS(ws) => ws.prepare(method, params),
+            Transport::BatchedRPC(batching) => batching.prepare(method, params),
+            Transport::BatchedWS(batching) => batching.prepare(method, params),
+            Transport::Recording(recording) => recording.prepare(method, params),
+            Transport::Replay(replayer) => replayer.prepare(method, params),
         }
     }
 
@@ -57,7 +125,803 @@ impl web3::Transport for Transport {
         match self {
             Transport::RPC(http) => Box::new(http.send(id, request)),
             Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
+            Transport::WS(ws) => ws.send(id, request),
+            Transport::BatchedRPC(batching) => batching.send(id, request),
+            Transport::BatchedWS(batching) => batching.send(id, request),
+            Transport::Recording(recording) => recording.send(id, request),
+            Transport::Replay(replayer) => Box::new(replayer.send(id, request)),
+        }
+    }
+}
+
+impl BatchTransport for Transport {
+    type Batch = Box<Future<Item = Vec<Result<Value, Error>>, Error = Error> + Send>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        match self {
+            Transport::RPC(http) => Box::new(http.send_batch(requests)),
+            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
+            Transport::WS(ws) => ws.send_batch(requests),
+            Transport::BatchedRPC(batching) => batching.send_batch(requests),
+            Transport::BatchedWS(batching) => batching.send_batch(requests),
+            Transport::Recording(recording) => recording.send_batch(requests),
+            Transport::Replay(replayer) => Box::new(replayer.send_batch(requests)),
+        }
+    }
+}
+
+impl PubSubTransport for Transport {
+    fn supports_pubsub(&self) -> bool {
+        match self {
+            Transport::RPC(_) | Transport::BatchedRPC(_) => false,
+            Transport::IPC(_) | Transport::WS(_) | Transport::BatchedWS(_) => true,
+            // Notifications are not recorded; blocks and logs are polled
+            // instead, so that they can be replayed
+            Transport::Recording(_) | Transport::Replay(_) => false,
+        }
+    }
+
+    fn subscribe(&self, params: Vec<Value>) -> NotificationStream {
+        match self {
+            Transport::RPC(_) | Transport::BatchedRPC(_) => pubsub::unsupported(),
+            Transport::IPC(ipc) => pubsub::subscribe(ipc, params),
+            Transport::WS(ws) => ws.subscribe(params),
+            // Subscriptions are not batched
+            Transport::BatchedWS(batching) => batching.inner.transport.subscribe(params),
+            Transport::Recording(_) | Transport::Replay(_) => pubsub::unsupported(),
+        }
+    }
+}
+
+/// A WebSocket transport that connects again when its connection is lost.
+///
+/// Requests and subscriptions that fail because the connection was lost
+/// still fail; callers are expected to retry them, which then happens over
+/// the new connection.
+#[derive(Clone)]
+pub struct ReconnectingWebSocket {
+    url: String,
+    /// Whether a second connection is opened for JSON-RPC batches.
+    batched: bool,
+    connection: Arc<Mutex<WebSocketConnection>>,
+}
+
+struct WebSocketConnection {
+    /// Incremented with every new connection, so that several requests
+    /// failing on the same connection only reconnect once.
+    generation: usize,
+    socket: ws::WebSocket,
+    batches: Option<BatchWebSocket>,
+    /// Event loop of the connection, kept so that the connection stays
+    /// open. The event loop of the initial connection is returned to the
+    /// creator of the transport instead.
+    _event_loop: Option<EventLoopHandle>,
+}
+
+impl fmt::Debug for ReconnectingWebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectingWebSocket")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+impl ReconnectingWebSocket {
+    pub fn new(url: &str) -> Result<(EventLoopHandle, Self), Error> {
+        Self::connect(url, false)
+    }
+
+    /// Creates a transport that can also send JSON-RPC batches, over a
+    /// connection of their own.
+    pub fn new_batched(url: &str) -> Result<(EventLoopHandle, Self), Error> {
+        Self::connect(url, true)
+    }
+
+    fn connect(url: &str, batched: bool) -> Result<(EventLoopHandle, Self), Error> {
+        let (event_loop, socket, batches) = open_web_socket(url, batched)?;
+        Ok((
+            event_loop,
+            ReconnectingWebSocket {
+                url: url.to_owned(),
+                batched,
+                connection: Arc::new(Mutex::new(WebSocketConnection {
+                    generation: 0,
+                    socket,
+                    batches,
+                    _event_loop: None,
+                })),
+            },
+        ))
+    }
+
+    fn connection(&self) -> (usize, ws::WebSocket) {
+        let connection = self.connection.lock().unwrap();
+        (connection.generation, connection.socket.clone())
+    }
+
+    fn batch_connection(&self) -> (usize, Option<BatchWebSocket>) {
+        let connection = self.connection.lock().unwrap();
+        (connection.generation, connection.batches.clone())
+    }
+
+    /// Connects again if `e` indicates that the connection with the given
+    /// generation was lost and no new connection was made since.
+    fn handle_error(&self, generation: usize, e: &Error) {
+        if !e.is_transient() {
+            return;
+        }
+
+        let mut connection = self.connection.lock().unwrap();
+        if connection.generation != generation {
+            return;
+        }
+
+        // If connecting fails, the next failing request tries again
+        if let Ok((event_loop, socket, batches)) = open_web_socket(&self.url, self.batched) {
+            connection.generation += 1;
+            connection.socket = socket;
+            connection.batches = batches;
+            connection._event_loop = Some(event_loop);
+        }
+    }
+}
+
+/// Opens a WebSocket connection to `url` on a new event loop, along with a
+/// second connection for JSON-RPC batches if `batched` is set.
+fn open_web_socket(
+    url: &str,
+    batched: bool,
+) -> Result<(EventLoopHandle, ws::WebSocket, Option<BatchWebSocket>), Error> {
+    let url = url.to_owned();
+    EventLoopHandle::spawn(move |handle| {
+        let socket = ws::WebSocket::with_event_loop(&url, handle)?;
+        let batches = if batched {
+            Some(BatchWebSocket::with_event_loop(&url, handle)?)
+        } else {
+            None
+        };
+        Ok((socket, batches))
+    })
+    .map(|(event_loop, (socket, batches))| (event_loop, socket, batches))
+}
+
+impl web3::Transport for ReconnectingWebSocket {
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.connection().1.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let (generation, socket) = self.connection();
+        let transport = self.clone();
+        Box::new(socket.send(id, request).map_err(move |e| {
+            transport.handle_error(generation, &e);
+            e
+        }))
+    }
+}
+
+impl BatchTransport for ReconnectingWebSocket {
+    type Batch = Box<Future<Item = Vec<Result<Value, Error>>, Error = Error> + Send>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let (generation, socket) = self.connection();
+        let transport = self.clone();
+        Box::new(socket.send_batch(requests).map_err(move |e| {
+            transport.handle_error(generation, &e);
+            e
+        }))
+    }
+}
+
+impl RawBatchTransport for ReconnectingWebSocket {
+    fn send_raw_batch(
+        &self,
+        requests: Vec<Call>,
+    ) -> Box<Future<Item = Vec<Output>, Error = Error> + Send> {
+        let (generation, batches) = match self.batch_connection() {
+            (generation, Some(batches)) => (generation, batches),
+            (_, None) => {
+                let details = String::from("WebSocket was not opened for batches");
+                return Box::new(future::err(ErrorKind::Transport(details).into()));
+            }
+        };
+        let transport = self.clone();
+        Box::new(batches.send_raw_batch(requests).map_err(move |e| {
+            transport.handle_error(generation, &e);
+            e
+        }))
+    }
+}
+
+impl PubSubTransport for ReconnectingWebSocket {
+    fn supports_pubsub(&self) -> bool {
+        true
+    }
+
+    fn subscribe(&self, params: Vec<Value>) -> NotificationStream {
+        let (generation, socket) = self.connection();
+        let transport = self.clone();
+        Box::new(pubsub::subscribe(&socket, params).map_err(move |e| {
+            transport.handle_error(generation, &e);
+            e
+        }))
+    }
+}
+
+/// Options for coalescing requests into JSON-RPC batches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Maximum number of requests in a batch. Once this many requests are
+    /// pending, they are sent right away.
+    pub max_batch_size: usize,
+    /// How long to wait for more requests after the first request of a
+    /// batch before sending the batch.
+    pub linger: Duration,
+}
+
+/// Transports that send JSON-RPC batches and pass on the outputs of the
+/// response as they are.
+///
+/// web3's `BatchTransport` drops the ids of the outputs and assumes that they
+/// come in the order of the requests, but JSON-RPC allows the server to
+/// answer the requests of a batch in any order.
+pub trait RawBatchTransport: web3::Transport {
+    /// Sends `requests` as one batch.
+    fn send_raw_batch(
+        &self,
+        requests: Vec<Call>,
+    ) -> Box<Future<Item = Vec<Output>, Error = Error> + Send>;
+}
+
+/// Maximum number of HTTP requests in flight at once, as in web3.
+const MAX_PARALLEL_REQUESTS: usize = 64;
+
+/// A batch to POST, along with the sender for the response body.
+type HttpRequest = (hyper::Request, oneshot::Sender<Result<hyper::Chunk, Error>>);
+
+/// JSON-RPC over HTTP that sends batches itself, so that their responses
+/// can be matched to the requests by id.
+#[derive(Clone)]
+pub struct BatchHttp {
+    http: http::Http,
+    uri: hyper::Uri,
+    authorization: Option<hyper::header::Basic>,
+    /// Batches are POSTed by a client on the event loop, since the client
+    /// can't leave the event loop thread.
+    requests: UnboundedSender<HttpRequest>,
+}
+
+impl fmt::Debug for BatchHttp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchHttp").field("uri", &self.uri).finish()
+    }
+}
+
+impl BatchHttp {
+    /// Creates the transport on the event loop of `handle`.
+    pub fn with_event_loop(url: &str, handle: &reactor::Handle) -> Result<Self, Error> {
+        let http = http::Http::with_event_loop(url, handle, MAX_PARALLEL_REQUESTS)?;
+        let uri: hyper::Uri = url.parse().map_err(Error::from)?;
+
+        // Credentials in the URL are sent with every batch, like web3 does
+        // for single requests
+        let authorization = uri
+            .authority()
+            .and_then(|authority| authority.rfind('@').map(|at| &authority[..at]))
+            .map(|credentials| {
+                let mut credentials = credentials.splitn(2, ':');
+                hyper::header::Basic {
+                    username: credentials.next().unwrap_or_default().to_owned(),
+                    password: credentials.next().map(str::to_owned),
+                }
+            });
+
+        let client = hyper::Client::configure()
+            .connector(HttpsConnector::new(4, handle).map_err(Error::from)?)
+            .build(handle);
+        let (sender, receiver) = unbounded::<HttpRequest>();
+        let request_handle = handle.clone();
+        handle.spawn(receiver.for_each(move |(request, responder)| {
+            request_handle.spawn(
+                client
+                    .request(request)
+                    .map_err(Error::from)
+                    .and_then(|response| {
+                        if response.status().is_success() {
+                            future::Either::A(response.body().concat2().map_err(Error::from))
+                        } else {
+                            future::Either::B(future::err(
+                                ErrorKind::Transport(format!(
+                                    "Unexpected response status code: {}",
+                                    response.status()
+                                ))
+                                .into(),
+                            ))
+                        }
+                    })
+                    .then(move |result| {
+                        responder.send(result).ok();
+                        Ok(())
+                    }),
+            );
+            Ok(())
+        }));
+
+        Ok(BatchHttp {
+            http,
+            uri,
+            authorization,
+            requests: sender,
+        })
+    }
+}
+
+impl web3::Transport for BatchHttp {
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.http.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        Box::new(self.http.send(id, request))
+    }
+}
+
+impl RawBatchTransport for BatchHttp {
+    fn send_raw_batch(
+        &self,
+        requests: Vec<Call>,
+    ) -> Box<Future<Item = Vec<Output>, Error = Error> + Send> {
+        let body = serde_json::to_string(&Request::Batch(requests))
+            .expect("Failed to serialize JSON-RPC batch");
+        let mut request = hyper::Request::new(hyper::Method::Post, self.uri.clone());
+        request
+            .headers_mut()
+            .set(hyper::header::ContentType::json());
+        if let Some(ref authorization) = self.authorization {
+            request
+                .headers_mut()
+                .set(hyper::header::Authorization(authorization.clone()));
+        }
+        request.set_body(body);
+
+        let (responder, response) = oneshot::channel();
+        if self.requests.unbounded_send((request, responder)).is_err() {
+            let details = String::from("Event loop of the transport has stopped");
+            return Box::new(future::err(ErrorKind::Transport(details).into()));
+        }
+
+        Box::new(response.then(|result| {
+            let body = result.unwrap_or_else(|_| {
+                let details = String::from("Batch was dropped before it was answered");
+                Err(ErrorKind::Transport(details).into())
+            })?;
+            match serde_json::from_slice(&body) {
+                Ok(Response::Batch(outputs)) => Ok(outputs),
+                // Servers answer a batch they can't process at all, e.g.
+                // because it is too large, with a single error
+                Ok(Response::Single(Output::Failure(failure))) => {
+                    Err(ErrorKind::Rpc(failure.error).into())
+                }
+                Ok(Response::Single(Output::Success(_))) => Err(ErrorKind::InvalidResponse(
+                    String::from("Expected a batch response, got a single response"),
+                )
+                .into()),
+                Err(e) => Err(ErrorKind::InvalidResponse(format!("{}", e)).into()),
+            }
+        }))
+    }
+}
+
+impl BatchTransport for BatchHttp {
+    type Batch = Box<Future<Item = Vec<Result<Value, Error>>, Error = Error> + Send>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<Call> = requests.into_iter().map(|(_, request)| request).collect();
+        let ids: Vec<_> = requests.iter().map(call_id).collect();
+        Box::new(
+            self.send_raw_batch(requests)
+                .map(move |outputs| match_outputs(&ids, outputs)),
+        )
+    }
+}
+
+type BatchResponder = oneshot::Sender<Result<Vec<Output>, Error>>;
+
+/// Batches sent over a WebSocket that wait for their responses.
+#[derive(Default)]
+struct PendingWebSocketBatches {
+    /// Incremented with every batch, to tell the batches apart.
+    next_number: u64,
+    /// The request ids of each batch and the sender for its response.
+    batches: HashMap<u64, (Vec<Id>, BatchResponder)>,
+    /// The batch that each request id belongs to.
+    numbers: HashMap<Id, u64>,
+}
+
+impl PendingWebSocketBatches {
+    fn add(&mut self, ids: Vec<Id>, responder: BatchResponder) -> u64 {
+        let number = self.next_number;
+        self.next_number += 1;
+        for id in &ids {
+            self.numbers.insert(id.clone(), number);
+        }
+        self.batches.insert(number, (ids, responder));
+        number
+    }
+
+    fn remove(&mut self, number: u64) -> Option<BatchResponder> {
+        let (ids, responder) = self.batches.remove(&number)?;
+        for id in ids {
+            self.numbers.remove(&id);
+        }
+        Some(responder)
+    }
+
+    /// Passes a response on to the batch that it answers, which is found by
+    /// the id of any of its outputs.
+    fn respond(&mut self, message: &str) {
+        let (number, result) = match serde_json::from_str(message) {
+            Ok(Response::Batch(outputs)) => {
+                let number = outputs
+                    .iter()
+                    .filter_map(|output| self.numbers.get(output.id()))
+                    .next()
+                    .cloned();
+                (number, Ok(outputs))
+            }
+            // Servers answer a batch they can't process at all, e.g. because
+            // it is too large, with a single error that may lack an id; it
+            // is then taken to answer the oldest batch
+            Ok(Response::Single(Output::Failure(failure))) => {
+                let number = self
+                    .numbers
+                    .get(&failure.id)
+                    .cloned()
+                    .or_else(|| self.batches.keys().min().cloned());
+                (number, Err(ErrorKind::Rpc(failure.error).into()))
+            }
+            Ok(Response::Single(Output::Success(_))) | Err(_) => return,
+        };
+        if let Some(responder) = number.and_then(|number| self.remove(number)) {
+            responder.send(result).ok();
+        }
+    }
+
+    /// Fails all pending batches, since the connection was closed.
+    fn fail_all(&mut self) {
+        self.numbers.clear();
+        for (_, (_, responder)) in self.batches.drain() {
+            let details = String::from("WebSocket connection was closed");
+            responder
+                .send(Err(ErrorKind::Transport(details).into()))
+                .ok();
+        }
+    }
+}
+
+/// JSON-RPC over a WebSocket connection that only sends batches, so that
+/// their responses can be matched to the requests by id.
+///
+/// web3's WebSocket transport passes a batch response on to the request
+/// with the id of the first output and drops the ids of the outputs.
+#[derive(Clone)]
+pub struct BatchWebSocket {
+    pending: Arc<Mutex<PendingWebSocketBatches>>,
+    /// Messages are written by the connection on the event loop.
+    messages: UnboundedSender<OwnedMessage>,
+}
+
+impl BatchWebSocket {
+    /// Connects on the event loop of `handle`.
+    pub fn with_event_loop(url: &str, handle: &reactor::Handle) -> Result<Self, Error> {
+        let url: Url = url
+            .parse()
+            .map_err(|e| ErrorKind::Transport(format!("Invalid WebSocket URL: {}", e)))?;
+        let pending = Arc::new(Mutex::new(PendingWebSocketBatches::default()));
+        let (messages, outgoing) = unbounded();
+
+        let reader_pending = pending.clone();
+        let replies = messages.clone();
+        let connection = ClientBuilder::from_url(&url)
+            .async_connect(None, handle)
+            .map_err(|e| Error::from(ErrorKind::Transport(format!("{}", e))))
+            .and_then(move |(client, _)| {
+                let (sink, stream) = client.split();
+                let reader = stream
+                    .map_err(|e| Error::from(ErrorKind::Transport(format!("{}", e))))
+                    .for_each(move |message| {
+                        match message {
+                            OwnedMessage::Text(text) => {
+                                reader_pending.lock().unwrap().respond(&text)
+                            }
+                            OwnedMessage::Ping(data) => {
+                                replies.unbounded_send(OwnedMessage::Pong(data)).ok();
+                            }
+                            OwnedMessage::Close(data) => {
+                                replies.unbounded_send(OwnedMessage::Close(data)).ok();
+                            }
+                            _ => (),
+                        }
+                        Ok(())
+                    });
+                let writer = sink
+                    .sink_map_err(|e| Error::from(ErrorKind::Transport(format!("{}", e))))
+                    .send_all(outgoing.map_err(|()| Error::from(ErrorKind::Internal)))
+                    .map(|_| ());
+                reader.select(writer).map_err(|(e, _)| e)
+            });
+
+        // Once either direction of the connection ends, the connection is
+        // dropped, so that batches sent later fail right away
+        let closed_pending = pending.clone();
+        handle.spawn(connection.then(move |_| {
+            closed_pending.lock().unwrap().fail_all();
+            Ok(())
+        }));
+
+        Ok(BatchWebSocket { pending, messages })
+    }
+
+    /// Sends `requests` as one batch.
+    pub fn send_raw_batch(
+        &self,
+        requests: Vec<Call>,
+    ) -> Box<Future<Item = Vec<Output>, Error = Error> + Send> {
+        let ids: Vec<_> = requests.iter().filter_map(call_id).collect();
+        if ids.is_empty() {
+            return Box::new(future::ok(vec![]));
+        }
+        let message = serde_json::to_string(&Request::Batch(requests))
+            .expect("Failed to serialize JSON-RPC batch");
+
+        // The batch is added before it is sent, so that its response finds it
+        let (responder, response) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let number = pending.add(ids, responder);
+            if self
+                .messages
+                .unbounded_send(OwnedMessage::Text(message))
+                .is_err()
+            {
+                pending.remove(number);
+                let details = String::from("WebSocket connection was closed");
+                return Box::new(future::err(ErrorKind::Transport(details).into()));
+            }
+        }
+
+        Box::new(response.then(|result| {
+            result.unwrap_or_else(|_| {
+                let details = String::from("Batch was dropped before it was answered");
+                Err(ErrorKind::Transport(details).into())
+            })
+        }))
+    }
+}
+
+type Responder = oneshot::Sender<Result<Value, Error>>;
+
+/// The requests of the next batch.
+struct PendingBatch {
+    /// Incremented whenever a batch is sent, so that the linger timer of a
+    /// batch that was sent because it was full doesn't send the next batch
+    /// early.
+    number: u64,
+    requests: Vec<(Call, Responder)>,
+}
+
+struct BatchingInner<T> {
+    transport: T,
+    options: BatchOptions,
+    /// Event loop that runs the linger timers and passes on the responses.
+    remote: reactor::Remote,
+    pending: Mutex<PendingBatch>,
+}
+
+impl<T: RawBatchTransport> BatchingInner<T> {
+    /// Sends the pending requests as a batch.
+    fn flush(&self, pending: &mut PendingBatch) {
+        pending.number += 1;
+        let (requests, responders): (Vec<_>, Vec<_>) = pending.requests.drain(..).unzip();
+        let ids: Vec<_> = requests.iter().map(call_id).collect();
+
+        let batch = self.transport.send_raw_batch(requests);
+        self.remote.spawn(move |_| {
+            batch.then(move |result| {
+                respond(result, &ids, responders);
+                Ok(())
+            })
+        });
+    }
+}
+
+/// A transport that coalesces concurrent requests into JSON-RPC batches.
+///
+/// The first request of a batch starts a timer on the event loop; the batch
+/// is sent when the timer runs out or when the batch is full, whichever
+/// happens first.
+pub struct Batching<T> {
+    inner: Arc<BatchingInner<T>>,
+}
+
+impl<T> Clone for Batching<T> {
+    fn clone(&self) -> Self {
+        Batching {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Batching<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Batching")
+            .field("transport", &self.inner.transport)
+            .field("options", &self.inner.options)
+            .finish()
+    }
+}
+
+impl<T> Batching<T>
+where
+    T: RawBatchTransport + Send + Sync + 'static,
+{
+    /// Creates a batching transport whose timers run on the event loop of
+    /// `remote`, usually the event loop of `transport`.
+    pub fn new(transport: T, options: BatchOptions, remote: &reactor::Remote) -> Self {
+        assert!(options.max_batch_size > 0, "Batches must not be empty");
+
+        Batching {
+            inner: Arc::new(BatchingInner {
+                transport,
+                options,
+                remote: remote.clone(),
+                pending: Mutex::new(PendingBatch {
+                    number: 0,
+                    requests: vec![],
+                }),
+            }),
+        }
+    }
+
+    /// Sends the batch with the given number once it has lingered long
+    /// enough, unless it was sent already.
+    fn linger(&self, number: u64) {
+        let inner = self.inner.clone();
+        self.inner.remote.spawn(move |handle| {
+            future::result(Timeout::new(inner.options.linger, handle))
+                .flatten()
+                .then(move |_| {
+                    let mut pending = inner.pending.lock().unwrap();
+                    if pending.number == number {
+                        inner.flush(&mut pending);
+                    }
+                    Ok(())
+                })
+        });
+    }
+}
+
+impl<T> web3::Transport for Batching<T>
+where
+    T: RawBatchTransport + Send + Sync + 'static,
+{
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.transport.prepare(method, params)
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        let (responder, response) = oneshot::channel();
+
+        {
+            let mut pending = self.inner.pending.lock().unwrap();
+            pending.requests.push((request, responder));
+
+            if pending.requests.len() >= self.inner.options.max_batch_size {
+                self.inner.flush(&mut pending);
+            } else if pending.requests.len() == 1 {
+                self.linger(pending.number);
+            }
+        }
+
+        Box::new(response.then(|result| {
+            result.unwrap_or_else(|_| {
+                let details = String::from("Batch was dropped before it was sent");
+                Err(ErrorKind::Transport(details).into())
+            })
+        }))
+    }
+}
+
+impl<T> BatchTransport for Batching<T>
+where
+    T: RawBatchTransport + Send + Sync + 'static,
+{
+    type Batch = Box<Future<Item = Vec<Result<Value, Error>>, Error = Error> + Send>;
+
+    /// Adds the requests to the pending batches like single requests, so
+    /// that they are subject to the batch size limit and their responses
+    /// are matched by id.
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let responses: Vec<_> = requests
+            .into_iter()
+            .map(|(id, request)| self.send(id, request).then(Ok::<_, Error>))
+            .collect();
+        Box::new(future::join_all(responses))
+    }
+}
+
+/// Returns the id of a request, if it expects a response.
+fn call_id(call: &Call) -> Option<Id> {
+    match call {
+        Call::MethodCall(call) => Some(call.id.clone()),
+        _ => None,
+    }
+}
+
+/// Matches the outputs of a batch response to the requests with the given
+/// ids, whatever the order the server answered them in.
+fn match_outputs(ids: &[Option<Id>], outputs: Vec<Output>) -> Vec<Result<Value, Error>> {
+    let mut outputs: HashMap<Id, Output> = outputs
+        .into_iter()
+        .map(|output| (output.id().clone(), output))
+        .collect();
+    ids.iter()
+        .map(|id| match id.as_ref().and_then(|id| outputs.remove(id)) {
+            Some(Output::Success(success)) => Ok(success.result),
+            Some(Output::Failure(failure)) => Err(ErrorKind::Rpc(failure.error).into()),
+            None => Err(ErrorKind::InvalidResponse(String::from(
+                "Batch response is missing a response",
+            ))
+            .into()),
+        })
+        .collect()
+}
+
+/// Passes the responses to a batch on to the requests in the batch.
+fn respond(result: Result<Vec<Output>, Error>, ids: &[Option<Id>], responders: Vec<Responder>) {
+    match result {
+        Ok(outputs) => {
+            for (responder, response) in responders.into_iter().zip(match_outputs(ids, outputs)) {
+                responder.send(response).ok();
+            }
+        }
+        Err(e) => {
+            for responder in responders {
+                responder.send(Err(clone_error(&e))).ok();
+            }
+        }
+    }
+}
+
+/// Copies an error so it can be passed to every request of a failed batch.
+/// Errors that can't be copied are turned into transport errors.
+fn clone_error(e: &Error) -> Error {
+    match *e.kind() {
+        ErrorKind::Unreachable => ErrorKind::Unreachable.into(),
+        ErrorKind::InvalidResponse(ref details) => {
+            ErrorKind::InvalidResponse(details.clone()).into()
         }
+        ErrorKind::Transport(ref details) => ErrorKind::Transport(details.clone()).into(),
+        ErrorKind::Rpc(ref error) => ErrorKind::Rpc(error.clone()).into(),
+        ErrorKind::Internal => ErrorKind::Internal.into(),
+        _ => ErrorKind::Transport(format!("{}", e)).into(),
     }
 }
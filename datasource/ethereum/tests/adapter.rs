@@ -6,16 +6,23 @@ extern crate graph_datasource_ethereum;
 extern crate jsonrpc_core;
 extern crate web3;
 
-use ethabi::{Function, Param, ParamType, Token};
+use ethabi::{Function, LogParam, Param, ParamType, Token};
 use futures::prelude::*;
-use futures::{failed, finished};
-use graph::components::ethereum::EthereumContractCall;
+use futures::{failed, finished, stream};
+use graph::components::ethereum::{
+    BlockNumberRange, EthereumBlock, EthereumBlockSubscription, EthereumContractCall,
+};
 use graph::prelude::EthereumAdapter as EthereumAdapterTrait;
 use graph::serde_json;
-use graph_datasource_ethereum::{EthereumAdapter, EthereumAdapterConfig};
+use graph::util::log::logger;
+use graph::util::retry::RetryPolicy;
+use graph_datasource_ethereum::{
+    EthereumAdapter, EthereumAdapterConfig, NotificationStream, PubSubTransport,
+};
 use std::collections::VecDeque;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use web3::error::{Error, ErrorKind};
 use web3::helpers::*;
 use web3::types::*;
@@ -24,15 +31,23 @@ use web3::{RequestId, Transport};
 pub type Result<T> = Box<Future<Item = T, Error = Error> + Send + 'static>;
 
 fn mock_block() -> Block<U256> {
+    mock_block_with_number(1)
+}
+
+fn mock_block_with_number(number: u64) -> Block<U256> {
+    mock_block_with_hashes(number, number, number.saturating_sub(1))
+}
+
+fn mock_block_with_hashes(number: u64, hash: u64, parent_hash: u64) -> Block<U256> {
     Block {
-        hash: Some(H256::default()),
-        parent_hash: H256::default(),
+        hash: Some(H256::from(hash)),
+        parent_hash: H256::from(parent_hash),
         uncles_hash: H256::default(),
         author: H160::default(),
         state_root: H256::default(),
         transactions_root: H256::default(),
         receipts_root: H256::default(),
-        number: Some(U128::from(1)),
+        number: Some(U128::from(number)),
         gas_used: U256::from(100),
         gas_limit: U256::from(1000),
         extra_data: Bytes(String::from("0x00").into_bytes()),
@@ -51,7 +66,12 @@ fn mock_block() -> Block<U256> {
 pub struct TestTransport {
     asserted: usize,
     requests: Arc<Mutex<Vec<(String, Vec<jsonrpc_core::Value>)>>>,
-    response: Arc<Mutex<VecDeque<jsonrpc_core::Value>>>,
+    response: Arc<Mutex<VecDeque<::std::result::Result<jsonrpc_core::Value, jsonrpc_core::Error>>>>,
+    /// Number of upcoming requests that fail as if the node was unreachable.
+    failures: Arc<Mutex<usize>>,
+    /// Notifications for upcoming `eth_subscribe` subscriptions, if the
+    /// transport supports them.
+    subscriptions: Arc<Mutex<Option<VecDeque<Vec<jsonrpc_core::Value>>>>>,
 }
 
 impl Transport for TestTransport {
@@ -68,8 +88,15 @@ impl Transport for TestTransport {
     }
 
     fn send(&self, id: RequestId, request: jsonrpc_core::Call) -> Result<jsonrpc_core::Value> {
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Box::new(failed(ErrorKind::Unreachable.into()));
+        }
+
         match self.response.lock().unwrap().pop_front() {
-            Some(response) => Box::new(finished(response)),
+            Some(Ok(response)) => Box::new(finished(response)),
+            Some(Err(error)) => Box::new(failed(ErrorKind::Rpc(error).into())),
             None => {
                 println!("Unexpected request (id: {:?}): {:?}", id, request);
                 Box::new(failed(ErrorKind::Unreachable.into()))
@@ -78,13 +105,54 @@ impl Transport for TestTransport {
     }
 }
 
+impl PubSubTransport for TestTransport {
+    fn supports_pubsub(&self) -> bool {
+        self.subscriptions.lock().unwrap().is_some()
+    }
+
+    fn subscribe(&self, params: Vec<jsonrpc_core::Value>) -> NotificationStream {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(("eth_subscribe".into(), params));
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let subscriptions = subscriptions.as_mut().expect("pub/sub is not enabled");
+        let notifications = stream::iter_ok(subscriptions.pop_front().unwrap_or_default());
+
+        // All but the last subscription fail after their notifications, as
+        // if the connection was lost; the last one stays open
+        if subscriptions.is_empty() {
+            Box::new(notifications.chain(stream::poll_fn(|| Ok(Async::NotReady))))
+        } else {
+            Box::new(notifications.chain(stream::once(Err(ErrorKind::Unreachable.into()))))
+        }
+    }
+}
+
 impl TestTransport {
     pub fn set_response(&mut self, value: jsonrpc_core::Value) {
-        *self.response.lock().unwrap() = vec![value].into();
+        *self.response.lock().unwrap() = vec![Ok(value)].into();
     }
 
     pub fn add_response(&mut self, value: jsonrpc_core::Value) {
-        self.response.lock().unwrap().push_back(value);
+        self.response.lock().unwrap().push_back(Ok(value));
+    }
+
+    pub fn add_error(&mut self, error: jsonrpc_core::Error) {
+        self.response.lock().unwrap().push_back(Err(error));
+    }
+
+    pub fn add_failures(&mut self, count: usize) {
+        *self.failures.lock().unwrap() += count;
+    }
+
+    pub fn add_subscription(&mut self, notifications: Vec<jsonrpc_core::Value>) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Default::default)
+            .push_back(notifications);
     }
 
     pub fn assert_request(&mut self, method: &str, params: &[String]) {
@@ -117,17 +185,22 @@ impl TestTransport {
     }
 }
 
-#[test]
-fn contract_call() {
-    let mut transport = TestTransport::default();
-
-    transport.add_response(serde_json::to_value(mock_block()).unwrap());
-    transport.add_response(jsonrpc_core::Value::String(format!(
-        "{:?}",
-        H256::from(100000)
-    )));
+fn new_adapter(transport: TestTransport) -> EthereumAdapter<TestTransport> {
+    EthereumAdapter::new(
+        &logger(),
+        EthereumAdapterConfig {
+            transport,
+            retry_policy: RetryPolicy {
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+                max_attempts: Some(5),
+            },
+            call_cache: None,
+        },
+    )
+}
 
-    let mut adapter = EthereumAdapter::new(EthereumAdapterConfig { transport });
+fn balance_of_call() -> EthereumContractCall {
     let balance_of = Function {
         name: "balanceOf".to_owned(),
         inputs: vec![Param {
@@ -140,16 +213,369 @@ fn contract_call() {
         }],
         constant: true,
     };
-    let function = Function::from(balance_of);
     let gnt_addr = Address::from_str("eF7FfF64389B814A946f3E92105513705CA6B990").unwrap();
     let holder_addr = Address::from_str("00d04c4b12C4686305bb4F4fC93487CdFBa62580").unwrap();
-    let call = EthereumContractCall {
+    EthereumContractCall {
         address: gnt_addr,
         block_id: BlockId::Number(BlockNumber::Latest),
-        function: function,
+        function: balance_of,
         args: vec![Token::Address(holder_addr)],
+    }
+}
+
+#[test]
+fn contract_call() {
+    let mut transport = TestTransport::default();
+
+    transport.add_response(serde_json::to_value(mock_block()).unwrap());
+    transport.add_response(jsonrpc_core::Value::String(format!(
+        "{:?}",
+        H256::from(100000)
+    )));
+
+    let mut adapter = new_adapter(transport);
+    let call_result = adapter.contract_call(balance_of_call()).wait().unwrap();
+
+    assert_eq!(call_result[0], Token::Uint(U256::from(100000)));
+}
+
+#[test]
+fn contract_call_caches_results_by_block_hash() {
+    let mut transport = TestTransport::default();
+
+    transport.add_response(serde_json::to_value(mock_block()).unwrap());
+    transport.add_response(jsonrpc_core::Value::String(format!(
+        "{:?}",
+        H256::from(100000)
+    )));
+
+    let mut adapter = new_adapter(transport);
+    let call = EthereumContractCall {
+        block_id: BlockId::Hash(H256::default()),
+        ..balance_of_call()
     };
-    let call_result = adapter.contract_call(call).wait().unwrap();
+
+    // Only the first call reaches the node; the transport has no responses
+    // for any further requests
+    for _ in 0..3 {
+        let call_result = adapter.contract_call(call.clone()).wait().unwrap();
+        assert_eq!(call_result[0], Token::Uint(U256::from(100000)));
+    }
+}
+
+#[test]
+fn contract_call_retries_transient_failures() {
+    let mut transport = TestTransport::default();
+
+    // Fail to fetch the block, then fail to make the call
+    transport.add_failures(1);
+    transport.add_response(serde_json::to_value(mock_block()).unwrap());
+    transport.add_failures(1);
+    transport.add_response(serde_json::to_value(mock_block()).unwrap());
+    transport.add_response(jsonrpc_core::Value::String(format!(
+        "{:?}",
+        H256::from(100000)
+    )));
+
+    let mut adapter = new_adapter(transport);
+    let call_result = adapter.contract_call(balance_of_call()).wait().unwrap();
 
     assert_eq!(call_result[0], Token::Uint(U256::from(100000)));
 }
+
+#[test]
+fn contract_call_gives_up_after_max_attempts() {
+    let mut transport = TestTransport::default();
+    transport.add_failures(5);
+    transport.add_response(serde_json::to_value(mock_block()).unwrap());
+
+    let mut adapter = new_adapter(transport);
+
+    assert!(adapter.contract_call(balance_of_call()).wait().is_err());
+}
+
+#[test]
+fn subscribe_to_blocks_with_new_heads() {
+    let mut transport = TestTransport::default();
+    let new_head = |number: u64| {
+        let mut header = serde_json::Map::new();
+        header.insert(
+            "number".into(),
+            serde_json::to_value(U256::from(number)).unwrap(),
+        );
+        serde_json::Value::Object(header)
+    };
+
+    // The connection is lost after the head 2 was pushed and block 3 is
+    // mined before subscribing again
+    transport.add_subscription(vec![new_head(2)]);
+    transport.add_subscription(vec![new_head(4)]);
+    transport.add_response(serde_json::to_value(U256::from(1)).unwrap());
+    for number in 2..5 {
+        transport.add_response(serde_json::to_value(mock_block_with_number(number)).unwrap());
+    }
+
+    let mut adapter = new_adapter(transport.clone());
+    let blocks: Vec<u64> = adapter
+        .subscribe_to_blocks(EthereumBlockSubscription {
+            subscription_id: "blocks".to_owned(),
+            range: BlockNumberRange {
+                from: BlockNumber::Latest,
+                to: BlockNumber::Number(4),
+            },
+        })
+        .take(3)
+        .map(|block| block.number.as_u64())
+        .collect()
+        .wait()
+        .unwrap();
+
+    // Block 3 is fetched along with the new head after subscribing again
+    assert_eq!(blocks, vec![2, 3, 4]);
+    transport.assert_request("eth_blockNumber", &[]);
+    transport.assert_request("eth_subscribe", &[String::from(r#""newHeads""#)]);
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x2""#), String::from("true")],
+    );
+    transport.assert_request("eth_subscribe", &[String::from(r#""newHeads""#)]);
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x3""#), String::from("true")],
+    );
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x4""#), String::from("true")],
+    );
+    transport.assert_no_more_requests();
+}
+
+#[test]
+fn subscribe_to_blocks_passes_on_blocks_of_reorgs() {
+    let mut transport = TestTransport::default();
+    let new_head = |number: u64| {
+        let mut header = serde_json::Map::new();
+        header.insert(
+            "number".into(),
+            serde_json::to_value(U256::from(number)).unwrap(),
+        );
+        serde_json::Value::Object(header)
+    };
+
+    // Block 3 is replaced by block 33, then block 44 on top of it is
+    // replaced by block 444 along with the new head 555
+    transport.add_subscription(vec![
+        new_head(2),
+        new_head(3),
+        new_head(3),
+        new_head(4),
+        new_head(4),
+        new_head(5),
+    ]);
+    transport.add_response(serde_json::to_value(U256::from(1)).unwrap());
+    for block in vec![
+        mock_block_with_number(2),
+        mock_block_with_number(3),
+        mock_block_with_hashes(3, 33, 2),
+        mock_block_with_hashes(4, 44, 33),
+        mock_block_with_hashes(4, 44, 33),
+        mock_block_with_hashes(5, 555, 444),
+        mock_block_with_hashes(4, 444, 33),
+    ] {
+        transport.add_response(serde_json::to_value(block).unwrap());
+    }
+
+    let mut adapter = new_adapter(transport.clone());
+    let blocks: Vec<H256> = adapter
+        .subscribe_to_blocks(EthereumBlockSubscription {
+            subscription_id: "blocks".to_owned(),
+            range: BlockNumberRange {
+                from: BlockNumber::Latest,
+                to: BlockNumber::Number(5),
+            },
+        })
+        .take(6)
+        .map(|block| block.hash)
+        .collect()
+        .wait()
+        .unwrap();
+
+    // The replaced ancestor of the new head is fetched by its hash, and
+    // the block pushed twice is passed on once
+    let hashes: Vec<H256> = vec![2, 3, 33, 44, 444, 555]
+        .into_iter()
+        .map(H256::from)
+        .collect();
+    assert_eq!(blocks, hashes);
+    transport.assert_request("eth_blockNumber", &[]);
+    transport.assert_request("eth_subscribe", &[String::from(r#""newHeads""#)]);
+    for number in vec!["0x2", "0x3", "0x3", "0x4", "0x4", "0x5"] {
+        transport.assert_request(
+            "eth_getBlockByNumber",
+            &[format!("{:?}", number), String::from("true")],
+        );
+    }
+    transport.assert_request(
+        "eth_getBlockByHash",
+        &[
+            serde_json::to_string(&H256::from(444)).unwrap(),
+            String::from("true"),
+        ],
+    );
+    transport.assert_no_more_requests();
+}
+
+#[test]
+fn subscribe_to_blocks_reinstalls_lost_filter() {
+    let mut transport = TestTransport::default();
+    let filter_changes = |number: u64| serde_json::to_value(vec![H256::from(number)]).unwrap();
+
+    // The node forgets the filter after reporting block 2, and block 3 is
+    // mined before the filter is installed again
+    transport.add_response(serde_json::to_value(U256::from(1)).unwrap());
+    transport.add_response(jsonrpc_core::Value::String("0x1".into()));
+    transport.add_response(filter_changes(2));
+    transport.add_response(serde_json::to_value(mock_block_with_number(2)).unwrap());
+    transport.add_response(serde_json::to_value(mock_block_with_number(2)).unwrap());
+    transport.add_error(jsonrpc_core::Error::invalid_params("filter not found"));
+    transport.add_response(jsonrpc_core::Value::String("0x2".into()));
+    transport.add_response(filter_changes(4));
+    for number in vec![4, 3, 4] {
+        transport.add_response(serde_json::to_value(mock_block_with_number(number)).unwrap());
+    }
+
+    let mut adapter = new_adapter(transport.clone());
+    let blocks: Vec<u64> = adapter
+        .subscribe_to_blocks(EthereumBlockSubscription {
+            subscription_id: "blocks".to_owned(),
+            range: BlockNumberRange {
+                from: BlockNumber::Latest,
+                to: BlockNumber::Number(4),
+            },
+        })
+        .take(3)
+        .map(|block| block.number.as_u64())
+        .collect()
+        .wait()
+        .unwrap();
+
+    // Block 3 is fetched along with block 4 after installing the filter again
+    assert_eq!(blocks, vec![2, 3, 4]);
+    let block_hash = |number: u64| serde_json::to_string(&H256::from(number)).unwrap();
+    transport.assert_request("eth_blockNumber", &[]);
+    transport.assert_request("eth_newBlockFilter", &[]);
+    transport.assert_request("eth_getFilterChanges", &[String::from(r#""0x1""#)]);
+    transport.assert_request(
+        "eth_getBlockByHash",
+        &[block_hash(2), String::from("false")],
+    );
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x2""#), String::from("true")],
+    );
+    transport.assert_request("eth_getFilterChanges", &[String::from(r#""0x1""#)]);
+    transport.assert_request("eth_newBlockFilter", &[]);
+    transport.assert_request("eth_getFilterChanges", &[String::from(r#""0x2""#)]);
+    transport.assert_request(
+        "eth_getBlockByHash",
+        &[block_hash(4), String::from("false")],
+    );
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x3""#), String::from("true")],
+    );
+    transport.assert_request(
+        "eth_getBlockByNumber",
+        &[String::from(r#""0x4""#), String::from("true")],
+    );
+    transport.assert_no_more_requests();
+}
+
+#[test]
+fn calls_in_block() {
+    let mut transport = TestTransport::default();
+
+    // Traces recorded from `trace_filter`; only the first one is a successful,
+    // direct call to `transfer` in the requested block
+    let traces: serde_json::Value =
+        serde_json::from_str(include_str!("fixtures/trace_filter.json")).unwrap();
+    transport.add_response(traces);
+
+    let mut adapter = new_adapter(transport.clone());
+    let transfer = Function {
+        name: "transfer".to_owned(),
+        inputs: vec![
+            Param {
+                name: "_to".to_owned(),
+                kind: ParamType::Address,
+            },
+            Param {
+                name: "_value".to_owned(),
+                kind: ParamType::Uint(256),
+            },
+        ],
+        outputs: vec![Param {
+            name: "success".to_owned(),
+            kind: ParamType::Bool,
+        }],
+        constant: false,
+    };
+    let block = EthereumBlock {
+        hash: H256::from_str("abababababababababababababababababababababababababababababababab")
+            .unwrap(),
+        parent_hash: H256::default(),
+        number: U256::from(5000000),
+        timestamp: U256::from(100000),
+        author: H160::default(),
+        gas_used: U256::from(100),
+        gas_limit: U256::from(1000),
+        transactions: vec![],
+    };
+    let gnt_addr = Address::from_str("eF7FfF64389B814A946f3E92105513705CA6B990").unwrap();
+    let holder_addr = Address::from_str("00d04c4b12C4686305bb4F4fC93487CdFBa62580").unwrap();
+    let recipient_addr = Address::from_str("1111111111111111111111111111111111111111").unwrap();
+
+    let calls = adapter
+        .calls_in_block(&block, gnt_addr, vec![transfer])
+        .wait()
+        .unwrap();
+
+    transport.assert_request(
+        "trace_filter",
+        &[String::from(
+            r#"{"fromBlock":"0x4c4b40","toBlock":"0x4c4b40","toAddress":["0xef7fff64389b814a946f3e92105513705ca6b990"]}"#,
+        )],
+    );
+    transport.assert_no_more_requests();
+
+    assert_eq!(calls.len(), 1);
+    let call = &calls[0];
+    assert_eq!(call.from, holder_addr);
+    assert_eq!(call.to, gnt_addr);
+    assert_eq!(call.block_hash, block.hash);
+    assert_eq!(
+        call.transaction_hash,
+        H256::from_str("0101010101010101010101010101010101010101010101010101010101010101").unwrap()
+    );
+    assert_eq!(call.function_signature, "transfer(address,uint256)");
+    assert_eq!(
+        call.inputs,
+        vec![
+            LogParam {
+                name: "_to".to_owned(),
+                value: Token::Address(recipient_addr),
+            },
+            LogParam {
+                name: "_value".to_owned(),
+                value: Token::Uint(U256::from(1000)),
+            },
+        ]
+    );
+    assert_eq!(
+        call.outputs,
+        vec![LogParam {
+            name: "success".to_owned(),
+            value: Token::Bool(true),
+        }]
+    );
+}
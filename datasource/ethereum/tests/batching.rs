@@ -0,0 +1,185 @@
+extern crate futures;
+extern crate graph_datasource_ethereum;
+extern crate jsonrpc_core;
+extern crate web3;
+
+use futures::future::join_all;
+use futures::prelude::*;
+use futures::{failed, finished};
+use graph_datasource_ethereum::{BatchOptions, Batching, RawBatchTransport};
+use jsonrpc_core::types::{Call, Output, Success, Version};
+use jsonrpc_core::Value;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use web3::error::{Error, ErrorKind};
+use web3::helpers::*;
+use web3::transports::EventLoopHandle;
+use web3::{BatchTransport, RequestId, Transport};
+
+/// A transport that answers every request with its method name, in the
+/// reverse order of the requests, and records the size of every batch it
+/// receives.
+#[derive(Debug, Clone, Default)]
+struct FakeTransport {
+    id: Arc<AtomicUsize>,
+    reachable: Arc<AtomicBool>,
+    batches: Arc<Mutex<Vec<usize>>>,
+}
+
+impl FakeTransport {
+    fn new() -> Self {
+        let transport = FakeTransport::default();
+        transport.reachable.store(true, Ordering::SeqCst);
+        transport
+    }
+
+    fn batches(&self) -> Vec<usize> {
+        self.batches.lock().unwrap().clone()
+    }
+}
+
+impl Transport for FakeTransport {
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        (id, build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, _request: Call) -> Self::Out {
+        panic!("requests must be sent in batches")
+    }
+}
+
+impl RawBatchTransport for FakeTransport {
+    fn send_raw_batch(
+        &self,
+        requests: Vec<Call>,
+    ) -> Box<Future<Item = Vec<Output>, Error = Error> + Send> {
+        self.batches.lock().unwrap().push(requests.len());
+
+        let outputs: Vec<_> = requests
+            .into_iter()
+            .rev()
+            .map(|request| match request {
+                Call::MethodCall(call) => Output::Success(Success {
+                    jsonrpc: Some(Version::V2),
+                    result: Value::String(call.method),
+                    id: call.id,
+                }),
+                _ => panic!("not a method call"),
+            })
+            .collect();
+
+        if self.reachable.load(Ordering::SeqCst) {
+            Box::new(finished(outputs))
+        } else {
+            Box::new(failed(ErrorKind::Unreachable.into()))
+        }
+    }
+}
+
+/// Wraps `fake` in a batching transport whose timers run on a new event
+/// loop, which must be kept alive for as long as the transport is used.
+fn batching(
+    fake: &FakeTransport,
+    max_batch_size: usize,
+    linger_ms: u64,
+) -> (EventLoopHandle, Batching<FakeTransport>) {
+    let (event_loop, ()) = EventLoopHandle::spawn(|_| Ok(())).unwrap();
+    let transport = Batching::new(
+        fake.clone(),
+        BatchOptions {
+            max_batch_size,
+            linger: Duration::from_millis(linger_ms),
+        },
+        event_loop.remote(),
+    );
+    (event_loop, transport)
+}
+
+#[test]
+fn coalesce_concurrent_requests_into_batches() {
+    let fake = FakeTransport::new();
+    let (_event_loop, transport) = batching(&fake, 3, 50);
+
+    let responses = join_all(vec![
+        transport.execute("eth_blockNumber", vec![]),
+        transport.execute("eth_getBlockByHash", vec![]),
+        transport.execute("eth_call", vec![]),
+        transport.execute("eth_getLogs", vec![]),
+    ]).wait()
+        .unwrap();
+
+    // Responses are passed back to the requests they belong to, even though
+    // the fake transport answers them in reverse order
+    assert_eq!(
+        responses,
+        vec![
+            Value::String("eth_blockNumber".into()),
+            Value::String("eth_getBlockByHash".into()),
+            Value::String("eth_call".into()),
+            Value::String("eth_getLogs".into()),
+        ]
+    );
+
+    // A full batch is sent right away, the rest after lingering
+    assert_eq!(fake.batches(), vec![3, 1]);
+}
+
+#[test]
+fn split_batches_sent_as_a_whole_by_batch_size() {
+    let fake = FakeTransport::new();
+    let (_event_loop, transport) = batching(&fake, 2, 50);
+
+    let requests: Vec<_> = ["eth_blockNumber", "eth_call", "eth_getLogs"]
+        .iter()
+        .map(|method| transport.prepare(method, vec![]))
+        .collect();
+    let responses = transport.send_batch(requests).wait().unwrap();
+
+    assert_eq!(
+        responses
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+        vec![
+            Value::String("eth_blockNumber".into()),
+            Value::String("eth_call".into()),
+            Value::String("eth_getLogs".into()),
+        ]
+    );
+    assert_eq!(fake.batches(), vec![2, 1]);
+}
+
+#[test]
+fn send_incomplete_batch_after_lingering() {
+    let fake = FakeTransport::new();
+    let (_event_loop, transport) = batching(&fake, 100, 10);
+
+    assert_eq!(
+        transport.execute("eth_blockNumber", vec![]).wait().unwrap(),
+        Value::String("eth_blockNumber".into())
+    );
+    assert_eq!(fake.batches(), vec![1]);
+}
+
+#[test]
+fn fail_all_requests_of_a_failed_batch() {
+    let fake = FakeTransport::new();
+    let (_event_loop, transport) = batching(&fake, 2, 10);
+
+    fake.reachable.store(false, Ordering::SeqCst);
+
+    let responses = vec![
+        transport.execute("eth_blockNumber", vec![]),
+        transport.execute("eth_call", vec![]),
+    ];
+    for response in responses {
+        match response.wait() {
+            Err(Error(ErrorKind::Unreachable, _)) => (),
+            result => panic!("expected an unreachable error, got {:?}", result),
+        }
+    }
+}
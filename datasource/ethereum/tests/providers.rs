@@ -0,0 +1,324 @@
+extern crate futures;
+extern crate graph;
+extern crate graph_datasource_ethereum;
+extern crate jsonrpc_core;
+extern crate web3;
+
+use futures::prelude::*;
+use futures::{failed, finished};
+use graph::prelude::*;
+use graph::util::log::logger;
+use graph_datasource_ethereum::{Provider, ProviderCapabilities, ProviderOptions, ProviderPool};
+use jsonrpc_core::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use web3::error::{Error, ErrorKind};
+use web3::helpers::*;
+use web3::{RequestId, Transport};
+
+/// A provider that answers every request with its own name, and fails all
+/// requests while it is unreachable.
+#[derive(Debug, Clone)]
+struct FakeProvider {
+    name: String,
+    reachable: Arc<AtomicBool>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeProvider {
+    fn new(name: &str) -> Self {
+        FakeProvider {
+            name: name.to_owned(),
+            reachable: Arc::new(AtomicBool::new(true)),
+            requests: Default::default(),
+        }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for FakeProvider {
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, jsonrpc_core::Call) {
+        (1, build_request(1, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: jsonrpc_core::Call) -> Self::Out {
+        if let jsonrpc_core::Call::MethodCall(call) = request {
+            self.requests.lock().unwrap().push(call.method);
+        }
+
+        if self.reachable.load(Ordering::SeqCst) {
+            Box::new(finished(Value::String(self.name.clone())))
+        } else {
+            Box::new(failed(ErrorKind::Unreachable.into()))
+        }
+    }
+}
+
+fn provider(
+    fake: &FakeProvider,
+    weight: u32,
+    capabilities: ProviderCapabilities,
+) -> Provider<FakeProvider> {
+    Provider {
+        name: fake.name.clone(),
+        transport: fake.clone(),
+        options: ProviderOptions {
+            weight,
+            capabilities,
+        },
+    }
+}
+
+fn pool(providers: Vec<Provider<FakeProvider>>) -> ProviderPool<FakeProvider> {
+    ProviderPool::new(&logger(), providers)
+}
+
+fn json(value: &str) -> Value {
+    jsonrpc_core::serde_json::from_str(value).unwrap()
+}
+
+fn answered_by(pool: &ProviderPool<FakeProvider>, method: &str, params: Vec<Value>) -> String {
+    let response = pool.execute(method, params).wait().unwrap();
+    response.as_str().unwrap().to_owned()
+}
+
+#[test]
+fn parse_provider_options() {
+    assert_eq!(
+        ProviderOptions::parse("http://localhost:8545"),
+        Ok((ProviderOptions::default(), "http://localhost:8545"))
+    );
+    assert_eq!(
+        ProviderOptions::parse("[weight=3, archive,traces]http://localhost:8545"),
+        Ok((
+            ProviderOptions {
+                weight: 3,
+                capabilities: ProviderCapabilities {
+                    archive: true,
+                    traces: true,
+                },
+            },
+            "http://localhost:8545"
+        ))
+    );
+    assert!(ProviderOptions::parse("[weight=x]http://localhost:8545").is_err());
+    assert!(ProviderOptions::parse("[fast]http://localhost:8545").is_err());
+    assert!(ProviderOptions::parse("[archive http://localhost:8545").is_err());
+}
+
+#[test]
+fn balance_requests_by_weight() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 3, Default::default()),
+    ]);
+
+    for _ in 0..8 {
+        answered_by(&pool, "eth_blockNumber", vec![]);
+    }
+
+    assert_eq!(a.requests().len(), 2);
+    assert_eq!(b.requests().len(), 6);
+}
+
+#[test]
+fn fail_over_to_reachable_provider() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 1, Default::default()),
+    ]);
+
+    a.reachable.store(false, Ordering::SeqCst);
+
+    // Every request succeeds, and once `a` has failed, it is no longer used
+    for _ in 0..4 {
+        assert_eq!(answered_by(&pool, "eth_blockNumber", vec![]), "b");
+    }
+    assert_eq!(a.requests().len(), 1);
+    assert_eq!(b.requests().len(), 4);
+}
+
+#[test]
+fn fail_if_no_provider_is_reachable() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 1, Default::default()),
+    ]);
+
+    a.reachable.store(false, Ordering::SeqCst);
+    b.reachable.store(false, Ordering::SeqCst);
+
+    assert!(pool.execute("eth_blockNumber", vec![]).wait().is_err());
+    assert_eq!(a.requests().len(), 1);
+    assert_eq!(b.requests().len(), 1);
+}
+
+#[test]
+fn route_requests_by_capabilities() {
+    let full = FakeProvider::new("full");
+    let archive = FakeProvider::new("archive");
+    let pool = pool(vec![
+        provider(&full, 10, Default::default()),
+        provider(
+            &archive,
+            1,
+            ProviderCapabilities {
+                archive: true,
+                traces: true,
+            },
+        ),
+    ]);
+
+    for _ in 0..3 {
+        assert_eq!(answered_by(&pool, "trace_filter", vec![]), "archive");
+        assert_eq!(
+            answered_by(
+                &pool,
+                "eth_call",
+                vec![
+                    Value::Object(Default::default()),
+                    Value::String("0x10".into())
+                ]
+            ),
+            "archive"
+        );
+    }
+
+    // Calls against the latest block don't need an archive node
+    assert_eq!(
+        answered_by(
+            &pool,
+            "eth_call",
+            vec![
+                Value::Object(Default::default()),
+                Value::String("latest".into())
+            ]
+        ),
+        "full"
+    );
+}
+
+#[test]
+fn send_filter_requests_to_the_installing_provider() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 1, Default::default()),
+    ]);
+
+    // The fake providers return their names as filter IDs
+    let filter_id = answered_by(&pool, "eth_newBlockFilter", vec![]);
+
+    for _ in 0..4 {
+        assert_eq!(
+            answered_by(
+                &pool,
+                "eth_getFilterChanges",
+                vec![Value::String(filter_id.clone())]
+            ),
+            filter_id
+        );
+    }
+    assert_eq!(
+        answered_by(
+            &pool,
+            "eth_uninstallFilter",
+            vec![Value::String(filter_id.clone())]
+        ),
+        filter_id
+    );
+}
+
+#[test]
+fn send_requests_about_a_block_to_the_same_provider() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 1, Default::default()),
+    ]);
+
+    let block_provider = answered_by(
+        &pool,
+        "eth_getBlockByNumber",
+        vec![Value::String("0x10".into()), Value::Bool(true)],
+    );
+
+    for _ in 0..4 {
+        assert_eq!(
+            answered_by(
+                &pool,
+                "eth_call",
+                vec![
+                    Value::Object(Default::default()),
+                    Value::String("0x10".into())
+                ]
+            ),
+            block_provider
+        );
+        assert_eq!(
+            answered_by(
+                &pool,
+                "eth_getLogs",
+                vec![json(r#"{"fromBlock": "0x10", "toBlock": "0x10"}"#)]
+            ),
+            block_provider
+        );
+    }
+}
+
+#[test]
+fn reinstall_filters_when_their_provider_fails() {
+    let a = FakeProvider::new("a");
+    let b = FakeProvider::new("b");
+    let pool = pool(vec![
+        provider(&a, 1, Default::default()),
+        provider(&b, 1, Default::default()),
+    ]);
+
+    // The fake providers return their names as filter IDs
+    let filter_id = answered_by(
+        &pool,
+        "eth_newFilter",
+        vec![json(r#"{"fromBlock": "0x1"}"#)],
+    );
+    let (installing, other) = if filter_id == "a" { (&a, &b) } else { (&b, &a) };
+    installing.reachable.store(false, Ordering::SeqCst);
+
+    // The filter is installed on the other provider, which first returns
+    // all of its logs and then only the changes
+    for _ in 0..2 {
+        assert_eq!(
+            answered_by(
+                &pool,
+                "eth_getFilterChanges",
+                vec![Value::String(filter_id.clone())]
+            ),
+            other.name
+        );
+    }
+    assert_eq!(
+        other.requests(),
+        vec!["eth_newFilter", "eth_getFilterLogs", "eth_getFilterChanges"]
+    );
+
+    // Uninstalling the filter removes it from the other provider
+    answered_by(
+        &pool,
+        "eth_uninstallFilter",
+        vec![Value::String(filter_id.clone())],
+    );
+    assert_eq!(other.requests().last().unwrap(), "eth_uninstallFilter");
+}
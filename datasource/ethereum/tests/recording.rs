@@ -0,0 +1,137 @@
+extern crate futures;
+extern crate graph_datasource_ethereum;
+extern crate jsonrpc_core;
+extern crate web3;
+
+use futures::prelude::*;
+use futures::{failed, finished};
+use graph_datasource_ethereum::{Recorder, Recording, Replayer};
+use jsonrpc_core::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use web3::error::{Error, ErrorKind};
+use web3::helpers::*;
+use web3::{RequestId, Transport};
+
+/// A node that answers `eth_blockNumber` with an increasing block number,
+/// fails `eth_call`s with an RPC error and cannot be reached for anything
+/// else.
+#[derive(Debug, Clone, Default)]
+struct FakeNode {
+    block_number: Arc<Mutex<u64>>,
+}
+
+impl Transport for FakeNode {
+    type Out = Box<Future<Item = Value, Error = Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, jsonrpc_core::Call) {
+        (1, build_request(1, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: jsonrpc_core::Call) -> Self::Out {
+        let method = match request {
+            jsonrpc_core::Call::MethodCall(call) => call.method,
+            _ => String::new(),
+        };
+
+        match method.as_str() {
+            "eth_blockNumber" => {
+                let mut block_number = self.block_number.lock().unwrap();
+                *block_number += 1;
+                Box::new(finished(Value::String(format!("{:#x}", *block_number))))
+            }
+            "eth_call" => Box::new(failed(
+                ErrorKind::Rpc(jsonrpc_core::Error::invalid_params("bad call")).into(),
+            )),
+            _ => Box::new(failed(ErrorKind::Unreachable.into())),
+        }
+    }
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("graph-recording-{}-{}.jsonl", name, process::id()));
+    fs::remove_file(&path).ok();
+    path
+}
+
+fn block_number<T: Transport>(transport: &T) -> Value {
+    transport.execute("eth_blockNumber", vec![]).wait().unwrap()
+}
+
+#[test]
+fn replay_recorded_responses() {
+    let path = fixture_path("replay");
+
+    // Record a few requests against the node
+    let recording = Recording::new(FakeNode::default(), Recorder::create(&path).unwrap());
+    assert_eq!(block_number(&recording), Value::String("0x1".into()));
+    assert_eq!(block_number(&recording), Value::String("0x2".into()));
+    assert!(recording
+        .execute("eth_call", vec![Value::String("latest".into())])
+        .wait()
+        .is_err());
+    assert!(recording
+        .execute("eth_getBlockByNumber", vec![])
+        .wait()
+        .is_err());
+
+    // Responses are replayed in order, and the last one is repeated
+    let replayer = Replayer::load(&path).unwrap();
+    assert_eq!(block_number(&replayer), Value::String("0x1".into()));
+    assert_eq!(block_number(&replayer), Value::String("0x2".into()));
+    assert_eq!(block_number(&replayer), Value::String("0x2".into()));
+
+    // RPC errors are replayed as well
+    match replayer
+        .execute("eth_call", vec![Value::String("latest".into())])
+        .wait()
+    {
+        Err(Error(ErrorKind::Rpc(e), _)) => assert_eq!(e.message, "bad call"),
+        result => panic!("expected an RPC error, got {:?}", result),
+    }
+
+    // Requests that failed to reach the node, or that were never made,
+    // were not recorded
+    for (method, params) in vec![
+        ("eth_getBlockByNumber", vec![]),
+        ("eth_call", vec![Value::String("0x1".into())]),
+    ] {
+        match replayer.execute(method, params).wait() {
+            Err(Error(ErrorKind::InvalidResponse(_), _)) => (),
+            result => panic!("expected an invalid response, got {:?}", result),
+        }
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_no_filter_changes_after_recording() {
+    let path = fixture_path("filter-changes");
+    fs::write(
+        &path,
+        concat!(
+            r#"{"method":"eth_getFilterChanges","params":["0x1"],"response":{"result":["0xab"]}}"#,
+            "\n"
+        ),
+    )
+    .unwrap();
+
+    let replayer = Replayer::load(&path).unwrap();
+    let filter_changes = || {
+        replayer
+            .execute("eth_getFilterChanges", vec![Value::String("0x1".into())])
+            .wait()
+            .unwrap()
+    };
+    assert_eq!(
+        filter_changes(),
+        Value::Array(vec![Value::String("0xab".into())])
+    );
+    assert_eq!(filter_changes(), Value::Array(vec![]));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -1,8 +1,9 @@
 use ethabi::{Bytes, Error as ABIError, Event, Function, LogParam, Token};
-use ethereum_types::{Address, H256};
+use ethereum_types::{Address, H256, U256};
 use futures::{Future, Stream};
 use std::error::Error;
 use std::fmt;
+use util::retry::TransientError;
 use web3::error::Error as Web3Error;
 use web3::types::{BlockId, BlockNumber};
 
@@ -72,6 +73,15 @@ impl From<ABIError> for EthereumContractCallError {
     }
 }
 
+impl TransientError for EthereumContractCallError {
+    fn is_transient(&self) -> bool {
+        match self {
+            EthereumContractCallError::CallError(e) => e.is_transient(),
+            EthereumContractCallError::ABIError(_) => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum EthereumSubscriptionError {
     // Because `Web3Error` and `ABIError` are not `Sync`,
@@ -101,6 +111,15 @@ impl From<ABIError> for EthereumSubscriptionError {
     }
 }
 
+impl TransientError for EthereumSubscriptionError {
+    fn is_transient(&self) -> bool {
+        match self {
+            EthereumSubscriptionError::RpcError(e) => e.is_transient(),
+            EthereumSubscriptionError::ABIError(_) => false,
+        }
+    }
+}
+
 /// A range to allow event subscriptions to limit the block numbers to consider.
 #[derive(Debug)]
 pub struct BlockNumberRange {
@@ -124,10 +143,68 @@ pub struct EthereumEvent {
     pub address: Address,
     pub event_signature: H256,
     pub block_hash: H256,
+    pub block_number: U256,
     pub params: Vec<LogParam>,
     pub removed: bool,
 }
 
+/// A successful call to a function of a contract, obtained from the
+/// transaction traces of a block.
+#[derive(Clone, Debug)]
+pub struct EthereumCall {
+    pub from: Address,
+    pub to: Address,
+    pub block_hash: H256,
+    pub block_number: U256,
+    pub transaction_hash: H256,
+    /// Canonical signature of the called function, e.g.
+    /// `transfer(address,uint256)`.
+    pub function_signature: String,
+    pub inputs: Vec<LogParam>,
+    pub outputs: Vec<LogParam>,
+}
+
+/// A subscription to all blocks in a specific block range.
+#[derive(Debug)]
+pub struct EthereumBlockSubscription {
+    /// An ID that uniquely identifies the subscription (e.g. a GUID).
+    pub subscription_id: String,
+    pub range: BlockNumberRange,
+}
+
+/// A transaction included in an Ethereum block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EthereumTransaction {
+    pub hash: H256,
+    pub from: Address,
+    /// `None` for contract creation transactions.
+    pub to: Option<Address>,
+}
+
+/// An Ethereum block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EthereumBlock {
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub number: U256,
+    pub timestamp: U256,
+    pub author: Address,
+    pub gas_used: U256,
+    pub gas_limit: U256,
+    pub transactions: Vec<EthereumTransaction>,
+}
+
+impl EthereumBlock {
+    /// Whether the block contains a transaction that calls `address`
+    /// directly. Calls made by other contracts are not considered; see
+    /// `EthereumAdapter::is_called_in_block` for those.
+    pub fn calls_address(&self, address: &Address) -> bool {
+        self.transactions
+            .iter()
+            .any(|transaction| transaction.to.as_ref() == Some(address))
+    }
+}
+
 /// Common trait for components that watch and manage access to Ethereum.
 ///
 /// Implementations may be implemented against an in-process Ethereum node
@@ -145,6 +222,56 @@ pub trait EthereumAdapter: Send + 'static {
         subscription: EthereumEventSubscription,
     ) -> Box<Stream<Item = EthereumEvent, Error = EthereumSubscriptionError>>;
 
-    /// Cancel a specific event subscription. Returns true when the subscription existed before.
+    /// Cancel a specific event or block subscription. The subscription's
+    /// stream ends once any filters installed for it have been uninstalled.
+    /// Returns true when the subscription existed before.
     fn unsubscribe_from_event(&mut self, subscription_id: String) -> bool;
+
+    /// Whether the event or block subscription with the given ID has passed
+    /// on everything up to the chain head at the time it was made, so that
+    /// it only passes on new events or blocks from now on.
+    fn is_caught_up(&self, subscription_id: &str) -> bool;
+
+    /// Obtain the number of the latest block of the chain.
+    fn latest_block_number(
+        &mut self,
+    ) -> Box<Future<Item = U256, Error = EthereumSubscriptionError>>;
+
+    /// Subscribe to all blocks in a block range, in ascending order. After
+    /// a reorg, the blocks that replaced blocks passed on before are passed
+    /// on again, oldest first.
+    fn subscribe_to_blocks(
+        &mut self,
+        subscription: EthereumBlockSubscription,
+    ) -> Box<Stream<Item = EthereumBlock, Error = EthereumSubscriptionError>>;
+
+    /// Obtain the `events` logged by the contract at `address` in `block`,
+    /// in the order in which they were logged.
+    fn events_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+        events: Vec<Event>,
+    ) -> Box<Future<Item = Vec<EthereumEvent>, Error = EthereumSubscriptionError>>;
+
+    /// Obtain the successful calls to the `functions` of the contract at
+    /// `address` in `block`, in the order in which they were made. Calls
+    /// are obtained from transaction traces, which requires a node that
+    /// supports `trace_filter`.
+    fn calls_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+        functions: Vec<Function>,
+    ) -> Box<Future<Item = Vec<EthereumCall>, Error = EthereumSubscriptionError>>;
+
+    /// Whether the contract at `address` was called in `block`, either
+    /// directly by a transaction or internally by another contract.
+    /// Internal calls are found through transaction traces; nodes that do
+    /// not support `trace_filter` only report direct calls.
+    fn is_called_in_block(
+        &mut self,
+        block: &EthereumBlock,
+        address: Address,
+    ) -> Box<Future<Item = bool, Error = EthereumSubscriptionError>>;
 }
@@ -1,31 +1,497 @@
 use data::subgraph::Link;
 use failure;
 use futures::prelude::*;
+use futures::{future, stream};
 use ipfs_api;
+use serde_json;
+use slog::Logger;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use util::cache::ContentCache;
+use util::retry::{idle_timeout, retry, timeout, RetryPolicy, TimeoutError, TransientError};
+
+/// Number of attempts to fetch a file from IPFS before giving up.
+const IPFS_CAT_ATTEMPTS: u32 = 5;
+
+/// Stream of the JSON values in a file.
+pub type JsonValueStream = Box<Stream<Item = serde_json::Value, Error = failure::Error> + Send>;
 
 /// Resolves links to subgraph manifests and resources referenced by them.
 pub trait LinkResolver: Send + Sync + 'static {
     /// Fetches the link contents as bytes.
     fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send>;
+
+    /// Fetches the link contents as newline-delimited JSON, i.e. one JSON
+    /// value per line, and parses the values one by one. Empty lines are
+    /// skipped.
+    ///
+    /// The default implementation fetches the whole file with `cat` first,
+    /// which only suits small files, e.g. test fixtures; resolvers for real
+    /// files stream them instead.
+    fn json_stream(&self, link: &Link) -> JsonValueStream {
+        Box::new(JsonLines::new(
+            self.cat(link).into_stream(),
+            usize::max_value(),
+        ))
+    }
+}
+
+/// Splits a stream of bytes into lines and parses each line as JSON.
+struct JsonLines<S> {
+    chunks: S,
+    max_line_size: usize,
+    buffer: Vec<u8>,
+    values: VecDeque<serde_json::Value>,
+    done: bool,
+}
+
+impl<S> JsonLines<S> {
+    fn new(chunks: S, max_line_size: usize) -> Self {
+        JsonLines {
+            chunks,
+            max_line_size,
+            buffer: vec![],
+            values: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Parses the complete lines in the buffer, of which only the bytes
+    /// from `scanned` on may contain newlines, and returns where the last,
+    /// incomplete line starts.
+    fn parse_lines(&mut self, scanned: usize) -> Result<usize, failure::Error> {
+        let mut start = 0;
+        for i in scanned..self.buffer.len() {
+            if self.buffer[i] == b'\n' {
+                self.check_line_size(i - start)?;
+                push_value(&mut self.values, &self.buffer[start..i])?;
+                start = i + 1;
+            }
+        }
+        Ok(start)
+    }
+
+    fn check_line_size(&self, size: usize) -> Result<(), failure::Error> {
+        if size > self.max_line_size {
+            return Err(failure::err_msg(format!(
+                "JSON value is larger than the maximum of {} bytes",
+                self.max_line_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a line of JSON, unless it is empty.
+fn push_value(values: &mut VecDeque<serde_json::Value>, line: &[u8]) -> Result<(), failure::Error> {
+    if line.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(());
+    }
+    values.push_back(serde_json::from_slice(line)?);
+    Ok(())
+}
+
+impl<S, B> Stream for JsonLines<S>
+where
+    S: Stream<Item = B, Error = failure::Error>,
+    B: AsRef<[u8]>,
+{
+    type Item = serde_json::Value;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Option<serde_json::Value>, failure::Error> {
+        loop {
+            if let Some(value) = self.values.pop_front() {
+                return Ok(Async::Ready(Some(value)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match self.chunks.poll()? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(Some(chunk)) => {
+                    // The buffer holds no newlines before the new chunk, and
+                    // the parsed lines are removed all at once, so that large
+                    // chunks with many lines are parsed in linear time
+                    let scanned = self.buffer.len();
+                    self.buffer.extend_from_slice(chunk.as_ref());
+                    let start = self.parse_lines(scanned)?;
+                    self.buffer.drain(..start);
+                    self.check_line_size(self.buffer.len())?;
+                }
+                Async::Ready(None) => {
+                    // The last line need not end with a newline
+                    let line = mem::replace(&mut self.buffer, vec![]);
+                    push_value(&mut self.values, &line)?;
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+impl TransientError for ipfs_api::response::Error {
+    /// Failures to reach the IPFS node or to receive the whole response are
+    /// transient; errors reported by the IPFS API, e.g. for an invalid hash
+    /// or a directory, and malformed requests or responses are not.
+    fn is_transient(&self) -> bool {
+        use ipfs_api::response::Error::*;
+
+        match self {
+            Client(_) | Io(_) | StreamError(_) | Uncategorized(_) => true,
+            Api(_)
+            | Http(_)
+            | Parse(_)
+            | ParseUtf8(_)
+            | Url(_)
+            | EncodeUrl(_)
+            | UnrecognizedTrailerHeader(_) => false,
+        }
+    }
+}
+
+/// Limits and caching for an `IpfsResolver`.
+#[derive(Clone, Debug)]
+pub struct IpfsResolverConfig {
+    /// How long a single attempt to fetch a file may take.
+    pub timeout: Duration,
+    /// Files larger than this many bytes are rejected.
+    pub max_file_size: usize,
+    /// How often to retry failed or timed out attempts.
+    pub retry_policy: RetryPolicy,
+    /// How many bytes of files to cache in memory.
+    pub memory_cache_size: usize,
+    /// Directory in which to cache files, and how many bytes to cache there.
+    pub disk_cache: Option<(PathBuf, u64)>,
+}
+
+impl Default for IpfsResolverConfig {
+    fn default() -> Self {
+        IpfsResolverConfig {
+            timeout: Duration::from_secs(30),
+            max_file_size: 256 * 1024 * 1024,
+            retry_policy: RetryPolicy::limited(IPFS_CAT_ATTEMPTS),
+            memory_cache_size: 64 * 1024 * 1024,
+            disk_cache: None,
+        }
+    }
+}
+
+/// Failure of a single attempt to fetch a file from IPFS.
+#[derive(Debug)]
+enum CatError {
+    Ipfs(ipfs_api::response::Error),
+    TooLarge(usize),
+}
+
+impl fmt::Display for CatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatError::Ipfs(e) => write!(f, "{}", e),
+            CatError::TooLarge(max_file_size) => {
+                write!(
+                    f,
+                    "File is larger than the maximum of {} bytes",
+                    max_file_size
+                )
+            }
+        }
+    }
+}
+
+impl TransientError for CatError {
+    /// A file that is too large will still be too large when fetched again.
+    fn is_transient(&self) -> bool {
+        match self {
+            CatError::Ipfs(e) => e.is_transient(),
+            CatError::TooLarge(_) => false,
+        }
+    }
+}
+
+/// Resolves `/ipfs/` links through an IPFS node, with a timeout and a size
+/// limit for every file.
+///
+/// IPFS content is immutable, so files are cached by their path; mappings
+/// that fetch the same file over and over again and redeployed subgraphs
+/// don't have to wait for IPFS.
+#[derive(Clone)]
+pub struct IpfsResolver {
+    logger: Logger,
+    client: ipfs_api::IpfsClient,
+    config: IpfsResolverConfig,
+    cache: Arc<ContentCache>,
+}
+
+impl IpfsResolver {
+    pub fn new(logger: &Logger, client: ipfs_api::IpfsClient, config: IpfsResolverConfig) -> Self {
+        let mut cache = ContentCache::new(config.memory_cache_size);
+        if let Some((ref dir, max_size)) = config.disk_cache {
+            cache = cache.with_disk_cache(dir.clone(), max_size);
+        }
+
+        IpfsResolver {
+            logger: logger.new(o!("component" => "IpfsResolver")),
+            client,
+            config,
+            cache: Arc::new(cache),
+        }
+    }
 }
 
-impl LinkResolver for ipfs_api::IpfsClient {
-    /// Currently supports only links of the form `/ipfs/ipfs_hash`
+impl LinkResolver for IpfsResolver {
+    /// Supports only links of the form `/ipfs/ipfs_hash`, like the plain
+    /// IPFS client.
     fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
         let link = &link.link;
-        // Verify that the link is in the expected form `/ipfs/hash`.
         if !link.starts_with("/ipfs/") {
-            return Box::new(Err(failure::err_msg(format!("Invalid link {}", link))).into_future());
+            return Box::new(future::err(failure::err_msg(format!(
+                "Invalid link {}",
+                link
+            ))));
         }
+        let path = link.trim_left_matches("/ipfs/").to_owned();
 
-        // Discard the `/ipfs/` prefix to get the hash.
-        let path = link.trim_left_matches("/ipfs/");
+        if let Some(content) = self.cache.get(&path) {
+            return Box::new(future::ok(content));
+        }
 
+        let client = self.client.clone();
+        let config = self.config.clone();
+        let cache = self.cache.clone();
+        let operation = format!("IPFS cat {}", path);
+        let error_link = link.clone();
+        let cache_path = path.clone();
         Box::new(
-            self.cat(path)
-                .concat2()
-                .map(|x| x.to_vec())
-                .map_err(|e| failure::Error::from(e)),
+            retry(&self.logger, &operation, config.retry_policy, move || {
+                let max_file_size = config.max_file_size;
+                let content = client.cat(&path).map_err(CatError::Ipfs).fold(
+                    vec![],
+                    move |mut content, chunk| {
+                        if content.len() + chunk.len() > max_file_size {
+                            return Err(CatError::TooLarge(max_file_size));
+                        }
+                        content.extend_from_slice(&chunk);
+                        Ok(content)
+                    },
+                );
+                timeout(content, config.timeout)
+            })
+            .map_err(move |e: TimeoutError<CatError>| {
+                failure::err_msg(format!("Failed to fetch {}: {}", error_link, e))
+            })
+            .map(move |content| {
+                cache.insert(&cache_path, &content);
+                content
+            }),
         )
     }
+
+    /// Streams the file from IPFS without retries, so that files of any size
+    /// can be processed; only a single line may not be larger than the
+    /// maximum file size, and the stream fails if IPFS doesn't send the next
+    /// chunk within the timeout. Files that are cached already are not
+    /// fetched again, but streamed files are not added to the cache.
+    fn json_stream(&self, link: &Link) -> JsonValueStream {
+        if !link.link.starts_with("/ipfs/") {
+            return Box::new(stream::once(Err(failure::err_msg(format!(
+                "Invalid link {}",
+                link.link
+            )))));
+        }
+        let path = link.link.trim_left_matches("/ipfs/");
+        let max_line_size = self.config.max_file_size;
+
+        match self.cache.get(path) {
+            Some(content) => Box::new(JsonLines::new(stream::once(Ok(content)), max_line_size)),
+            None => {
+                let error_link = link.link.clone();
+                let chunks =
+                    idle_timeout(self.client.cat(path), self.config.timeout).map_err(move |e| {
+                        failure::err_msg(format!("Failed to fetch {}: {}", error_link, e))
+                    });
+                Box::new(JsonLines::new(chunks, max_line_size))
+            }
+        }
+    }
+}
+
+impl LinkResolver for Box<LinkResolver> {
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        (**self).cat(link)
+    }
+
+    fn json_stream(&self, link: &Link) -> JsonValueStream {
+        (**self).json_stream(link)
+    }
+}
+
+/// Resolves links to files on the local filesystem, so that subgraphs can be
+/// developed without an IPFS node.
+///
+/// Relative paths are resolved relative to a base directory, usually the
+/// directory of the subgraph manifest. Links of the form `/ipfs/ipfs_hash`
+/// are passed on to IPFS, if an `IpfsResolver` is configured.
+pub struct FileLinkResolver {
+    base_dir: PathBuf,
+    ipfs: Option<IpfsResolver>,
+}
+
+impl FileLinkResolver {
+    pub fn new<P: Into<PathBuf>>(base_dir: P) -> Self {
+        FileLinkResolver {
+            base_dir: base_dir.into(),
+            ipfs: None,
+        }
+    }
+
+    /// Resolves `/ipfs/` links through `ipfs` instead of the filesystem.
+    pub fn with_ipfs(self, ipfs: IpfsResolver) -> Self {
+        FileLinkResolver {
+            ipfs: Some(ipfs),
+            ..self
+        }
+    }
+
+    /// Returns the path of the file a link refers to.
+    pub fn path(&self, link: &Link) -> PathBuf {
+        let link = link.link.trim_left_matches("file://");
+
+        // Joining an absolute path replaces the base directory
+        self.base_dir.join(link)
+    }
+}
+
+impl LinkResolver for FileLinkResolver {
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        if link.link.starts_with("/ipfs/") {
+            if let Some(ref ipfs) = self.ipfs {
+                return ipfs.cat(link);
+            }
+        }
+
+        let path = self.path(link);
+        Box::new(
+            fs::read(&path)
+                .map_err(|e| {
+                    failure::err_msg(format!("Failed to read file {}: {}", path.display(), e))
+                })
+                .into_future(),
+        )
+    }
+
+    fn json_stream(&self, link: &Link) -> JsonValueStream {
+        if link.link.starts_with("/ipfs/") {
+            if let Some(ref ipfs) = self.ipfs {
+                return ipfs.json_stream(link);
+            }
+        }
+
+        let path = self.path(link);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Box::new(stream::once(Err(failure::err_msg(format!(
+                    "Failed to read file {}: {}",
+                    path.display(),
+                    e
+                )))))
+            }
+        };
+
+        // Read the file in blocks rather than all at once
+        let chunks = stream::unfold(file, |mut file| {
+            let mut chunk = vec![0; 64 * 1024];
+            match file.read(&mut chunk) {
+                Ok(0) => None,
+                Ok(len) => {
+                    chunk.truncate(len);
+                    Some(future::ok((chunk, file)))
+                }
+                Err(e) => Some(future::err(failure::Error::from(e))),
+            }
+        });
+        Box::new(JsonLines::new(chunks, usize::max_value()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn link(link: &str) -> Link {
+        Link {
+            link: link.to_owned(),
+        }
+    }
+
+    #[test]
+    fn resolve_relative_and_absolute_paths() {
+        let dir = env::temp_dir().join(format!("graph-file-link-resolver-{}", process::id()));
+        fs::create_dir_all(dir.join("mappings")).unwrap();
+        fs::write(dir.join("mappings").join("mapping.wasm"), b"wasm").unwrap();
+
+        let resolver = FileLinkResolver::new(&dir);
+        let absolute = dir.join("mappings").join("mapping.wasm");
+        for l in vec![
+            "mappings/mapping.wasm",
+            "./mappings/mapping.wasm",
+            absolute.to_str().unwrap(),
+        ] {
+            assert_eq!(resolver.cat(&link(l)).wait().unwrap(), b"wasm".to_vec());
+        }
+
+        // Without an IPFS client, `/ipfs/` links are looked up on disk
+        assert!(resolver.cat(&link("/ipfs/QmHash")).wait().is_err());
+        assert!(resolver.cat(&link("missing.wasm")).wait().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn json(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn stream_newline_delimited_json() {
+        let chunks: Vec<Result<&[u8], failure::Error>> = vec![
+            Ok(&b"{\"a\": 1}\n\n[1,"[..]),
+            Ok(&b" 2]\n\"b\""[..]),
+            Ok(&b"\n  \n3"[..]),
+        ];
+        let values = JsonLines::new(stream::iter_result(chunks), 10)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                json(r#"{"a": 1}"#),
+                json("[1, 2]"),
+                json(r#""b""#),
+                json("3"),
+            ]
+        );
+
+        // Lines that are too large or aren't JSON fail the stream
+        let chunks: Vec<Result<&[u8], failure::Error>> = vec![Ok(&b"1\n[1, 2, 3, 4, 5]"[..])];
+        assert!(JsonLines::new(stream::iter_result(chunks), 10)
+            .collect()
+            .wait()
+            .is_err());
+        let chunks: Vec<Result<&[u8], failure::Error>> = vec![Ok(&b"1\nnot json\n"[..])];
+        assert!(JsonLines::new(stream::iter_result(chunks), 10)
+            .collect()
+            .wait()
+            .is_err());
+    }
 }
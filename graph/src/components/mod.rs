@@ -34,6 +34,10 @@
 //! configuration of component graphs.
 
 use futures::prelude::*;
+use futures::sync::mpsc::{channel, Receiver, SendError, Sender};
+use prometheus::IntGauge;
+
+use util::metrics;
 
 /// Components dealing with subgraphs.
 pub mod subgraph;
@@ -89,6 +93,80 @@ pub fn forward2<
     })
 }
 
+/// Creates the channel through which a component receives the events of
+/// the pipe named `pipe`, with room for `buffer` events. Events count
+/// towards the backlog of the pipe from when the channel accepts them until
+/// the component takes them out of the receiver.
+pub fn backlog_channel<E>(pipe: &str, buffer: usize) -> (BacklogSender<E>, BacklogReceiver<E>) {
+    let backlog = metrics::FORWARD_BACKLOG.with_label_values(&[pipe]);
+    let (sender, receiver) = channel(buffer);
+    (
+        BacklogSender {
+            sender,
+            backlog: backlog.clone(),
+        },
+        BacklogReceiver { receiver, backlog },
+    )
+}
+
+/// Sending half of a `backlog_channel`.
+pub struct BacklogSender<E> {
+    sender: Sender<E>,
+    backlog: IntGauge,
+}
+
+impl<E> Clone for BacklogSender<E> {
+    fn clone(&self) -> Self {
+        BacklogSender {
+            sender: self.sender.clone(),
+            backlog: self.backlog.clone(),
+        }
+    }
+}
+
+impl<E> Sink for BacklogSender<E> {
+    type SinkItem = E;
+    type SinkError = SendError<E>;
+
+    fn start_send(&mut self, item: E) -> StartSend<E, SendError<E>> {
+        // Count the event before the receiver can take it out again
+        self.backlog.inc();
+        let result = self.sender.start_send(item);
+        match result {
+            Ok(AsyncSink::Ready) => {}
+            _ => self.backlog.dec(),
+        }
+        result
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), SendError<E>> {
+        self.sender.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), SendError<E>> {
+        self.sender.close()
+    }
+}
+
+/// Receiving half of a `backlog_channel`.
+pub struct BacklogReceiver<E> {
+    receiver: Receiver<E>,
+    backlog: IntGauge,
+}
+
+impl<E> Stream for BacklogReceiver<E> {
+    type Item = E;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<E>, ()> {
+        let item = self.receiver.poll();
+        if let Ok(Async::Ready(Some(_))) = item {
+            self.backlog.dec();
+        }
+        item
+    }
+}
+
 /// A component that receives events of type `T`.
 pub trait EventConsumer<E> {
     /// Get the event sink.
@@ -106,3 +184,24 @@ pub trait EventProducer<E> {
     /// Avoid calling directly, prefer helpers such as `forward`.
     fn take_event_stream(&mut self) -> Option<Box<Stream<Item = E, Error = ()> + Send>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn backlog_counts_events_until_they_are_received() {
+        let backlog = metrics::FORWARD_BACKLOG.with_label_values(&["test"]);
+        let (sender, receiver) = backlog_channel("test", 10);
+
+        let events = stream::iter_ok::<_, SendError<u32>>(vec![1, 2, 3]);
+        let (_sender, _) = sender.send_all(events).wait().unwrap();
+        assert_eq!(backlog.get(), 3);
+
+        let (event, _receiver) = receiver.skip(2).into_future().wait().ok().unwrap();
+        assert_eq!(event, Some(3));
+        assert_eq!(backlog.get(), 0);
+    }
+}
@@ -1,8 +1,10 @@
-use ethereum_types::H256;
+use ethereum_types::{Address, H256};
+use failure;
 use futures::sync::mpsc::{Receiver, Sender};
 
 use components::schema::SchemaProviderEvent;
 use data::store::*;
+use data::subgraph::{SubgraphDeployment, SubgraphName};
 use std::fmt;
 use util::stream::StreamError;
 
@@ -19,6 +21,17 @@ pub struct StoreKey {
     pub id: String,
 }
 
+/// A change to an entity, as part of a set of changes that are applied to
+/// the store together.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityOperation {
+    /// Creates or updates the entity with the given key.
+    Set { key: StoreKey, data: Entity },
+
+    /// Removes the entity with the given key.
+    Remove { key: StoreKey },
+}
+
 /// Supported types of store filters.
 #[derive(Clone, Debug, PartialEq)]
 pub enum StoreFilter {
@@ -133,4 +146,57 @@ pub trait Store: BasicStore + Send {
     /// Receiver from which others can read events emitted by the store.
     /// Can only be called once. Any consecutive call will result in a StreamError.
     fn event_stream(&mut self) -> Result<Receiver<StoreEvent>, StreamError>;
+
+    /// Applies all `operations` in order, or none of them if any fails.
+    fn transact(
+        &mut self,
+        operations: Vec<EntityOperation>,
+        event_source: EventSource,
+    ) -> Result<(), ()>;
+
+    /// Counts the entities that are stored for `subgraph`.
+    fn count_entities(&self, subgraph: &str) -> Result<u64, ()>;
+}
+
+/// Persistent cache for the results of Ethereum contract calls.
+///
+/// A call made against a specific block always returns the same result, so
+/// cached results are keyed by the block hash and never need to be
+/// invalidated.
+pub trait EthereumCallCache: Send {
+    /// Looks up the result of calling `contract_address` with the ABI-encoded
+    /// `encoded_call` at the block with hash `block_hash`.
+    fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block_hash: H256,
+    ) -> Result<Option<Vec<u8>>, failure::Error>;
+
+    /// Stores the result of calling `contract_address` with the ABI-encoded
+    /// `encoded_call` at the block with hash `block_hash`.
+    fn set_call(
+        &mut self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block_hash: H256,
+        return_value: &[u8],
+    ) -> Result<(), failure::Error>;
+}
+
+/// Persistent record of the deployment that each subgraph name points at.
+pub trait SubgraphNameStore: Send {
+    /// Looks up the deployment that `name` currently points at.
+    fn current_deployment(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<SubgraphDeployment>, failure::Error>;
+
+    /// Points `name` at `deployment`. The deployment it pointed at before,
+    /// if any, is replaced in a single step.
+    fn set_current_deployment(
+        &mut self,
+        name: &SubgraphName,
+        deployment: &SubgraphDeployment,
+    ) -> Result<(), failure::Error>;
 }
@@ -7,12 +7,23 @@ pub enum RuntimeHostEvent {
     EntitySet(StoreKey, Entity, EventSource),
     /// An entity should be removed.
     EntityRemoved(StoreKey, EventSource),
+    /// Entities should be changed, either all of them or none.
+    EntityOperations(Vec<EntityOperation>, EventSource),
+    /// A data source was created from a template and needs a runtime host.
+    DataSourceCreated(DataSource, EventSource),
 }
 
 /// Common trait for runtime host implementations.
 pub trait RuntimeHost: EventProducer<RuntimeHostEvent> + Send {
     /// The subgraph definition the runtime is for.
     fn subgraph_manifest(&self) -> &SubgraphManifest;
+
+    /// Whether the host has processed all events and blocks up to the chain
+    /// head at the time it was started, and now only processes new ones.
+    fn is_caught_up(&self) -> bool;
+
+    /// How far the host has processed the chain and which errors occurred.
+    fn status(&self) -> RuntimeHostStatus;
 }
 
 pub trait RuntimeHostBuilder: Send + 'static {
@@ -1,3 +1,5 @@
+use futures::prelude::*;
+
 use components::EventConsumer;
 
 use super::SubgraphProviderEvent;
@@ -9,4 +11,8 @@ use super::SubgraphProviderEvent;
 /// When a subgraph is added, the runtime manager creates and starts
 /// one or more runtime hosts for the subgraph. When a subgraph is removed,
 /// the runtime manager stops and removes the runtime hosts for this subgraph.
-pub trait RuntimeManager: EventConsumer<SubgraphProviderEvent> {}
+pub trait RuntimeManager: EventConsumer<SubgraphProviderEvent> {
+    /// Resolves once all runtime hosts of the subgraph with the given ID
+    /// have caught up with the chain head.
+    fn caught_up(&self, subgraph_id: &str) -> Box<Future<Item = (), Error = ()> + Send>;
+}
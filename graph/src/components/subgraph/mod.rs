@@ -1,7 +1,12 @@
 mod host;
 mod manager;
 mod provider;
+mod status;
 
 pub use self::host::{RuntimeHost, RuntimeHostBuilder, RuntimeHostEvent};
 pub use self::manager::RuntimeManager;
 pub use self::provider::{SchemaEvent, SubgraphProvider, SubgraphProviderEvent};
+pub use self::status::{
+    IndexingStatusProvider, RuntimeHostStatus, SubgraphError, SubgraphHealth,
+    SubgraphIndexingStatus,
+};
@@ -18,6 +18,9 @@ pub enum SchemaEvent {
     SchemaAdded(Schema),
     /// A subgraph with an existing schema was removed.
     SchemaRemoved(Schema),
+    /// The first schema was replaced by the second one, e.g. when a subgraph
+    /// name switches to a new deployment.
+    SchemaReplaced(Schema, Schema),
 }
 
 /// Common trait for subgraph providers.
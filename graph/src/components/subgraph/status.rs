@@ -0,0 +1,189 @@
+use std::fmt;
+
+/// An error that occurred while indexing a subgraph.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubgraphError {
+    pub message: String,
+    /// Number of the block that was being processed, if known.
+    pub block_number: Option<u64>,
+    /// Name of the mapping handler that failed, if the error was raised
+    /// by a handler.
+    pub handler: Option<String>,
+}
+
+/// How well the indexing of a subgraph is going.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubgraphHealth {
+    /// No errors occurred.
+    Healthy,
+    /// Handlers failed, but the subgraph is still being indexed.
+    Unhealthy,
+    /// The subgraph has stopped being indexed because of a fatal error.
+    Failed,
+}
+
+impl SubgraphHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubgraphHealth::Healthy => "healthy",
+            SubgraphHealth::Unhealthy => "unhealthy",
+            SubgraphHealth::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for SubgraphHealth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Progress and errors of a single runtime host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuntimeHostStatus {
+    /// Number of the last block that the host has processed.
+    pub latest_block: Option<u64>,
+    /// Error that made the host stop processing events and blocks.
+    pub fatal_error: Option<SubgraphError>,
+    /// Handler errors; the events or blocks they occurred for were skipped.
+    pub non_fatal_errors: Vec<SubgraphError>,
+}
+
+/// Indexing status of a subgraph, combined from the statuses of its hosts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubgraphIndexingStatus {
+    pub subgraph: String,
+    /// Whether all hosts of the subgraph have caught up with the chain head.
+    pub synced: bool,
+    pub health: SubgraphHealth,
+    pub fatal_error: Option<SubgraphError>,
+    pub non_fatal_errors: Vec<SubgraphError>,
+    /// Number of the latest block of the chain.
+    pub chain_head_block: Option<u64>,
+    /// Number of the latest block that all hosts of the subgraph have
+    /// processed.
+    pub latest_block: Option<u64>,
+    /// Number of entities the subgraph has stored.
+    pub entity_count: u64,
+}
+
+impl SubgraphIndexingStatus {
+    /// Combines the statuses of the hosts of `subgraph`; `synced` is whether
+    /// all of them have caught up with the chain head.
+    pub fn from_host_statuses(
+        subgraph: String,
+        synced: bool,
+        host_statuses: Vec<RuntimeHostStatus>,
+        chain_head_block: Option<u64>,
+        entity_count: u64,
+    ) -> Self {
+        let mut fatal_error = None;
+        let mut non_fatal_errors = vec![];
+        let mut latest_blocks = vec![];
+        for status in host_statuses {
+            fatal_error = fatal_error.or(status.fatal_error);
+            non_fatal_errors.extend(status.non_fatal_errors);
+            latest_blocks.push(status.latest_block);
+        }
+
+        let health = if fatal_error.is_some() {
+            SubgraphHealth::Failed
+        } else if !non_fatal_errors.is_empty() {
+            SubgraphHealth::Unhealthy
+        } else {
+            SubgraphHealth::Healthy
+        };
+        let synced = synced && fatal_error.is_none();
+
+        // Hosts that only handle events don't see blocks without their
+        // events, so once all hosts are synced, the subgraph is at the head
+        let latest_block = if synced && chain_head_block.is_some() {
+            chain_head_block
+        } else {
+            latest_blocks.into_iter().min().unwrap_or(None)
+        };
+
+        SubgraphIndexingStatus {
+            subgraph,
+            synced,
+            health,
+            fatal_error,
+            non_fatal_errors,
+            chain_head_block,
+            latest_block,
+            entity_count,
+        }
+    }
+
+    /// Number of blocks between the chain head and the latest block, if both
+    /// are known.
+    pub fn blocks_behind(&self) -> Option<u64> {
+        match (self.chain_head_block, self.latest_block) {
+            (Some(chain_head_block), Some(latest_block)) => {
+                Some(chain_head_block.saturating_sub(latest_block))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Common trait for components that report how far subgraphs have been
+/// indexed.
+pub trait IndexingStatusProvider: Send + Sync {
+    /// Returns the indexing status of every subgraph that is being indexed.
+    fn indexing_statuses(&self) -> Vec<SubgraphIndexingStatus>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(message: &str, block_number: u64) -> SubgraphError {
+        SubgraphError {
+            message: message.to_owned(),
+            block_number: Some(block_number),
+            handler: Some("handleTransfer".to_owned()),
+        }
+    }
+
+    #[test]
+    fn combine_host_statuses() {
+        let status = SubgraphIndexingStatus::from_host_statuses(
+            "subgraph".to_owned(),
+            false,
+            vec![
+                RuntimeHostStatus {
+                    latest_block: Some(12),
+                    fatal_error: None,
+                    non_fatal_errors: vec![error("abort", 7)],
+                },
+                RuntimeHostStatus {
+                    latest_block: Some(10),
+                    fatal_error: None,
+                    non_fatal_errors: vec![],
+                },
+            ],
+            Some(20),
+            3,
+        );
+        assert_eq!(status.health, SubgraphHealth::Unhealthy);
+        assert_eq!(status.latest_block, Some(10));
+        assert_eq!(status.blocks_behind(), Some(10));
+        assert_eq!(status.non_fatal_errors, vec![error("abort", 7)]);
+
+        let status = SubgraphIndexingStatus::from_host_statuses(
+            "subgraph".to_owned(),
+            true,
+            vec![RuntimeHostStatus {
+                latest_block: Some(12),
+                fatal_error: Some(error("subscription failed", 12)),
+                non_fatal_errors: vec![],
+            }],
+            Some(20),
+            3,
+        );
+        assert_eq!(status.health, SubgraphHealth::Failed);
+        assert_eq!(status.synced, false);
+        assert_eq!(status.latest_block, Some(12));
+    }
+}
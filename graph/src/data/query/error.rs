@@ -21,6 +21,25 @@ pub enum QueryExecutionError {
     MissingArgumentError(Pos, String),
 }
 
+impl QueryExecutionError {
+    /// Short name of the kind of error, e.g. to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QueryExecutionError::OperationNameRequired => "operation_name_required",
+            QueryExecutionError::OperationNotFound(_) => "operation_not_found",
+            QueryExecutionError::NotSupported(_) => "not_supported",
+            QueryExecutionError::NoRootQueryObjectType => "no_root_query_object_type",
+            QueryExecutionError::ResolveEntityError(_, _) => "resolve_entity",
+            QueryExecutionError::NonNullError(_, _) => "non_null",
+            QueryExecutionError::ListValueError(_, _) => "list_value",
+            QueryExecutionError::NamedTypeError(_) => "named_type",
+            QueryExecutionError::AbstractTypeError(_) => "abstract_type",
+            QueryExecutionError::InvalidArgumentError(_, _, _) => "invalid_argument",
+            QueryExecutionError::MissingArgumentError(_, _) => "missing_argument",
+        }
+    }
+}
+
 impl Error for QueryExecutionError {
     fn description(&self) -> &str {
         "Query execution error"
@@ -75,6 +94,17 @@ pub enum QueryError {
     ExecutionError(QueryExecutionError),
 }
 
+impl QueryError {
+    /// Short name of the kind of error, e.g. to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QueryError::EncodingError(_) => "encoding",
+            QueryError::ParseError(_) => "parse",
+            QueryError::ExecutionError(e) => e.kind(),
+        }
+    }
+}
+
 impl From<FromUtf8Error> for QueryError {
     fn from(e: FromUtf8Error) -> Self {
         QueryError::EncodingError(e)
@@ -1,3 +1,4 @@
+use ethereum_types::U256;
 use hex;
 use num_bigint;
 use serde::{self, Deserialize, Serialize};
@@ -18,6 +19,17 @@ impl BigInt {
     }
 }
 
+impl From<U256> for BigInt {
+    fn from(n: U256) -> BigInt {
+        let mut bytes = [0; 32];
+        n.to_little_endian(&mut bytes);
+        BigInt(num_bigint::BigInt::from_bytes_le(
+            num_bigint::Sign::Plus,
+            &bytes,
+        ))
+    }
+}
+
 impl Display for BigInt {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         self.0.fmt(f)
@@ -57,6 +69,12 @@ impl Display for Bytes {
     }
 }
 
+impl Bytes {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl FromStr for Bytes {
     type Err = hex::FromHexError;
 
@@ -1,23 +1,69 @@
 use components::link_resolver::LinkResolver;
 use data::schema::Schema;
 use ethabi::Contract;
+use ethereum_types::{Address, H256};
 use failure;
 use failure::SyncFailure;
 use futures::stream;
 use graphql_parser;
+use graphql_parser::schema;
 use parity_wasm;
-use parity_wasm::elements::Module;
+use parity_wasm::elements::{Internal, Module};
+use semver::{Version, VersionReq};
 use serde_yaml;
+use tiny_keccak::keccak256;
 use tokio::prelude::*;
+use util::ethereum::{
+    contract_event_with_signature, contract_function_with_signature, event_signature,
+    function_signature,
+};
 
+use std::collections::HashSet;
+use std::fmt;
 use std::sync::Arc;
 
+/// The versions of the manifest format (`specVersion`) that this node can run.
+pub const SUPPORTED_SPEC_VERSIONS: &str = "=0.0.1";
+
+/// The versions of the mapping API (`apiVersion`) that this node provides.
+/// Which host functions a mapping can import depends on its `apiVersion`.
+pub const SUPPORTED_API_VERSIONS: &str = ">=0.0.1, <=0.0.2";
+
 #[derive(Debug)]
 pub enum SubgraphManifestResolveError {
     ParseError(serde_yaml::Error),
     NonUtf8,
-    InvalidFormat,
+    InvalidFormat(String),
     ResolveError(failure::Error),
+    /// The manifest was resolved, but is inconsistent with its schema, ABIs
+    /// or mappings.
+    InvalidManifest(Vec<SubgraphManifestValidationError>),
+}
+
+impl fmt::Display for SubgraphManifestResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubgraphManifestResolveError::ParseError(e) => {
+                write!(f, "Failed to parse subgraph manifest: {}", e)
+            }
+            SubgraphManifestResolveError::NonUtf8 => {
+                write!(f, "Subgraph manifest is not valid UTF-8")
+            }
+            SubgraphManifestResolveError::InvalidFormat(e) => {
+                write!(f, "Invalid subgraph manifest: {}", e)
+            }
+            SubgraphManifestResolveError::ResolveError(e) => {
+                write!(f, "Failed to resolve subgraph manifest: {}", e)
+            }
+            SubgraphManifestResolveError::InvalidManifest(errors) => {
+                write!(f, "Invalid subgraph manifest:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl From<serde_yaml::Error> for SubgraphManifestResolveError {
@@ -26,6 +72,68 @@ impl From<serde_yaml::Error> for SubgraphManifestResolveError {
     }
 }
 
+/// A problem found in a subgraph manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubgraphManifestValidationError {
+    /// Where in the manifest the problem is, e.g.
+    /// `dataSources[0].mapping.eventHandlers[1].event`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SubgraphManifestValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// A human-readable name of a subgraph, e.g. `ens` or `graphprotocol/ens`,
+/// that points at the deployment of the subgraph that is currently served.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct SubgraphName(String);
+
+impl SubgraphName {
+    /// Names consist of one or more segments separated by `/`; each segment
+    /// consists of ASCII letters, digits, `-` and `_`.
+    pub fn new<S: Into<String>>(name: S) -> Result<Self, String> {
+        let name = name.into();
+        let valid = name.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+        if valid {
+            Ok(SubgraphName(name))
+        } else {
+            Err(format!(
+                "Invalid subgraph name `{}`, names consist of letters, digits, `-` and `_`, \
+                 optionally in segments separated by `/`",
+                name
+            ))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SubgraphName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A deployment of a subgraph, i.e. one version of it.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct SubgraphDeployment {
+    /// ID of the subgraph, e.g. the IPFS hash of its manifest.
+    pub id: String,
+    /// Link to the manifest, from which the deployment can be resolved again.
+    pub location: String,
+}
+
 /// IPLD link.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct Link {
@@ -58,6 +166,10 @@ impl SchemaData {
 pub struct Source {
     pub address: String,
     pub abi: String,
+    /// Block from which the data source is processed. For data sources
+    /// created from templates, this is the block they were created in.
+    #[serde(rename = "startBlock", default)]
+    pub start_block: u64,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -91,18 +203,47 @@ pub struct MappingEventHandler {
     pub handler: String,
 }
 
+/// Restricts the blocks for which a block handler is called.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BlockHandlerFilter {
+    /// Only call the handler for blocks that contain a transaction calling
+    /// the data source contract.
+    Call,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingBlockHandler {
+    pub handler: String,
+    pub filter: Option<BlockHandlerFilter>,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingCallHandler {
+    pub function: String,
+    pub handler: String,
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct BaseMapping<C, W> {
     pub kind: String,
     #[serde(rename = "apiVersion")]
-    pub api_version: String,
+    pub api_version: Version,
     pub language: String,
     pub entities: Vec<String>,
     pub abis: Vec<BaseMappingABI<C>>,
-    #[serde(rename = "eventHandlers")]
+    #[serde(rename = "eventHandlers", default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(rename = "callHandlers", default)]
+    pub call_handlers: Vec<MappingCallHandler>,
+    #[serde(rename = "blockHandlers", default)]
+    pub block_handlers: Vec<MappingBlockHandler>,
     #[serde(rename = "file")]
     pub runtime: W,
+    /// Keccak-256 hash of the raw bytes of `runtime`, set when the mapping
+    /// is resolved. Identifies the compiled module in module caches.
+    #[serde(skip)]
+    pub runtime_hash: H256,
 }
 
 pub type UnresolvedMapping = BaseMapping<Link, Link>;
@@ -120,7 +261,10 @@ impl UnresolvedMapping {
             entities,
             abis,
             event_handlers,
+            call_handlers,
+            block_handlers,
             runtime,
+            ..
         } = self;
 
         // resolve each abi
@@ -131,16 +275,22 @@ impl UnresolvedMapping {
             .join(
                 resolver
                     .cat(&runtime)
-                    .and_then(|module_bytes| Ok(parity_wasm::deserialize_buffer(&module_bytes)?)),
+                    .and_then(|module_bytes| {
+                        let runtime_hash = H256::from(keccak256(&module_bytes));
+                        Ok((parity_wasm::deserialize_buffer(&module_bytes)?, runtime_hash))
+                    }),
             )
-            .map(|(abis, runtime)| Mapping {
+            .map(|(abis, (runtime, runtime_hash))| Mapping {
                 kind,
                 api_version,
                 language,
                 entities,
                 abis,
                 event_handlers,
+                call_handlers,
+                block_handlers,
                 runtime,
+                runtime_hash,
             })
     }
 }
@@ -148,6 +298,8 @@ impl UnresolvedMapping {
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct BaseDataSource<C, W> {
     pub kind: String,
+    #[serde(default)]
+    pub network: Option<String>,
     pub name: String,
     pub source: Source,
     pub mapping: BaseMapping<C, W>,
@@ -163,12 +315,58 @@ impl UnresolvedDataSource {
     ) -> impl Future<Item = DataSource, Error = failure::Error> {
         let UnresolvedDataSource {
             kind,
+            network,
             name,
             source,
             mapping,
         } = self;
         mapping.resolve(resolver).map(|mapping| DataSource {
             kind,
+            network,
+            name,
+            source,
+            mapping,
+        })
+    }
+}
+
+/// The source of a data source template. Unlike `Source`, it has no
+/// address; the address is provided when the template is instantiated.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct TemplateSource {
+    pub abi: String,
+}
+
+/// A template from which data sources can be created at runtime, e.g. for
+/// contracts that are deployed by a factory contract.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct BaseDataSourceTemplate<C, W> {
+    pub kind: String,
+    #[serde(default)]
+    pub network: Option<String>,
+    pub name: String,
+    pub source: TemplateSource,
+    pub mapping: BaseMapping<C, W>,
+}
+
+pub type UnresolvedDataSourceTemplate = BaseDataSourceTemplate<Link, Link>;
+pub type DataSourceTemplate = BaseDataSourceTemplate<Contract, Module>;
+
+impl UnresolvedDataSourceTemplate {
+    pub fn resolve(
+        self,
+        resolver: &impl LinkResolver,
+    ) -> impl Future<Item = DataSourceTemplate, Error = failure::Error> {
+        let UnresolvedDataSourceTemplate {
+            kind,
+            network,
+            name,
+            source,
+            mapping,
+        } = self;
+        mapping.resolve(resolver).map(|mapping| DataSourceTemplate {
+            kind,
+            network,
             name,
             source,
             mapping,
@@ -176,26 +374,48 @@ impl UnresolvedDataSource {
     }
 }
 
+impl DataSourceTemplate {
+    /// Creates a data source from the template that watches the contract
+    /// at `address`, starting at `start_block`. The data source is named
+    /// after the template.
+    pub fn create_data_source(&self, address: Address, start_block: u64) -> DataSource {
+        DataSource {
+            kind: self.kind.clone(),
+            network: self.network.clone(),
+            name: self.name.clone(),
+            source: Source {
+                address: format!("{:x}", address),
+                abi: self.source.abi.clone(),
+                start_block,
+            },
+            mapping: self.mapping.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
-pub struct BaseSubgraphManifest<S, D> {
+pub struct BaseSubgraphManifest<S, D, T> {
     pub id: String,
     pub location: String,
     #[serde(rename = "specVersion")]
-    pub spec_version: String,
+    pub spec_version: Version,
     pub schema: S,
     #[serde(rename = "dataSources")]
     pub data_sources: Vec<D>,
+    #[serde(default)]
+    pub templates: Vec<T>,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
-impl<S, D> PartialEq for BaseSubgraphManifest<S, D> {
+impl<S, D, T> PartialEq for BaseSubgraphManifest<S, D, T> {
     fn eq(&self, other: &Self) -> bool {
         self.location == other.location
     }
 }
 
-pub type UnresolvedSubgraphManifest = BaseSubgraphManifest<SchemaData, UnresolvedDataSource>;
-pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource>;
+pub type UnresolvedSubgraphManifest =
+    BaseSubgraphManifest<SchemaData, UnresolvedDataSource, UnresolvedDataSourceTemplate>;
+pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource, DataSourceTemplate>;
 
 impl SubgraphManifest {
     /// Entry point for resolving a subgraph definition.
@@ -213,9 +433,11 @@ impl SubgraphManifest {
                     .map_err(|_| SubgraphManifestResolveError::NonUtf8)?;
                 let mut raw: serde_yaml::Value = serde_yaml::from_str(&file)?;
                 {
-                    let raw_mapping = raw
-                        .as_mapping_mut()
-                        .ok_or(SubgraphManifestResolveError::InvalidFormat)?;
+                    let raw_mapping = raw.as_mapping_mut().ok_or_else(|| {
+                        SubgraphManifestResolveError::InvalidFormat(String::from(
+                            "expected a YAML mapping at the top level",
+                        ))
+                    })?;
 
                     // Inject the IPFS hash as the ID of the subgraph
                     // into the definition.
@@ -240,6 +462,200 @@ impl SubgraphManifest {
                     .resolve(&*resolver)
                     .map_err(|e| SubgraphManifestResolveError::ResolveError(e))
             })
+            .and_then(|manifest| {
+                let errors = manifest.validate();
+                if errors.is_empty() {
+                    Ok(manifest)
+                } else {
+                    Err(SubgraphManifestResolveError::InvalidManifest(errors))
+                }
+            })
+    }
+
+    /// Checks that the manifest and mapping API versions are supported and
+    /// that the data sources and templates are consistent with the schema,
+    /// their ABIs and their mappings, so that mistakes are reported before
+    /// the subgraph is run. Returns all problems found.
+    pub fn validate(&self) -> Vec<SubgraphManifestValidationError> {
+        let mut errors = vec![];
+        if !supports(SUPPORTED_SPEC_VERSIONS, &self.spec_version) {
+            errors.push(SubgraphManifestValidationError {
+                path: String::from("specVersion"),
+                message: format!(
+                    "Spec version `{}` is not supported, supported versions are `{}`",
+                    self.spec_version, SUPPORTED_SPEC_VERSIONS
+                ),
+            });
+        }
+
+        let entity_types: HashSet<&str> = self
+            .schema
+            .document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                schema::Definition::TypeDefinition(schema::TypeDefinition::Object(object_type)) => {
+                    Some(object_type.name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (i, data_source) in self.data_sources.iter().enumerate() {
+            validate_mapping(
+                &format!("dataSources[{}]", i),
+                &data_source.source.abi,
+                &data_source.mapping,
+                &entity_types,
+                &mut errors,
+            );
+        }
+        for (i, template) in self.templates.iter().enumerate() {
+            validate_mapping(
+                &format!("templates[{}]", i),
+                &template.source.abi,
+                &template.mapping,
+                &entity_types,
+                &mut errors,
+            );
+        }
+        errors
+    }
+}
+
+/// Checks the mapping of a data source or template whose main contract has
+/// the ABI named `abi_name`.
+fn validate_mapping(
+    path: &str,
+    abi_name: &str,
+    mapping: &Mapping,
+    entity_types: &HashSet<&str>,
+    errors: &mut Vec<SubgraphManifestValidationError>,
+) {
+    let mut error = |field: String, message: String| {
+        errors.push(SubgraphManifestValidationError {
+            path: format!("{}.{}", path, field),
+            message,
+        })
+    };
+
+    if !supports(SUPPORTED_API_VERSIONS, &mapping.api_version) {
+        error(
+            String::from("mapping.apiVersion"),
+            format!(
+                "API version `{}` is not supported, supported versions are `{}`",
+                mapping.api_version, SUPPORTED_API_VERSIONS
+            ),
+        );
+    }
+
+    match mapping.abis.iter().find(|abi| abi.name == abi_name) {
+        None => error(
+            String::from("source.abi"),
+            format!(
+                "ABI `{}` is not one of the ABIs in `mapping.abis`: {}",
+                abi_name,
+                list(mapping.abis.iter().map(|abi| abi.name.clone()))
+            ),
+        ),
+        Some(abi) => {
+            for (i, handler) in mapping.event_handlers.iter().enumerate() {
+                if contract_event_with_signature(&abi.contract, &handler.event).is_none() {
+                    error(
+                        format!("mapping.eventHandlers[{}].event", i),
+                        format!(
+                            "Event `{}` is not in ABI `{}`, which has the events: {}",
+                            handler.event,
+                            abi_name,
+                            list(abi.contract.events().map(event_signature))
+                        ),
+                    );
+                }
+            }
+            for (i, handler) in mapping.call_handlers.iter().enumerate() {
+                if contract_function_with_signature(&abi.contract, &handler.function).is_none() {
+                    error(
+                        format!("mapping.callHandlers[{}].function", i),
+                        format!(
+                            "Function `{}` is not in ABI `{}`, which has the functions: {}",
+                            handler.function,
+                            abi_name,
+                            list(abi.contract.functions().map(function_signature))
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // Handlers must be functions exported by the WASM module
+    let exports: HashSet<&str> = mapping
+        .runtime
+        .export_section()
+        .map(|section| {
+            section
+                .entries()
+                .iter()
+                .filter(|export| match export.internal() {
+                    Internal::Function(_) => true,
+                    _ => false,
+                })
+                .map(|export| export.field())
+                .collect()
+        })
+        .unwrap_or_default();
+    let handlers = mapping
+        .event_handlers
+        .iter()
+        .enumerate()
+        .map(|(i, handler)| (format!("eventHandlers[{}]", i), &handler.handler))
+        .chain(
+            mapping
+                .call_handlers
+                .iter()
+                .enumerate()
+                .map(|(i, handler)| (format!("callHandlers[{}]", i), &handler.handler)),
+        )
+        .chain(
+            mapping
+                .block_handlers
+                .iter()
+                .enumerate()
+                .map(|(i, handler)| (format!("blockHandlers[{}]", i), &handler.handler)),
+        );
+    for (handler_path, handler) in handlers {
+        if !exports.contains(handler.as_str()) {
+            error(
+                format!("mapping.{}.handler", handler_path),
+                format!("Handler `{}` is not exported by the mapping", handler),
+            );
+        }
+    }
+
+    for (i, entity) in mapping.entities.iter().enumerate() {
+        if !entity_types.contains(entity.as_str()) {
+            error(
+                format!("mapping.entities[{}]", i),
+                format!("Entity type `{}` is not defined in the schema", entity),
+            );
+        }
+    }
+}
+
+/// Checks whether `version` is in the range of `supported` versions.
+fn supports(supported: &str, version: &Version) -> bool {
+    VersionReq::parse(supported)
+        .expect("Invalid range of supported versions")
+        .matches(version)
+}
+
+/// Formats names for an error message.
+fn list<I: Iterator<Item = String>>(names: I) -> String {
+    let names: Vec<_> = names.map(|name| format!("`{}`", name)).collect();
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join(", ")
     }
 }
 
@@ -254,6 +670,7 @@ impl UnresolvedSubgraphManifest {
             spec_version,
             schema,
             data_sources,
+            templates,
         } = self;
 
         // resolve each data set
@@ -262,13 +679,186 @@ impl UnresolvedSubgraphManifest {
                 .into_iter()
                 .map(|data_set| data_set.resolve(resolver)),
         ).collect()
-            .join(schema.resolve(resolver))
-            .map(|(data_sources, schema)| SubgraphManifest {
+            .join3(
+                stream::futures_ordered(
+                    templates
+                        .into_iter()
+                        .map(|template| template.resolve(resolver)),
+                ).collect(),
+                schema.resolve(resolver),
+            )
+            .map(|(data_sources, templates, schema)| SubgraphManifest {
                 id,
                 location,
                 spec_version,
                 schema,
                 data_sources,
+                templates,
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parity_wasm::builder;
+
+    const ABI: &str = r#"[
+        {
+            "type": "event",
+            "name": "Transfer",
+            "anonymous": false,
+            "inputs": [
+                {"name": "from", "type": "address", "indexed": true},
+                {"name": "to", "type": "address", "indexed": true},
+                {"name": "value", "type": "uint256", "indexed": false}
+            ]
+        },
+        {
+            "type": "function",
+            "name": "transfer",
+            "constant": false,
+            "inputs": [
+                {"name": "to", "type": "address"},
+                {"name": "value", "type": "uint256"}
+            ],
+            "outputs": [{"name": "", "type": "bool"}]
+        }
+    ]"#;
+
+    /// A mapping for the `Token` ABI whose WASM module exports `handleTransfer`.
+    fn mapping(event: &str, handler: &str, entities: Vec<&str>) -> Mapping {
+        Mapping {
+            kind: String::from("ethereum/events"),
+            api_version: Version::new(0, 0, 1),
+            language: String::from("wasm/assemblyscript"),
+            entities: entities.into_iter().map(String::from).collect(),
+            abis: vec![MappingABI {
+                name: String::from("Token"),
+                contract: Contract::load(ABI.as_bytes()).unwrap(),
+            }],
+            event_handlers: vec![MappingEventHandler {
+                event: String::from(event),
+                handler: String::from(handler),
+            }],
+            call_handlers: vec![],
+            block_handlers: vec![],
+            runtime: builder::module()
+                .function()
+                .signature()
+                .build()
+                .body()
+                .build()
+                .build()
+                .export()
+                .field("handleTransfer")
+                .internal()
+                .func(0)
+                .build()
+                .build(),
+            runtime_hash: H256::zero(),
+        }
+    }
+
+    fn manifest(data_source_abi: &str, template_mapping: Mapping) -> SubgraphManifest {
+        SubgraphManifest {
+            id: String::from("QmManifest"),
+            location: String::from("/ipfs/QmManifest"),
+            spec_version: Version::new(0, 0, 1),
+            schema: Schema {
+                id: String::from("QmSchema"),
+                document: graphql_parser::parse_schema("type Transfer { id: ID! }").unwrap(),
+            },
+            data_sources: vec![DataSource {
+                kind: String::from("ethereum/contract"),
+                network: None,
+                name: String::from("Token"),
+                source: Source {
+                    address: String::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
+                    abi: String::from(data_source_abi),
+                    start_block: 0,
+                },
+                mapping: mapping(
+                    "Transfer(address,address,uint256)",
+                    "handleTransfer",
+                    vec!["Transfer"],
+                ),
+            }],
+            templates: vec![DataSourceTemplate {
+                kind: String::from("ethereum/contract"),
+                network: None,
+                name: String::from("Template"),
+                source: TemplateSource {
+                    abi: String::from("Token"),
+                },
+                mapping: template_mapping,
+            }],
+        }
+    }
+
+    #[test]
+    fn valid_manifest() {
+        let template_mapping = mapping(
+            "Transfer(address,address,uint256)",
+            "handleTransfer",
+            vec!["Transfer"],
+        );
+        assert_eq!(manifest("Token", template_mapping).validate(), vec![]);
+    }
+
+    #[test]
+    fn report_all_problems_with_their_paths() {
+        let template_mapping = mapping(
+            "Transfer(address,uint256)",
+            "handleTransferred",
+            vec!["Transfer", "Approval"],
+        );
+        let paths: Vec<_> = manifest("Missing", template_mapping)
+            .validate()
+            .into_iter()
+            .map(|error| error.path)
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "dataSources[0].source.abi",
+                "templates[0].mapping.eventHandlers[0].event",
+                "templates[0].mapping.eventHandlers[0].handler",
+                "templates[0].mapping.entities[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_subgraph_names() {
+        for name in &["ens", "graphprotocol/ens", "a-b_c/D9"] {
+            assert_eq!(SubgraphName::new(*name).unwrap().as_str(), *name);
+        }
+        for name in &["", "/ens", "ens/", "graphprotocol//ens", "ens.eth", "ens name"] {
+            assert!(SubgraphName::new(*name).is_err(), "accepted `{}`", name);
+        }
+    }
+
+    #[test]
+    fn reject_unsupported_versions() {
+        let mut template_mapping = mapping(
+            "Transfer(address,address,uint256)",
+            "handleTransfer",
+            vec!["Transfer"],
+        );
+        template_mapping.api_version = Version::new(0, 1, 0);
+        let mut manifest = manifest("Token", template_mapping);
+        manifest.spec_version = Version::new(1, 0, 0);
+
+        let paths: Vec<_> = manifest
+            .validate()
+            .into_iter()
+            .map(|error| error.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["specVersion", "templates[0].mapping.apiVersion"]
+        );
+    }
+}
@@ -10,9 +10,15 @@ extern crate serde_derive;
 extern crate serde_yaml;
 #[macro_use]
 pub extern crate slog;
-extern crate failure;
+pub extern crate failure;
 extern crate ipfs_api;
+#[macro_use]
+extern crate lazy_static;
 extern crate parity_wasm;
+#[macro_use]
+extern crate prometheus;
+extern crate rand;
+pub extern crate semver;
 pub extern crate serde_json;
 extern crate slog_async;
 extern crate slog_term;
@@ -54,12 +60,13 @@ pub mod prelude {
     pub use components::schema::{SchemaProvider, SchemaProviderEvent};
     pub use components::server::GraphQLServer;
     pub use components::store::{
-        BasicStore, EventSource, Store, StoreEvent, StoreFilter, StoreKey, StoreOrder, StoreQuery,
-        StoreRange,
+        BasicStore, EntityOperation, EthereumCallCache, EventSource, Store, StoreEvent,
+        StoreFilter, StoreKey, StoreOrder, StoreQuery, StoreRange, SubgraphNameStore,
     };
     pub use components::subgraph::{
-        RuntimeHost, RuntimeHostBuilder, RuntimeHostEvent, RuntimeManager, SchemaEvent,
-        SubgraphProvider, SubgraphProviderEvent,
+        IndexingStatusProvider, RuntimeHost, RuntimeHostBuilder, RuntimeHostEvent,
+        RuntimeHostStatus, RuntimeManager, SchemaEvent, SubgraphError, SubgraphHealth,
+        SubgraphIndexingStatus, SubgraphProvider, SubgraphProviderEvent,
     };
     pub use components::{EventConsumer, EventProducer};
 
@@ -68,6 +75,9 @@ pub mod prelude {
     };
     pub use data::schema::Schema;
     pub use data::store::{Attribute, Entity, Value};
-    pub use data::subgraph::{DataSource, Link, SubgraphManifest, SubgraphManifestResolveError};
+    pub use data::subgraph::{
+        DataSource, Link, SubgraphDeployment, SubgraphManifest, SubgraphManifestResolveError,
+        SubgraphName,
+    };
     pub use util::stream::StreamError;
 }
@@ -0,0 +1,316 @@
+use hex;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tiny_keccak::keccak256;
+
+/// Content kept in memory, evicting the least recently used entries once
+/// the total size exceeds `max_size` bytes.
+struct MemoryCache {
+    entries: HashMap<String, Vec<u8>>,
+    recently_used: VecDeque<String>,
+    size: usize,
+    max_size: usize,
+}
+
+impl MemoryCache {
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        let content = self.entries.get(key)?.clone();
+        self.recently_used.retain(|k| k != key);
+        self.recently_used.push_back(key.to_owned());
+        Some(content)
+    }
+
+    fn insert(&mut self, key: &str, content: &[u8]) {
+        if content.len() > self.max_size || self.entries.contains_key(key) {
+            return;
+        }
+
+        while self.size + content.len() > self.max_size {
+            let evicted = match self.recently_used.pop_front() {
+                Some(evicted) => evicted,
+                None => break,
+            };
+            if let Some(evicted) = self.entries.remove(&evicted) {
+                self.size -= evicted.len();
+            }
+        }
+
+        self.size += content.len();
+        self.entries.insert(key.to_owned(), content.to_vec());
+        self.recently_used.push_back(key.to_owned());
+    }
+}
+
+/// Length of the checksum that precedes the content in every file.
+const CHECKSUM_LEN: usize = 32;
+
+/// Sizes of the files in the cache directory, so that making room for new
+/// content doesn't require reading the directory.
+#[derive(Default)]
+struct DiskIndex {
+    sizes: HashMap<String, u64>,
+    oldest_first: VecDeque<String>,
+    size: u64,
+}
+
+impl DiskIndex {
+    /// Indexes the files that are in `dir` already, oldest first. Leftover
+    /// temporary files are removed.
+    fn load(dir: &Path) -> io::Result<Self> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tmp") {
+                fs::remove_file(entry.path()).ok();
+            } else {
+                files.push((metadata.modified()?, metadata.len(), name));
+            }
+        }
+        files.sort();
+
+        let mut index = DiskIndex::default();
+        for (_, len, name) in files {
+            index.add(name, len);
+        }
+        Ok(index)
+    }
+
+    fn add(&mut self, name: String, len: u64) {
+        self.size += len;
+        self.sizes.insert(name.clone(), len);
+        self.oldest_first.push_back(name);
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(len) = self.sizes.remove(name) {
+            self.size -= len;
+            self.oldest_first.retain(|n| n != name);
+        }
+    }
+}
+
+/// Content stored in files in a directory, evicting the oldest files once
+/// their total size exceeds `max_size` bytes.
+///
+/// Every file starts with the Keccak-256 hash of its content, so that files
+/// that were corrupted on disk are detected and removed instead of being
+/// passed on as content.
+struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<DiskIndex>,
+}
+
+impl DiskCache {
+    fn new(dir: PathBuf, max_size: u64) -> Self {
+        let index = DiskIndex::load(&dir).unwrap_or_default();
+        DiskCache {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+        }
+    }
+
+    /// Keys may contain slashes and be arbitrarily long, e.g. paths inside
+    /// IPFS directories, so file names are derived from their hashes.
+    fn file_name(key: &str) -> String {
+        hex::encode(keccak256(key.as_bytes()))
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let name = Self::file_name(key);
+        let path = self.dir.join(&name);
+        let mut data = fs::read(&path).ok()?;
+
+        if data.len() < CHECKSUM_LEN || keccak256(&data[CHECKSUM_LEN..]) != data[..CHECKSUM_LEN] {
+            fs::remove_file(&path).ok();
+            self.index.lock().unwrap().remove(&name);
+            return None;
+        }
+
+        Some(data.split_off(CHECKSUM_LEN))
+    }
+
+    fn insert(&self, key: &str, content: &[u8]) -> io::Result<()> {
+        let len = (CHECKSUM_LEN + content.len()) as u64;
+        if len > self.max_size {
+            return Ok(());
+        }
+
+        let name = Self::file_name(key);
+        let mut index = self.index.lock().unwrap();
+        if index.sizes.contains_key(&name) {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        self.evict(&mut index, self.max_size - len)?;
+
+        // Write to a temporary file first so that readers never see a
+        // partially written file
+        let path = self.dir.join(&name);
+        let tmp_path = path.with_extension("tmp");
+        let mut data = keccak256(content).to_vec();
+        data.extend_from_slice(content);
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &path)?;
+
+        index.add(name, len);
+        Ok(())
+    }
+
+    /// Removes the oldest files until the remaining ones take up at most
+    /// `max_size` bytes.
+    fn evict(&self, index: &mut DiskIndex, max_size: u64) -> io::Result<()> {
+        while index.size > max_size {
+            let name = match index.oldest_first.pop_front() {
+                Some(name) => name,
+                None => break,
+            };
+            if let Some(len) = index.sizes.remove(&name) {
+                index.size -= len;
+            }
+            if let Err(e) = fs::remove_file(self.dir.join(&name)) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A bounded cache for immutable content, such as files on IPFS, keyed by
+/// the hash of the content.
+///
+/// Content is kept in memory and, if a directory is configured, on disk, so
+/// that it survives restarts of the node. Since content never changes for a
+/// given key, entries never need to be invalidated.
+pub struct ContentCache {
+    memory: Mutex<MemoryCache>,
+    disk: Option<DiskCache>,
+}
+
+impl ContentCache {
+    /// Creates a cache that keeps up to `max_memory_size` bytes in memory.
+    pub fn new(max_memory_size: usize) -> Self {
+        ContentCache {
+            memory: Mutex::new(MemoryCache {
+                entries: HashMap::new(),
+                recently_used: VecDeque::new(),
+                size: 0,
+                max_size: max_memory_size,
+            }),
+            disk: None,
+        }
+    }
+
+    /// Additionally stores up to `max_size` bytes of content in `dir`. The
+    /// files already in `dir` are indexed right away.
+    pub fn with_disk_cache<P: Into<PathBuf>>(self, dir: P, max_size: u64) -> Self {
+        ContentCache {
+            disk: Some(DiskCache::new(dir.into(), max_size)),
+            ..self
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if let Some(content) = self.memory.lock().unwrap().get(key) {
+            return Some(content);
+        }
+
+        let content = self.disk.as_ref().and_then(|disk| disk.get(key))?;
+        self.memory.lock().unwrap().insert(key, &content);
+        Some(content)
+    }
+
+    /// Adds content to the cache. Failing to write it to disk is not an
+    /// error, since the content can always be fetched again.
+    pub fn insert(&self, key: &str, content: &[u8]) {
+        self.memory.lock().unwrap().insert(key, content);
+        if let Some(ref disk) = self.disk {
+            disk.insert(key, content).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn evict_least_recently_used_content_from_memory() {
+        let cache = ContentCache::new(10);
+        cache.insert("a", b"aaaa");
+        cache.insert("b", b"bbbb");
+        assert_eq!(cache.get("a"), Some(b"aaaa".to_vec()));
+
+        // Makes room by evicting `b`, which was used less recently than `a`
+        cache.insert("c", b"cccc");
+        assert_eq!(cache.get("a"), Some(b"aaaa".to_vec()));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(b"cccc".to_vec()));
+
+        // Content that doesn't fit is not cached at all
+        cache.insert("d", b"ddddddddddd");
+        assert_eq!(cache.get("d"), None);
+        assert_eq!(cache.get("a"), Some(b"aaaa".to_vec()));
+    }
+
+    #[test]
+    fn keep_content_on_disk() {
+        let dir = env::temp_dir().join(format!("graph-content-cache-{}", process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        // Every file takes up 36 bytes: a 32 byte checksum and the content
+        let cache = ContentCache::new(0).with_disk_cache(&dir, 100);
+        cache.insert("QmA/file.json", b"aaaa");
+        cache.insert("QmB", b"bbbb");
+        cache.insert("QmC", b"cccc");
+
+        // A new cache on the same directory finds the content, except for
+        // the oldest entry, which was evicted to make room for the newest
+        let cache = ContentCache::new(0).with_disk_cache(&dir, 100);
+        let remaining: Vec<_> = ["QmA/file.json", "QmB", "QmC"]
+            .iter()
+            .filter_map(|key| cache.get(key))
+            .collect();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(cache.get("QmC"), Some(b"cccc".to_vec()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_corrupted_files_on_disk() {
+        let dir = env::temp_dir().join(format!("graph-corrupted-cache-{}", process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        // Keys too long to be used as file names are fine
+        let key = format!("QmA/{}", "a".repeat(300));
+        let cache = ContentCache::new(0).with_disk_cache(&dir, 100);
+        cache.insert(&key, b"aaaa");
+        assert_eq!(cache.get(&key), Some(b"aaaa".to_vec()));
+
+        let path = dir.join(DiskCache::file_name(&key));
+        let mut data = fs::read(&path).unwrap();
+        data[CHECKSUM_LEN] = b'b';
+        fs::write(&path, data).unwrap();
+
+        // The corrupted file is removed rather than passed on
+        assert_eq!(cache.get(&key), None);
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
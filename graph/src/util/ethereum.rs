@@ -1,4 +1,5 @@
-use ethabi::{Contract, Event};
+use ethabi::param_type::Writer;
+use ethabi::{Contract, Event, Function};
 use ethereum_types::H256;
 use tiny_keccak::Keccak;
 
@@ -23,3 +24,52 @@ pub fn contract_event_with_signature<'a>(
         .filter(|event| event.signature() == string_to_h256(signature))
         .next()
 }
+
+/// Returns the canonical signature of a contract event, e.g.
+/// `Transfer(address,address,uint256)`.
+pub fn event_signature(event: &Event) -> String {
+    format!(
+        "{}({})",
+        event.name,
+        event
+            .inputs
+            .iter()
+            .map(|param| Writer::write(&param.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Returns the canonical signature of a contract function, e.g.
+/// `transfer(address,uint256)`.
+pub fn function_signature(function: &Function) -> String {
+    format!(
+        "{}({})",
+        function.name,
+        function
+            .inputs
+            .iter()
+            .map(|param| Writer::write(&param.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Returns the 4-byte selector that identifies calls to the function with
+/// the given signature.
+pub fn function_selector(signature: &str) -> [u8; 4] {
+    let mut selector = [0u8; 4];
+    selector.copy_from_slice(&string_to_h256(signature)[..4]);
+    selector
+}
+
+/// Returns the contract function with the given signature, if it exists.
+pub fn contract_function_with_signature<'a>(
+    contract: &'a Contract,
+    signature: &str,
+) -> Option<&'a Function> {
+    let signature = signature.replace(" ", "");
+    contract
+        .functions()
+        .find(|function| function_signature(function) == signature)
+}
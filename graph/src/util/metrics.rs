@@ -0,0 +1,108 @@
+//! Metrics of the node in the Prometheus format. All metrics are registered
+//! with the default registry when they are first used; `gather` encodes them
+//! for the `/metrics` endpoint.
+
+use prometheus::{
+    self, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+lazy_static! {
+    /// Number of GraphQL queries that were executed.
+    pub static ref QUERY_EXECUTIONS: IntCounter = register_int_counter!(
+        "query_executions_total",
+        "Number of GraphQL queries that were executed"
+    ).unwrap();
+
+    /// Time it took to execute GraphQL queries.
+    pub static ref QUERY_EXECUTION_TIME: Histogram = register_histogram!(
+        "query_execution_duration_seconds",
+        "Time it took to execute GraphQL queries"
+    ).unwrap();
+
+    /// Number of errors returned for GraphQL queries, by kind of error.
+    pub static ref QUERY_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "query_errors_total",
+        "Number of errors returned for GraphQL queries",
+        &["kind"]
+    ).unwrap();
+
+    /// Time it took to run store operations, by operation (`get`, `set` or
+    /// `find`).
+    pub static ref STORE_OPERATION_TIME: HistogramVec = register_histogram_vec!(
+        "store_operation_duration_seconds",
+        "Time it took to run store operations",
+        &["operation"]
+    ).unwrap();
+
+    /// Time it took to run mapping handlers, by subgraph and handler.
+    pub static ref HANDLER_EXECUTION_TIME: HistogramVec = register_histogram_vec!(
+        "handler_execution_duration_seconds",
+        "Time it took to run mapping handlers",
+        &["subgraph", "handler"]
+    ).unwrap();
+
+    /// Number of contract calls made by mappings through `ethereum.call`.
+    pub static ref ETHEREUM_CALLS: IntCounter = register_int_counter!(
+        "ethereum_calls_total",
+        "Number of contract calls made by mappings"
+    ).unwrap();
+
+    /// Time it took to answer contract calls made by mappings.
+    pub static ref ETHEREUM_CALL_TIME: Histogram = register_histogram!(
+        "ethereum_call_duration_seconds",
+        "Time it took to answer contract calls made by mappings"
+    ).unwrap();
+
+    /// Number of failed requests to Ethereum providers, by provider.
+    pub static ref ETHEREUM_RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ethereum_rpc_errors_total",
+        "Number of failed requests to Ethereum providers",
+        &["provider"]
+    ).unwrap();
+
+    /// Number of blocks between the chain head and the latest block a
+    /// subgraph has been indexed up to, by subgraph.
+    pub static ref BLOCKS_BEHIND_HEAD: IntGaugeVec = register_int_gauge_vec!(
+        "subgraph_blocks_behind_head",
+        "Number of blocks a subgraph is behind the chain head",
+        &["subgraph"]
+    ).unwrap();
+
+    /// Number of events that wait in the input channels of components for
+    /// the components to take them, by pipe.
+    pub static ref FORWARD_BACKLOG: IntGaugeVec = register_int_gauge_vec!(
+        "forward_backlog_events",
+        "Number of events waiting to be taken by consumers",
+        &["pipe"]
+    ).unwrap();
+}
+
+/// Encodes all metrics in the Prometheus text format. Returns the encoded
+/// metrics along with their content type.
+pub fn gather() -> (Vec<u8>, String) {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+    (buffer, encoder.format_type().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gather_encodes_used_metrics() {
+        QUERY_ERRORS.with_label_values(&["parse"]).inc();
+        STORE_OPERATION_TIME
+            .with_label_values(&["get"])
+            .observe(0.5);
+
+        let (metrics, content_type) = gather();
+        let metrics = String::from_utf8(metrics).unwrap();
+        assert!(content_type.starts_with("text/plain"));
+        assert!(metrics.contains("query_errors_total{kind=\"parse\"}"));
+        assert!(metrics.contains("store_operation_duration_seconds_count{operation=\"get\"} 1"));
+    }
+}
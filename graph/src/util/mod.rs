@@ -6,3 +6,12 @@ pub mod log;
 
 /// Utils for working with ethereum data types
 pub mod ethereum;
+
+/// Retrying operations that fail with transient errors.
+pub mod retry;
+
+/// Bounded caches for immutable content.
+pub mod cache;
+
+/// Prometheus metrics of the node.
+pub mod metrics;
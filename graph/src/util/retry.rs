@@ -0,0 +1,471 @@
+use futures::prelude::*;
+use futures::sync::oneshot;
+use rand::{self, Rng};
+use slog::Logger;
+use std::cmp::{self, Ordering};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use web3::error::{Error as Web3Error, ErrorKind as Web3ErrorKind};
+
+/// Errors that know whether the operation that failed with them may succeed
+/// when it is retried.
+pub trait TransientError {
+    /// Returns `true` if the failure is temporary, e.g. because a remote
+    /// service could not be reached, and `false` if retrying is pointless.
+    fn is_transient(&self) -> bool;
+}
+
+impl TransientError for Web3Error {
+    fn is_transient(&self) -> bool {
+        match *self.kind() {
+            Web3ErrorKind::Unreachable | Web3ErrorKind::Transport(_) | Web3ErrorKind::Io(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// How often and how long to wait before retrying a failed operation.
+///
+/// The delay doubles after every failed attempt, up to `max_delay`. A random
+/// jitter of up to half the delay is subtracted, so that clients that failed
+/// at the same time don't all retry at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+    /// Maximum number of attempts, including the first one; `None` retries
+    /// transient failures forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the default policy, limited to `max_attempts` attempts.
+    pub fn limited(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: Some(max_attempts),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns whether an operation may be attempted again after failing
+    /// `attempts` times.
+    fn allows_retry(&self, attempts: u32) -> bool {
+        self.max_attempts.map_or(true, |max| attempts < max)
+    }
+
+    /// Returns how long to wait after the operation failed `attempts` times.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = cmp::min(attempts.saturating_sub(1), 16);
+        let delay = cmp::min(self.initial_delay * (1 << exponent), self.max_delay);
+
+        let delay_ms = millis(delay);
+        let jitter_ms = rand::thread_rng().gen_range(0, delay_ms / 2 + 1);
+
+        Duration::from_millis(delay_ms - jitter_ms)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+/// A deadline of a `Sleep`, ordered so that the earliest deadline is the
+/// greatest, i.e. at the top of a `BinaryHeap`.
+struct Deadline {
+    at: Instant,
+    sender: oneshot::Sender<()>,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+/// The deadlines of all pending `Sleep`s, which are measured by a single
+/// timer thread.
+struct Timer {
+    deadlines: Mutex<BinaryHeap<Deadline>>,
+    changed: Condvar,
+}
+
+lazy_static! {
+    static ref TIMER: Arc<Timer> = Timer::start();
+}
+
+impl Timer {
+    /// Starts the timer thread; it runs for as long as the process does.
+    fn start() -> Arc<Timer> {
+        let timer = Arc::new(Timer {
+            deadlines: Mutex::new(BinaryHeap::new()),
+            changed: Condvar::new(),
+        });
+        let thread_timer = timer.clone();
+        thread::Builder::new()
+            .name("timer".into())
+            .spawn(move || thread_timer.run())
+            .expect("failed to spawn timer thread");
+        timer
+    }
+
+    /// Returns a receiver that is notified once `at` has passed.
+    fn notify_at(&self, at: Instant) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let earliest = deadlines.peek().map_or(true, |deadline| at < deadline.at);
+        deadlines.push(Deadline { at, sender });
+        if earliest {
+            self.changed.notify_one();
+        }
+        receiver
+    }
+
+    fn run(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let mut purged_len = 0;
+        loop {
+            let now = Instant::now();
+            while deadlines
+                .peek()
+                .map_or(false, |deadline| deadline.at <= now)
+            {
+                // The `Sleep` may have been dropped already, e.g. because the
+                // future it was a timeout for completed
+                deadlines.pop().unwrap().sender.send(()).ok();
+            }
+
+            // Whenever the number of deadlines has doubled, drop the ones
+            // nobody waits for anymore, so that the timeouts of futures that
+            // completed long ago don't pile up until they expire
+            if deadlines.len() > 2 * purged_len {
+                let pending = deadlines
+                    .drain()
+                    .filter(|deadline| !deadline.sender.is_canceled())
+                    .collect();
+                *deadlines = pending;
+                purged_len = deadlines.len();
+            }
+
+            deadlines = match deadlines.peek().map(|deadline| deadline.at - now) {
+                Some(wait) => self.changed.wait_timeout(deadlines, wait).unwrap().0,
+                None => self.changed.wait(deadlines).unwrap(),
+            };
+        }
+    }
+}
+
+/// A future that resolves after a delay.
+///
+/// All delays are measured by a single timer thread, so unlike
+/// `tokio::timer` this works on any executor, including the WASM workers.
+struct Sleep(oneshot::Receiver<()>);
+
+impl Sleep {
+    fn new(duration: Duration) -> Self {
+        Sleep(TIMER.notify_at(Instant::now() + duration))
+    }
+}
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+/// Error of a future that was given a limited amount of time to complete.
+#[derive(Debug)]
+pub enum TimeoutError<E> {
+    /// The future did not complete in time.
+    Elapsed(Duration),
+    /// The future failed in time.
+    Inner(E),
+}
+
+impl<E: fmt::Display> fmt::Display for TimeoutError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutError::Elapsed(duration) => {
+                write!(f, "Timed out after {}ms", millis(*duration))
+            }
+            TimeoutError::Inner(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: TransientError> TransientError for TimeoutError<E> {
+    /// Operations that timed out may well succeed when they are retried.
+    fn is_transient(&self) -> bool {
+        match self {
+            TimeoutError::Elapsed(_) => true,
+            TimeoutError::Inner(e) => e.is_transient(),
+        }
+    }
+}
+
+/// Future returned by `timeout`.
+pub struct Timeout<F> {
+    future: F,
+    duration: Duration,
+    sleep: Sleep,
+}
+
+/// Fails with `TimeoutError::Elapsed` if `future` doesn't complete within
+/// `duration`. Like `retry`, this works on any executor.
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        duration,
+        sleep: Sleep::new(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<F::Item, TimeoutError<F::Error>> {
+        match self.future.poll().map_err(TimeoutError::Inner)? {
+            Async::Ready(item) => Ok(Async::Ready(item)),
+            Async::NotReady => match self.sleep.poll() {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                _ => Err(TimeoutError::Elapsed(self.duration)),
+            },
+        }
+    }
+}
+
+/// Stream returned by `idle_timeout`.
+pub struct IdleTimeout<S> {
+    stream: S,
+    duration: Duration,
+    sleep: Sleep,
+}
+
+/// Fails with `TimeoutError::Elapsed` if `stream` doesn't produce its next
+/// item or end within `duration`, so that streams of any length can be given
+/// a limit that only applies while they are stalled.
+pub fn idle_timeout<S: Stream>(stream: S, duration: Duration) -> IdleTimeout<S> {
+    IdleTimeout {
+        stream,
+        duration,
+        sleep: Sleep::new(duration),
+    }
+}
+
+impl<S: Stream> Stream for IdleTimeout<S> {
+    type Item = S::Item;
+    type Error = TimeoutError<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, TimeoutError<S::Error>> {
+        match self.stream.poll().map_err(TimeoutError::Inner)? {
+            Async::Ready(item) => {
+                self.sleep = Sleep::new(self.duration);
+                Ok(Async::Ready(item))
+            }
+            Async::NotReady => match self.sleep.poll() {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                _ => Err(TimeoutError::Elapsed(self.duration)),
+            },
+        }
+    }
+}
+
+enum RetryState<F> {
+    Running(F),
+    Waiting(Sleep),
+}
+
+/// Future returned by `retry`.
+pub struct Retry<F, R>
+where
+    R: IntoFuture,
+{
+    logger: Logger,
+    operation: String,
+    policy: RetryPolicy,
+    attempts: u32,
+    make_future: F,
+    state: RetryState<R::Future>,
+}
+
+/// Runs the future returned by `make_future`, running it again whenever it
+/// fails with a transient error, as long as `policy` allows.
+pub fn retry<F, R>(
+    logger: &Logger,
+    operation: &str,
+    policy: RetryPolicy,
+    mut make_future: F,
+) -> Retry<F, R>
+where
+    F: FnMut() -> R,
+    R: IntoFuture,
+    R::Error: TransientError + fmt::Display,
+{
+    let state = RetryState::Running(make_future().into_future());
+    Retry {
+        logger: logger.clone(),
+        operation: operation.to_owned(),
+        policy,
+        attempts: 0,
+        make_future,
+        state,
+    }
+}
+
+impl<F, R> Future for Retry<F, R>
+where
+    F: FnMut() -> R,
+    R: IntoFuture,
+    R::Error: TransientError + fmt::Display,
+{
+    type Item = R::Item;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<R::Item, R::Error> {
+        loop {
+            let next_state = match self.state {
+                RetryState::Running(ref mut future) => match future.poll() {
+                    Err(e) => {
+                        self.attempts += 1;
+                        if !e.is_transient() || !self.policy.allows_retry(self.attempts) {
+                            return Err(e);
+                        }
+
+                        let delay = self.policy.delay(self.attempts);
+                        warn!(self.logger, "Operation failed, retrying";
+                              "operation" => &self.operation,
+                              "attempt" => self.attempts,
+                              "retry_in_ms" => millis(delay),
+                              "error" => format!("{}", e));
+                        RetryState::Waiting(Sleep::new(delay))
+                    }
+                    result => return result,
+                },
+                RetryState::Waiting(ref mut sleep) => {
+                    if let Ok(Async::NotReady) = sleep.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                    RetryState::Running((self.make_future)().into_future())
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// Stream returned by `retry_stream`.
+pub struct RetryStream<F, S> {
+    logger: Logger,
+    operation: String,
+    policy: RetryPolicy,
+    attempts: u32,
+    make_stream: F,
+    stream: S,
+    sleep: Option<Sleep>,
+}
+
+/// Polls the stream returned by `make_stream`. If the stream fails with a
+/// transient error, it is replaced by a new stream from `make_stream`, as
+/// long as `policy` allows. The attempts are counted from the last item the
+/// stream produced.
+pub fn retry_stream<F, S>(
+    logger: &Logger,
+    operation: &str,
+    policy: RetryPolicy,
+    mut make_stream: F,
+) -> RetryStream<F, S>
+where
+    F: FnMut() -> S,
+    S: Stream,
+    S::Error: TransientError + fmt::Display,
+{
+    let stream = make_stream();
+    RetryStream {
+        logger: logger.clone(),
+        operation: operation.to_owned(),
+        policy,
+        attempts: 0,
+        make_stream,
+        stream,
+        sleep: None,
+    }
+}
+
+impl<F, S> Stream for RetryStream<F, S>
+where
+    F: FnMut() -> S,
+    S: Stream,
+    S::Error: TransientError + fmt::Display,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        loop {
+            if let Some(ref mut sleep) = self.sleep {
+                if let Ok(Async::NotReady) = sleep.poll() {
+                    return Ok(Async::NotReady);
+                }
+                self.stream = (self.make_stream)();
+            }
+            self.sleep = None;
+
+            match self.stream.poll() {
+                Err(e) => {
+                    self.attempts += 1;
+                    if !e.is_transient() || !self.policy.allows_retry(self.attempts) {
+                        return Err(e);
+                    }
+
+                    let delay = self.policy.delay(self.attempts);
+                    warn!(self.logger, "Stream failed, retrying";
+                          "operation" => &self.operation,
+                          "attempt" => self.attempts,
+                          "retry_in_ms" => millis(delay),
+                          "error" => format!("{}", e));
+                    self.sleep = Some(Sleep::new(delay));
+                }
+                Ok(Async::Ready(Some(item))) => {
+                    self.attempts = 0;
+                    return Ok(Async::Ready(Some(item)));
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,5 @@
+mod resolver;
+mod schema;
+
+pub use self::resolver::IndexingStatusResolver;
+pub use self::schema::indexing_status_schema;
@@ -0,0 +1,143 @@
+use graphql_parser::{query as q, schema as s};
+use std::cmp;
+use std::collections::HashMap;
+
+use graph::prelude::*;
+
+use prelude::*;
+
+fn object_field<'a>(object: &'a Option<q::Value>, field: &str) -> Option<&'a q::Value> {
+    object
+        .as_ref()
+        .and_then(|object| match object {
+            q::Value::Object(ref data) => Some(data),
+            _ => None,
+        })
+        .and_then(|data| data.get(field))
+}
+
+/// GraphQL `Int`s are 32 bit wide; larger numbers are capped.
+fn int_value(n: u64) -> q::Value {
+    q::Value::Int(q::Number::from(cmp::min(n, i32::max_value() as u64) as i32))
+}
+
+fn optional_int_value(n: Option<u64>) -> q::Value {
+    n.map(int_value).unwrap_or(q::Value::Null)
+}
+
+fn error_object(error: &SubgraphError) -> q::Value {
+    object_value(vec![
+        ("message", q::Value::String(error.message.clone())),
+        ("blockNumber", optional_int_value(error.block_number)),
+        (
+            "handler",
+            error
+                .handler
+                .clone()
+                .map(q::Value::String)
+                .unwrap_or(q::Value::Null),
+        ),
+    ])
+}
+
+fn status_object(status: &SubgraphIndexingStatus) -> q::Value {
+    object_value(vec![
+        ("subgraph", q::Value::String(status.subgraph.clone())),
+        ("synced", q::Value::Boolean(status.synced)),
+        (
+            "health",
+            q::Value::String(status.health.as_str().to_owned()),
+        ),
+        (
+            "fatalError",
+            status
+                .fatal_error
+                .as_ref()
+                .map(error_object)
+                .unwrap_or(q::Value::Null),
+        ),
+        (
+            "nonFatalErrors",
+            q::Value::List(status.non_fatal_errors.iter().map(error_object).collect()),
+        ),
+        (
+            "chainHeadBlock",
+            optional_int_value(status.chain_head_block),
+        ),
+        ("latestBlock", optional_int_value(status.latest_block)),
+        ("entityCount", int_value(status.entity_count)),
+    ])
+}
+
+/// A resolver for the indexing status API. It answers queries from the
+/// statuses that were collected when the query was received.
+#[derive(Clone)]
+pub struct IndexingStatusResolver {
+    statuses: Vec<SubgraphIndexingStatus>,
+}
+
+impl IndexingStatusResolver {
+    pub fn new(statuses: Vec<SubgraphIndexingStatus>) -> Self {
+        IndexingStatusResolver { statuses }
+    }
+
+    /// Returns the statuses of the subgraphs listed in the `subgraphs`
+    /// argument, or of all subgraphs if the argument is missing.
+    fn indexing_statuses(&self, arguments: &HashMap<&q::Name, q::Value>) -> q::Value {
+        let subgraphs = match arguments.get(&String::from("subgraphs")) {
+            Some(q::Value::List(subgraphs)) => Some(
+                subgraphs
+                    .iter()
+                    .filter_map(|subgraph| match subgraph {
+                        q::Value::String(subgraph) => Some(subgraph),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        };
+
+        q::Value::List(
+            self.statuses
+                .iter()
+                .filter(|status| {
+                    subgraphs
+                        .as_ref()
+                        .map_or(true, |subgraphs| subgraphs.contains(&&status.subgraph))
+                })
+                .map(status_object)
+                .collect(),
+        )
+    }
+}
+
+impl Resolver for IndexingStatusResolver {
+    fn resolve_objects(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Name,
+        _field_definition: &s::Field,
+        _object_type: &s::ObjectType,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> q::Value {
+        match field.as_str() {
+            "indexingStatuses" => self.indexing_statuses(arguments),
+            _ => object_field(parent, field.as_str())
+                .cloned()
+                .unwrap_or(q::Value::Null),
+        }
+    }
+
+    fn resolve_object(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Name,
+        _field_definition: &s::Field,
+        _object_type: &s::ObjectType,
+        _arguments: &HashMap<&q::Name, q::Value>,
+    ) -> q::Value {
+        object_field(parent, field.as_str())
+            .cloned()
+            .unwrap_or(q::Value::Null)
+    }
+}
@@ -0,0 +1,43 @@
+use graphql_parser;
+
+use graph::prelude::*;
+
+const INDEXING_STATUS_SCHEMA: &'static str = "
+scalar Boolean
+scalar Int
+scalar String
+
+type Query {
+  indexingStatuses(subgraphs: [String!]): [SubgraphIndexingStatus!]!
+}
+
+type SubgraphIndexingStatus {
+  subgraph: String!
+  synced: Boolean!
+  health: Health!
+  fatalError: SubgraphError
+  nonFatalErrors: [SubgraphError!]!
+  chainHeadBlock: Int
+  latestBlock: Int
+  entityCount: Int!
+}
+
+enum Health {
+  healthy
+  unhealthy
+  failed
+}
+
+type SubgraphError {
+  message: String!
+  blockNumber: Int
+  handler: String
+}";
+
+/// The schema of the API that reports how far subgraphs have been indexed.
+pub fn indexing_status_schema() -> Schema {
+    Schema {
+        id: String::from("indexing-status"),
+        document: graphql_parser::parse_schema(INDEXING_STATUS_SCHEMA).unwrap(),
+    }
+}
@@ -13,6 +13,9 @@ pub mod introspection;
 /// Utilities for executing GraphQL queries and working with query ASTs.
 pub mod query;
 
+/// Utilities for reporting how far subgraphs have been indexed.
+pub mod indexing_status;
+
 /// Utilities for working with GraphQL values.
 mod values;
 
@@ -21,9 +24,10 @@ mod store;
 
 /// Prelude that exports the most important traits and types.
 pub mod prelude {
+    pub use super::indexing_status::{indexing_status_schema, IndexingStatusResolver};
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
     pub use super::query::{execute, ExecutionOptions, Resolver};
-    pub use super::schema::{api_schema, APISchemaError};
+    pub use super::schema::{api_schema, validate_schema, APISchemaError, SchemaValidationError};
     pub use super::store::{build_query, StoreResolver};
     pub use super::values::{object_value, MaybeCoercible, SerializableValue};
 }
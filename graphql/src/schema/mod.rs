@@ -4,4 +4,8 @@ pub mod api;
 /// Utilities for working with GraphQL schema ASTs.
 pub mod ast;
 
+/// Validation of subgraph schemas.
+pub mod validation;
+
 pub use self::api::{api_schema, APISchemaError};
+pub use self::validation::{validate_schema, SchemaValidationError};
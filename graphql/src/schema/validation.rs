@@ -0,0 +1,334 @@
+use graphql_parser::schema::*;
+use std::error::Error;
+use std::fmt;
+
+use schema::ast;
+
+/// Scalar types that are added to every API schema and can be used without
+/// being defined.
+const BUILTIN_SCALAR_TYPES: &[&str] =
+    &["Boolean", "ID", "Int", "Float", "String", "Bytes", "BigInt"];
+
+/// Types that are added to every API schema.
+const RESERVED_TYPE_NAMES: &[&str] = &["Query", "Mutation", "Subscription", "OrderDirection"];
+
+/// Suffixes of the types that are derived for every entity type.
+const RESERVED_TYPE_SUFFIXES: &[&str] = &["_filter", "_orderBy"];
+
+/// A violation of the rules that subgraph schemas have to follow.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaValidationError {
+    /// An entity type has no `id` field.
+    IdFieldMissing(String),
+    /// The `id` field of an entity type is not of type `ID!`.
+    IdFieldNotIdType(String),
+    /// A type name clashes with a type that is generated for the API schema.
+    ReservedTypeName(String),
+    /// A field refers to a type that is not defined.
+    UndefinedType {
+        type_name: String,
+        field: String,
+        referenced: String,
+    },
+    /// An object type implements an interface that is not defined.
+    UndefinedInterface {
+        type_name: String,
+        interface: String,
+    },
+    /// A `@derivedFrom` directive is not valid.
+    InvalidDerivedFrom {
+        type_name: String,
+        field: String,
+        reason: String,
+    },
+}
+
+impl Error for SchemaValidationError {
+    fn description(&self) -> &str {
+        "Schema validation error"
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+impl fmt::Display for SchemaValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaValidationError::IdFieldMissing(type_name) => write!(
+                f,
+                "Entity type \"{}\" is missing the field \"id: ID!\"",
+                type_name
+            ),
+            SchemaValidationError::IdFieldNotIdType(type_name) => {
+                write!(f, "Field \"{}.id\" must be of type \"ID!\"", type_name)
+            }
+            SchemaValidationError::ReservedTypeName(type_name) => write!(
+                f,
+                "Type name \"{}\" is reserved for the generated API schema",
+                type_name
+            ),
+            SchemaValidationError::UndefinedType {
+                type_name,
+                field,
+                referenced,
+            } => write!(
+                f,
+                "Field \"{}.{}\" refers to the undefined type \"{}\"",
+                type_name, field, referenced
+            ),
+            SchemaValidationError::UndefinedInterface {
+                type_name,
+                interface,
+            } => write!(
+                f,
+                "Type \"{}\" implements the undefined interface \"{}\"",
+                type_name, interface
+            ),
+            SchemaValidationError::InvalidDerivedFrom {
+                type_name,
+                field,
+                reason,
+            } => write!(
+                f,
+                "Invalid @derivedFrom on field \"{}.{}\": {}",
+                type_name, field, reason
+            ),
+        }
+    }
+}
+
+/// Validates a subgraph schema before an API schema is derived from it.
+///
+/// Returns all violations that were found, not just the first one.
+pub fn validate_schema(schema: &Document) -> Result<(), Vec<SchemaValidationError>> {
+    let mut errors = vec![];
+
+    for typedef in ast::get_type_definitions(schema) {
+        let name = ast::get_type_name(typedef);
+        if is_reserved_type_name(name) {
+            errors.push(SchemaValidationError::ReservedTypeName(name.clone()));
+        }
+    }
+
+    for object_type in ast::get_object_type_definitions(schema) {
+        validate_id_field(object_type, &mut errors);
+
+        for interface in object_type.implements_interfaces.iter() {
+            match ast::get_named_type(schema, interface) {
+                Some(TypeDefinition::Interface(_)) => (),
+                _ => errors.push(SchemaValidationError::UndefinedInterface {
+                    type_name: object_type.name.clone(),
+                    interface: interface.clone(),
+                }),
+            }
+        }
+
+        validate_fields(schema, &object_type.name, &object_type.fields, &mut errors);
+    }
+
+    for interface_type in ast::get_interface_type_definitions(schema) {
+        validate_fields(
+            schema,
+            &interface_type.name,
+            &interface_type.fields,
+            &mut errors,
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_reserved_type_name(name: &str) -> bool {
+    BUILTIN_SCALAR_TYPES.contains(&name)
+        || RESERVED_TYPE_NAMES.contains(&name)
+        || RESERVED_TYPE_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// Entity types need an `id: ID!` field to be stored and queried.
+fn validate_id_field(object_type: &ObjectType, errors: &mut Vec<SchemaValidationError>) {
+    match ast::get_field_type(object_type, &"id".to_string()) {
+        None => errors.push(SchemaValidationError::IdFieldMissing(
+            object_type.name.clone(),
+        )),
+        Some(field) => match field.field_type {
+            Type::NonNullType(ref t) if **t == Type::NamedType("ID".to_string()) => (),
+            _ => errors.push(SchemaValidationError::IdFieldNotIdType(
+                object_type.name.clone(),
+            )),
+        },
+    }
+}
+
+fn validate_fields(
+    schema: &Document,
+    type_name: &Name,
+    fields: &[Field],
+    errors: &mut Vec<SchemaValidationError>,
+) {
+    for field in fields {
+        let referenced = get_base_type_name(&field.field_type);
+        if !BUILTIN_SCALAR_TYPES.contains(&referenced.as_str())
+            && ast::get_named_type(schema, referenced).is_none()
+        {
+            errors.push(SchemaValidationError::UndefinedType {
+                type_name: type_name.clone(),
+                field: field.name.clone(),
+                referenced: referenced.clone(),
+            });
+            continue;
+        }
+
+        if let Err(reason) = validate_derived_from(schema, type_name, field) {
+            errors.push(SchemaValidationError::InvalidDerivedFrom {
+                type_name: type_name.clone(),
+                field: field.name.clone(),
+                reason,
+            });
+        }
+    }
+}
+
+/// Checks that the field named by `@derivedFrom(field: "...")` exists on the
+/// type of the derived field and refers back to the type that declares the
+/// derived field (or one of the interfaces it implements).
+fn validate_derived_from(schema: &Document, type_name: &Name, field: &Field) -> Result<(), String> {
+    let directive = match field
+        .directives
+        .iter()
+        .find(|directive| directive.name == "derivedFrom")
+    {
+        Some(directive) => directive,
+        None => return Ok(()),
+    };
+
+    let derived_from = match directive.arguments.iter().find(|(name, _)| name == "field") {
+        Some((_, Value::String(derived_from))) => derived_from,
+        _ => return Err("the argument \"field\" must be a string".to_owned()),
+    };
+
+    let target_name = get_base_type_name(&field.field_type);
+    let target_fields = match ast::get_named_type(schema, target_name) {
+        Some(TypeDefinition::Object(t)) => &t.fields,
+        Some(TypeDefinition::Interface(t)) => &t.fields,
+        _ => {
+            return Err(format!(
+                "type \"{}\" is not an entity type or interface",
+                target_name
+            ))
+        }
+    };
+
+    let target_field = target_fields
+        .iter()
+        .find(|target_field| &target_field.name == derived_from)
+        .ok_or_else(|| format!("type \"{}\" has no field \"{}\"", target_name, derived_from))?;
+
+    let referenced = get_base_type_name(&target_field.field_type);
+    let refers_back = referenced == type_name
+        || match ast::get_named_type(schema, type_name) {
+            Some(TypeDefinition::Object(t)) => t.implements_interfaces.contains(referenced),
+            _ => false,
+        };
+    if refers_back {
+        Ok(())
+    } else {
+        Err(format!(
+            "field \"{}.{}\" refers to \"{}\" instead of \"{}\"",
+            target_name, derived_from, referenced, type_name
+        ))
+    }
+}
+
+/// Returns the name of the named type inside list and non-null types.
+fn get_base_type_name(field_type: &Type) -> &Name {
+    match field_type {
+        Type::NamedType(name) => name,
+        Type::ListType(t) => get_base_type_name(t),
+        Type::NonNullType(t) => get_base_type_name(t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graphql_parser::schema::parse_schema;
+
+    use super::*;
+
+    fn validate(schema: &str) -> Result<(), Vec<SchemaValidationError>> {
+        validate_schema(&parse_schema(schema).expect("Failed to parse schema"))
+    }
+
+    #[test]
+    fn valid_schema() {
+        let result = validate(
+            "
+            interface Named { id: ID!, name: String! }
+            type Band implements Named {
+                id: ID!
+                name: String!
+                members: [Musician!]! @derivedFrom(field: \"bands\")
+                songs: [Song!]! @derivedFrom(field: \"band\")
+            }
+            type Musician implements Named { id: ID!, name: String!, bands: [Band!]! }
+            type Song { id: ID!, band: Band!, genre: Genre, price: BigInt }
+            enum Genre { rock, jazz }
+            ",
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn report_all_violations() {
+        let result = validate(
+            "
+            type Query { id: ID! }
+            type User_filter { id: ID! }
+            type Band {
+                name: String!
+                members: [Musician!]! @derivedFrom(field: \"groups\")
+                songs: [Song!]! @derivedFrom(field: \"title\")
+                label: Label
+            }
+            type Musician implements Artist { id: String!, bands: [Band!]! }
+            type Song { id: ID!, title: String! }
+            ",
+        );
+        assert_eq!(
+            result,
+            Err(vec![
+                SchemaValidationError::ReservedTypeName("Query".to_string()),
+                SchemaValidationError::ReservedTypeName("User_filter".to_string()),
+                SchemaValidationError::IdFieldMissing("Band".to_string()),
+                SchemaValidationError::InvalidDerivedFrom {
+                    type_name: "Band".to_string(),
+                    field: "members".to_string(),
+                    reason: "type \"Musician\" has no field \"groups\"".to_string(),
+                },
+                SchemaValidationError::InvalidDerivedFrom {
+                    type_name: "Band".to_string(),
+                    field: "songs".to_string(),
+                    reason: "field \"Song.title\" refers to \"String\" instead of \"Band\""
+                        .to_string(),
+                },
+                SchemaValidationError::UndefinedType {
+                    type_name: "Band".to_string(),
+                    field: "label".to_string(),
+                    referenced: "Label".to_string(),
+                },
+                SchemaValidationError::IdFieldNotIdType("Musician".to_string()),
+                SchemaValidationError::UndefinedInterface {
+                    type_name: "Musician".to_string(),
+                    interface: "Artist".to_string(),
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,107 @@
+extern crate futures;
+extern crate graph;
+extern crate graph_graphql;
+extern crate graphql_parser;
+
+use futures::sync::oneshot;
+use graphql_parser::query as q;
+use std::collections::BTreeMap;
+
+use graph::prelude::*;
+use graph_graphql::prelude::*;
+
+fn indexing_status_query(statuses: Vec<SubgraphIndexingStatus>, query: &str) -> QueryResult {
+    let (sender, _) = oneshot::channel();
+    let query = Query {
+        schema: indexing_status_schema(),
+        document: graphql_parser::parse_query(query).unwrap(),
+        variables: None,
+        result_sender: sender,
+    };
+
+    execute(
+        &query,
+        ExecutionOptions {
+            logger: slog::Logger::root(slog::Discard, o!()),
+            resolver: IndexingStatusResolver::new(statuses),
+        },
+    )
+}
+
+fn status(subgraph: &str, health: SubgraphHealth) -> SubgraphIndexingStatus {
+    SubgraphIndexingStatus {
+        subgraph: subgraph.to_owned(),
+        synced: false,
+        health,
+        fatal_error: None,
+        non_fatal_errors: vec![SubgraphError {
+            message: "abort".to_owned(),
+            block_number: Some(7),
+            handler: Some("handleTransfer".to_owned()),
+        }],
+        chain_head_block: Some(20),
+        latest_block: Some(10),
+        entity_count: 3,
+    }
+}
+
+fn object(data: Vec<(&str, q::Value)>) -> q::Value {
+    let mut map = BTreeMap::new();
+    for (key, value) in data {
+        map.insert(key.to_owned(), value);
+    }
+    q::Value::Object(map)
+}
+
+fn int(n: i32) -> q::Value {
+    q::Value::Int(q::Number::from(n))
+}
+
+#[test]
+fn query_indexing_statuses_of_selected_subgraphs() {
+    let result = indexing_status_query(
+        vec![
+            status("subgraph-a", SubgraphHealth::Unhealthy),
+            status("subgraph-b", SubgraphHealth::Healthy),
+        ],
+        "
+        {
+          indexingStatuses(subgraphs: [\"subgraph-a\"]) {
+            subgraph
+            synced
+            health
+            fatalError { message }
+            nonFatalErrors { message blockNumber handler }
+            chainHeadBlock
+            latestBlock
+            entityCount
+          }
+        }
+        ",
+    );
+
+    assert!(result.errors.is_none());
+    assert_eq!(
+        result.data,
+        Some(object(vec![(
+            "indexingStatuses",
+            q::Value::List(vec![object(vec![
+                ("subgraph", q::Value::String("subgraph-a".to_owned())),
+                ("synced", q::Value::Boolean(false)),
+                ("health", q::Value::Enum("unhealthy".to_owned())),
+                ("fatalError", q::Value::Null),
+                (
+                    "nonFatalErrors",
+                    q::Value::List(vec![object(vec![
+                        ("message", q::Value::String("abort".to_owned())),
+                        ("blockNumber", int(7)),
+                        ("handler", q::Value::String("handleTransfer".to_owned())),
+                    ])]),
+                ),
+                ("chainHeadBlock", int(20)),
+                ("latestBlock", int(10)),
+                ("entityCount", int(3)),
+            ])]),
+        )]))
+    );
+}
@@ -73,7 +73,9 @@ impl MockSchemaProvider {
 
             // Mock processing the event from the subgraph provider
             match event {
-                SchemaEvent::SchemaAdded(schema) | SchemaEvent::SchemaRemoved(schema) => {
+                SchemaEvent::SchemaAdded(schema)
+                | SchemaEvent::SchemaRemoved(schema)
+                | SchemaEvent::SchemaReplaced(_, schema) => {
                     SchemaProviderEvent::SchemaChanged(Some(schema))
                 }
             }
@@ -62,33 +62,44 @@ impl MockStore {
                 .unwrap();
         }
     }
-}
 
-impl BasicStore for MockStore {
-    fn get(&self, key: StoreKey) -> Result<Entity, ()> {
+    /// Returns the position of the user with the key's ID, if there is one.
+    fn position(&self, key: &StoreKey) -> Option<usize> {
         if key.entity == "User" {
-            self.entities
-                .iter()
-                .find(|entity| {
-                    let id = entity.get("id").unwrap();
-                    match id {
-                        &Value::String(ref s) => s == &key.id,
-                        _ => false,
-                    }
-                })
-                .map(|entity| entity.clone())
-                .ok_or(())
+            self.entities.iter().position(|entity| {
+                let id = entity.get("id").unwrap();
+                match id {
+                    &Value::String(ref s) => s == &key.id,
+                    _ => false,
+                }
+            })
         } else {
             unimplemented!()
         }
     }
+}
 
-    fn set(&mut self, _key: StoreKey, _entity: Entity, _source: EventSource) -> Result<(), ()> {
-        unimplemented!();
+impl BasicStore for MockStore {
+    fn get(&self, key: StoreKey) -> Result<Entity, ()> {
+        self.position(&key)
+            .map(|i| self.entities[i].clone())
+            .ok_or(())
     }
 
-    fn delete(&mut self, _key: StoreKey, _source: EventSource) -> Result<(), ()> {
-        unimplemented!();
+    fn set(&mut self, key: StoreKey, mut entity: Entity, _source: EventSource) -> Result<(), ()> {
+        entity.insert("id".to_string(), Value::String(key.id.clone()));
+        match self.position(&key) {
+            Some(i) => self.entities[i].merge(entity),
+            None => self.entities.push(entity),
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: StoreKey, _source: EventSource) -> Result<(), ()> {
+        if let Some(i) = self.position(&key) {
+            self.entities.remove(i);
+        }
+        Ok(())
     }
 
     fn find(&self, _query: StoreQuery) -> Result<Vec<Entity>, ()> {
@@ -115,6 +126,24 @@ impl Store for MockStore {
         self.generate_mock_events();
         result
     }
+
+    fn transact(
+        &mut self,
+        operations: Vec<EntityOperation>,
+        source: EventSource,
+    ) -> Result<(), ()> {
+        for operation in operations {
+            match operation {
+                EntityOperation::Set { key, data } => self.set(key, data, source.clone())?,
+                EntityOperation::Remove { key } => self.delete(key, source.clone())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn count_entities(&self, _subgraph: &str) -> Result<u64, ()> {
+        Ok(self.entities.len() as u64)
+    }
 }
 
 pub struct FakeStore;
@@ -145,4 +174,12 @@ impl Store for FakeStore {
     fn event_stream(&mut self) -> Result<Receiver<StoreEvent>, StreamError> {
         panic!("called FakeStore")
     }
+
+    fn transact(&mut self, _: Vec<EntityOperation>, _source: EventSource) -> Result<(), ()> {
+        panic!("called FakeStore")
+    }
+
+    fn count_entities(&self, _: &str) -> Result<u64, ()> {
+        panic!("called FakeStore")
+    }
 }
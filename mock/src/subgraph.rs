@@ -2,6 +2,7 @@ use futures::sync::mpsc::{channel, Receiver, Sender};
 use graphql_parser;
 
 use graph::prelude::*;
+use graph::semver::Version;
 use graphql_parser::schema::Document;
 
 /// A mock `SubgraphProvider`.
@@ -46,7 +47,7 @@ impl MockSubgraphProvider {
         let mock_subgraph = SubgraphManifest {
             id: String::from("mock subgraph"),
             location: String::from("/tmp/example-data-source.yaml"),
-            spec_version: String::from("0.1"),
+            spec_version: Version::new(0, 0, 1),
             schema: Schema {
                 id: String::from("exampled id"),
                 document: Document {
@@ -54,6 +55,7 @@ impl MockSubgraphProvider {
                 },
             },
             data_sources: vec![],
+            templates: vec![],
         };
 
         self.event_sink
@@ -1,6 +1,7 @@
 extern crate futures;
 extern crate graph;
 extern crate graph_core;
+extern crate graph_graphql;
 extern crate graphql_parser;
 
 mod subgraph;
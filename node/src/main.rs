@@ -16,14 +16,22 @@ extern crate ipfs_api;
 use clap::{App, Arg};
 use ipfs_api::IpfsClient;
 use std::env;
+use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use graph::components::forward;
+use graph::components::link_resolver::{FileLinkResolver, IpfsResolver, IpfsResolverConfig};
 use graph::prelude::*;
 use graph::util::log::logger;
-use graph_datasource_ethereum::Transport;
+use graph::util::retry::RetryPolicy;
+use graph_datasource_ethereum::web3::transports::EventLoopHandle;
+use graph_datasource_ethereum::{
+    BatchOptions, Provider, ProviderOptions, ProviderPool, Recorder, Transport,
+};
 use graph_node::SubgraphProvider as IpfsSubgraphProvider;
 use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
 use graph_server_http::GraphQLServer as HyperGraphQLServer;
@@ -48,8 +56,22 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
                 .takes_value(true)
                 .required(true)
                 .long("subgraph")
-                .value_name("IPFS_HASH")
-                .help("IPFS hash of the subgraph manifest"),
+                .value_name("IPFS_HASH|FILE")
+                .help(
+                    "IPFS hash of the subgraph manifest, or the path of a local \
+                     manifest file, e.g. ./subgraph.yaml",
+                ),
+        )
+        .arg(
+            Arg::with_name("subgraph-name")
+                .takes_value(true)
+                .long("subgraph-name")
+                .value_name("NAME")
+                .help(
+                    "Name under which the subgraph is served, e.g. graphprotocol/ens; \
+                     a new deployment of a name is indexed in the background until \
+                     it has caught up with the chain head",
+                ),
         )
         .arg(
             Arg::with_name("postgres-url")
@@ -62,37 +84,139 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
         .arg(
             Arg::with_name("ethereum-rpc")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-ws", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-ws", "ethereum-ipc"])
+                .multiple(true)
+                .number_of_values(1)
+                .required_unless_one(&["ethereum-ws", "ethereum-ipc", "ethereum-replay"])
+                .conflicts_with("ethereum-replay")
                 .long("ethereum-rpc")
-                .value_name("URL")
-                .help("Ethereum RPC endpoint"),
+                .value_name("[OPTIONS]URL")
+                .help(
+                    "Ethereum RPC endpoint; may be repeated. \
+                     OPTIONS is a comma-separated list of weight=N, archive and traces",
+                ),
         )
         .arg(
             Arg::with_name("ethereum-ws")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ipc"])
+                .multiple(true)
+                .number_of_values(1)
+                .required_unless_one(&["ethereum-rpc", "ethereum-ipc", "ethereum-replay"])
+                .conflicts_with("ethereum-replay")
                 .long("ethereum-ws")
-                .value_name("URL")
-                .help("Ethereum WebSocket endpoint"),
+                .value_name("[OPTIONS]URL")
+                .help(
+                    "Ethereum WebSocket endpoint; may be repeated. \
+                     OPTIONS is a comma-separated list of weight=N, archive and traces",
+                ),
         )
         .arg(
             Arg::with_name("ethereum-ipc")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ws"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ws"])
+                .multiple(true)
+                .number_of_values(1)
+                .required_unless_one(&["ethereum-rpc", "ethereum-ws", "ethereum-replay"])
+                .conflicts_with("ethereum-replay")
                 .long("ethereum-ipc")
-                .value_name("FILE")
-                .help("Ethereum IPC pipe"),
+                .value_name("[OPTIONS]FILE")
+                .help(
+                    "Ethereum IPC pipe; may be repeated. \
+                     OPTIONS is a comma-separated list of weight=N, archive and traces",
+                ),
         )
         .arg(
             Arg::with_name("ipfs")
                 .takes_value(true)
-                .required(true)
                 .long("ipfs")
                 .value_name("HOST:PORT")
-                .help("HTTP address of an IPFS node"),
+                .help(
+                    "HTTP address of an IPFS node; \
+                     only optional with a local subgraph manifest",
+                ),
+        )
+        .arg(
+            Arg::with_name("ipfs-timeout")
+                .takes_value(true)
+                .long("ipfs-timeout")
+                .value_name("SECONDS")
+                .default_value("30")
+                .help("How long to wait for a file from IPFS before trying again"),
+        )
+        .arg(
+            Arg::with_name("ipfs-attempts")
+                .takes_value(true)
+                .long("ipfs-attempts")
+                .value_name("COUNT")
+                .default_value("5")
+                .help("How often to try fetching a file from IPFS before giving up"),
+        )
+        .arg(
+            Arg::with_name("ipfs-max-file-size")
+                .takes_value(true)
+                .long("ipfs-max-file-size")
+                .value_name("BYTES")
+                .default_value("268435456")
+                .help("Maximum size of files fetched from IPFS"),
+        )
+        .arg(
+            Arg::with_name("ipfs-cache-dir")
+                .takes_value(true)
+                .long("ipfs-cache-dir")
+                .value_name("DIR")
+                .help("Cache files fetched from IPFS in DIR"),
+        )
+        .arg(
+            Arg::with_name("ipfs-cache-size")
+                .takes_value(true)
+                .long("ipfs-cache-size")
+                .value_name("MEGABYTES")
+                .default_value("1024")
+                .requires("ipfs-cache-dir")
+                .help("Maximum size of the IPFS cache in --ipfs-cache-dir"),
+        )
+        .arg(
+            Arg::with_name("wasm-worker-threads")
+                .takes_value(true)
+                .long("wasm-worker-threads")
+                .value_name("COUNT")
+                .default_value("4")
+                .help("Number of threads on which subgraph mappings are run"),
+        )
+        .arg(
+            Arg::with_name("ethereum-batch-size")
+                .takes_value(true)
+                .long("ethereum-batch-size")
+                .value_name("SIZE")
+                .help(
+                    "Send concurrent requests to Ethereum RPC and WebSocket \
+                     endpoints in JSON-RPC batches of up to SIZE requests",
+                ),
+        )
+        .arg(
+            Arg::with_name("ethereum-batch-linger")
+                .takes_value(true)
+                .long("ethereum-batch-linger")
+                .value_name("MILLISECONDS")
+                .default_value("10")
+                .requires("ethereum-batch-size")
+                .help("How long to wait for more requests before sending a batch"),
+        )
+        .arg(
+            Arg::with_name("ethereum-record")
+                .takes_value(true)
+                .long("ethereum-record")
+                .value_name("FILE")
+                .conflicts_with("ethereum-replay")
+                .help("Record all Ethereum requests and their responses to FILE"),
+        )
+        .arg(
+            Arg::with_name("ethereum-replay")
+                .takes_value(true)
+                .long("ethereum-replay")
+                .value_name("FILE")
+                .help(
+                    "Serve Ethereum requests from responses recorded with \
+                     --ethereum-record instead of connecting to Ethereum",
+                ),
         )
         .get_matches();
 
@@ -100,15 +224,103 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
     let postgres_url = matches.value_of("postgres-url").unwrap().to_string();
 
     // Obtain subgraph related command-line arguments
-    let subgraph_hash = matches.value_of("subgraph").unwrap();
+    let subgraph = matches.value_of("subgraph").unwrap();
+    let subgraph_name = matches.value_of("subgraph-name").map(|name| {
+        SubgraphName::new(name)
+            .unwrap_or_else(|e| panic!("invalid --subgraph-name {}: {}", name, e))
+    });
+
+    // IPFS hashes contain neither slashes nor dots, so anything that does is
+    // a path to a local manifest
+    let subgraph_manifest_path = if subgraph.contains('/') || subgraph.contains('.') {
+        Some(
+            fs::canonicalize(subgraph)
+                .unwrap_or_else(|e| panic!("could not find subgraph manifest {}: {}", subgraph, e)),
+        )
+    } else {
+        None
+    };
 
     // Obtain the Ethereum RPC/WS/IPC transport locations
-    let ethereum_rpc = matches.value_of("ethereum-rpc");
-    let ethereum_ipc = matches.value_of("ethereum-ipc");
-    let ethereum_ws = matches.value_of("ethereum-ws");
+    let ethereum_locations = |arg| -> Vec<&str> {
+        matches
+            .values_of(arg)
+            .map(|values| values.collect())
+            .unwrap_or(vec![])
+    };
+    let ethereum_rpc = ethereum_locations("ethereum-rpc");
+    let ethereum_ipc = ethereum_locations("ethereum-ipc");
+    let ethereum_ws = ethereum_locations("ethereum-ws");
+
+    let ipfs_socket_addr = matches.value_of("ipfs").map(|addr| {
+        SocketAddr::from_str(addr)
+            .expect("could not parse IPFS address, expected format is host:port")
+    });
+
+    // Safe to unwrap because the arguments have default values
+    let ipfs_config = IpfsResolverConfig {
+        timeout: Duration::from_secs(
+            matches
+                .value_of("ipfs-timeout")
+                .unwrap()
+                .parse::<u64>()
+                .expect("could not parse --ipfs-timeout, expected a number"),
+        ),
+        max_file_size: matches
+            .value_of("ipfs-max-file-size")
+            .unwrap()
+            .parse::<usize>()
+            .expect("could not parse --ipfs-max-file-size, expected a number"),
+        retry_policy: RetryPolicy::limited(
+            matches
+                .value_of("ipfs-attempts")
+                .unwrap()
+                .parse::<u32>()
+                .ok()
+                .filter(|attempts| *attempts > 0)
+                .expect("could not parse --ipfs-attempts, expected a positive number"),
+        ),
+        disk_cache: matches.value_of("ipfs-cache-dir").map(|dir| {
+            let megabytes = matches
+                .value_of("ipfs-cache-size")
+                .unwrap()
+                .parse::<u64>()
+                .expect("could not parse --ipfs-cache-size, expected a number");
+            (PathBuf::from(dir), megabytes * 1024 * 1024)
+        }),
+        ..IpfsResolverConfig::default()
+    };
 
-    let ipfs_socket_addr = SocketAddr::from_str(matches.value_of("ipfs").unwrap())
-        .expect("could not parse IPFS address, expected format is host:port");
+    // Safe to unwrap because the argument has a default value
+    let wasm_worker_threads = matches
+        .value_of("wasm-worker-threads")
+        .unwrap()
+        .parse::<usize>()
+        .expect("could not parse --wasm-worker-threads, expected a number");
+
+    // Only batch Ethereum requests if a batch size is given
+    let ethereum_batch_options = matches.value_of("ethereum-batch-size").map(|size| {
+        BatchOptions {
+            max_batch_size: size
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .expect("could not parse --ethereum-batch-size, expected a positive number"),
+            // Safe to unwrap because the argument has a default value
+            linger: Duration::from_millis(
+                matches
+                    .value_of("ethereum-batch-linger")
+                    .unwrap()
+                    .parse::<u64>()
+                    .expect("could not parse --ethereum-batch-linger, expected a number"),
+            ),
+        }
+    });
+
+    let ethereum_recorder = matches.value_of("ethereum-record").map(|path| {
+        Recorder::create(path).unwrap_or_else(|e| panic!("could not create {}: {}", path, e))
+    });
+    let ethereum_replay = matches.value_of("ethereum-replay");
 
     debug!(logger, "Setting up Sentry");
 
@@ -129,18 +341,34 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
     info!(logger, "Starting up");
 
     // Create system components
-    let resolver = Arc::new(
-        IpfsClient::new(
-            &format!("{}", ipfs_socket_addr.ip()),
-            ipfs_socket_addr.port(),
-        ).expect("Failed to start IPFS client"),
-    );
-    let (mut subgraph_provider, subgraph_provider_events) = IpfsSubgraphProvider::new(
-        logger.clone(),
-        &format!("/ipfs/{}", subgraph_hash.clone()),
-        resolver.clone(),
-    );
-    tokio::spawn(subgraph_provider_events.map_err(|_| ()));
+    let ipfs_resolver = ipfs_socket_addr.map(|addr| {
+        IpfsResolver::new(
+            &logger,
+            IpfsClient::new(&format!("{}", addr.ip()), addr.port())
+                .expect("Failed to start IPFS client"),
+            ipfs_config,
+        )
+    });
+
+    // Local manifests and the files they reference are read from disk,
+    // relative to the directory of the manifest
+    let (resolver, subgraph_link): (Box<LinkResolver>, _) = match subgraph_manifest_path {
+        Some(path) => {
+            let mut file_resolver = FileLinkResolver::new(path.parent().unwrap());
+            if let Some(ipfs_resolver) = ipfs_resolver {
+                file_resolver = file_resolver.with_ipfs(ipfs_resolver);
+            }
+            (
+                Box::new(file_resolver),
+                path.file_name().unwrap().to_string_lossy().into_owned(),
+            )
+        }
+        None => (
+            Box::new(ipfs_resolver.expect("--ipfs is required for subgraphs on IPFS")),
+            format!("/ipfs/{}", subgraph),
+        ),
+    };
+    let resolver = Arc::new(resolver);
 
     let mut schema_provider = graph_core::SchemaProvider::new(&logger);
     let store = DieselStore::new(StoreConfig { url: postgres_url }, &logger);
@@ -148,24 +376,105 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
     let mut graphql_server = HyperGraphQLServer::new(&logger);
 
     // Create Ethereum adapter
-    let (transport_event_loop, transport) = ethereum_ipc
-        .map(Transport::new_ipc)
-        .or(ethereum_ws.map(Transport::new_ws))
-        .or(ethereum_rpc.map(Transport::new_rpc))
-        .expect("One of --ethereum-ipc, --ethereum-ws or --ethereum-rpc must be provided");
-    // If we drop the event loop the transport will stop working. For now it's
-    // fine to just leak it.
-    std::mem::forget(transport_event_loop);
+    let ethereum_providers = match ethereum_replay {
+        Some(path) => vec![Provider {
+            name: path.to_owned(),
+            transport: Transport::new_replay(path),
+            options: ProviderOptions::default(),
+        }],
+        None => ethereum_ipc
+            .into_iter()
+            .map(|value| ethereum_provider(value, &Transport::new_ipc, ethereum_recorder.clone()))
+            .chain(ethereum_ws.into_iter().map(|value| {
+                let new_transport = |ws: &str| match ethereum_batch_options {
+                    Some(batch_options) => Transport::new_batched_ws(ws, batch_options),
+                    None => Transport::new_ws(ws),
+                };
+                ethereum_provider(value, &new_transport, ethereum_recorder.clone())
+            }))
+            .chain(ethereum_rpc.into_iter().map(|value| {
+                let new_transport = |rpc: &str| match ethereum_batch_options {
+                    Some(batch_options) => Transport::new_batched_rpc(rpc, batch_options),
+                    None => Transport::new_rpc(rpc),
+                };
+                ethereum_provider(value, &new_transport, ethereum_recorder.clone())
+            }))
+            .collect(),
+    };
+
+    // Requests made while recording or replaying must not depend on the
+    // state of the node, e.g. on health checks or on previously cached
+    // contract calls
+    let deterministic = ethereum_recorder.is_some() || ethereum_replay.is_some();
+    let transport = ProviderPool::new(&logger, ethereum_providers);
+    if !deterministic {
+        transport.start_health_checks(Duration::from_secs(10));
+    }
     let ethereum_watcher = graph_datasource_ethereum::EthereumAdapter::new(
-        graph_datasource_ethereum::EthereumAdapterConfig { transport },
+        &logger,
+        graph_datasource_ethereum::EthereumAdapterConfig {
+            transport,
+            retry_policy: RetryPolicy::default(),
+            call_cache: if deterministic {
+                None
+            } else {
+                Some(protected_store.clone())
+            },
+        },
+    );
+    let ethereum_watcher = Arc::new(Mutex::new(ethereum_watcher));
+    let runtime_host_builder = WASMRuntimeHostBuilder::new(
+        &logger,
+        ethereum_watcher.clone(),
+        resolver.clone(),
+        wasm_worker_threads,
     );
-    let runtime_host_builder =
-        WASMRuntimeHostBuilder::new(&logger, Arc::new(Mutex::new(ethereum_watcher)), resolver);
-    let runtime_manager =
-        graph_core::RuntimeManager::new(&logger, protected_store.clone(), runtime_host_builder);
+    let runtime_manager = Arc::new(graph_core::RuntimeManager::new(
+        &logger,
+        protected_store.clone(),
+        runtime_host_builder,
+    ));
+
+    // Report the indexing status of subgraphs via the GraphQL server; the
+    // chain head is not polled while recording or replaying, since that
+    // would add requests
+    let indexing_status_provider = graph_core::IndexingStatusProvider::new(
+        &logger,
+        runtime_manager.clone(),
+        protected_store.clone(),
+    );
+    if !deterministic {
+        indexing_status_provider
+            .start_chain_head_polling(ethereum_watcher.clone(), Duration::from_secs(5));
+    }
+    graphql_server.set_indexing_status_provider(Arc::new(indexing_status_provider));
+    graphql_server.set_subgraph_names(protected_store.clone());
+
+    // Named subgraphs are switched over to new deployments only once these
+    // have caught up, which requires the runtime manager
+    let mut subgraph_provider = match subgraph_name {
+        Some(name) => {
+            let (provider, events) = IpfsSubgraphProvider::new_with_name(
+                logger.clone(),
+                name,
+                &subgraph_link,
+                resolver,
+                protected_store.clone(),
+                runtime_manager.clone(),
+            );
+            tokio::spawn(events.map_err(|_| ()));
+            provider
+        }
+        None => {
+            let (provider, events) =
+                IpfsSubgraphProvider::new(logger.clone(), &subgraph_link, resolver);
+            tokio::spawn(events.map_err(|_| ()));
+            provider
+        }
+    };
 
     // Forward subgraph events from the subgraph provider to the runtime manager
-    tokio::spawn(forward(&mut subgraph_provider, &runtime_manager).unwrap());
+    tokio::spawn(forward(&mut subgraph_provider, &*runtime_manager).unwrap());
 
     // Forward schema events from the subgraph provider to the schema provider
     tokio::spawn(forward(&mut subgraph_provider, &schema_provider).unwrap());
@@ -216,3 +525,30 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
         .expect("Failed to start GraphQL server");
     http_server
 }
+
+/// Creates an Ethereum provider from a command-line value of the form
+/// `[OPTIONS]LOCATION`, recording its requests if a `recorder` is given.
+fn ethereum_provider(
+    value: &str,
+    new_transport: &Fn(&str) -> (EventLoopHandle, Transport),
+    recorder: Option<Recorder>,
+) -> Provider<Transport> {
+    let (options, location) = ProviderOptions::parse(value)
+        .unwrap_or_else(|e| panic!("could not parse Ethereum provider: {}", e));
+    let (transport_event_loop, transport) = new_transport(location);
+
+    // If we drop the event loop the transport will stop working. For now it's
+    // fine to just leak it.
+    std::mem::forget(transport_event_loop);
+
+    let transport = match recorder {
+        Some(recorder) => transport.recorded(recorder),
+        None => transport,
+    };
+
+    Provider {
+        name: location.to_owned(),
+        transport,
+        options,
+    }
+}
@@ -1,8 +1,13 @@
-use futures::sync::mpsc::{channel, Receiver};
+use futures::future;
+use futures::stream;
+use futures::sync::mpsc::{channel, Receiver, Sender};
 use graphql_parser::{schema, Pos};
+use std::sync::Mutex;
 
 use graph::data::subgraph::SubgraphManifestResolveError;
+use graph::failure;
 use graph::prelude::{SubgraphProvider as SubgraphProviderTrait, *};
+use graph_graphql::prelude::{validate_schema, SchemaValidationError};
 
 pub struct SubgraphProvider {
     _logger: slog::Logger,
@@ -12,6 +17,9 @@ pub struct SubgraphProvider {
 
 pub enum SubgraphProviderError {
     ResolveError(SubgraphManifestResolveError),
+    InvalidSchema(Vec<SchemaValidationError>),
+    NameStoreError(failure::Error),
+    SwitchCancelled,
     SendError,
 }
 impl SubgraphProvider {
@@ -28,45 +36,293 @@ impl SubgraphProvider {
         let (schema_event_sink, schema_event_stream) = channel(100);
         let (event_sink, event_stream) = channel(100);
 
-        // Load the subgraph definition
+        // Load the subgraph definition and push the subgraph and its schema
+        // into their streams
         let send_logger = logger.clone();
-        let send = SubgraphManifest::resolve(
-            Link {
-                link: link.to_owned(),
+        let send = Self::resolve_subgraph(logger.clone(), link.to_owned(), resolver).and_then(
+            move |subgraph| {
+                Self::add_subgraph(send_logger, schema_event_sink, event_sink, subgraph)
             },
-            resolver,
-        ).map_err(SubgraphProviderError::ResolveError)
+        );
+
+        (
+            Self::with_streams(logger, event_stream, schema_event_stream),
+            send,
+        )
+    }
+
+    /// Like `new`, but deploys the subgraph under `name`.
+    ///
+    /// If the name points at a different deployment already, that deployment
+    /// keeps being served while the new one is indexed in the background.
+    /// Once the new deployment has caught up with the chain head, the name
+    /// is switched over to it and the old deployment is stopped. Entities of
+    /// the old deployment are kept in the store.
+    pub fn new_with_name<R, S, M>(
+        logger: slog::Logger,
+        name: SubgraphName,
+        link: &str,
+        resolver: Arc<R>,
+        store: Arc<Mutex<S>>,
+        runtime_manager: Arc<M>,
+    ) -> (
+        Self,
+        impl Future<Item = (), Error = SubgraphProviderError> + Send,
+    )
+    where
+        R: LinkResolver,
+        S: SubgraphNameStore + 'static,
+        M: RuntimeManager + Send + Sync + 'static,
+    {
+        let (schema_event_sink, schema_event_stream) = channel(100);
+        let (event_sink, event_stream) = channel(100);
+
+        let send_logger = logger.clone();
+        let deployment_link = link.to_owned();
+        let send = Self::resolve_subgraph(logger.clone(), link.to_owned(), resolver.clone())
             .and_then(move |subgraph| {
-                let schema = Self::add_subgraph_id_directives(
-                    &mut subgraph.schema.clone(),
-                    subgraph.id.clone(),
-                );
-                // Push the subgraph and the schema into their streams
-                let event_logger = send_logger.clone();
-                schema_event_sink
-                    .send(SchemaEvent::SchemaAdded(schema))
-                    .map_err(move |e| {
-                        error!(send_logger, "Failed to forward subgraph schema: {}", e)
-                    })
-                    .join(
-                        event_sink
-                            .send(SubgraphProviderEvent::SubgraphAdded(subgraph))
-                            .map_err(move |e| {
-                                error!(event_logger, "Failed to forward subgraph: {}", e)
-                            }),
-                    )
-                    .map_err(|_| SubgraphProviderError::SendError)
-                    .map(|_| ())
+                let logger = send_logger.new(o!("subgraph_name" => name.to_string()));
+                let deployment = SubgraphDeployment {
+                    id: subgraph.id.clone(),
+                    location: deployment_link,
+                };
+
+                let current = match store.lock().unwrap().current_deployment(&name) {
+                    Ok(current) => current,
+                    Err(e) => {
+                        error!(logger, "Failed to look up current deployment: {}", e);
+                        return Box::new(future::err(SubgraphProviderError::NameStoreError(e)))
+                            as Box<Future<Item = _, Error = _> + Send>;
+                    }
+                };
+
+                match current {
+                    Some(ref current) if current.id != deployment.id => {
+                        info!(logger, "Deploy subgraph in the background";
+                              "current" => &current.id,
+                              "new" => &deployment.id);
+                        Box::new(Self::switch_deployment(
+                            logger,
+                            name,
+                            current.clone(),
+                            deployment,
+                            subgraph,
+                            resolver,
+                            store,
+                            runtime_manager,
+                            schema_event_sink,
+                            event_sink,
+                        ))
+                    }
+                    _ => Box::new(
+                        future::result(Self::set_current_deployment(
+                            &logger,
+                            &*store,
+                            &name,
+                            &deployment,
+                        ))
+                        .and_then(move |_| {
+                            Self::add_subgraph(logger, schema_event_sink, event_sink, subgraph)
+                        }),
+                    ),
+                }
             });
 
-        // Create the subgraph provider
-        let provider = SubgraphProvider {
+        (
+            Self::with_streams(logger, event_stream, schema_event_stream),
+            send,
+        )
+    }
+
+    fn with_streams(
+        logger: slog::Logger,
+        event_stream: Receiver<SubgraphProviderEvent>,
+        schema_event_stream: Receiver<SchemaEvent>,
+    ) -> Self {
+        SubgraphProvider {
             _logger: logger.new(o!("component" => "SubgraphProvider")),
             event_stream: Some(event_stream),
             schema_event_stream: Some(schema_event_stream),
-        };
+        }
+    }
+
+    /// Resolves the subgraph manifest behind `link` and validates its schema.
+    fn resolve_subgraph(
+        logger: slog::Logger,
+        link: String,
+        resolver: Arc<impl LinkResolver>,
+    ) -> impl Future<Item = SubgraphManifest, Error = SubgraphProviderError> + Send {
+        let error_logger = logger.clone();
+        SubgraphManifest::resolve(Link { link }, resolver)
+            .map_err(move |e| {
+                error!(error_logger, "{}", e);
+                SubgraphProviderError::ResolveError(e)
+            })
+            .and_then(move |subgraph| {
+                // Refuse subgraphs whose schema would result in a broken API
+                match validate_schema(&subgraph.schema.document) {
+                    Ok(()) => Ok(subgraph),
+                    Err(errors) => {
+                        for e in errors.iter() {
+                            error!(logger, "Invalid subgraph schema: {}", e;
+                                   "subgraph" => &subgraph.id);
+                        }
+                        Err(SubgraphProviderError::InvalidSchema(errors))
+                    }
+                }
+            })
+    }
+
+    /// Starts indexing `subgraph` and serves its schema.
+    fn add_subgraph(
+        logger: slog::Logger,
+        schema_event_sink: Sender<SchemaEvent>,
+        event_sink: Sender<SubgraphProviderEvent>,
+        subgraph: SubgraphManifest,
+    ) -> impl Future<Item = (), Error = SubgraphProviderError> + Send {
+        let schema = Self::subgraph_schema(&subgraph);
+        Self::send_events(
+            logger.clone(),
+            schema_event_sink,
+            vec![SchemaEvent::SchemaAdded(schema)],
+        )
+        .join(Self::send_events(
+            logger,
+            event_sink,
+            vec![SubgraphProviderEvent::SubgraphAdded(subgraph)],
+        ))
+        .map(|_| ())
+    }
+
+    /// Indexes the `new` deployment next to the `current` one and switches
+    /// `name` over to it once it has caught up with the chain head.
+    fn switch_deployment<R, S, M>(
+        logger: slog::Logger,
+        name: SubgraphName,
+        current: SubgraphDeployment,
+        new: SubgraphDeployment,
+        subgraph: SubgraphManifest,
+        resolver: Arc<R>,
+        store: Arc<Mutex<S>>,
+        runtime_manager: Arc<M>,
+        schema_event_sink: Sender<SchemaEvent>,
+        event_sink: Sender<SubgraphProviderEvent>,
+    ) -> impl Future<Item = (), Error = SubgraphProviderError> + Send
+    where
+        R: LinkResolver,
+        S: SubgraphNameStore + 'static,
+        M: RuntimeManager + Send + Sync + 'static,
+    {
+        SubgraphManifest::resolve(
+            Link {
+                link: current.location.clone(),
+            },
+            resolver,
+        )
+        .then(move |result| {
+            let current_subgraph = match result {
+                Ok(current_subgraph) => current_subgraph,
+                Err(e) => {
+                    // Without the current deployment there is nothing to
+                    // keep serving in the meantime, so switch immediately
+                    warn!(logger, "Failed to resolve current deployment: {}", e;
+                          "location" => &current.location);
+                    return Box::new(
+                        future::result(Self::set_current_deployment(&logger, &*store, &name, &new))
+                            .and_then(move |_| {
+                                Self::add_subgraph(logger, schema_event_sink, event_sink, subgraph)
+                            }),
+                    ) as Box<Future<Item = _, Error = _> + Send>;
+                }
+            };
+
+            let current_schema = Self::subgraph_schema(&current_subgraph);
+            let new_schema = Self::subgraph_schema(&subgraph);
+            let caught_up_logger = logger.clone();
+            let switch_logger = logger.clone();
+
+            // Keep serving the current deployment while the new one is
+            // being indexed
+            Box::new(
+                Self::add_subgraph(
+                    logger.clone(),
+                    schema_event_sink.clone(),
+                    event_sink.clone(),
+                    current_subgraph.clone(),
+                )
+                .join(Self::send_events(
+                    logger.clone(),
+                    event_sink.clone(),
+                    vec![SubgraphProviderEvent::SubgraphAdded(subgraph)],
+                ))
+                .and_then(move |_| {
+                    runtime_manager
+                        .caught_up(&new.id)
+                        .map_err(move |_| {
+                            error!(caught_up_logger, "Stopped waiting for new deployment";
+                                   "deployment" => &new.id);
+                            SubgraphProviderError::SwitchCancelled
+                        })
+                        .map(move |_| new)
+                })
+                .and_then(move |new| {
+                    Self::set_current_deployment(&switch_logger, &*store, &name, &new)?;
+                    info!(switch_logger, "Switched subgraph name to new deployment";
+                              "deployment" => &new.id);
+                    Ok(())
+                })
+                .and_then(move |_| {
+                    // Queries for the name wait until the new schema
+                    // reaches the GraphQL server
+                    Self::send_events(
+                        logger.clone(),
+                        schema_event_sink,
+                        vec![SchemaEvent::SchemaReplaced(current_schema, new_schema)],
+                    )
+                    .join(Self::send_events(
+                        logger,
+                        event_sink,
+                        vec![SubgraphProviderEvent::SubgraphRemoved(current_subgraph)],
+                    ))
+                    .map(|_| ())
+                }),
+            )
+        })
+    }
+
+    fn set_current_deployment<S: SubgraphNameStore>(
+        logger: &slog::Logger,
+        store: &Mutex<S>,
+        name: &SubgraphName,
+        deployment: &SubgraphDeployment,
+    ) -> Result<(), SubgraphProviderError> {
+        store
+            .lock()
+            .unwrap()
+            .set_current_deployment(name, deployment)
+            .map_err(|e| {
+                error!(logger, "Failed to set current deployment: {}", e);
+                SubgraphProviderError::NameStoreError(e)
+            })
+    }
+
+    fn send_events<T: Send + 'static>(
+        logger: slog::Logger,
+        sink: Sender<T>,
+        events: Vec<T>,
+    ) -> impl Future<Item = (), Error = SubgraphProviderError> + Send {
+        sink.send_all(stream::iter_ok(events))
+            .map_err(move |e| {
+                error!(logger, "Failed to forward subgraph events: {}", e);
+                SubgraphProviderError::SendError
+            })
+            .map(|_| ())
+    }
 
-        (provider, send)
+    /// Returns the schema of `subgraph` with the directives that route
+    /// queries to it.
+    fn subgraph_schema(subgraph: &SubgraphManifest) -> Schema {
+        Self::add_subgraph_id_directives(&mut subgraph.schema.clone(), subgraph.id.clone())
     }
 
     // Adds a @subgraphId(id: ...) directive to object/interface/enum types in the schema.
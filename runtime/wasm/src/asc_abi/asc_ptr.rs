@@ -42,6 +42,16 @@ impl<C: AscType> AscPtr<C> {
         unsafe { mem::transmute(u32_bytes) }
     }
 
+    /// A null pointer, for returning optional values.
+    pub(crate) fn null() -> Self {
+        AscPtr(0, PhantomData)
+    }
+
+    /// Whether this is a null pointer, as passed for optional arguments.
+    pub(crate) fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+
     /// Conversion to `u64` for use with `AscEnum`.
     pub(crate) fn to_payload(&self) -> u64 {
         self.0 as u64
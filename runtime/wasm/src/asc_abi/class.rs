@@ -1,5 +1,6 @@
 use super::{AscHeap, AscPtr, AscType, AscValue};
 use ethabi;
+use graph::data::store;
 use graph::serde_json;
 use std::mem::{self, size_of, size_of_val};
 
@@ -317,6 +318,23 @@ impl Default for StoreValueKind {
 impl AscType for StoreValueKind {}
 impl AscValue for StoreValueKind {}
 
+impl StoreValueKind {
+    pub(crate) fn get_kind(value: &store::Value) -> Self {
+        use self::store::Value;
+
+        match value {
+            Value::String(_) => StoreValueKind::String,
+            Value::Int(_) => StoreValueKind::Int,
+            Value::Float(_) => StoreValueKind::Float,
+            Value::Bool(_) => StoreValueKind::Bool,
+            Value::List(_) => StoreValueKind::Array,
+            Value::Null => StoreValueKind::Null,
+            Value::Bytes(_) => StoreValueKind::Bytes,
+            Value::BigInt(_) => StoreValueKind::BigInt,
+        }
+    }
+}
+
 #[repr(C)]
 pub(crate) struct AscLogParam {
     pub name: AscPtr<AscString>,
@@ -345,6 +363,31 @@ pub(crate) struct AscEthereumEvent {
 
 impl AscType for AscEthereumEvent {}
 
+#[repr(C)]
+pub(crate) struct AscEthereumBlock {
+    pub hash: AscPtr<AscH256>,
+    pub parent_hash: AscPtr<AscH256>,
+    pub number: AscPtr<BigInt>,
+    pub timestamp: AscPtr<BigInt>,
+    pub author: AscPtr<AscH160>,
+    pub gas_used: AscPtr<BigInt>,
+    pub gas_limit: AscPtr<BigInt>,
+}
+
+impl AscType for AscEthereumBlock {}
+
+#[repr(C)]
+pub(crate) struct AscEthereumCall {
+    pub address: AscPtr<AscAddress>,
+    pub from: AscPtr<AscAddress>,
+    pub block_hash: AscPtr<AscH256>,
+    pub transaction_hash: AscPtr<AscH256>,
+    pub inputs: AscPtr<AscLogParamArray>,
+    pub outputs: AscPtr<AscLogParamArray>,
+}
+
+impl AscType for AscEthereumCall {}
+
 #[repr(C)]
 pub(crate) struct AscTypedMapEntry<K, V> {
     pub key: AscPtr<K>,
@@ -1,19 +1,26 @@
+use ethabi::{Event, Function};
 use ethereum_types::Address;
 use futures::sync::mpsc::{channel, Receiver};
+use futures::sync::oneshot;
+use std::panic::{self, AssertUnwindSafe};
 use std::str::FromStr;
 use std::sync::Mutex;
-use std::thread;
 use uuid::Uuid;
 
 use graph::components::ethereum::*;
 use graph::components::subgraph::RuntimeHostEvent;
-use graph::data::subgraph::DataSource;
+use graph::data::subgraph::{BlockHandlerFilter, DataSource};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
 use graph::util;
 
 use module::{WasmiModule, WasmiModuleConfig};
+use pool::{Assignment, WorkerPool, WorkerState, WorkerTask};
+
+/// Maximum number of handler errors a host keeps for its status; older
+/// errors are dropped first.
+const MAX_NON_FATAL_ERRORS: usize = 100;
 
 #[derive(Clone)]
 pub struct RuntimeHostConfig {
@@ -25,6 +32,7 @@ pub struct RuntimeHostBuilder<T, L> {
     logger: Logger,
     ethereum_adapter: Arc<Mutex<T>>,
     link_resolver: Arc<L>,
+    worker_pool: WorkerPool,
 }
 
 impl<T, L> RuntimeHostBuilder<T, L>
@@ -32,11 +40,19 @@ where
     T: EthereumAdapter,
     L: LinkResolver,
 {
-    pub fn new(logger: &Logger, ethereum_adapter: Arc<Mutex<T>>, link_resolver: Arc<L>) -> Self {
+    /// Creates a builder for runtime hosts that run their mappings on a
+    /// pool of `worker_threads` WASM worker threads.
+    pub fn new(
+        logger: &Logger,
+        ethereum_adapter: Arc<Mutex<T>>,
+        link_resolver: Arc<L>,
+        worker_threads: usize,
+    ) -> Self {
         RuntimeHostBuilder {
             logger: logger.new(o!("component" => "RuntimeHostBuilder")),
             ethereum_adapter,
             link_resolver,
+            worker_pool: WorkerPool::new(logger, worker_threads),
         }
     }
 }
@@ -57,6 +73,7 @@ where
             &self.logger,
             self.ethereum_adapter.clone(),
             self.link_resolver.clone(),
+            &self.worker_pool,
             RuntimeHostConfig {
                 subgraph_manifest,
                 data_source,
@@ -65,9 +82,22 @@ where
     }
 }
 
+/// The Ethereum subscriptions made by a runtime host.
+#[derive(Default)]
+struct HostSubscriptions {
+    subscription_ids: Vec<String>,
+    /// Set once all subscriptions of the host have been made.
+    subscribed: bool,
+    /// Set when the host is dropped; no new subscriptions are made after that.
+    cancelled: bool,
+}
+
 pub struct RuntimeHost {
     config: RuntimeHostConfig,
     output: Option<Receiver<RuntimeHostEvent>>,
+    ethereum_adapter: Arc<Mutex<EthereumAdapter>>,
+    subscriptions: Arc<Mutex<HostSubscriptions>>,
+    status: Arc<Mutex<RuntimeHostStatus>>,
 }
 
 impl RuntimeHost {
@@ -75,6 +105,7 @@ impl RuntimeHost {
         logger: &Logger,
         ethereum_adapter: Arc<Mutex<T>>,
         link_resolver: Arc<L>,
+        worker_pool: &WorkerPool,
         config: RuntimeHostConfig,
     ) -> Self
     where
@@ -87,8 +118,8 @@ impl RuntimeHost {
         let (event_sender, event_receiver) = channel(100);
 
         let wasmi_config = WasmiModuleConfig {
-            subgraph: config.subgraph_manifest.clone(),
-            data_source: config.data_source.clone(),
+            subgraph: Arc::new(config.subgraph_manifest.clone()),
+            data_source: Arc::new(config.data_source.clone()),
             event_sink: event_sender,
             ethereum_adapter: ethereum_adapter.clone(),
             link_resolver: link_resolver.clone(),
@@ -96,25 +127,29 @@ impl RuntimeHost {
 
         info!(logger, "Loading WASM runtime"; "data_source" => &config.data_source.name);
 
-        // wasmi modules are not `Send` therefore they cannot be scheduled by
-        // the regular tokio executor, so we create a dedicated thread inside
-        // which we may wait on futures.
-        thread::spawn(move || {
-            let data_source = wasmi_config.data_source.clone();
-
-            // Load the mappings as a WASM module
-            let module = WasmiModule::new(&logger, wasmi_config);
+        let subscriptions = Arc::new(Mutex::new(HostSubscriptions::default()));
+        let status = Arc::new(Mutex::new(RuntimeHostStatus::default()));
 
-            // Process one event at a time, blocking the thread when waiting for
-            // the next event.
-            Self::subscribe_to_events(&logger, data_source, module, ethereum_adapter)
-                .wait()
-                .for_each(drop);
-        });
+        // wasmi modules are not `Send` therefore they cannot be scheduled by
+        // the regular tokio executor; instead, the triggers of the data
+        // source are streamed on the pool's dispatcher thread and each of
+        // them is handled on the WASM worker thread that the data source is
+        // assigned to, which instantiates the module there once
+        worker_pool.dispatch(Box::new(DataSourceTask {
+            logger,
+            wasmi_config,
+            worker_pool: worker_pool.clone(),
+            assignment: worker_pool.assign(),
+            subscriptions: subscriptions.clone(),
+            status: status.clone(),
+        }));
 
         RuntimeHost {
             config,
             output: Some(event_receiver),
+            ethereum_adapter,
+            subscriptions,
+            status,
         }
     }
 
@@ -122,9 +157,11 @@ impl RuntimeHost {
     /// `subgraph`.
     fn subscribe_to_events<T, L>(
         logger: &Logger,
-        data_source: DataSource,
-        mut module: WasmiModule<T, L>,
-        ethereum_adapter: Arc<Mutex<T>>,
+        wasmi_config: WasmiModuleConfig<T, L>,
+        worker_pool: WorkerPool,
+        assignment: Assignment,
+        subscriptions: Arc<Mutex<HostSubscriptions>>,
+        status: Arc<Mutex<RuntimeHostStatus>>,
     ) -> impl Stream<Item = (), Error = ()> + 'static
     where
         T: EthereumAdapter + 'static,
@@ -132,95 +169,581 @@ impl RuntimeHost {
     {
         info!(logger, "Subscribe to events");
 
-        // Obtain the contract address of the data set.
-        let address = Address::from_str(data_source.source.address.as_str())
-            .expect("Failed to parse contract address");
+        let data_source = wasmi_config.data_source.clone();
+        let ethereum_adapter = wasmi_config.ethereum_adapter.clone();
+        let address = Self::contract_address(&data_source);
+
+        // Merge the event streams of all events handled by the data source,
+        // unless the host has been dropped in the meantime.
+        let mut subscriptions = subscriptions.lock().unwrap();
+        let mut event_stream: Box<Stream<Item = _, Error = _>> = Box::new(stream::empty());
+        for event in Self::handled_events(&data_source) {
+            if subscriptions.cancelled {
+                break;
+            }
+
+            info!(logger, "Subscribe to event"; "name" => &event.name);
+
+            let subscription_id = Uuid::new_v4().simple().to_string();
+            subscriptions.subscription_ids.push(subscription_id.clone());
+
+            let subscription = EthereumEventSubscription {
+                address,
+                event,
+                range: BlockNumberRange {
+                    from: BlockNumber::Number(data_source.source.start_block),
+                    to: BlockNumber::Latest,
+                },
+                subscription_id,
+            };
+
+            event_stream = Box::new(
+                event_stream.select(
+                    ethereum_adapter
+                        .lock()
+                        .unwrap()
+                        .subscribe_to_event(subscription),
+                ),
+            );
+        }
+        subscriptions.subscribed = !subscriptions.cancelled;
+
+        let trigger_logger = logger.clone();
+        let error_logger = logger.clone();
+        let error_status = status.clone();
+
+        event_stream
+            .map_err(move |e| {
+                error!(error_logger, "Event subscription failed: {}", e);
+                Self::record_fatal_error(
+                    &error_status,
+                    format!("Event subscription failed: {}", e),
+                );
+            })
+            .and_then(move |event| {
+                Self::handle_trigger(
+                    &trigger_logger,
+                    &worker_pool,
+                    assignment,
+                    &wasmi_config,
+                    &status,
+                    Trigger::Event(event),
+                )
+            })
+    }
+
+    /// Subscribe to all blocks and process them with the block handlers of
+    /// `data_source`. Within a block, event handlers are called first,
+    /// then call handlers and finally block handlers, so that handlers
+    /// always see blocks in chain order.
+    fn subscribe_to_blocks<T, L>(
+        logger: &Logger,
+        wasmi_config: WasmiModuleConfig<T, L>,
+        worker_pool: WorkerPool,
+        assignment: Assignment,
+        subscriptions: Arc<Mutex<HostSubscriptions>>,
+        status: Arc<Mutex<RuntimeHostStatus>>,
+    ) -> impl Stream<Item = (), Error = ()> + 'static
+    where
+        T: EthereumAdapter + 'static,
+        L: LinkResolver + 'static,
+    {
+        info!(logger, "Subscribe to blocks");
+
+        let data_source = wasmi_config.data_source.clone();
+        let ethereum_adapter = wasmi_config.ethereum_adapter.clone();
+        let address = Self::contract_address(&data_source);
+        let events = Self::handled_events(&data_source);
+        let functions = Self::handled_functions(&data_source);
+        let filters_calls = data_source
+            .mapping
+            .block_handlers
+            .iter()
+            .any(|block_handler| block_handler.filter == Some(BlockHandlerFilter::Call));
+
+        // Subscribe to blocks, unless the host has been dropped in the
+        // meantime
+        let block_stream: Box<Stream<Item = _, Error = _>> = {
+            let mut subscriptions = subscriptions.lock().unwrap();
+            if subscriptions.cancelled {
+                Box::new(stream::empty())
+            } else {
+                let subscription_id = Uuid::new_v4().simple().to_string();
+                subscriptions.subscription_ids.push(subscription_id.clone());
+
+                let subscription = EthereumBlockSubscription {
+                    range: BlockNumberRange {
+                        from: BlockNumber::Number(data_source.source.start_block),
+                        to: BlockNumber::Latest,
+                    },
+                    subscription_id,
+                };
+
+                subscriptions.subscribed = true;
+                ethereum_adapter
+                    .lock()
+                    .unwrap()
+                    .subscribe_to_blocks(subscription)
+            }
+        };
+
+        let trigger_logger = logger.clone();
+        let error_logger = logger.clone();
+        let error_status = status.clone();
+
+        block_stream
+            .and_then(move |block| {
+                // Only look for events if the data source handles any
+                let block_events: Box<Future<Item = _, Error = _>> = if events.is_empty() {
+                    Box::new(future::ok(vec![]))
+                } else {
+                    ethereum_adapter.lock().unwrap().events_in_block(
+                        &block,
+                        address,
+                        events.clone(),
+                    )
+                };
+
+                // Only look for calls if the data source handles any
+                let block_calls: Box<Future<Item = _, Error = _>> = if functions.is_empty() {
+                    Box::new(future::ok(vec![]))
+                } else {
+                    ethereum_adapter.lock().unwrap().calls_in_block(
+                        &block,
+                        address,
+                        functions.clone(),
+                    )
+                };
+
+                // Only look for calls to the contract, including calls made
+                // by other contracts, if a block handler filters on them
+                let block_called: Box<Future<Item = _, Error = _>> = if filters_calls {
+                    ethereum_adapter
+                        .lock()
+                        .unwrap()
+                        .is_called_in_block(&block, address)
+                } else {
+                    Box::new(future::ok(false))
+                };
+
+                block_events
+                    .join3(block_calls, block_called)
+                    .map(move |(events, calls, called)| BlockTrigger {
+                        block,
+                        events,
+                        calls,
+                        called,
+                    })
+            })
+            .map_err(move |e| {
+                error!(error_logger, "Block subscription failed: {}", e);
+                Self::record_fatal_error(
+                    &error_status,
+                    format!("Block subscription failed: {}", e),
+                );
+            })
+            .and_then(move |block_trigger| {
+                Self::handle_trigger(
+                    &trigger_logger,
+                    &worker_pool,
+                    assignment,
+                    &wasmi_config,
+                    &status,
+                    Trigger::Block(block_trigger),
+                )
+            })
+    }
+
+    /// Handles `trigger` with a short task on the worker of `worker_pool`
+    /// that the data source is assigned to. The returned future resolves
+    /// once the trigger has been handled, so that the triggers of a data
+    /// source are handled one at a time and in order.
+    fn handle_trigger<T, L>(
+        logger: &Logger,
+        worker_pool: &WorkerPool,
+        assignment: Assignment,
+        wasmi_config: &WasmiModuleConfig<T, L>,
+        status: &Arc<Mutex<RuntimeHostStatus>>,
+        trigger: Trigger,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        T: EthereumAdapter + 'static,
+        L: LinkResolver + 'static,
+    {
+        let (done, handled) = oneshot::channel();
+        worker_pool.spawn(
+            assignment,
+            Box::new(TriggerTask {
+                logger: logger.clone(),
+                assignment,
+                wasmi_config: wasmi_config.clone(),
+                status: status.clone(),
+                trigger,
+                done,
+            }),
+        );
+
+        // The task is dropped without completing if handling the trigger
+        // panicked, e.g. because the module could not be instantiated
+        let logger = logger.clone();
+        let status = status.clone();
+        handled.map_err(move |_| {
+            error!(logger, "Failed to handle trigger on WASM worker");
+            Self::record_fatal_error(&status, String::from("Failed to handle trigger"));
+        })
+    }
+
+    /// Passes the events and calls in a block to the event and call
+    /// handlers of `data_source`, and then the block itself to its block
+    /// handlers.
+    fn handle_block<T, L>(
+        logger: &Logger,
+        data_source: &DataSource,
+        module: &mut WasmiModule<T, L>,
+        status: &Mutex<RuntimeHostStatus>,
+        block_trigger: BlockTrigger,
+    ) where
+        T: EthereumAdapter + 'static,
+        L: LinkResolver + 'static,
+    {
+        let BlockTrigger {
+            block,
+            events,
+            calls,
+            called,
+        } = block_trigger;
+
+        debug!(logger, "Ethereum block received";
+               "number" => block.number.to_string(),
+               "hash" => block.hash.to_string());
+
+        let block_number = block.number.as_u64();
+
+        for event in events {
+            Self::handle_event(logger, data_source, module, status, event);
+        }
+
+        for call in calls {
+            Self::handle_call(logger, data_source, module, status, block_number, call);
+        }
+
+        for block_handler in data_source.mapping.block_handlers.iter() {
+            let skip = match block_handler.filter {
+                Some(BlockHandlerFilter::Call) => !called,
+                None => false,
+            };
+            if skip {
+                continue;
+            }
+
+            debug!(logger, "  Call block handler";
+                   "name" => &block_handler.handler);
+
+            let result =
+                module.handle_ethereum_block(block_handler.handler.as_str(), block.clone());
+            Self::record_handler_result(status, &block_handler.handler, block_number, result);
+        }
+
+        status.lock().unwrap().latest_block = Some(block_number);
+    }
+
+    /// Passes `event` to the matching event handler of `data_source`.
+    fn handle_event<T, L>(
+        logger: &Logger,
+        data_source: &DataSource,
+        module: &mut WasmiModule<T, L>,
+        status: &Mutex<RuntimeHostStatus>,
+        event: EthereumEvent,
+    ) where
+        T: EthereumAdapter + 'static,
+        L: LinkResolver + 'static,
+    {
+        info!(logger, "Ethereum event received");
+
+        if event.removed {
+            info!(logger, "Event removed";
+                  "block" => event.block_hash.to_string());
+        } else {
+            let event_handler = data_source
+                .mapping
+                .event_handlers
+                .iter()
+                .find(|event_handler| {
+                    util::ethereum::string_to_h256(event_handler.event.as_str())
+                        == event.event_signature
+                })
+                .expect("Received an Ethereum event not mentioned in the data set")
+                .to_owned();
+
+            debug!(logger, "  Call event handler";
+                   "name" => &event_handler.handler);
+
+            let block_number = event.block_number.as_u64();
+            let result = module.handle_ethereum_event(event_handler.handler.as_str(), event);
+            Self::record_handler_result(status, &event_handler.handler, block_number, result);
+        }
+    }
+
+    /// Passes `call` to the matching call handler of `data_source`.
+    fn handle_call<T, L>(
+        logger: &Logger,
+        data_source: &DataSource,
+        module: &mut WasmiModule<T, L>,
+        status: &Mutex<RuntimeHostStatus>,
+        block_number: u64,
+        call: EthereumCall,
+    ) where
+        T: EthereumAdapter + 'static,
+        L: LinkResolver + 'static,
+    {
+        info!(logger, "Ethereum call received");
+
+        let call_handler = data_source
+            .mapping
+            .call_handlers
+            .iter()
+            .find(|call_handler| call_handler.function.replace(" ", "") == call.function_signature)
+            .expect("Received an Ethereum call not mentioned in the data set")
+            .to_owned();
+
+        debug!(logger, "  Call call handler";
+               "name" => &call_handler.handler);
+
+        let result = module.handle_ethereum_call(call_handler.handler.as_str(), call);
+        Self::record_handler_result(status, &call_handler.handler, block_number, result);
+    }
+
+    /// Records a failed handler as a non-fatal error; the event, call or
+    /// block it failed for is skipped.
+    fn record_handler_result(
+        status: &Mutex<RuntimeHostStatus>,
+        handler: &str,
+        block_number: u64,
+        result: Result<(), String>,
+    ) {
+        if let Err(message) = result {
+            let mut status = status.lock().unwrap();
+            if status.non_fatal_errors.len() >= MAX_NON_FATAL_ERRORS {
+                status.non_fatal_errors.remove(0);
+            }
+            status.non_fatal_errors.push(SubgraphError {
+                message,
+                block_number: Some(block_number),
+                handler: Some(handler.to_owned()),
+            });
+        }
+    }
 
+    /// Records the error that stopped the host from processing any further
+    /// events or blocks.
+    fn record_fatal_error(status: &Mutex<RuntimeHostStatus>, message: String) {
+        let mut status = status.lock().unwrap();
+        let block_number = status.latest_block;
+        status.fatal_error = Some(SubgraphError {
+            message,
+            block_number,
+            handler: None,
+        });
+    }
+
+    /// Obtain the contract address of the data source.
+    fn contract_address(data_source: &DataSource) -> Address {
+        Address::from_str(data_source.source.address.as_str())
+            .expect("Failed to parse contract address")
+    }
+
+    /// Looks up the contract events for all event handlers of `data_source`.
+    fn handled_events(data_source: &DataSource) -> Vec<Event> {
         // Load the main dataset contract.
-        let contract = data_source
+        let contract = &data_source
             .mapping
             .abis
             .iter()
             .find(|abi| abi.name == data_source.source.abi)
             .expect("No ABI entry found for the main contract of the dataset")
-            .contract
-            .clone();
-
-        // Prepare subscriptions for all events.
-        let event_stream = {
-            let subscription_results = {
-                data_source
-                    .mapping
-                    .event_handlers
-                    .iter()
-                    .map(|event_handler| {
-                        let subscription_id = Uuid::new_v4().simple().to_string();
-                        let event = util::ethereum::contract_event_with_signature(
-                            &contract,
-                            event_handler.event.as_str(),
-                        ).expect(
-                            format!("Event not found in contract: {}", event_handler.event)
-                                .as_str(),
-                        );
-
-                        EthereumEventSubscription {
-                            address,
-                            event: event.clone(),
-                            range: BlockNumberRange {
-                                from: BlockNumber::Number(0),
-                                to: BlockNumber::Latest,
-                            },
-                            subscription_id: subscription_id.clone(),
-                        }
-                    })
-            };
+            .contract;
 
-            // Merge all event streams.
-            let mut event_stream: Box<Stream<Item = _, Error = _>> = Box::new(stream::empty());
-            for subscription in subscription_results {
-                info!(logger, "Subscribe to event"; "name" => &subscription.event.name);
-
-                event_stream = Box::new(
-                    event_stream.select(
-                        ethereum_adapter
-                            .lock()
-                            .unwrap()
-                            .subscribe_to_event(subscription),
-                    ),
-                );
-            }
-            event_stream
+        data_source
+            .mapping
+            .event_handlers
+            .iter()
+            .map(|event_handler| {
+                util::ethereum::contract_event_with_signature(
+                    contract,
+                    event_handler.event.as_str(),
+                ).expect(format!("Event not found in contract: {}", event_handler.event).as_str())
+                    .clone()
+            })
+            .collect()
+    }
+
+    /// Looks up the contract functions for all call handlers of
+    /// `data_source`.
+    fn handled_functions(data_source: &DataSource) -> Vec<Function> {
+        let contract = &data_source
+            .mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == data_source.source.abi)
+            .expect("No ABI entry found for the main contract of the dataset")
+            .contract;
+
+        data_source
+            .mapping
+            .call_handlers
+            .iter()
+            .map(|call_handler| {
+                util::ethereum::contract_function_with_signature(
+                    contract,
+                    call_handler.function.as_str(),
+                ).expect(
+                    format!("Function not found in contract: {}", call_handler.function).as_str(),
+                )
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+/// What the handlers of a data source are called for.
+enum Trigger {
+    /// An event, for the matching event handler.
+    Event(EthereumEvent),
+    /// A block, for the event, call and block handlers.
+    Block(BlockTrigger),
+}
+
+/// A block along with the events and calls of the data source in it.
+struct BlockTrigger {
+    block: EthereumBlock,
+    events: Vec<EthereumEvent>,
+    calls: Vec<EthereumCall>,
+    /// Whether the contract of the data source was called in the block.
+    called: bool,
+}
+
+/// Streams the triggers of a data source on the dispatcher thread and hands
+/// each of them to a WASM worker.
+struct DataSourceTask<T, L> {
+    logger: Logger,
+    wasmi_config: WasmiModuleConfig<T, L>,
+    worker_pool: WorkerPool,
+    assignment: Assignment,
+    subscriptions: Arc<Mutex<HostSubscriptions>>,
+    status: Arc<Mutex<RuntimeHostStatus>>,
+}
+
+impl<T, L> WorkerTask for DataSourceTask<T, L>
+where
+    T: EthereumAdapter,
+    L: LinkResolver,
+{
+    fn run(self: Box<Self>, _worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>> {
+        let DataSourceTask {
+            logger,
+            wasmi_config,
+            worker_pool,
+            assignment,
+            subscriptions,
+            status,
+        } = *self;
+        let data_source = wasmi_config.data_source.clone();
+
+        // Process one event (or block) at a time. Call and block handlers
+        // require looking at every block. The streams end when the host is
+        // dropped and its subscriptions are cancelled.
+        let block_driven = !data_source.mapping.block_handlers.is_empty()
+            || !data_source.mapping.call_handlers.is_empty();
+        let stream: Box<Stream<Item = (), Error = ()>> = if block_driven {
+            Box::new(RuntimeHost::subscribe_to_blocks(
+                &logger,
+                wasmi_config,
+                worker_pool.clone(),
+                assignment,
+                subscriptions,
+                status,
+            ))
+        } else {
+            Box::new(RuntimeHost::subscribe_to_events(
+                &logger,
+                wasmi_config,
+                worker_pool.clone(),
+                assignment,
+                subscriptions,
+                status,
+            ))
         };
 
-        let event_logger = logger.clone();
-        let error_logger = logger.clone();
+        Box::new(stream.for_each(|()| Ok(())).then(move |result| {
+            // The event sender has been dropped with the stream, so the
+            // host's output stream ends once it is drained; the module
+            // instance is dropped by the worker
+            worker_pool.release(assignment);
+            info!(logger, "WASM runtime stopped");
+            result
+        }))
+    }
+}
 
-        event_stream
-            .map(move |event| {
-                info!(event_logger, "Ethereum event received");
+/// Handles a single trigger of a data source on the WASM worker thread it
+/// is assigned to, with the module instance that the worker keeps for the
+/// data source.
+struct TriggerTask<T, L> {
+    logger: Logger,
+    assignment: Assignment,
+    wasmi_config: WasmiModuleConfig<T, L>,
+    status: Arc<Mutex<RuntimeHostStatus>>,
+    trigger: Trigger,
+    /// Completed once the trigger has been handled.
+    done: oneshot::Sender<()>,
+}
 
-                if event.removed {
-                    info!(event_logger, "Event removed";
-                              "block" => event.block_hash.to_string());
-                } else {
-                    let event_handler = data_source
-                        .mapping
-                        .event_handlers
-                        .iter()
-                        .find(|event_handler| {
-                            util::ethereum::string_to_h256(event_handler.event.as_str())
-                                == event.event_signature
-                        })
-                        .expect("Received an Ethereum event not mentioned in the data set")
-                        .to_owned();
-
-                    debug!(event_logger, "  Call event handler";
-                               "name" => &event_handler.handler);
-
-                    module.handle_ethereum_event(event_handler.handler.as_str(), event);
+impl<T, L> WorkerTask for TriggerTask<T, L>
+where
+    T: EthereumAdapter,
+    L: LinkResolver,
+{
+    fn run(self: Box<Self>, worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>> {
+        let TriggerTask {
+            logger,
+            assignment,
+            wasmi_config,
+            status,
+            trigger,
+            done,
+        } = *self;
+
+        // A panic must not take the worker down with it; dropping `done`
+        // tells the dispatcher that the trigger could not be handled. The
+        // instance is taken out of the worker while it runs, so that one
+        // that panicked is not used again
+        let _ = panic::catch_unwind(AssertUnwindSafe(move || {
+            let data_source = wasmi_config.data_source.clone();
+            let mut module = match worker.instances.take(assignment.id) {
+                Some(module) => module,
+                None => WasmiModule::new(&logger, wasmi_config, &worker.modules),
+            };
+
+            match trigger {
+                Trigger::Event(event) => {
+                    let block_number = event.block_number.as_u64();
+                    RuntimeHost::handle_event(&logger, &data_source, &mut module, &status, event);
+                    status.lock().unwrap().latest_block = Some(block_number);
                 }
-            })
-            .map_err(move |e| error!(error_logger, "Event subscription failed: {}", e))
+                Trigger::Block(block_trigger) => RuntimeHost::handle_block(
+                    &logger,
+                    &data_source,
+                    &mut module,
+                    &status,
+                    block_trigger,
+                ),
+            }
+
+            worker.instances.insert(assignment.id, module);
+            let _ = done.send(());
+        }));
+
+        Box::new(future::ok(()))
     }
 }
 
@@ -238,4 +761,32 @@ impl RuntimeHostTrait for RuntimeHost {
     fn subgraph_manifest(&self) -> &SubgraphManifest {
         &self.config.subgraph_manifest
     }
+
+    fn is_caught_up(&self) -> bool {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        let ethereum_adapter = self.ethereum_adapter.lock().unwrap();
+        subscriptions.subscribed
+            && subscriptions
+                .subscription_ids
+                .iter()
+                .all(|subscription_id| ethereum_adapter.is_caught_up(subscription_id))
+    }
+
+    fn status(&self) -> RuntimeHostStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+impl Drop for RuntimeHost {
+    fn drop(&mut self) {
+        // Cancel all subscriptions of the host; this ends its event or block
+        // stream on the dispatcher thread
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.cancelled = true;
+
+        let mut ethereum_adapter = self.ethereum_adapter.lock().unwrap();
+        for subscription_id in subscriptions.subscription_ids.drain(..) {
+            ethereum_adapter.unsubscribe_from_event(subscription_id);
+        }
+    }
 }
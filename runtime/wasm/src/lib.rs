@@ -1,9 +1,12 @@
+extern crate bs58;
 extern crate ethabi;
 extern crate ethereum_types;
 extern crate futures;
 extern crate graph;
 extern crate hex;
 extern crate nan_preserving_float;
+extern crate parity_wasm;
+extern crate tiny_keccak;
 extern crate uuid;
 extern crate wasmi;
 extern crate web3;
@@ -11,6 +14,7 @@ extern crate web3;
 mod asc_abi;
 mod host;
 mod module;
+mod pool;
 mod to_from;
 
 pub use self::host::{RuntimeHost, RuntimeHostBuilder, RuntimeHostConfig};
@@ -1,9 +1,12 @@
+use ethabi;
 use ethereum_types::{H160, H256, U256};
 use futures::sync::mpsc::Sender;
+use graph::semver::Version;
 use graph::serde_json;
 use nan_preserving_float::F64;
 use std::collections::HashMap;
 use std::fmt;
+use std::mem;
 use std::str::FromStr;
 use std::sync::Mutex;
 
@@ -15,17 +18,21 @@ use wasmi::{
 use web3::types::BlockId;
 
 use graph::components::ethereum::*;
-use graph::components::store::{EventSource, StoreKey};
+use graph::components::store::{EntityOperation, EventSource, StoreKey};
 use graph::components::subgraph::RuntimeHostEvent;
 use graph::data::store::scalar;
 use graph::data::subgraph::DataSource;
 use graph::prelude::*;
+use graph::util::metrics;
 
 use super::UnresolvedContractCall;
+use pool::ModuleCache;
 use asc_abi::asc_ptr::*;
 use asc_abi::class::*;
 use asc_abi::*;
+use bs58;
 use hex;
+use tiny_keccak;
 
 /// AssemblyScript-compatible WASM memory heap.
 #[derive(Clone)]
@@ -85,10 +92,27 @@ const JSON_TO_U64_FUNC_INDEX: usize = 16;
 const JSON_TO_F64_FUNC_INDEX: usize = 17;
 const JSON_TO_BIG_INT_FUNC_INDEX: usize = 18;
 const IPFS_CAT_FUNC_INDEX: usize = 19;
+const CRYPTO_KECCAK_256_FUNC_INDEX: usize = 20;
+const TYPE_CONVERSION_STRING_TO_BYTES_FUNC_INDEX: usize = 21;
+const TYPE_CONVERSION_BYTES_TO_BASE_58_FUNC_INDEX: usize = 22;
+const TYPE_CONVERSION_BASE_58_TO_BYTES_FUNC_INDEX: usize = 23;
+const LOG_DEBUG_FUNC_INDEX: usize = 24;
+const LOG_INFO_FUNC_INDEX: usize = 25;
+const LOG_WARNING_FUNC_INDEX: usize = 26;
+const LOG_ERROR_FUNC_INDEX: usize = 27;
+const LOG_CRITICAL_FUNC_INDEX: usize = 28;
+const ETHEREUM_ENCODE_FUNC_INDEX: usize = 29;
+const ETHEREUM_DECODE_FUNC_INDEX: usize = 30;
+const DATA_SOURCE_ADDRESS_FUNC_INDEX: usize = 31;
+const DATA_SOURCE_NETWORK_FUNC_INDEX: usize = 32;
+const DATA_SOURCE_NAME_FUNC_INDEX: usize = 33;
+const DATA_SOURCE_SUBGRAPH_ID_FUNC_INDEX: usize = 34;
+const DATA_SOURCE_CREATE_FUNC_INDEX: usize = 35;
+const IPFS_MAP_FUNC_INDEX: usize = 36;
 
 pub struct WasmiModuleConfig<T, L> {
-    pub subgraph: SubgraphManifest,
-    pub data_source: DataSource,
+    pub subgraph: Arc<SubgraphManifest>,
+    pub data_source: Arc<DataSource>,
     pub event_sink: Sender<RuntimeHostEvent>,
     pub ethereum_adapter: Arc<Mutex<T>>,
     pub link_resolver: Arc<L>,
@@ -119,11 +143,16 @@ where
     T: EthereumAdapter,
     L: LinkResolver,
 {
-    /// Creates a new wasmi module
-    pub fn new(logger: &Logger, config: WasmiModuleConfig<T, L>) -> Self {
+    /// Creates a new wasmi module, reusing the compiled module from
+    /// `modules` if the same code has been compiled before
+    pub fn new(logger: &Logger, config: WasmiModuleConfig<T, L>, modules: &ModuleCache) -> Self {
         let logger = logger.new(o!("component" => "WasmiModule"));
 
-        let module = Module::from_parity_wasm_module(config.data_source.mapping.runtime.clone())
+        let module = modules
+            .get_or_compile(
+                config.data_source.mapping.runtime_hash,
+                &config.data_source.mapping.runtime,
+            )
             .expect(
                 format!(
                     "Wasmi could not interpret module of data source: {}",
@@ -133,12 +162,36 @@ where
 
         // Build import resolver
         let mut imports = ImportsBuilder::new();
+        // Which host functions the mapping can import depends on its API
+        // version
+        let api_version = &config.data_source.mapping.api_version;
+        let ethereum_resolver = EthereumModuleResolver {
+            api_version: api_version.clone(),
+        };
+        let type_conversion_resolver = TypeConversionModuleResolver {
+            api_version: api_version.clone(),
+        };
+        let ipfs_resolver = IpfsModuleResolver {
+            api_version: api_version.clone(),
+        };
+        let crypto_resolver = CryptoModuleResolver {
+            api_version: api_version.clone(),
+        };
+        let log_resolver = LogModuleResolver {
+            api_version: api_version.clone(),
+        };
+        let data_source_resolver = DataSourceModuleResolver {
+            api_version: api_version.clone(),
+        };
         imports.push_resolver("env", &EnvModuleResolver);
         imports.push_resolver("store", &StoreModuleResolver);
-        imports.push_resolver("ethereum", &EthereumModuleResolver);
-        imports.push_resolver("typeConversion", &TypeConversionModuleResolver);
+        imports.push_resolver("ethereum", &ethereum_resolver);
+        imports.push_resolver("typeConversion", &type_conversion_resolver);
         imports.push_resolver("json", &JsonModuleResolver);
-        imports.push_resolver("ipfs", &IpfsModuleResolver);
+        imports.push_resolver("ipfs", &ipfs_resolver);
+        imports.push_resolver("crypto", &crypto_resolver);
+        imports.push_resolver("log", &log_resolver);
+        imports.push_resolver("dataSource", &data_source_resolver);
 
         // Instantiate the runtime module using hosted functions and import resolver
         let module =
@@ -158,14 +211,19 @@ where
 
         // Create new instance of externally hosted functions invoker
         let mut externals = HostExternals {
-            subgraph: config.subgraph,
-            data_source: config.data_source,
+            subgraph: config.subgraph.clone(),
+            data_source: config.data_source.clone(),
             logger: logger.clone(),
             event_sink: config.event_sink.clone(),
             heap: heap.clone(),
             ethereum_adapter: config.ethereum_adapter.clone(),
             link_resolver: config.link_resolver.clone(),
             block_hash: H256::zero(),
+            block_number: U256::zero(),
+            handler: String::new(),
+            entity_operations: vec![],
+            config,
+            modules: modules.clone(),
         };
 
         let module = module
@@ -180,23 +238,130 @@ where
         }
     }
 
-    pub fn handle_ethereum_event(&mut self, handler_name: &str, event: EthereumEvent) {
+    pub fn handle_ethereum_event(
+        &mut self,
+        handler_name: &str,
+        event: EthereumEvent,
+    ) -> Result<(), String> {
         self.externals.block_hash = event.block_hash.clone();
-        self.module
+        self.externals.block_number = event.block_number;
+        self.externals.handler = handler_name.to_owned();
+        let _timer = metrics::HANDLER_EXECUTION_TIME
+            .with_label_values(&[self.externals.subgraph.id.as_str(), handler_name])
+            .start_timer();
+        let result = self
+            .module
             .invoke_export(
                 handler_name,
                 &[RuntimeValue::from(self.heap.asc_new(&event))],
                 &mut self.externals,
             )
-            .unwrap_or_else(|e| {
+            .map(|_| ())
+            .map_err(|e| {
+                let error = format_handler_error(&e);
                 warn!(self.logger, "Failed to handle Ethereum event";
                       "handler" => &handler_name,
-                      "error" => format!("{}", e));
-                None
+                      "block_hash" => format!("{:x}", event.block_hash),
+                      "error" => &error);
+                error
+            });
+        self.externals.finish_handler(result.is_ok());
+        result
+    }
+
+    pub fn handle_ethereum_call(
+        &mut self,
+        handler_name: &str,
+        call: EthereumCall,
+    ) -> Result<(), String> {
+        self.externals.block_hash = call.block_hash.clone();
+        self.externals.block_number = call.block_number;
+        self.externals.handler = handler_name.to_owned();
+        let _timer = metrics::HANDLER_EXECUTION_TIME
+            .with_label_values(&[self.externals.subgraph.id.as_str(), handler_name])
+            .start_timer();
+        let result = self
+            .module
+            .invoke_export(
+                handler_name,
+                &[RuntimeValue::from(self.heap.asc_new(&call))],
+                &mut self.externals,
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                let error = format_handler_error(&e);
+                warn!(self.logger, "Failed to handle Ethereum call";
+                      "handler" => &handler_name,
+                      "block_hash" => format!("{:x}", call.block_hash),
+                      "transaction_hash" => format!("{:x}", call.transaction_hash),
+                      "error" => &error);
+                error
             });
+        self.externals.finish_handler(result.is_ok());
+        result
+    }
+
+    pub fn handle_ethereum_block(
+        &mut self,
+        handler_name: &str,
+        block: EthereumBlock,
+    ) -> Result<(), String> {
+        self.externals.block_hash = block.hash.clone();
+        self.externals.block_number = block.number;
+        self.externals.handler = handler_name.to_owned();
+        let _timer = metrics::HANDLER_EXECUTION_TIME
+            .with_label_values(&[self.externals.subgraph.id.as_str(), handler_name])
+            .start_timer();
+        let result = self
+            .module
+            .invoke_export(
+                handler_name,
+                &[RuntimeValue::from(self.heap.asc_new(&block))],
+                &mut self.externals,
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                let error = format_handler_error(&e);
+                warn!(self.logger, "Failed to handle Ethereum block";
+                      "handler" => &handler_name,
+                      "block_hash" => format!("{:x}", block.hash),
+                      "error" => &error);
+                error
+            });
+        self.externals.finish_handler(result.is_ok());
+        result
+    }
+}
+
+/// Formats an error returned from invoking a handler. Errors raised by host
+/// functions (e.g. an `abort` from the mapping) are unwrapped so that their
+/// message is reported as is.
+fn format_handler_error(e: &Error) -> String {
+    match e {
+        Error::Trap(trap) => match trap.kind() {
+            TrapKind::Host(host_error) => format!("{}", host_error),
+            kind => format!("{:?}", kind),
+        },
+        e => format!("{}", e),
     }
 }
 
+/// Replaces each `{}` placeholder in `message` with the next argument.
+/// Placeholders without a matching argument are left as they are.
+fn format_log_message(message: &str, args: &[String]) -> String {
+    let mut args = args.iter();
+    let mut parts = message.split("{}");
+    let mut formatted = String::from(parts.next().unwrap_or(""));
+    for part in parts {
+        match args.next() {
+            Some(arg) => formatted.push_str(arg),
+            None => formatted.push_str("{}"),
+        }
+        formatted.push_str(part);
+    }
+    formatted
+}
+
 /// Error raised in host functions.
 #[derive(Debug)]
 struct HostExternalsError<E>(E);
@@ -218,14 +383,25 @@ fn host_error(message: String) -> Trap {
 /// Hosted functions for external use by wasm module
 pub struct HostExternals<T, L> {
     logger: Logger,
-    subgraph: SubgraphManifest,
-    data_source: DataSource,
+    subgraph: Arc<SubgraphManifest>,
+    data_source: Arc<DataSource>,
     event_sink: Sender<RuntimeHostEvent>,
     heap: WasmiAscHeap,
     ethereum_adapter: Arc<Mutex<T>>,
     link_resolver: Arc<L>,
     // Block hash of the event being mapped.
     block_hash: H256,
+    // Block number of the event being mapped.
+    block_number: U256,
+    // Name of the handler that is currently running.
+    handler: String,
+    // Entity operations of the handler that is currently running, which
+    // are applied together once it succeeds.
+    entity_operations: Vec<EntityOperation>,
+    // What the module was instantiated from, so that `ipfs.map` can run
+    // its callbacks in fresh instances.
+    config: WasmiModuleConfig<T, L>,
+    modules: ModuleCache,
 }
 
 impl<T, L> HostExternals<T, L>
@@ -233,14 +409,80 @@ where
     T: EthereumAdapter,
     L: LinkResolver,
 {
+    /// Sends the entity operations of the handler that just ran as one
+    /// event if it succeeded and discards them if it failed, so that the
+    /// changes of a handler are applied either all together or not at all.
+    fn finish_handler(&mut self, succeeded: bool) {
+        let operations = mem::replace(&mut self.entity_operations, vec![]);
+        if !succeeded || operations.is_empty() {
+            return;
+        }
+
+        let logger = self.logger.clone();
+        self.event_sink
+            .clone()
+            .send(RuntimeHostEvent::EntityOperations(
+                operations,
+                EventSource::EthereumBlock(self.block_hash.clone()),
+            ))
+            .map_err(move |e| {
+                error!(logger, "Failed to forward runtime host event";
+                        "error" => format!("{}", e));
+            })
+            .wait()
+            .ok();
+    }
+
+    /// Called by AssemblyScript when an assertion fails or an error is
+    /// thrown. The message and file name may be null.
+    ///
+    /// function abort(message: string, fileName: string, lineNumber: u32, columnNumber: u32): void
+    fn abort(
+        &self,
+        message_ptr: AscPtr<AscString>,
+        file_name_ptr: AscPtr<AscString>,
+        line_number: u32,
+        column_number: u32,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let message: Option<String> = if message_ptr.is_null() {
+            None
+        } else {
+            Some(self.heap.asc_get(message_ptr))
+        };
+        let file_name: Option<String> = if file_name_ptr.is_null() {
+            None
+        } else {
+            Some(self.heap.asc_get(file_name_ptr))
+        };
+
+        let location = match file_name {
+            Some(file_name) => format!(
+                "{}, line {}, column {}",
+                file_name, line_number, column_number
+            ),
+            None => String::from("unknown location"),
+        };
+        let message = match message {
+            Some(message) => format!("Mapping aborted at {}, with message: {}", location, message),
+            None => format!("Mapping aborted at {}, without a message", location),
+        };
+
+        error!(self.logger, "{}", message;
+               "subgraph_id" => &self.subgraph.id,
+               "data_source" => &self.data_source.name,
+               "handler" => &self.handler,
+               "block_hash" => format!("{:x}", self.block_hash));
+
+        Err(host_error(message))
+    }
+
     /// function store.set(entity: string, id: string, data: Entity): void
     fn store_set(
-        &self,
+        &mut self,
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
         data_ptr: AscPtr<AscEntity>,
     ) -> Result<Option<RuntimeValue>, Trap> {
-        let block_hash: H256 = self.block_hash.clone();
         let entity: String = self.heap.asc_get(entity_ptr);
         let id: String = self.heap.asc_get(id_ptr);
         let data: HashMap<String, Value> = self.heap.asc_get(data_ptr);
@@ -250,34 +492,19 @@ where
             id,
         };
 
-        let entity_data = Entity::from(data);
-
-        // Send an entity set event
-        let logger = self.logger.clone();
-        self.event_sink
-            .clone()
-            .send(RuntimeHostEvent::EntitySet(
-                store_key,
-                entity_data,
-                EventSource::EthereumBlock(block_hash),
-            ))
-            .map_err(move |e| {
-                error!(logger, "Failed to forward runtime host event";
-                        "error" => format!("{}", e));
-            })
-            .wait()
-            .ok();
-
+        self.entity_operations.push(EntityOperation::Set {
+            key: store_key,
+            data: Entity::from(data),
+        });
         Ok(None)
     }
 
     /// function store.remove(entity: string, id: string): void
     fn store_remove(
-        &self,
+        &mut self,
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
     ) -> Result<Option<RuntimeValue>, Trap> {
-        let block_hash: H256 = self.block_hash.clone();
         let entity: String = self.heap.asc_get(entity_ptr);
         let id: String = self.heap.asc_get(id_ptr);
         let store_key = StoreKey {
@@ -286,22 +513,8 @@ where
             id,
         };
 
-        // Send an entity removed event
-        let logger = self.logger.clone();
-        self.event_sink
-            .clone()
-            .send(RuntimeHostEvent::EntityRemoved(
-                store_key,
-                EventSource::EthereumBlock(block_hash),
-            ))
-            .map_err(move |e| {
-                error!(logger, "Failed to forward runtime host event";
-                        "error" => format!("{}", e));
-            })
-            .and_then(|_| Ok(()))
-            .wait()
-            .ok();
-
+        self.entity_operations
+            .push(EntityOperation::Remove { key: store_key });
         Ok(None)
     }
 
@@ -346,10 +559,12 @@ where
             args: unresolved_call.function_args.clone(),
         };
 
-        self.ethereum_adapter
-            .lock()
-            .unwrap()
-            .contract_call(call)
+        metrics::ETHEREUM_CALLS.inc();
+        let _timer = metrics::ETHEREUM_CALL_TIME.start_timer();
+        // Only hold on to the adapter while making the call, not while
+        // waiting for its result
+        let result = self.ethereum_adapter.lock().unwrap().contract_call(call);
+        result
             .wait()
             .map(|result| Some(RuntimeValue::from(self.heap.asc_new(&*result))))
             .map_err(|e| {
@@ -360,13 +575,75 @@ where
             })
     }
 
+    /// ABI-encodes a single value.
+    ///
+    /// function ethereum.encode(token: ethereum.Value): Bytes
+    fn ethereum_encode(
+        &self,
+        token_ptr: AscPtr<AscEnum<EthereumValueKind>>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let token: ethabi::Token = self.heap.asc_get(token_ptr);
+        let bytes = ethabi::encode(&[token]);
+        let bytes_obj: AscPtr<Uint8Array> = self.heap.asc_new(&*bytes);
+        Ok(Some(RuntimeValue::from(bytes_obj)))
+    }
+
+    /// ABI-decodes a single value of the type given by `type_signature`
+    /// (e.g. `uint256` or `address[]`). Returns null if the data cannot be
+    /// decoded as that type.
+    ///
+    /// function ethereum.decode(typeSignature: string, data: Bytes): ethereum.Value | null
+    fn ethereum_decode(
+        &self,
+        type_signature_ptr: AscPtr<AscString>,
+        data_ptr: AscPtr<Uint8Array>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let type_signature: String = self.heap.asc_get(type_signature_ptr);
+        let data: Vec<u8> = self.heap.asc_get(data_ptr);
+
+        let param_type = ethabi::param_type::Reader::read(&type_signature).map_err(|e| {
+            host_error(format!(
+                "Invalid type signature `{}` passed to ethereum.decode: {}",
+                type_signature, e
+            ))
+        })?;
+
+        let token = ethabi::decode(&[param_type], &data)
+            .map_err(|e| {
+                warn!(self.logger, "Failed to ABI-decode data";
+                      "type_signature" => &type_signature,
+                      "error" => format!("{}", e));
+            })
+            .ok()
+            .and_then(|mut tokens| tokens.pop());
+
+        Ok(Some(match token {
+            Some(token) => {
+                let token_obj: AscPtr<AscEnum<EthereumValueKind>> = self.heap.asc_new(&token);
+                RuntimeValue::from(token_obj)
+            }
+            None => RuntimeValue::from(AscPtr::<AscEnum<EthereumValueKind>>::null()),
+        }))
+    }
+
+    /// Invalid UTF-8 sequences are reported in the logs and replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER` rather than trapping.
+    ///
     /// function typeConversion.bytesToString(bytes: Bytes): string
     fn convert_bytes_to_string(
         &self,
         bytes_ptr: AscPtr<Uint8Array>,
     ) -> Result<Option<RuntimeValue>, Trap> {
         let bytes: Vec<u8> = self.heap.asc_get(bytes_ptr);
-        let s = String::from_utf8_lossy(&*bytes);
+        let s = match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(self.logger, "Bytes converted to string are not valid UTF-8";
+                      "valid_up_to" => e.utf8_error().valid_up_to(),
+                      "bytes" => format!("0x{}", hex::encode(e.as_bytes())));
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+        };
         // The string may have been encoded in a fixed length
         // buffer and padded with null characters, so trim
         // trailing nulls.
@@ -374,6 +651,33 @@ where
         Ok(Some(RuntimeValue::from(self.heap.asc_new(trimmed_s))))
     }
 
+    /// Encodes the string as UTF-8.
+    ///
+    /// function typeConversion.stringToBytes(s: string): Bytes
+    fn string_to_bytes(&self, str_ptr: AscPtr<AscString>) -> Result<Option<RuntimeValue>, Trap> {
+        let s: String = self.heap.asc_get(str_ptr);
+        let bytes_obj: AscPtr<Uint8Array> = self.heap.asc_new(s.as_bytes());
+        Ok(Some(RuntimeValue::from(bytes_obj)))
+    }
+
+    /// function typeConversion.bytesToBase58(bytes: Bytes): string
+    fn bytes_to_base58(&self, bytes_ptr: AscPtr<Uint8Array>) -> Result<Option<RuntimeValue>, Trap> {
+        let bytes: Vec<u8> = self.heap.asc_get(bytes_ptr);
+        let base58_string = bs58::encode(&bytes).into_string();
+        let base58_string_obj = self.heap.asc_new(base58_string.as_str());
+        Ok(Some(RuntimeValue::from(base58_string_obj)))
+    }
+
+    /// function typeConversion.base58ToBytes(s: string): Bytes
+    fn base58_to_bytes(&self, str_ptr: AscPtr<AscString>) -> Result<Option<RuntimeValue>, Trap> {
+        let s: String = self.heap.asc_get(str_ptr);
+        let bytes = bs58::decode(&s)
+            .into_vec()
+            .map_err(|e| host_error(format!("Failed to decode base58 string `{}`: {}", s, e)))?;
+        let bytes_obj: AscPtr<Uint8Array> = self.heap.asc_new(&*bytes);
+        Ok(Some(RuntimeValue::from(bytes_obj)))
+    }
+
     /// function typeConversion.u64ArrayToString(u64_array: U64Array): string
     fn u64_array_to_string(
         &self,
@@ -505,6 +809,219 @@ where
         Ok(Some(RuntimeValue::from(bytes_obj)))
     }
 
+    /// Calls the exported function named `callback` with every JSON value
+    /// in the newline-delimited JSON file `link`, and `user_data`. The file
+    /// is streamed and each callback runs in a fresh instance of the module,
+    /// so the file may be larger than the memory of the module.
+    ///
+    /// The entity changes made by the callbacks are applied together with
+    /// the other changes of the handler; if any callback or the handler
+    /// fails, none of them are applied.
+    ///
+    /// function ipfs.map(link: String, callback: String, userData: Value): void
+    fn ipfs_map(
+        &mut self,
+        link_ptr: AscPtr<AscString>,
+        callback_ptr: AscPtr<AscString>,
+        user_data_ptr: AscPtr<AscEnum<StoreValueKind>>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let link: String = self.heap.asc_get(link_ptr);
+        let callback: String = self.heap.asc_get(callback_ptr);
+
+        let operations_before = self.entity_operations.len();
+        let count = self.map_json_values(&link, &callback, user_data_ptr)?;
+
+        info!(self.logger, "Mapped JSON values from IPFS";
+              "link" => &link,
+              "callback" => &callback,
+              "values" => count,
+              "entity_operations" => self.entity_operations.len() - operations_before);
+
+        Ok(None)
+    }
+
+    /// Invokes `callback` with each value of the JSON file at `link`, each
+    /// time in a fresh instance of the module whose memory is dropped with
+    /// it, so that the values don't pile up in memory. The entity operations
+    /// of the callbacks are added to those of the handler. Returns the
+    /// number of values.
+    fn map_json_values(
+        &mut self,
+        link: &str,
+        callback: &str,
+        user_data_ptr: AscPtr<AscEnum<StoreValueKind>>,
+    ) -> Result<usize, Trap> {
+        let user_data: Value = self.heap.asc_get(user_data_ptr);
+        let values = self.link_resolver.json_stream(&Link {
+            link: link.to_owned(),
+        });
+
+        let mut count = 0;
+        for value in values.wait() {
+            let value = value
+                .map_err(|e| host_error(format!("Failed to read JSON from {}: {}", link, e)))?;
+
+            let mut instance = WasmiModule::new(&self.logger, self.config.clone(), &self.modules);
+            instance.externals.block_hash = self.block_hash.clone();
+            instance.externals.block_number = self.block_number;
+            instance.externals.handler = self.handler.clone();
+            let value_ptr: AscPtr<AscEnum<JsonValueKind>> = instance.heap.asc_new(&value);
+            let user_data_ptr: AscPtr<AscEnum<StoreValueKind>> = instance.heap.asc_new(&user_data);
+            instance
+                .module
+                .invoke_export(
+                    callback,
+                    &[
+                        RuntimeValue::from(value_ptr),
+                        RuntimeValue::from(user_data_ptr),
+                    ],
+                    &mut instance.externals,
+                )
+                .map_err(|e| {
+                    host_error(format!(
+                        "Callback `{}` of ipfs.map failed: {}",
+                        callback,
+                        format_handler_error(&e)
+                    ))
+                })?;
+            self.entity_operations
+                .append(&mut instance.externals.entity_operations);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// function dataSource.address(): Address
+    fn data_source_address(&self) -> Result<Option<RuntimeValue>, Trap> {
+        let address = H160::from_str(&self.data_source.source.address).map_err(|e| {
+            host_error(format!(
+                "Failed to parse address of data source \"{}\": {}",
+                self.data_source.name, e
+            ))
+        })?;
+        let address_obj: AscPtr<AscH160> = self.heap.asc_new(&address);
+        Ok(Some(RuntimeValue::from(address_obj)))
+    }
+
+    /// Returns null if the data source does not specify a network.
+    ///
+    /// function dataSource.network(): string | null
+    fn data_source_network(&self) -> Result<Option<RuntimeValue>, Trap> {
+        Ok(Some(match self.data_source.network {
+            Some(ref network) => RuntimeValue::from(self.heap.asc_new(network.as_str())),
+            None => RuntimeValue::from(AscPtr::<AscString>::null()),
+        }))
+    }
+
+    /// function dataSource.name(): string
+    fn data_source_name(&self) -> Result<Option<RuntimeValue>, Trap> {
+        let name_obj = self.heap.asc_new(self.data_source.name.as_str());
+        Ok(Some(RuntimeValue::from(name_obj)))
+    }
+
+    /// function dataSource.subgraphId(): string
+    fn data_source_subgraph_id(&self) -> Result<Option<RuntimeValue>, Trap> {
+        let id_obj = self.heap.asc_new(self.subgraph.id.as_str());
+        Ok(Some(RuntimeValue::from(id_obj)))
+    }
+
+    /// Creates a new data source from the template named `name` that
+    /// watches the contract at `address`. The runtime manager starts a host
+    /// for it and persists it so that it is re-created after a restart.
+    ///
+    /// function dataSource.create(name: string, address: Address): void
+    fn data_source_create(
+        &self,
+        name_ptr: AscPtr<AscString>,
+        address_ptr: AscPtr<AscAddress>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let block_hash: H256 = self.block_hash.clone();
+        let name: String = self.heap.asc_get(name_ptr);
+        let address: H160 = self.heap.asc_get(address_ptr);
+
+        let data_source = self
+            .subgraph
+            .templates
+            .iter()
+            .find(|template| template.name == name)
+            .ok_or(host_error(format!(
+                "Unknown data source template \"{}\" used in dataSource.create",
+                name
+            )))?
+            .create_data_source(address, self.block_number.as_u64());
+
+        info!(self.logger, "Create data source from template";
+              "template" => &name,
+              "address" => format!("{:x}", address));
+
+        // Send a data source created event
+        let logger = self.logger.clone();
+        self.event_sink
+            .clone()
+            .send(RuntimeHostEvent::DataSourceCreated(
+                data_source,
+                EventSource::EthereumBlock(block_hash),
+            ))
+            .map_err(move |e| {
+                error!(logger, "Failed to forward runtime host event";
+                        "error" => format!("{}", e));
+            })
+            .wait()
+            .ok();
+
+        Ok(None)
+    }
+
+    /// function crypto.keccak256(input: Bytes): Bytes
+    fn crypto_keccak_256(
+        &self,
+        input_ptr: AscPtr<Uint8Array>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let input: Vec<u8> = self.heap.asc_get(input_ptr);
+        let hash = tiny_keccak::keccak256(&input);
+        let hash_obj: AscPtr<Uint8Array> = self.heap.asc_new(&hash[..]);
+        Ok(Some(RuntimeValue::from(hash_obj)))
+    }
+
+    /// Logs a message from the mapping, replacing each `{}` placeholder in
+    /// `message` with the next argument in `args`. Logging never fails the
+    /// running handler, not even at the critical level; mappings that want
+    /// to stop processing should call `abort` instead.
+    ///
+    /// function log.debug(message: string, args: Array<string>): void
+    /// function log.info(message: string, args: Array<string>): void
+    /// function log.warning(message: string, args: Array<string>): void
+    /// function log.error(message: string, args: Array<string>): void
+    /// function log.critical(message: string, args: Array<string>): void
+    fn log(
+        &self,
+        level: slog::Level,
+        message_ptr: AscPtr<AscString>,
+        args_ptr: AscPtr<Array<AscPtr<AscString>>>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let message: String = self.heap.asc_get(message_ptr);
+        let args: Vec<String> = self.heap.asc_get(args_ptr);
+        let message = format_log_message(&message, &args);
+
+        let logger = self.logger.new(o!(
+            "subgraph_id" => self.subgraph.id.clone(),
+            "data_source" => self.data_source.name.clone(),
+            "handler" => self.handler.clone(),
+            "block_number" => format!("{}", self.block_number),
+            "block_hash" => format!("{:x}", self.block_hash),
+        ));
+
+        match level {
+            slog::Level::Critical => crit!(logger, "{}", message),
+            slog::Level::Error => error!(logger, "{}", message),
+            slog::Level::Warning => warn!(logger, "{}", message),
+            slog::Level::Info => info!(logger, "{}", message),
+            slog::Level::Debug | slog::Level::Trace => debug!(logger, "{}", message),
+        }
+
+        Ok(None)
+    }
+
     /// Expects a decimal string.
     /// function json.toI64(json: String): i64
     fn json_to_i64(&self, json_ptr: AscPtr<AscString>) -> Result<Option<RuntimeValue>, Trap> {
@@ -554,6 +1071,12 @@ where
         args: RuntimeArgs,
     ) -> Result<Option<RuntimeValue>, Trap> {
         match index {
+            ABORT_FUNC_INDEX => self.abort(
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+                args.nth_checked(2)?,
+                args.nth_checked(3)?,
+            ),
             STORE_SET_FUNC_INDEX => self.store_set(
                 args.nth_checked(0)?,
                 args.nth_checked(1)?,
@@ -563,6 +1086,10 @@ where
                 self.store_remove(args.nth_checked(0)?, args.nth_checked(1)?)
             }
             ETHEREUM_CALL_FUNC_INDEX => self.ethereum_call(args.nth_checked(0)?),
+            ETHEREUM_ENCODE_FUNC_INDEX => self.ethereum_encode(args.nth_checked(0)?),
+            ETHEREUM_DECODE_FUNC_INDEX => {
+                self.ethereum_decode(args.nth_checked(0)?, args.nth_checked(1)?)
+            }
             TYPE_CONVERSION_BYTES_TO_STRING_FUNC_INDEX => {
                 self.convert_bytes_to_string(args.nth_checked(0)?)
             }
@@ -587,6 +1114,53 @@ where
             JSON_TO_F64_FUNC_INDEX => self.json_to_f64(args.nth_checked(0)?),
             JSON_TO_BIG_INT_FUNC_INDEX => self.json_to_big_int(args.nth_checked(0)?),
             IPFS_CAT_FUNC_INDEX => self.ipfs_cat(args.nth_checked(0)?),
+            IPFS_MAP_FUNC_INDEX => self.ipfs_map(
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+                args.nth_checked(2)?,
+            ),
+            LOG_DEBUG_FUNC_INDEX => self.log(
+                slog::Level::Debug,
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+            ),
+            LOG_INFO_FUNC_INDEX => self.log(
+                slog::Level::Info,
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+            ),
+            LOG_WARNING_FUNC_INDEX => self.log(
+                slog::Level::Warning,
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+            ),
+            LOG_ERROR_FUNC_INDEX => self.log(
+                slog::Level::Error,
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+            ),
+            LOG_CRITICAL_FUNC_INDEX => self.log(
+                slog::Level::Critical,
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+            ),
+            DATA_SOURCE_ADDRESS_FUNC_INDEX => self.data_source_address(),
+            DATA_SOURCE_NETWORK_FUNC_INDEX => self.data_source_network(),
+            DATA_SOURCE_NAME_FUNC_INDEX => self.data_source_name(),
+            DATA_SOURCE_SUBGRAPH_ID_FUNC_INDEX => self.data_source_subgraph_id(),
+            DATA_SOURCE_CREATE_FUNC_INDEX => {
+                self.data_source_create(args.nth_checked(0)?, args.nth_checked(1)?)
+            }
+            CRYPTO_KECCAK_256_FUNC_INDEX => self.crypto_keccak_256(args.nth_checked(0)?),
+            TYPE_CONVERSION_STRING_TO_BYTES_FUNC_INDEX => {
+                self.string_to_bytes(args.nth_checked(0)?)
+            }
+            TYPE_CONVERSION_BYTES_TO_BASE_58_FUNC_INDEX => {
+                self.bytes_to_base58(args.nth_checked(0)?)
+            }
+            TYPE_CONVERSION_BASE_58_TO_BYTES_FUNC_INDEX => {
+                self.base58_to_bytes(args.nth_checked(0)?)
+            }
             _ => panic!("Unimplemented function at {}", index),
         }
     }
@@ -645,7 +1219,9 @@ impl ModuleImportResolver for StoreModuleResolver {
 }
 
 /// Ethereum module resolver
-pub struct EthereumModuleResolver;
+pub struct EthereumModuleResolver {
+    api_version: Version,
+}
 
 impl ModuleImportResolver for EthereumModuleResolver {
     fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
@@ -654,6 +1230,20 @@ impl ModuleImportResolver for EthereumModuleResolver {
                 Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
                 ETHEREUM_CALL_FUNC_INDEX,
             ),
+            "encode" => {
+                require_api_version(&self.api_version, Version::new(0, 0, 2), "ethereum.encode")?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                    ETHEREUM_ENCODE_FUNC_INDEX,
+                )
+            }
+            "decode" => {
+                require_api_version(&self.api_version, Version::new(0, 0, 2), "ethereum.decode")?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32, ValueType::I32][..], Some(ValueType::I32)),
+                    ETHEREUM_DECODE_FUNC_INDEX,
+                )
+            }
             _ => {
                 return Err(Error::Instantiation(format!(
                     "Export '{}' not found",
@@ -665,7 +1255,9 @@ impl ModuleImportResolver for EthereumModuleResolver {
 }
 
 /// Types conversion module resolver
-pub struct TypeConversionModuleResolver;
+pub struct TypeConversionModuleResolver {
+    api_version: Version,
+}
 
 impl ModuleImportResolver for TypeConversionModuleResolver {
     fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
@@ -710,6 +1302,39 @@ impl ModuleImportResolver for TypeConversionModuleResolver {
                 Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
                 TYPE_CONVERSION_INT256_TO_BIG_INT_FUNC_INDEX,
             ),
+            "stringToBytes" => {
+                require_api_version(
+                    &self.api_version,
+                    Version::new(0, 0, 2),
+                    "typeConversion.stringToBytes",
+                )?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                    TYPE_CONVERSION_STRING_TO_BYTES_FUNC_INDEX,
+                )
+            }
+            "bytesToBase58" => {
+                require_api_version(
+                    &self.api_version,
+                    Version::new(0, 0, 2),
+                    "typeConversion.bytesToBase58",
+                )?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                    TYPE_CONVERSION_BYTES_TO_BASE_58_FUNC_INDEX,
+                )
+            }
+            "base58ToBytes" => {
+                require_api_version(
+                    &self.api_version,
+                    Version::new(0, 0, 2),
+                    "typeConversion.base58ToBytes",
+                )?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                    TYPE_CONVERSION_BASE_58_TO_BYTES_FUNC_INDEX,
+                )
+            }
             _ => {
                 return Err(Error::Instantiation(format!(
                     "Export '{}' not found",
@@ -755,7 +1380,25 @@ impl ModuleImportResolver for JsonModuleResolver {
     }
 }
 
-struct IpfsModuleResolver;
+/// Fails if the mapping API version `api_version` predates `introduced_in`,
+/// the version in which the host function `name` was added.
+fn require_api_version(
+    api_version: &Version,
+    introduced_in: Version,
+    name: &str,
+) -> Result<(), Error> {
+    if api_version < &introduced_in {
+        return Err(Error::Instantiation(format!(
+            "Export '{}' requires apiVersion {} or later, but the mapping uses {}",
+            name, introduced_in, api_version
+        )));
+    }
+    Ok(())
+}
+
+struct IpfsModuleResolver {
+    api_version: Version,
+}
 
 impl ModuleImportResolver for IpfsModuleResolver {
     fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
@@ -764,6 +1407,13 @@ impl ModuleImportResolver for IpfsModuleResolver {
                 Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
                 IPFS_CAT_FUNC_INDEX,
             ),
+            "map" => {
+                require_api_version(&self.api_version, Version::new(0, 0, 2), "ipfs.map")?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32, ValueType::I32, ValueType::I32][..], None),
+                    IPFS_MAP_FUNC_INDEX,
+                )
+            }
             _ => {
                 return Err(Error::Instantiation(format!(
                     "Export '{}' not found",
@@ -774,6 +1424,104 @@ impl ModuleImportResolver for IpfsModuleResolver {
     }
 }
 
+struct CryptoModuleResolver {
+    api_version: Version,
+}
+
+impl ModuleImportResolver for CryptoModuleResolver {
+    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
+        Ok(match field_name {
+            "keccak256" => {
+                require_api_version(&self.api_version, Version::new(0, 0, 2), "crypto.keccak256")?;
+                FuncInstance::alloc_host(
+                    Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                    CRYPTO_KECCAK_256_FUNC_INDEX,
+                )
+            }
+            _ => {
+                return Err(Error::Instantiation(format!(
+                    "Export '{}' not found",
+                    field_name
+                )))
+            }
+        })
+    }
+}
+
+struct LogModuleResolver {
+    api_version: Version,
+}
+
+impl ModuleImportResolver for LogModuleResolver {
+    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
+        let index = match field_name {
+            "debug" => LOG_DEBUG_FUNC_INDEX,
+            "info" => LOG_INFO_FUNC_INDEX,
+            "warning" => LOG_WARNING_FUNC_INDEX,
+            "error" => LOG_ERROR_FUNC_INDEX,
+            "critical" => LOG_CRITICAL_FUNC_INDEX,
+            _ => {
+                return Err(Error::Instantiation(format!(
+                    "Export '{}' not found",
+                    field_name
+                )))
+            }
+        };
+        require_api_version(
+            &self.api_version,
+            Version::new(0, 0, 2),
+            &format!("log.{}", field_name),
+        )?;
+        Ok(FuncInstance::alloc_host(
+            Signature::new(&[ValueType::I32, ValueType::I32][..], None),
+            index,
+        ))
+    }
+}
+
+struct DataSourceModuleResolver {
+    api_version: Version,
+}
+
+impl ModuleImportResolver for DataSourceModuleResolver {
+    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
+        let func = match field_name {
+            "create" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I32][..], None),
+                DATA_SOURCE_CREATE_FUNC_INDEX,
+            ),
+            "address" => FuncInstance::alloc_host(
+                Signature::new(&[][..], Some(ValueType::I32)),
+                DATA_SOURCE_ADDRESS_FUNC_INDEX,
+            ),
+            "network" => FuncInstance::alloc_host(
+                Signature::new(&[][..], Some(ValueType::I32)),
+                DATA_SOURCE_NETWORK_FUNC_INDEX,
+            ),
+            "name" => FuncInstance::alloc_host(
+                Signature::new(&[][..], Some(ValueType::I32)),
+                DATA_SOURCE_NAME_FUNC_INDEX,
+            ),
+            "subgraphId" => FuncInstance::alloc_host(
+                Signature::new(&[][..], Some(ValueType::I32)),
+                DATA_SOURCE_SUBGRAPH_ID_FUNC_INDEX,
+            ),
+            _ => {
+                return Err(Error::Instantiation(format!(
+                    "Export '{}' not found",
+                    field_name
+                )))
+            }
+        };
+        require_api_version(
+            &self.api_version,
+            Version::new(0, 0, 2),
+            &format!("dataSource.{}", field_name),
+        )?;
+        Ok(func)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate failure;
@@ -783,8 +1531,9 @@ mod tests {
     use self::graphql_parser::schema::Document;
     use ethabi::{LogParam, Token};
     use ethereum_types::Address;
-    use futures::sync::mpsc::channel;
+    use futures::sync::mpsc::{channel, Receiver};
     use std::collections::HashMap;
+    use std::fs;
     use std::iter::FromIterator;
     use std::sync::Mutex;
 
@@ -817,6 +1566,49 @@ mod tests {
         fn unsubscribe_from_event(&mut self, _subscription_id: String) -> bool {
             false
         }
+
+        fn is_caught_up(&self, _subscription_id: &str) -> bool {
+            false
+        }
+
+        fn latest_block_number(
+            &mut self,
+        ) -> Box<Future<Item = U256, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_blocks(
+            &mut self,
+            _subscription: EthereumBlockSubscription,
+        ) -> Box<Stream<Item = EthereumBlock, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn events_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: Address,
+            _events: Vec<ethabi::Event>,
+        ) -> Box<Future<Item = Vec<EthereumEvent>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn calls_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: Address,
+            _functions: Vec<ethabi::Function>,
+        ) -> Box<Future<Item = Vec<EthereumCall>, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
+
+        fn is_called_in_block(
+            &mut self,
+            _block: &EthereumBlock,
+            _address: Address,
+        ) -> Box<Future<Item = bool, Error = EthereumSubscriptionError>> {
+            unimplemented!()
+        }
     }
 
     struct FakeLinkResolver;
@@ -831,7 +1623,7 @@ mod tests {
         SubgraphManifest {
             id: String::from("example subgraph"),
             location: String::from("/path/to/example-subgraph.yaml"),
-            spec_version: String::from("0.1.0"),
+            spec_version: Version::new(0, 0, 1),
             schema: Schema {
                 id: String::from("exampled id"),
                 document: Document {
@@ -839,31 +1631,504 @@ mod tests {
                 },
             },
             data_sources: vec![],
+            templates: vec![],
         }
     }
 
     fn mock_data_source(path: &str) -> DataSource {
-        let runtime = parity_wasm::deserialize_file(path).expect("Failed to deserialize wasm");
+        let bytes = fs::read(path).expect("Failed to read wasm");
+        let runtime = parity_wasm::deserialize_buffer(&bytes).expect("Failed to deserialize wasm");
 
         DataSource {
             kind: String::from("ethereum/contract"),
+            network: None,
             name: String::from("example data source"),
             source: Source {
                 address: String::from("0123123123"),
                 abi: String::from("123123"),
+                start_block: 0,
             },
             mapping: Mapping {
                 kind: String::from("ethereum/events"),
-                api_version: String::from("0.1.0"),
+                api_version: Version::new(0, 0, 2),
                 language: String::from("wasm/assemblyscript"),
                 entities: vec![],
                 abis: vec![],
                 event_handlers: vec![],
+                call_handlers: vec![],
+                block_handlers: vec![],
                 runtime,
+                runtime_hash: H256::from(tiny_keccak::keccak256(&bytes)),
+            },
+        }
+    }
+
+    fn test_module(
+        path: &str,
+        logger: &Logger,
+    ) -> (
+        WasmiModule<MockEthereumAdapter, FakeLinkResolver>,
+        Receiver<RuntimeHostEvent>,
+    ) {
+        test_module_with_link_resolver(path, logger, FakeLinkResolver)
+    }
+
+    fn test_module_with_link_resolver<L: LinkResolver>(
+        path: &str,
+        logger: &Logger,
+        link_resolver: L,
+    ) -> (
+        WasmiModule<MockEthereumAdapter, L>,
+        Receiver<RuntimeHostEvent>,
+    ) {
+        let (sender, receiver) = channel(1);
+        let module = WasmiModule::new(
+            logger,
+            WasmiModuleConfig {
+                subgraph: Arc::new(mock_subgraph()),
+                data_source: Arc::new(mock_data_source(path)),
+                event_sink: sender,
+                ethereum_adapter: Arc::new(Mutex::new(MockEthereumAdapter::default())),
+                link_resolver: Arc::new(link_resolver),
+            },
+            &ModuleCache::default(),
+        );
+        (module, receiver)
+    }
+
+    /// Loads a module that exports nothing but its allocator, so that host
+    /// functions can be called on it directly.
+    fn host_function_test_module(
+        logger: &Logger,
+    ) -> (
+        WasmiModule<MockEthereumAdapter, FakeLinkResolver>,
+        Receiver<RuntimeHostEvent>,
+    ) {
+        test_module("wasm_test/abi_token.wasm", logger)
+    }
+
+    /// Unwraps the pointer returned by a host function.
+    fn returned_ptr<C>(result: Result<Option<RuntimeValue>, Trap>) -> AscPtr<C> {
+        result
+            .expect("Host function failed")
+            .expect("Host function returned nothing")
+            .try_into()
+            .expect("Host function did not return a pointer")
+    }
+
+    #[test]
+    fn crypto_keccak_256() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let input: AscPtr<Uint8Array> = module.heap.asc_new(&b""[..]);
+        let hash: Vec<u8> = module
+            .heap
+            .asc_get(returned_ptr(module.externals.crypto_keccak_256(input)));
+        assert_eq!(
+            hex::encode(hash),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+
+    #[test]
+    fn convert_strings_to_bytes_and_base58() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let string = module.heap.asc_new("Hello World!");
+        let bytes_ptr: AscPtr<Uint8Array> = returned_ptr(module.externals.string_to_bytes(string));
+        let bytes: Vec<u8> = module.heap.asc_get(bytes_ptr);
+        assert_eq!(bytes, b"Hello World!".to_vec());
+
+        let base58_ptr: AscPtr<AscString> =
+            returned_ptr(module.externals.bytes_to_base58(bytes_ptr));
+        let base58: String = module.heap.asc_get(base58_ptr);
+        assert_eq!(base58, "2NEpo7TZRRrLZSi2U");
+
+        let decoded: Vec<u8> = module
+            .heap
+            .asc_get(returned_ptr(module.externals.base58_to_bytes(base58_ptr)));
+        assert_eq!(decoded, bytes);
+
+        // Invalid base58 strings make the handler fail
+        let invalid = module.heap.asc_new("0OIl");
+        assert!(module.externals.base58_to_bytes(invalid).is_err());
+    }
+
+    #[test]
+    fn convert_invalid_utf8_bytes_to_string_lossily() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let bytes: AscPtr<Uint8Array> = module.heap.asc_new(&[0x61, 0xff, 0x62, 0, 0][..]);
+        let string: String = module.heap.asc_get(returned_ptr(
+            module.externals.convert_bytes_to_string(bytes),
+        ));
+        assert_eq!(string, "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn ethereum_encode_and_decode_round_trip() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let round_trip = |type_signature: &str, token: Token| {
+            let token_ptr: AscPtr<AscEnum<EthereumValueKind>> = module.heap.asc_new(&token);
+            let bytes_ptr: AscPtr<Uint8Array> =
+                returned_ptr(module.externals.ethereum_encode(token_ptr));
+            let bytes: Vec<u8> = module.heap.asc_get(bytes_ptr);
+            assert_eq!(bytes, ethabi::encode(&[token.clone()]));
+
+            let type_signature = module.heap.asc_new(type_signature);
+            let decoded_ptr: AscPtr<AscEnum<EthereumValueKind>> =
+                returned_ptr(module.externals.ethereum_decode(type_signature, bytes_ptr));
+            let decoded: Token = module.heap.asc_get(decoded_ptr);
+            assert_eq!(decoded, token);
+        };
+
+        round_trip("uint256", Token::Uint(U256::from(42)));
+        round_trip("int256", Token::Int(U256::max_value()));
+        round_trip("bool", Token::Bool(true));
+        round_trip(
+            "address",
+            Token::Address(Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835")),
+        );
+        round_trip("bytes", Token::Bytes(vec![1, 2, 3]));
+        round_trip("bytes4", Token::FixedBytes(vec![1, 2, 3, 4]));
+        round_trip("string", Token::String(String::from("some data")));
+        round_trip(
+            "uint256[]",
+            Token::Array(vec![Token::Uint(U256::from(1)), Token::Uint(U256::from(2))]),
+        );
+        round_trip(
+            "bool[2]",
+            Token::FixedArray(vec![Token::Bool(true), Token::Bool(false)]),
+        );
+    }
+
+    #[test]
+    fn ethereum_encode_known_value() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let token_ptr: AscPtr<AscEnum<EthereumValueKind>> =
+            module.heap.asc_new(&Token::Uint(U256::from(42)));
+        let bytes: Vec<u8> = module
+            .heap
+            .asc_get(returned_ptr(module.externals.ethereum_encode(token_ptr)));
+        assert_eq!(
+            hex::encode(bytes),
+            "000000000000000000000000000000000000000000000000000000000000002a"
+        );
+    }
+
+    #[test]
+    fn ethereum_decode_invalid_data_yields_null() {
+        let (module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+
+        let type_signature = module.heap.asc_new("uint256");
+        let data: AscPtr<Uint8Array> = module.heap.asc_new(&[1, 2, 3][..]);
+        let decoded: AscPtr<AscEnum<EthereumValueKind>> =
+            returned_ptr(module.externals.ethereum_decode(type_signature, data));
+        assert!(decoded.is_null());
+
+        // Unknown types make the handler fail
+        let type_signature = module.heap.asc_new("notatype");
+        assert!(module
+            .externals
+            .ethereum_decode(type_signature, data)
+            .is_err());
+    }
+
+    #[test]
+    fn data_source_address_and_network() {
+        let (mut module, _) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+        module.externals.data_source.source.address =
+            String::from("22843e74c59580b3eaf6c233fa67d8b7c561a835");
+        module.externals.data_source.network = Some(String::from("mainnet"));
+
+        let address: H160 = module.heap.asc_get(returned_ptr::<AscH160>(
+            module.externals.data_source_address(),
+        ));
+        assert_eq!(
+            address,
+            Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835")
+        );
+
+        let network: String = module.heap.asc_get(returned_ptr::<AscString>(
+            module.externals.data_source_network(),
+        ));
+        assert_eq!(network, "mainnet");
+
+        // Data sources without a network yield null
+        module.externals.data_source.network = None;
+        let network: AscPtr<AscString> = returned_ptr(module.externals.data_source_network());
+        assert!(network.is_null());
+
+        // Invalid addresses make the handler fail
+        module.externals.data_source.source.address = String::from("not an address");
+        assert!(module.externals.data_source_address().is_err());
+    }
+
+    #[test]
+    fn data_source_create_starts_at_the_current_block() {
+        let (mut module, receiver) = host_function_test_module(&Logger::root(slog::Discard, o!()));
+        let template = DataSourceTemplate {
+            kind: String::from("ethereum/contract"),
+            network: Some(String::from("mainnet")),
+            name: String::from("Template"),
+            source: TemplateSource {
+                abi: String::from("Token"),
+            },
+            mapping: mock_data_source("wasm_test/abi_token.wasm").mapping,
+        };
+        module.externals.subgraph.templates = vec![template.clone()];
+        module.externals.block_hash = util::ethereum::string_to_h256("example block hash");
+        module.externals.block_number = U256::from(42);
+
+        let address = Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835");
+        let name = module.heap.asc_new("Template");
+        let address_ptr: AscPtr<AscAddress> = module.heap.asc_new(&address);
+        assert_eq!(
+            module
+                .externals
+                .data_source_create(name, address_ptr)
+                .expect("Failed to create data source"),
+            None
+        );
+
+        let event = receiver
+            .take(1)
+            .into_future()
+            .wait()
+            .expect("No event received from runtime")
+            .0
+            .expect("Event must not be None");
+        assert_eq!(
+            event,
+            RuntimeHostEvent::DataSourceCreated(
+                template.create_data_source(address, 42),
+                EventSource::EthereumBlock(util::ethereum::string_to_h256("example block hash")),
+            )
+        );
+        match event {
+            RuntimeHostEvent::DataSourceCreated(data_source, _) => {
+                assert_eq!(data_source.source.address, format!("{:x}", address));
+                assert_eq!(data_source.source.start_block, 42);
+            }
+            _ => unreachable!(),
+        }
+
+        // Unknown templates make the handler fail
+        let name = module.heap.asc_new("Unknown");
+        assert!(module
+            .externals
+            .data_source_create(name, address_ptr)
+            .is_err());
+    }
+
+    /// Records every log message along with its key-value pairs.
+    #[derive(Clone, Default)]
+    struct RecordingDrain(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for RecordingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record,
+            values: &slog::OwnedKVList,
+        ) -> Result<(), slog::Never> {
+            let mut line = format!("{} {}", record.level().as_short_str(), record.msg());
+            {
+                let mut serializer = RecordingSerializer(&mut line);
+                record.kv().serialize(record, &mut serializer).unwrap();
+                values.serialize(record, &mut serializer).unwrap();
+            }
+            self.0.lock().unwrap().push(line);
+            Ok(())
+        }
+    }
+
+    struct RecordingSerializer<'a>(&'a mut String);
+
+    impl<'a> slog::Serializer for RecordingSerializer<'a> {
+        fn emit_arguments(&mut self, key: slog::Key, value: &fmt::Arguments) -> slog::Result {
+            self.0.push_str(&format!(" {}={}", key, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn log_messages_with_context() {
+        let drain = RecordingDrain::default();
+        let (mut module, _) = host_function_test_module(&Logger::root(drain.clone(), o!()));
+        module.externals.handler = String::from("handleExample");
+        module.externals.block_number = U256::from(42);
+
+        let message = module.heap.asc_new("{} and {}");
+        let args: AscPtr<Array<AscPtr<AscString>>> = module
+            .heap
+            .asc_new(&[String::from("one"), String::from("two")][..]);
+
+        // Logging at the critical level does not fail the handler
+        assert_eq!(
+            module
+                .externals
+                .log(slog::Level::Critical, message, args)
+                .expect("Logging failed"),
+            None
+        );
+
+        let lines = drain.0.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("CRIT one and two"));
+        assert!(lines[0].contains(" subgraph_id=example subgraph"));
+        assert!(lines[0].contains(" data_source=example data source"));
+        assert!(lines[0].contains(" handler=handleExample"));
+        assert!(lines[0].contains(" block_number=42"));
+    }
+
+    #[test]
+    fn abort_fails_handler_with_message_and_location() {
+        let (mut module, _) =
+            test_module("wasm_test/abort.wasm", &Logger::root(slog::Discard, o!()));
+        assert_abort_message(&mut module);
+    }
+
+    #[test]
+    fn abort_with_message_and_location_in_api_version_0_0_1() {
+        let mut data_source = mock_data_source("wasm_test/abort.wasm");
+        data_source.mapping.api_version = Version::new(0, 0, 1);
+
+        let (sender, _receiver) = channel(1);
+        let mut module = WasmiModule::new(
+            &Logger::root(slog::Discard, o!()),
+            WasmiModuleConfig {
+                subgraph: Arc::new(mock_subgraph()),
+                data_source: Arc::new(data_source),
+                event_sink: sender,
+                ethereum_adapter: Arc::new(Mutex::new(MockEthereumAdapter::default())),
+                link_resolver: Arc::new(FakeLinkResolver),
+            },
+            &ModuleCache::default(),
+        );
+        assert_abort_message(&mut module);
+    }
+
+    fn assert_abort_message(module: &mut WasmiModule<MockEthereumAdapter, FakeLinkResolver>) {
+        let ethereum_event = EthereumEvent {
+            address: Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
+            event_signature: util::ethereum::string_to_h256("ExampleEvent(string)"),
+            block_hash: util::ethereum::string_to_h256("example block hash"),
+            block_number: U256::from(1),
+            params: vec![],
+            removed: false,
+        };
+
+        assert_eq!(
+            module.handle_ethereum_event("handleExampleEvent", ethereum_event),
+            Err(String::from(
+                "Mapping aborted at mapping.ts, line 12, column 34, with message: Oops"
+            ))
+        );
+    }
+
+    #[test]
+    fn import_host_functions_of_api_version() {
+        // crypto.keccak256 was added in apiVersion 0.0.2
+        test_module(
+            "wasm_test/import_keccak256.wasm",
+            &Logger::root(slog::Discard, o!()),
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Export 'crypto.keccak256' requires apiVersion 0.0.2 or later, but the mapping uses 0.0.1"
+    )]
+    fn reject_host_functions_newer_than_api_version() {
+        let mut data_source = mock_data_source("wasm_test/import_keccak256.wasm");
+        data_source.mapping.api_version = Version::new(0, 0, 1);
+
+        let (sender, _receiver) = channel(1);
+        WasmiModule::new(
+            &Logger::root(slog::Discard, o!()),
+            WasmiModuleConfig {
+                subgraph: Arc::new(mock_subgraph()),
+                data_source: Arc::new(data_source),
+                event_sink: sender,
+                ethereum_adapter: Arc::new(Mutex::new(MockEthereumAdapter::default())),
+                link_resolver: Arc::new(FakeLinkResolver),
             },
+            &ModuleCache::default(),
+        );
+    }
+
+    #[test]
+    fn discard_entity_operations_of_failed_handler() {
+        let (mut module, receiver) = test_module(
+            "wasm_test/remove_then_abort.wasm",
+            &Logger::root(slog::Discard, o!()),
+        );
+
+        let ethereum_event = EthereumEvent {
+            address: Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
+            event_signature: util::ethereum::string_to_h256("ExampleEvent(string)"),
+            block_hash: util::ethereum::string_to_h256("example block hash"),
+            block_number: U256::from(1),
+            params: vec![],
+            removed: false,
+        };
+        assert!(module
+            .handle_ethereum_event("handleExampleEvent", ethereum_event)
+            .is_err());
+
+        // The entity removed before the handler aborted stays in the store
+        drop(module);
+        assert_eq!(receiver.collect().wait().unwrap(), vec![]);
+    }
+
+    /// Serves a newline-delimited JSON file of `count` strings of 100
+    /// characters each.
+    struct JsonStringsResolver {
+        count: usize,
+    }
+
+    impl LinkResolver for JsonStringsResolver {
+        fn cat(&self, _: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+            let line = format!("\"{}\"\n", "x".repeat(100));
+            Box::new(future::ok(line.repeat(self.count).into_bytes()))
         }
     }
 
+    #[test]
+    fn map_more_json_values_than_fit_into_memory() {
+        // Each string takes up more than 200 bytes in the module's single
+        // page of 64 KiB, so that only a few hundred of them fit at once
+        let (mut module, _) = test_module_with_link_resolver(
+            "wasm_test/map_values.wasm",
+            &Logger::root(slog::Discard, o!()),
+            JsonStringsResolver { count: 1000 },
+        );
+
+        let link: AscPtr<AscString> = module.heap.asc_new("/ipfs/QmStrings");
+        let callback: AscPtr<AscString> = module.heap.asc_new("mapValue");
+        let user_data: AscPtr<AscEnum<StoreValueKind>> =
+            module.heap.asc_new(&Value::from("example id"));
+        module
+            .externals
+            .ipfs_map(link, callback, user_data)
+            .expect("ipfs.map failed");
+
+        // The callbacks' changes are kept for the handler
+        let removal = EntityOperation::Remove {
+            key: StoreKey {
+                subgraph: String::from("example subgraph"),
+                entity: String::from("ExampleEntity"),
+                id: String::from("example id"),
+            },
+        };
+        assert_eq!(module.externals.entity_operations, vec![removal; 1000]);
+    }
+
     #[test]
     fn call_invalid_event_handler_and_dont_crash() {
         // This test passing means the module doesn't crash when an invalid
@@ -876,12 +2141,13 @@ mod tests {
         let mut module = WasmiModule::new(
             &logger,
             WasmiModuleConfig {
-                subgraph: mock_subgraph(),
-                data_source: mock_data_source("wasm_test/example_event_handler.wasm"),
+                subgraph: Arc::new(mock_subgraph()),
+                data_source: Arc::new(mock_data_source("wasm_test/example_event_handler.wasm")),
                 event_sink: sender,
                 ethereum_adapter: mock_ethereum_adapter,
                 link_resolver: Arc::new(FakeLinkResolver),
             },
+            &ModuleCache::default(),
         );
 
         // Create a mock Ethereum event
@@ -889,6 +2155,7 @@ mod tests {
             address: Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
             event_signature: util::ethereum::string_to_h256("ExampleEvent(string)"),
             block_hash: util::ethereum::string_to_h256("example block hash"),
+            block_number: U256::from(1),
             params: vec![LogParam {
                 name: String::from("exampleParam"),
                 value: Token::String(String::from("some data")),
@@ -898,10 +2165,9 @@ mod tests {
 
         // Call a non-existent event handler in the test module; if the test hasn't
         // crashed until now, it means it survives Ethereum event handler errors
-        assert_eq!(
-            module.handle_ethereum_event("handleNonExistentExampleEvent", ethereum_event),
-            ()
-        );
+        assert!(module
+            .handle_ethereum_event("handleNonExistentExampleEvent", ethereum_event)
+            .is_err());
     }
 
     #[test]
@@ -922,12 +2188,15 @@ mod tests {
                 let mut module = WasmiModule::new(
                     &logger,
                     WasmiModuleConfig {
-                        subgraph: mock_subgraph(),
-                        data_source: mock_data_source("wasm_test/example_event_handler.wasm"),
+                        subgraph: Arc::new(mock_subgraph()),
+                        data_source: Arc::new(mock_data_source(
+                            "wasm_test/example_event_handler.wasm",
+                        )),
                         event_sink: sender,
                         ethereum_adapter: mock_ethereum_adapter,
                         link_resolver: Arc::new(FakeLinkResolver),
                     },
+                    &ModuleCache::default(),
                 );
 
                 // Create a mock Ethereum event
@@ -935,6 +2204,7 @@ mod tests {
                     address: Address::from("22843e74c59580b3eaf6c233fa67d8b7c561a835"),
                     event_signature: util::ethereum::string_to_h256("ExampleEvent(string)"),
                     block_hash: util::ethereum::string_to_h256("example block hash"),
+                    block_number: U256::from(1),
                     params: vec![LogParam {
                         name: String::from("exampleParam"),
                         value: Token::String(String::from("some data")),
@@ -943,10 +2213,12 @@ mod tests {
                 };
 
                 // Call the event handler in the test module and pass the event to it
-                module.handle_ethereum_event("handleExampleEvent", ethereum_event);
+                module
+                    .handle_ethereum_event("handleExampleEvent", ethereum_event)
+                    .expect("Failed to handle Ethereum event");
 
-                // Expect a store set call to be made by the handler and a
-                // RuntimeHostEvent::EntitySet event to be written to the event stream
+                // Expect a store set call to be made by the handler and to be
+                // written to the event stream once the handler succeeded
                 let work = receiver.take(1).into_future();
                 let store_event = work
                     .wait()
@@ -957,16 +2229,18 @@ mod tests {
                 // Verify that this event matches what the test module is sending
                 assert_eq!(
                     store_event,
-                    RuntimeHostEvent::EntitySet(
-                        StoreKey {
-                            subgraph: String::from("example subgraph"),
-                            entity: String::from("ExampleEntity"),
-                            id: String::from("example id"),
-                        },
-                        Entity::from(HashMap::from_iter(
-                            vec![(String::from("exampleAttribute"), Value::from("some data"))]
-                                .into_iter()
-                        )),
+                    RuntimeHostEvent::EntityOperations(
+                        vec![EntityOperation::Set {
+                            key: StoreKey {
+                                subgraph: String::from("example subgraph"),
+                                entity: String::from("ExampleEntity"),
+                                id: String::from("example id"),
+                            },
+                            data: Entity::from(HashMap::from_iter(
+                                vec![(String::from("exampleAttribute"), Value::from("some data"))]
+                                    .into_iter()
+                            )),
+                        }],
                         EventSource::EthereumBlock(util::ethereum::string_to_h256(
                             "example block hash",
                         )),
@@ -976,6 +2250,21 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn format_log_messages() {
+        let args = vec![String::from("1"), String::from("two")];
+        assert_eq!(format_log_message("no args", &args), "no args");
+        assert_eq!(
+            format_log_message("{} and {}", &args),
+            String::from("1 and two")
+        );
+        assert_eq!(
+            format_log_message("{}, {} and {}", &args),
+            String::from("1, two and {}")
+        );
+        assert_eq!(format_log_message("{}", &[]), String::from("{}"));
+    }
+
     #[test]
     fn json_conversions() {
         tokio::run(future::lazy(|| {
@@ -987,12 +2276,13 @@ mod tests {
                 let mut module = WasmiModule::new(
                     &logger,
                     WasmiModuleConfig {
-                        subgraph: mock_subgraph(),
-                        data_source: mock_data_source("wasm_test/string_to_number.wasm"),
+                        subgraph: Arc::new(mock_subgraph()),
+                        data_source: Arc::new(mock_data_source("wasm_test/string_to_number.wasm")),
                         event_sink: sender,
                         ethereum_adapter: mock_ethereum_adapter,
                         link_resolver: Arc::new(FakeLinkResolver),
                     },
+                    &ModuleCache::default(),
                 );
 
                 // test u64 conversion
@@ -0,0 +1,365 @@
+use ethereum_types::H256;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use parity_wasm;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use wasmi::{self, Module};
+
+use graph::prelude::*;
+use graph::tokio::executor::current_thread;
+
+/// Work that is run on a WASM worker thread.
+///
+/// wasmi modules are not `Send`, so tasks are sent to a worker as a whole
+/// and instantiate their modules on the worker they are run on, which keeps
+/// the instances for later tasks.
+pub trait WorkerTask: Send {
+    /// Returns a future that performs the task on the worker's executor.
+    /// Unlike the task itself, the future does not need to be `Send`.
+    fn run(self: Box<Self>, worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>>;
+}
+
+/// What a worker thread provides to the tasks run on it.
+pub struct WorkerState {
+    /// Compiled modules, shared by all workers.
+    pub modules: ModuleCache,
+    /// Module instances, which stay on this worker.
+    pub instances: ModuleInstances,
+}
+
+/// Module instances kept on a worker, by the id of the data source they
+/// belong to. Their types are erased, since a worker runs the modules of
+/// all kinds of data sources.
+#[derive(Default)]
+pub struct ModuleInstances {
+    instances: HashMap<usize, Box<Any>>,
+}
+
+impl ModuleInstances {
+    /// Takes the instance of data source `id` out of the worker, if there is
+    /// one. Once the task is done with it, it puts it back with `insert`.
+    pub fn take<M: 'static>(&mut self, id: usize) -> Option<M> {
+        self.instances
+            .remove(&id)
+            .and_then(|instance| instance.downcast().ok())
+            .map(|instance| *instance)
+    }
+
+    /// Keeps `instance` on the worker for the next task of data source `id`.
+    pub fn insert<M: 'static>(&mut self, id: usize, instance: M) {
+        self.instances.insert(id, Box::new(instance));
+    }
+}
+
+/// The worker that a data source is assigned to. All tasks of the data
+/// source run on this worker, so that its module instance can be reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Assignment {
+    worker: usize,
+    /// Identifies the data source among all data sources of the pool.
+    pub id: usize,
+}
+
+/// Drops the module instance of a data source that was released.
+struct ReleaseTask {
+    id: usize,
+}
+
+impl WorkerTask for ReleaseTask {
+    fn run(self: Box<Self>, worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>> {
+        worker.instances.instances.remove(&self.id);
+        Box::new(future::ok(()))
+    }
+}
+
+/// Compiled WASM modules, by the Keccak-256 hash of their code. Modules
+/// are shared between all workers, so identical mappings are only
+/// compiled once.
+#[derive(Clone, Default)]
+pub struct ModuleCache {
+    modules: Arc<Mutex<HashMap<H256, Arc<Module>>>>,
+}
+
+impl ModuleCache {
+    /// Returns the compiled module for `code`, whose raw bytes hash to
+    /// `hash`, compiling it if it is not in the cache yet.
+    pub fn get_or_compile(
+        &self,
+        hash: H256,
+        code: &parity_wasm::elements::Module,
+    ) -> Result<Arc<Module>, wasmi::Error> {
+        if let Some(module) = self.modules.lock().unwrap().get(&hash) {
+            return Ok(module.clone());
+        }
+
+        // Compile outside of the lock; if another worker compiles the same
+        // code in the meantime, the first module to be inserted wins
+        let module = Arc::new(Module::from_parity_wasm_module(code.clone())?);
+        Ok(self
+            .modules
+            .lock()
+            .unwrap()
+            .entry(hash)
+            .or_insert(module)
+            .clone())
+    }
+}
+
+/// A thread that runs tasks on its own executor.
+struct Worker {
+    sender: UnboundedSender<Box<WorkerTask>>,
+    /// Number of data sources assigned to the worker.
+    assigned: AtomicUsize,
+}
+
+impl Worker {
+    fn spawn(logger: Logger, name: String, modules: ModuleCache) -> Self {
+        let (sender, receiver) = unbounded::<Box<WorkerTask>>();
+
+        thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                let mut state = WorkerState {
+                    modules,
+                    instances: ModuleInstances::default(),
+                };
+
+                // Run tasks until the pool is dropped and all tasks have
+                // finished
+                current_thread::block_on_all(receiver.for_each(move |task| {
+                    current_thread::spawn(task.run(&mut state));
+                    Ok(())
+                })).unwrap_or_else(|_| error!(logger, "WASM worker failed"));
+            })
+            .expect("Failed to spawn WASM worker thread");
+
+        Worker {
+            sender,
+            assigned: AtomicUsize::new(0),
+        }
+    }
+
+    fn send(&self, task: Box<WorkerTask>) {
+        self.sender
+            .unbounded_send(task)
+            .expect("WASM worker thread terminated");
+    }
+}
+
+/// A fixed-size pool of threads that run WASM tasks.
+///
+/// Long-lived tasks, such as the trigger streams of data sources, are run
+/// on a single dispatcher thread, where they wait for their triggers without
+/// blocking. Each trigger is then handled by a short task on the worker
+/// that the data source is assigned to, so that a handler that waits for
+/// the network only holds up its own worker, and only while it runs.
+#[derive(Clone)]
+pub struct WorkerPool {
+    dispatcher: Arc<Worker>,
+    workers: Arc<Vec<Worker>>,
+    /// Id of the next data source that is assigned to a worker.
+    next_id: Arc<AtomicUsize>,
+}
+
+impl WorkerPool {
+    /// Creates a pool with `size` worker threads.
+    pub fn new(logger: &Logger, size: usize) -> Self {
+        let logger = logger.new(o!("component" => "WorkerPool"));
+        let modules = ModuleCache::default();
+
+        assert!(size > 0, "A worker pool needs at least one thread");
+        info!(logger, "Starting WASM worker threads"; "count" => size);
+
+        let dispatcher = Worker::spawn(
+            logger.new(o!("worker" => "dispatcher")),
+            String::from("wasm-dispatcher"),
+            modules.clone(),
+        );
+        let workers = (0..size)
+            .map(|i| {
+                Worker::spawn(
+                    logger.new(o!("worker" => i)),
+                    format!("wasm-worker-{}", i),
+                    modules.clone(),
+                )
+            })
+            .collect();
+
+        WorkerPool {
+            dispatcher: Arc::new(dispatcher),
+            workers: Arc::new(workers),
+            next_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Runs `task` on the dispatcher thread. Tasks run there must not block.
+    pub fn dispatch(&self, task: Box<WorkerTask>) {
+        self.dispatcher.send(task);
+    }
+
+    /// Assigns a data source to the worker with the fewest data sources.
+    pub fn assign(&self) -> Assignment {
+        let (worker, _) = self
+            .workers
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, worker)| worker.assigned.load(Ordering::SeqCst))
+            .unwrap();
+        self.workers[worker].assigned.fetch_add(1, Ordering::SeqCst);
+
+        Assignment {
+            worker,
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /// Runs `task` on the worker of the data source with `assignment`.
+    pub fn spawn(&self, assignment: Assignment, task: Box<WorkerTask>) {
+        self.workers[assignment.worker].send(task);
+    }
+
+    /// Drops the module instance of the data source with `assignment` once
+    /// the tasks sent before have run, and frees its place on the worker.
+    pub fn release(&self, assignment: Assignment) {
+        let worker = &self.workers[assignment.worker];
+        worker.assigned.fetch_sub(1, Ordering::SeqCst);
+        worker.send(Box::new(ReleaseTask { id: assignment.id }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::sync::mpsc::{channel, Sender};
+    use parity_wasm;
+    use tiny_keccak;
+
+    use super::*;
+
+    struct SendTask {
+        id: usize,
+        sender: Sender<(usize, String)>,
+    }
+
+    impl WorkerTask for SendTask {
+        fn run(self: Box<Self>, _worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>> {
+            let worker = thread::current().name().unwrap().to_owned();
+            Box::new(
+                self.sender
+                    .send((self.id, worker))
+                    .map(|_| ())
+                    .map_err(|_| ()),
+            )
+        }
+    }
+
+    /// Counts the tasks of a data source in its module instance.
+    struct CountTask {
+        assignment: Assignment,
+        sender: Sender<usize>,
+    }
+
+    impl WorkerTask for CountTask {
+        fn run(self: Box<Self>, worker: &mut WorkerState) -> Box<Future<Item = (), Error = ()>> {
+            let count = worker
+                .instances
+                .take::<usize>(self.assignment.id)
+                .unwrap_or(0)
+                + 1;
+            worker.instances.insert(self.assignment.id, count);
+            Box::new(self.sender.send(count).map(|_| ()).map_err(|_| ()))
+        }
+    }
+
+    #[test]
+    fn run_the_tasks_of_a_data_source_on_its_worker() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pool = WorkerPool::new(&logger, 2);
+        let assignments = vec![pool.assign(), pool.assign()];
+        let (sender, receiver) = channel(10);
+
+        for id in 0..10 {
+            pool.spawn(
+                assignments[id % 2],
+                Box::new(SendTask {
+                    id,
+                    sender: sender.clone(),
+                }),
+            );
+        }
+        drop(sender);
+
+        let mut results: Vec<_> = receiver.wait().map(|result| result.unwrap()).collect();
+        results.sort();
+
+        assert_eq!(
+            results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+
+        // Data sources are spread over the workers, and each data source
+        // stays on its worker
+        assert_ne!(results[0].1, results[1].1);
+        assert!(
+            results
+                .iter()
+                .all(|(id, worker)| *worker == results[id % 2].1
+                    && worker.starts_with("wasm-worker-"))
+        );
+    }
+
+    #[test]
+    fn keep_instances_until_data_source_is_released() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pool = WorkerPool::new(&logger, 1);
+        let assignment = pool.assign();
+        let (sender, receiver) = channel(10);
+
+        let count = |sender: &Sender<usize>| {
+            Box::new(CountTask {
+                assignment,
+                sender: sender.clone(),
+            })
+        };
+        pool.spawn(assignment, count(&sender));
+        pool.spawn(assignment, count(&sender));
+        pool.release(assignment);
+        pool.spawn(assignment, count(&sender));
+        drop(sender);
+
+        let counts: Vec<_> = receiver.wait().map(|result| result.unwrap()).collect();
+        assert_eq!(counts, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn dispatch_tasks_to_the_dispatcher() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pool = WorkerPool::new(&logger, 2);
+        let (sender, receiver) = channel(1);
+
+        pool.dispatch(Box::new(SendTask { id: 0, sender }));
+
+        let results: Vec<_> = receiver.wait().map(|result| result.unwrap()).collect();
+        assert_eq!(results, vec![(0, String::from("wasm-dispatcher"))]);
+    }
+
+    #[test]
+    fn compile_identical_modules_once() {
+        let code = parity_wasm::deserialize_file("wasm_test/add_fn.wasm")
+            .expect("Failed to deserialize wasm");
+        let other_code = parity_wasm::deserialize_file("wasm_test/string_to_number.wasm")
+            .expect("Failed to deserialize wasm");
+        let hash = H256::from(tiny_keccak::keccak256(b"add_fn"));
+        let other_hash = H256::from(tiny_keccak::keccak256(b"string_to_number"));
+        let modules = ModuleCache::default();
+
+        let module = modules.get_or_compile(hash, &code).unwrap();
+        let same_module = modules.get_or_compile(hash, &code.clone()).unwrap();
+        let other_module = modules.get_or_compile(other_hash, &other_code).unwrap();
+
+        assert!(Arc::ptr_eq(&module, &same_module));
+        assert!(!Arc::ptr_eq(&module, &other_module));
+    }
+}
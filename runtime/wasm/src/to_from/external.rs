@@ -2,7 +2,7 @@ use ethabi;
 use ethereum_types;
 use graph::serde_json;
 
-use graph::components::ethereum::EthereumEvent;
+use graph::components::ethereum::{EthereumBlock, EthereumCall, EthereumEvent};
 use graph::data::store;
 
 use asc_abi::class::*;
@@ -175,6 +175,34 @@ impl FromAscObj<AscEnum<StoreValueKind>> for store::Value {
     }
 }
 
+impl ToAscObj<AscEnum<StoreValueKind>> for store::Value {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &H) -> AscEnum<StoreValueKind> {
+        use self::store::Value;
+
+        let payload = match self {
+            Value::String(string) => heap.asc_new(string.as_str()).into(),
+            Value::Int(n) => EnumPayload::from(i64::from(*n)),
+            Value::Float(n) => EnumPayload(f64::from(*n).to_bits()),
+            Value::Bool(b) => EnumPayload::from(*b),
+            Value::List(values) => heap.asc_new(values.as_slice()).into(),
+            Value::Null => EnumPayload(0),
+            Value::Bytes(bytes) => {
+                let bytes_obj: AscPtr<Bytes> = heap.asc_new(bytes.as_slice());
+                bytes_obj.into()
+            }
+            Value::BigInt(big_int) => {
+                let bytes_obj: AscPtr<BigInt> = heap.asc_new(&*big_int.to_signed_bytes_le());
+                bytes_obj.into()
+            }
+        };
+
+        AscEnum {
+            kind: StoreValueKind::get_kind(self),
+            payload,
+        }
+    }
+}
+
 impl ToAscObj<AscLogParam> for ethabi::LogParam {
     fn to_asc_obj<H: AscHeap>(&self, heap: &H) -> AscLogParam {
         AscLogParam {
@@ -223,6 +251,37 @@ impl ToAscObj<AscEthereumEvent> for EthereumEvent {
     }
 }
 
+impl ToAscObj<AscEthereumBlock> for EthereumBlock {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &H) -> AscEthereumBlock {
+        let big_int = |value: ethereum_types::U256| -> AscPtr<BigInt> {
+            heap.asc_new(&*store::scalar::BigInt::from(value).to_signed_bytes_le())
+        };
+
+        AscEthereumBlock {
+            hash: heap.asc_new(&self.hash),
+            parent_hash: heap.asc_new(&self.parent_hash),
+            number: big_int(self.number),
+            timestamp: big_int(self.timestamp),
+            author: heap.asc_new(&self.author),
+            gas_used: big_int(self.gas_used),
+            gas_limit: big_int(self.gas_limit),
+        }
+    }
+}
+
+impl ToAscObj<AscEthereumCall> for EthereumCall {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &H) -> AscEthereumCall {
+        AscEthereumCall {
+            address: heap.asc_new(&self.to),
+            from: heap.asc_new(&self.from),
+            block_hash: heap.asc_new(&self.block_hash),
+            transaction_hash: heap.asc_new(&self.transaction_hash),
+            inputs: heap.asc_new(self.inputs.as_slice()),
+            outputs: heap.asc_new(self.outputs.as_slice()),
+        }
+    }
+}
+
 impl FromAscObj<AscUnresolvedContractCall> for UnresolvedContractCall {
     fn from_asc_obj<H: AscHeap>(asc_call: AscUnresolvedContractCall, heap: &H) -> Self {
         UnresolvedContractCall {
@@ -0,0 +1,34 @@
+;; Source of `abort.wasm`. `handleExampleEvent` aborts with the message
+;; "Oops" at line 12, column 34 of "mapping.ts", the same way AssemblyScript
+;; reports a failed assertion. Strings are laid out as AssemblyScript strings:
+;; a `u32` length followed by UTF-16LE code units.
+(module
+  (type (func (param i32 i32 i32 i32)))
+  (type (func (param i32) (result i32)))
+  (type (func (param i32)))
+  (import "env" "abort" (func $abort (type 0)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator that never frees; sizes are rounded up to 8 bytes.
+  (func (export "allocate_memory") (type 1)
+    get_global $heap
+    get_global $heap
+    get_local 0
+    i32.add
+    i32.const 7
+    i32.add
+    i32.const -8
+    i32.and
+    set_global $heap)
+
+  (func (export "handleExampleEvent") (type 2)
+    i32.const 16
+    i32.const 48
+    i32.const 12
+    i32.const 34
+    call $abort
+    unreachable)
+
+  (data (i32.const 16) "\04\00\00\00O\00o\00p\00s\00")
+  (data (i32.const 48) "\0a\00\00\00m\00a\00p\00p\00i\00n\00g\00.\00t\00s\00"))
@@ -0,0 +1,20 @@
+;; Source of `import_keccak256.wasm`, a module that imports
+;; `crypto.keccak256`, which requires apiVersion 0.0.2, and exports nothing
+;; but its memory and allocator.
+(module
+  (type (func (param i32) (result i32)))
+  (import "crypto" "keccak256" (func $keccak256 (type 0)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator that never frees; sizes are rounded up to 8 bytes.
+  (func (export "allocate_memory") (type 0)
+    get_global $heap
+    get_global $heap
+    get_local 0
+    i32.add
+    i32.const 7
+    i32.add
+    i32.const -8
+    i32.and
+    set_global $heap))
@@ -0,0 +1,33 @@
+;; Source of `map_values.wasm`. `mapValue` is an `ipfs.map` callback that
+;; ignores the JSON value and removes the entity of type "ExampleEntity"
+;; whose id is the string passed as user data. Its memory is a single page
+;; that is never grown, so a module that kept the values of a large file in
+;; memory would run out of it. Strings are laid out as in `abort.wat`.
+(module
+  (type (func (param i32) (result i32)))
+  (type (func (param i32 i32)))
+  (import "store" "remove" (func $remove (type 1)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator that never frees; sizes are rounded up to 8 bytes.
+  (func (export "allocate_memory") (type 0)
+    get_global $heap
+    get_global $heap
+    get_local 0
+    i32.add
+    i32.const 7
+    i32.add
+    i32.const -8
+    i32.and
+    set_global $heap)
+
+  ;; The user data is a store value whose payload, a pointer to the
+  ;; string, follows the kind at offset 8.
+  (func (export "mapValue") (type 1)
+    i32.const 16
+    get_local 1
+    i32.load offset=8
+    call $remove)
+
+  (data (i32.const 16) "\0d\00\00\00E\00x\00a\00m\00p\00l\00e\00E\00n\00t\00i\00t\00y\00"))
@@ -0,0 +1,40 @@
+;; Source of `remove_then_abort.wasm`. `handleExampleEvent` removes the
+;; entity "example id" of type "ExampleEntity" and then aborts with the
+;; message "Oops", so none of its changes must be applied. Strings are laid
+;; out as in `abort.wat`.
+(module
+  (type (func (param i32 i32 i32 i32)))
+  (type (func (param i32) (result i32)))
+  (type (func (param i32)))
+  (type (func (param i32 i32)))
+  (import "env" "abort" (func $abort (type 0)))
+  (import "store" "remove" (func $remove (type 3)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator that never frees; sizes are rounded up to 8 bytes.
+  (func (export "allocate_memory") (type 1)
+    get_global $heap
+    get_global $heap
+    get_local 0
+    i32.add
+    i32.const 7
+    i32.add
+    i32.const -8
+    i32.and
+    set_global $heap)
+
+  (func (export "handleExampleEvent") (type 2)
+    i32.const 16
+    i32.const 48
+    call $remove
+    i32.const 80
+    i32.const 0
+    i32.const 7
+    i32.const 3
+    call $abort
+    unreachable)
+
+  (data (i32.const 16) "\0d\00\00\00E\00x\00a\00m\00p\00l\00e\00E\00n\00t\00i\00t\00y\00")
+  (data (i32.const 48) "\0a\00\00\00e\00x\00a\00m\00p\00l\00e\00 \00i\00d\00")
+  (data (i32.const 80) "\04\00\00\00O\00o\00p\00s\00"))
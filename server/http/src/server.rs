@@ -51,6 +51,8 @@ pub struct GraphQLServer {
     schema_provider_event_sink: Sender<SchemaProviderEvent>,
     store_event_sink: Sender<StoreEvent>,
     schema: Arc<Mutex<Option<Schema>>>,
+    indexing_status_provider: Option<Arc<IndexingStatusProvider>>,
+    subgraph_names: Option<Arc<Mutex<SubgraphNameStore>>>,
 }
 
 impl GraphQLServer {
@@ -67,6 +69,8 @@ impl GraphQLServer {
             schema_provider_event_sink: schema_provider_sink,
             store_event_sink: store_sink,
             schema: Arc::new(Mutex::new(None)),
+            indexing_status_provider: None,
+            subgraph_names: None,
         };
 
         // Spawn tasks to handle incoming events from the schema provider and store
@@ -77,6 +81,18 @@ impl GraphQLServer {
         server
     }
 
+    /// Serves the indexing status API at /indexing-status, answering
+    /// queries with the statuses reported by `provider`.
+    pub fn set_indexing_status_provider(&mut self, provider: Arc<IndexingStatusProvider>) {
+        self.indexing_status_provider = Some(provider);
+    }
+
+    /// Serves queries for subgraph names at /subgraphs/name/<name>, using
+    /// the deployment that each name points at in `subgraph_names`.
+    pub fn set_subgraph_names(&mut self, subgraph_names: Arc<Mutex<SubgraphNameStore>>) {
+        self.subgraph_names = Some(subgraph_names);
+    }
+
     /// Handle incoming events from the schema provider
     fn handle_schema_provider_events(&mut self, stream: Receiver<SchemaProviderEvent>) {
         let logger = self.logger.clone();
@@ -145,8 +161,18 @@ impl GraphQLServerTrait for GraphQLServer {
         // incoming queries to the query sink.
         let query_sink = query_sink.clone();
         let schema = self.schema.clone();
+        let indexing_status_provider = self.indexing_status_provider.clone();
+        let subgraph_names = self.subgraph_names.clone();
+        let service_logger = self.logger.clone();
         let new_service = move || {
-            let service = GraphQLService::new(schema.clone(), query_sink.clone());
+            let mut service = GraphQLService::new(schema.clone(), query_sink.clone());
+            if let Some(ref provider) = indexing_status_provider {
+                service =
+                    service.with_indexing_status_provider(service_logger.clone(), provider.clone());
+            }
+            if let Some(ref subgraph_names) = subgraph_names {
+                service = service.with_subgraph_names(subgraph_names.clone());
+            }
             future::ok::<GraphQLService, hyper::Error>(service)
         };
 
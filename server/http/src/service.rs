@@ -2,28 +2,78 @@ use futures::sync::mpsc::Sender;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use graph::components::server::GraphQLServerError;
 use graph::prelude::*;
+use graph::tokio::timer::Interval;
+use graph::util::metrics;
+use graph_graphql::prelude::{
+    execute, indexing_status_schema, ExecutionOptions, IndexingStatusResolver,
+};
 
 use request::GraphQLRequest;
 use response::GraphQLResponse;
 
+/// Path under which queries for a subgraph name are received, e.g.
+/// `/subgraphs/name/example` for the subgraph named `example`.
+const SUBGRAPH_NAME_PATH: &str = "/subgraphs/name/";
+
+/// How often a query for a subgraph name checks whether the schema of the
+/// deployment that the name points at is served yet.
+const DEPLOYMENT_SCHEMA_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many times a query for a subgraph name checks for the schema of the
+/// deployment before it fails.
+const DEPLOYMENT_SCHEMA_CHECKS: u64 = 500;
+
 /// An asynchronous response to a GraphQL request.
 pub type GraphQLServiceResponse =
     Box<Future<Item = Response<Body>, Error = GraphQLServerError> + Send>;
 
+/// Answers queries against the indexing status API.
+#[derive(Clone)]
+struct IndexingStatus {
+    logger: Logger,
+    provider: Arc<IndexingStatusProvider>,
+}
+
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct GraphQLService {
     schema: Arc<Mutex<Option<Schema>>>,
     query_sink: Sender<Query>,
+    indexing_status: Option<IndexingStatus>,
+    subgraph_names: Option<Arc<Mutex<SubgraphNameStore>>>,
 }
 
 impl GraphQLService {
     /// Creates a new GraphQL service.
     pub fn new(schema: Arc<Mutex<Option<Schema>>>, query_sink: Sender<Query>) -> Self {
-        GraphQLService { schema, query_sink }
+        GraphQLService {
+            schema,
+            query_sink,
+            indexing_status: None,
+            subgraph_names: None,
+        }
+    }
+
+    /// Makes the service answer queries against the indexing status API via
+    /// POST /indexing-status.
+    pub fn with_indexing_status_provider(
+        mut self,
+        logger: Logger,
+        provider: Arc<IndexingStatusProvider>,
+    ) -> Self {
+        self.indexing_status = Some(IndexingStatus { logger, provider });
+        self
+    }
+
+    /// Makes the service answer queries via POST /subgraphs/name/<name> with
+    /// the deployment that the name currently points at in `subgraph_names`,
+    /// so that clients can keep their URL when the subgraph is redeployed.
+    pub fn with_subgraph_names(mut self, subgraph_names: Arc<Mutex<SubgraphNameStore>>) -> Self {
+        self.subgraph_names = Some(subgraph_names);
+        self
     }
 
     /// Serves a GraphiQL index.html.
@@ -36,8 +86,13 @@ impl GraphQLService {
         ))
     }
 
-    /// Handles GraphQL queries received via POST /.
-    fn handle_graphql_query(&self, request: Request<Body>) -> GraphQLServiceResponse {
+    /// Handles GraphQL queries received via POST /. If a `deployment` is
+    /// given, the query is only run against the schema of that deployment.
+    fn handle_graphql_query(
+        &self,
+        request: Request<Body>,
+        deployment: Option<SubgraphDeployment>,
+    ) -> GraphQLServiceResponse {
         let query_sink = self.query_sink.clone();
         let schema = self.schema.clone();
 
@@ -47,8 +102,11 @@ impl GraphQLService {
                 .concat2()
                 .map_err(|_| GraphQLServerError::from("Failed to read request body"))
                 .and_then(move |body| {
-                    let schema = schema.lock().unwrap();
-                    GraphQLRequest::new(body, schema.clone())
+                    let schema: Box<Future<Item = _, Error = _> + Send> = match deployment {
+                        Some(deployment) => Box::new(Self::deployment_schema(schema, deployment)),
+                        None => Box::new(future::ok(schema.lock().unwrap().clone())),
+                    };
+                    schema.and_then(move |schema| GraphQLRequest::new(body, schema))
                 })
                 .and_then(move |(query, receiver)| {
                     // Forward the query to the system
@@ -64,6 +122,107 @@ impl GraphQLService {
         )
     }
 
+    /// Resolves to the schema of `deployment` once the server serves it, or
+    /// to `None` if it isn't served in time.
+    ///
+    /// A name is switched over to a new deployment just before the schema
+    /// of the deployment reaches the server. Queries for the name that
+    /// arrive in between wait for the new schema, so that they are answered
+    /// by either the old or the new deployment.
+    fn deployment_schema(
+        schema: Arc<Mutex<Option<Schema>>>,
+        deployment: SubgraphDeployment,
+    ) -> impl Future<Item = Option<Schema>, Error = GraphQLServerError> + Send {
+        let served = move || {
+            schema
+                .lock()
+                .unwrap()
+                .clone()
+                .filter(|schema| schema.id == deployment.id)
+        };
+        if let Some(schema) = served() {
+            return future::Either::A(future::ok(Some(schema)));
+        }
+
+        future::Either::B(
+            Interval::new(
+                Instant::now() + DEPLOYMENT_SCHEMA_CHECK_INTERVAL,
+                DEPLOYMENT_SCHEMA_CHECK_INTERVAL,
+            )
+            .take(DEPLOYMENT_SCHEMA_CHECKS)
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))
+            .filter_map(move |_| served())
+            .into_future()
+            .map(|(schema, _)| schema)
+            .map_err(|(e, _)| e),
+        )
+    }
+
+    /// Handles GraphQL queries received via POST /subgraphs/name/<name>.
+    fn handle_named_query(&self, request: Request<Body>) -> GraphQLServiceResponse {
+        let subgraph_names = match self.subgraph_names {
+            Some(ref subgraph_names) => subgraph_names.clone(),
+            None => return self.handle_not_found(request),
+        };
+        let name = match SubgraphName::new(
+            request
+                .uri()
+                .path()
+                .trim_left_matches(SUBGRAPH_NAME_PATH)
+                .trim_right_matches('/'),
+        ) {
+            Ok(name) => name,
+            Err(_) => return self.handle_not_found(request),
+        };
+
+        let deployment = subgraph_names.lock().unwrap().current_deployment(&name);
+        match deployment {
+            Ok(Some(deployment)) => self.handle_graphql_query(request, Some(deployment)),
+            Ok(None) => self.handle_not_found(request),
+            Err(e) => Box::new(GraphQLResponse::new(Err(
+                GraphQLServerError::InternalError(format!(
+                    "Failed to look up subgraph name {}: {}",
+                    name, e
+                )),
+            ))),
+        }
+    }
+
+    /// Handles indexing status queries received via POST /indexing-status.
+    fn handle_indexing_status_query(&self, request: Request<Body>) -> GraphQLServiceResponse {
+        let IndexingStatus { logger, provider } = match self.indexing_status {
+            Some(ref indexing_status) => indexing_status.clone(),
+            None => return self.handle_not_found(request),
+        };
+
+        Box::new(
+            request
+                .into_body()
+                .concat2()
+                .map_err(|_| GraphQLServerError::from("Failed to read request body"))
+                .and_then(|body| GraphQLRequest::new(body, Some(indexing_status_schema())))
+                .map(move |(query, _)| {
+                    // The statuses are collected once per query, so all
+                    // fields of a query see the same state
+                    let resolver = IndexingStatusResolver::new(provider.indexing_statuses());
+                    execute(&query, ExecutionOptions { logger, resolver })
+                })
+                .then(|result| GraphQLResponse::new(result)),
+        )
+    }
+
+    /// Serves the metrics of the node in the Prometheus text format.
+    fn serve_metrics(&self) -> GraphQLServiceResponse {
+        let (metrics, content_type) = metrics::gather();
+        Box::new(future::ok(
+            Response::builder()
+                .status(200)
+                .header("Content-Type", content_type.as_str())
+                .body(Body::from(metrics))
+                .unwrap(),
+        ))
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         Box::new(future::ok(
@@ -105,11 +264,28 @@ impl Service for GraphQLService {
             }
 
             // POST / receives GraphQL queries
-            (&Method::POST, "/graphql") => self.handle_graphql_query(req),
+            (&Method::POST, "/graphql") => self.handle_graphql_query(req, None),
 
             // OPTIONS / allows to check for GraphQL HTTP features
             (&Method::OPTIONS, "/graphql") => self.handle_graphql_options(req),
 
+            // POST /indexing-status receives queries against the indexing
+            // status API
+            (&Method::POST, "/indexing-status") => self.handle_indexing_status_query(req),
+            (&Method::OPTIONS, "/indexing-status") => self.handle_graphql_options(req),
+
+            // POST /subgraphs/name/<name> receives GraphQL queries for the
+            // deployment that a subgraph name points at
+            (&Method::POST, path) if path.starts_with(SUBGRAPH_NAME_PATH) => {
+                self.handle_named_query(req)
+            }
+            (&Method::OPTIONS, path) if path.starts_with(SUBGRAPH_NAME_PATH) => {
+                self.handle_graphql_options(req)
+            }
+
+            // GET /metrics is scraped by Prometheus
+            (&Method::GET, "/metrics") => self.serve_metrics(),
+
             // Everything else results in a 404
             _ => self.handle_not_found(req),
         }
@@ -126,8 +302,11 @@ mod tests {
     use hyper::{Body, Method, Request};
     use std::collections::BTreeMap;
     use std::sync::Mutex;
+    use std::time::{Duration, Instant};
 
+    use graph::failure;
     use graph::prelude::*;
+    use graph::tokio::timer::Delay;
 
     use super::GraphQLService;
     use test_utils;
@@ -221,4 +400,246 @@ mod tests {
             })
         }))
     }
+
+    struct FakeIndexingStatusProvider;
+
+    impl IndexingStatusProvider for FakeIndexingStatusProvider {
+        fn indexing_statuses(&self) -> Vec<SubgraphIndexingStatus> {
+            vec![SubgraphIndexingStatus::from_host_statuses(
+                "subgraph".to_string(),
+                true,
+                vec![RuntimeHostStatus::default()],
+                Some(20),
+                3,
+            )]
+        }
+    }
+
+    #[test]
+    fn posting_indexing_status_queries_yields_statuses() {
+        let schema = Arc::new(Mutex::new(None));
+        let (query_sink, _) = channel(1);
+        let logger = Logger::root(slog::Discard, o!());
+        let mut service = GraphQLService::new(schema, query_sink)
+            .with_indexing_status_provider(logger, Arc::new(FakeIndexingStatusProvider));
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:8000/indexing-status")
+            .body(Body::from(
+                "{\"query\": \"{ indexingStatuses { subgraph synced latestBlock } }\"}",
+            ))
+            .unwrap();
+
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        let data = test_utils::assert_successful_response(response);
+
+        let statuses = data
+            .get("indexingStatuses")
+            .expect("Query result data has no \"indexingStatuses\" field")
+            .as_array()
+            .expect("Query result field \"indexingStatuses\" is not a list");
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0]["subgraph"], "subgraph");
+        assert_eq!(statuses[0]["synced"], true);
+        assert_eq!(statuses[0]["latestBlock"], 20);
+    }
+
+    #[test]
+    fn indexing_status_is_not_found_without_provider() {
+        let schema = Arc::new(Mutex::new(None));
+        let (query_sink, _) = channel(1);
+        let mut service = GraphQLService::new(schema, query_sink);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:8000/indexing-status")
+            .body(Body::from(
+                "{\"query\": \"{ indexingStatuses { subgraph } }\"}",
+            ))
+            .unwrap();
+
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn getting_metrics_yields_prometheus_text() {
+        let schema = Arc::new(Mutex::new(None));
+        let (query_sink, _) = channel(1);
+        let mut service = GraphQLService::new(schema, query_sink);
+
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("http://localhost:8000/metrics")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+    }
+
+    struct FakeSubgraphNameStore;
+
+    impl SubgraphNameStore for FakeSubgraphNameStore {
+        fn current_deployment(
+            &self,
+            name: &SubgraphName,
+        ) -> Result<Option<SubgraphDeployment>, failure::Error> {
+            Ok(if name == &SubgraphName::new("test/subgraph").unwrap() {
+                Some(SubgraphDeployment {
+                    id: "test-schema".to_string(),
+                    location: "/ipfs/test-schema".to_string(),
+                })
+            } else {
+                None
+            })
+        }
+
+        fn set_current_deployment(
+            &mut self,
+            _name: &SubgraphName,
+            _deployment: &SubgraphDeployment,
+        ) -> Result<(), failure::Error> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn posting_queries_for_subgraph_names_yields_result_response() {
+        tokio::run(future::lazy(|| {
+            Ok({
+                let schema = Arc::new(Mutex::new(Some(Schema {
+                    id: "test-schema".to_string(),
+                    document: graphql_parser::parse_schema(
+                        "\
+                         scalar String \
+                         type Query { name: String } \
+                         ",
+                    )
+                    .unwrap(),
+                })));
+                let (query_sink, query_stream) = channel(1);
+                let mut service = GraphQLService::new(schema, query_sink)
+                    .with_subgraph_names(Arc::new(Mutex::new(FakeSubgraphNameStore)));
+
+                tokio::spawn(
+                    query_stream
+                        .for_each(move |query| {
+                            let mut map = BTreeMap::new();
+                            map.insert("name".to_string(), Value::String("Jordi".to_string()));
+                            let result = QueryResult::new(Some(Value::Object(map)));
+                            query.result_sender.send(result).unwrap();
+                            Ok(())
+                        })
+                        .fuse(),
+                );
+
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri("http://localhost:8000/subgraphs/name/test/subgraph")
+                    .body(Body::from("{\"query\": \"{ name }\"}"))
+                    .unwrap();
+
+                let response = service
+                    .call(request)
+                    .wait()
+                    .expect("Should return a response");
+                let data = test_utils::assert_successful_response(response);
+                assert_eq!(data["name"], "Jordi");
+            })
+        }))
+    }
+
+    #[test]
+    fn queries_for_switched_subgraph_names_wait_for_the_new_schema() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime
+            .block_on(future::lazy(|| {
+                let schema = Arc::new(Mutex::new(Some(Schema {
+                    id: "old-schema".to_string(),
+                    document: graphql_parser::parse_schema("type Query { old: Boolean }").unwrap(),
+                })));
+                let (query_sink, query_stream) = channel(1);
+                let mut service = GraphQLService::new(schema.clone(), query_sink)
+                    .with_subgraph_names(Arc::new(Mutex::new(FakeSubgraphNameStore)));
+
+                tokio::spawn(
+                    query_stream
+                        .for_each(move |query| {
+                            let mut map = BTreeMap::new();
+                            map.insert("name".to_string(), Value::String("Jordi".to_string()));
+                            let result = QueryResult::new(Some(Value::Object(map)));
+                            query.result_sender.send(result).unwrap();
+                            Ok(())
+                        })
+                        .fuse(),
+                );
+
+                // The name already points at the new deployment, whose
+                // schema only reaches the server a little later
+                tokio::spawn(
+                    Delay::new(Instant::now() + Duration::from_millis(50))
+                        .map(move |_| {
+                            *schema.lock().unwrap() = Some(Schema {
+                                id: "test-schema".to_string(),
+                                document: graphql_parser::parse_schema(
+                                    "\
+                                     scalar String \
+                                     type Query { name: String } \
+                                     ",
+                                )
+                                .unwrap(),
+                            });
+                        })
+                        .map_err(|_| ()),
+                );
+
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri("http://localhost:8000/subgraphs/name/test/subgraph")
+                    .body(Body::from("{\"query\": \"{ name }\"}"))
+                    .unwrap();
+                service.call(request)
+            }))
+            .expect("Should return a response");
+
+        let data = test_utils::assert_successful_response(response);
+        assert_eq!(data["name"], "Jordi");
+    }
+
+    #[test]
+    fn posting_queries_for_unknown_subgraph_names_yields_not_found() {
+        let schema = Arc::new(Mutex::new(None));
+        let (query_sink, _) = channel(1);
+        let mut service = GraphQLService::new(schema, query_sink)
+            .with_subgraph_names(Arc::new(Mutex::new(FakeSubgraphNameStore)));
+
+        for name in &["other/subgraph", "invalid~name"] {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("http://localhost:8000/subgraphs/name/{}", name).as_str())
+                .body(Body::from("{\"query\": \"{ name }\"}"))
+                .unwrap();
+
+            let response = service
+                .call(request)
+                .wait()
+                .expect("Should return a response");
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
 }
@@ -0,0 +1,4 @@
+/**************************************************************
+* DROP TABLE
+**************************************************************/
+DROP TABLE eth_call_cache;
@@ -0,0 +1,11 @@
+/**************************************************************
+* CREATE TABLE
+**************************************************************/
+-- Results of Ethereum contract calls. The ID is the Keccak-256 hash of the
+-- block hash, the contract address and the ABI-encoded call
+CREATE TABLE IF NOT EXISTS eth_call_cache (
+     id BYTEA NOT NULL PRIMARY KEY,
+     return_value BYTEA NOT NULL,
+     contract_address BYTEA NOT NULL,
+     block_hash BYTEA NOT NULL
+ );
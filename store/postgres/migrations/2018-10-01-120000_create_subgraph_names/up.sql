@@ -0,0 +1,10 @@
+/**************************************************************
+* CREATE TABLE
+**************************************************************/
+-- The deployment that each subgraph name points at. The location is the
+-- link to the manifest of the deployment
+CREATE TABLE IF NOT EXISTS subgraph_names (
+     name VARCHAR NOT NULL PRIMARY KEY,
+     deployment VARCHAR NOT NULL,
+     location VARCHAR NOT NULL
+ );
@@ -7,3 +7,20 @@ table! {
         event_source -> Varchar,
     }
 }
+
+table! {
+    eth_call_cache (id) {
+        id -> Bytea,
+        return_value -> Bytea,
+        contract_address -> Bytea,
+        block_hash -> Bytea,
+    }
+}
+
+table! {
+    subgraph_names (name) {
+        name -> Varchar,
+        deployment -> Varchar,
+        location -> Varchar,
+    }
+}
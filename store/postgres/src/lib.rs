@@ -4,8 +4,11 @@ extern crate diesel;
 extern crate diesel_dynamic_schema;
 #[macro_use]
 extern crate diesel_migrations;
+extern crate ethereum_types;
+extern crate failure;
 extern crate futures;
 extern crate graph;
+extern crate tiny_keccak;
 
 pub mod db_schema;
 mod filter;
@@ -4,15 +4,21 @@ use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::sql_types::Text;
 use diesel::{debug_query, delete, insert_into, result, select};
+use ethereum_types::{Address, H256};
+use failure;
 use filter::store_filter;
 use futures::sync::mpsc::{channel, Receiver, Sender};
 use graph::serde_json;
 use graph::tokio;
+use tiny_keccak;
 
 use functions::{revert_block, set_config};
 
-use graph::components::store::{EventSource, Store as StoreTrait};
+use graph::components::store::{
+    EthereumCallCache, EventSource, Store as StoreTrait, SubgraphNameStore,
+};
 use graph::prelude::*;
+use graph::util::metrics;
 
 embed_migrations!("./migrations");
 
@@ -90,39 +96,17 @@ impl Store {
         }));
     }
 
-    /// Handles block reorganizations.
-    /// Revert all store events related to the given block
-    pub fn revert_events(&self, block_hash: String) {
-        select(revert_block(block_hash))
-            .execute(&self.conn)
-            .unwrap();
-    }
-}
-
-impl BasicStore for Store {
-    fn get(&self, key: StoreKey) -> Result<Entity, ()> {
-        debug!(self.logger, "get"; "key" => format!("{:?}", key));
-
-        use db_schema::entities::dsl::*;
-
-        // Use primary key fields to get the entity; deserialize the result JSON
-        entities
-            .find((key.id, key.subgraph, key.entity))
-            .select(data)
-            .first::<serde_json::Value>(&self.conn)
-            .map(|value| {
-                serde_json::from_value::<Entity>(value).expect("Failed to deserialize entity")
-            })
-            .map_err(|_| ())
-    }
-
-    fn set(
-        &mut self,
+    /// Creates or updates an entity, merging it into the existing entity.
+    fn set_entity(
+        &self,
         key: StoreKey,
         input_entity: Entity,
         input_event_source: EventSource,
-    ) -> Result<(), ()> {
+    ) -> Result<(), result::Error> {
         debug!(self.logger, "set"; "key" => format!("{:?}", key));
+        let _timer = metrics::STORE_OPERATION_TIME
+            .with_label_values(&["set"])
+            .start_timer();
 
         use db_schema::entities::dsl::*;
 
@@ -159,10 +143,14 @@ impl BasicStore for Store {
             ))
             .execute(&self.conn)
             .map(|_| ())
-            .map_err(|_| ())
     }
 
-    fn delete(&mut self, key: StoreKey, input_event_source: EventSource) -> Result<(), ()> {
+    /// Removes an entity.
+    fn delete_entity(
+        &self,
+        key: StoreKey,
+        input_event_source: EventSource,
+    ) -> Result<(), result::Error> {
         debug!(self.logger, "delete"; "key" => format!("{:?}", key));
 
         use db_schema::entities::dsl::*;
@@ -186,10 +174,55 @@ impl BasicStore for Store {
                 ).execute(&self.conn)
             })
             .map(|_| ())
+    }
+
+    /// Handles block reorganizations.
+    /// Revert all store events related to the given block
+    pub fn revert_events(&self, block_hash: String) {
+        select(revert_block(block_hash))
+            .execute(&self.conn)
+            .unwrap();
+    }
+}
+
+impl BasicStore for Store {
+    fn get(&self, key: StoreKey) -> Result<Entity, ()> {
+        debug!(self.logger, "get"; "key" => format!("{:?}", key));
+        let _timer = metrics::STORE_OPERATION_TIME
+            .with_label_values(&["get"])
+            .start_timer();
+
+        use db_schema::entities::dsl::*;
+
+        // Use primary key fields to get the entity; deserialize the result JSON
+        entities
+            .find((key.id, key.subgraph, key.entity))
+            .select(data)
+            .first::<serde_json::Value>(&self.conn)
+            .map(|value| {
+                serde_json::from_value::<Entity>(value).expect("Failed to deserialize entity")
+            })
             .map_err(|_| ())
     }
 
+    fn set(
+        &mut self,
+        key: StoreKey,
+        input_entity: Entity,
+        input_event_source: EventSource,
+    ) -> Result<(), ()> {
+        self.set_entity(key, input_entity, input_event_source).map_err(|_| ())
+    }
+
+    fn delete(&mut self, key: StoreKey, input_event_source: EventSource) -> Result<(), ()> {
+        self.delete_entity(key, input_event_source).map_err(|_| ())
+    }
+
     fn find(&self, query: StoreQuery) -> Result<Vec<Entity>, ()> {
+        let _timer = metrics::STORE_OPERATION_TIME
+            .with_label_values(&["find"])
+            .start_timer();
+
         use db_schema::entities::dsl::*;
 
         // Create base boxed query; this will be added to based on the
@@ -268,4 +301,137 @@ impl StoreTrait for Store {
             }
         }
     }
+
+    fn transact(
+        &mut self,
+        operations: Vec<EntityOperation>,
+        input_event_source: EventSource,
+    ) -> Result<(), ()> {
+        debug!(self.logger, "transact"; "operations" => operations.len());
+
+        self.conn
+            .transaction::<(), result::Error, _>(|| {
+                for operation in operations {
+                    match operation {
+                        EntityOperation::Set { key, data } => {
+                            self.set_entity(key, data, input_event_source.clone())?
+                        }
+                        EntityOperation::Remove { key } => {
+                            self.delete_entity(key, input_event_source.clone())?
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e| {
+                error!(self.logger, "Failed to apply entity operations: {}", e);
+            })
+    }
+
+    fn count_entities(&self, subgraph_id: &str) -> Result<u64, ()> {
+        use db_schema::entities::dsl::*;
+
+        entities
+            .filter(subgraph.eq(subgraph_id))
+            .count()
+            .get_result::<i64>(&self.conn)
+            .map(|count| count as u64)
+            .map_err(|e| {
+                error!(self.logger, "Failed to count entities: {}", e;
+                       "subgraph" => subgraph_id);
+            })
+    }
+}
+
+/// ID of a cached contract call: the Keccak-256 hash of the block hash, the
+/// contract address and the ABI-encoded call.
+fn call_id(contract_address: Address, encoded_call: &[u8], block_hash: H256) -> [u8; 32] {
+    let mut data = Vec::with_capacity(32 + 20 + encoded_call.len());
+    data.extend_from_slice(&block_hash);
+    data.extend_from_slice(&contract_address);
+    data.extend_from_slice(encoded_call);
+    tiny_keccak::keccak256(&data)
+}
+
+impl EthereumCallCache for Store {
+    fn get_call(
+        &self,
+        contract: Address,
+        encoded_call: &[u8],
+        block: H256,
+    ) -> Result<Option<Vec<u8>>, failure::Error> {
+        use db_schema::eth_call_cache::dsl::*;
+
+        let call = call_id(contract, encoded_call, block);
+        eth_call_cache
+            .find(&call[..])
+            .select(return_value)
+            .first::<Vec<u8>>(&self.conn)
+            .optional()
+            .map_err(failure::Error::from)
+    }
+
+    fn set_call(
+        &mut self,
+        contract: Address,
+        encoded_call: &[u8],
+        block: H256,
+        value: &[u8],
+    ) -> Result<(), failure::Error> {
+        use db_schema::eth_call_cache::dsl::*;
+
+        let call = call_id(contract, encoded_call, block);
+        insert_into(eth_call_cache)
+            .values((
+                id.eq(&call[..]),
+                return_value.eq(value),
+                contract_address.eq(contract.to_vec()),
+                block_hash.eq(block.to_vec()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&self.conn)
+            .map(|_| ())
+            .map_err(failure::Error::from)
+    }
+}
+
+impl SubgraphNameStore for Store {
+    fn current_deployment(
+        &self,
+        subgraph_name: &SubgraphName,
+    ) -> Result<Option<SubgraphDeployment>, failure::Error> {
+        use db_schema::subgraph_names::dsl::*;
+
+        subgraph_names
+            .find(subgraph_name.as_str())
+            .select((deployment, location))
+            .first::<(String, String)>(&self.conn)
+            .optional()
+            .map(|row| row.map(|(id, link)| SubgraphDeployment { id, location: link }))
+            .map_err(failure::Error::from)
+    }
+
+    fn set_current_deployment(
+        &mut self,
+        subgraph_name: &SubgraphName,
+        new_deployment: &SubgraphDeployment,
+    ) -> Result<(), failure::Error> {
+        use db_schema::subgraph_names::dsl::*;
+
+        insert_into(subgraph_names)
+            .values((
+                name.eq(subgraph_name.as_str()),
+                deployment.eq(&new_deployment.id),
+                location.eq(&new_deployment.location),
+            ))
+            .on_conflict(name)
+            .do_update()
+            .set((
+                deployment.eq(&new_deployment.id),
+                location.eq(&new_deployment.location),
+            ))
+            .execute(&self.conn)
+            .map(|_| ())
+            .map_err(failure::Error::from)
+    }
 }
@@ -8,13 +8,14 @@ extern crate graph_store_postgres;
 
 use diesel::pg::PgConnection;
 use diesel::*;
-use ethereum_types::H256;
+use ethereum_types::{Address, H256};
 use futures::sync::oneshot;
 use std::panic;
 use std::sync::Mutex;
 
 use graph::components::store::{
-    EventSource, StoreFilter, StoreKey, StoreOrder, StoreQuery, StoreRange,
+    EthereumCallCache, EventSource, StoreFilter, StoreKey, StoreOrder, StoreQuery, StoreRange,
+    SubgraphNameStore,
 };
 use graph::prelude::*;
 use graph_store_postgres::{db_schema, Store as DieselStore, StoreConfig};
@@ -1546,3 +1547,78 @@ fn revert_block_with_partial_update() {
         assert_eq!(reverted_entity, original_entity);
     })
 }
+
+#[test]
+fn cache_eth_calls() {
+    run_test(|| {
+        let logger = Logger::root(slog::Discard, o!());
+        let url = postgres_test_url();
+        let mut store = DieselStore::new(StoreConfig { url }, &logger);
+
+        let contract = Address::random();
+        let block = H256::random();
+        let other_block = H256::random();
+        let call = vec![1, 2, 3, 4];
+
+        assert_eq!(store.get_call(contract, &call, block).unwrap(), None);
+
+        store
+            .set_call(contract, &call, block, &[5, 6, 7])
+            .expect("Failed to cache contract call");
+
+        // Caching the same call again is allowed and keeps the result
+        store
+            .set_call(contract, &call, block, &[5, 6, 7])
+            .expect("Failed to cache contract call twice");
+
+        assert_eq!(
+            store.get_call(contract, &call, block).unwrap(),
+            Some(vec![5, 6, 7])
+        );
+        assert_eq!(store.get_call(contract, &call, other_block).unwrap(), None);
+        assert_eq!(store.get_call(contract, &[1, 2, 3], block).unwrap(), None);
+    })
+}
+
+#[test]
+fn switch_subgraph_name_to_new_deployment() {
+    run_test(|| {
+        let logger = Logger::root(slog::Discard, o!());
+        let url = postgres_test_url();
+        let mut store = DieselStore::new(StoreConfig { url }, &logger);
+
+        let name = SubgraphName::new(format!("test/{:x}", H256::random())).unwrap();
+        let first = SubgraphDeployment {
+            id: String::from("QmFirst"),
+            location: String::from("/ipfs/QmFirst"),
+        };
+        let second = SubgraphDeployment {
+            id: String::from("QmSecond"),
+            location: String::from("/ipfs/QmSecond"),
+        };
+
+        assert_eq!(store.current_deployment(&name).unwrap(), None);
+
+        store
+            .set_current_deployment(&name, &first)
+            .expect("Failed to set deployment of subgraph name");
+        assert_eq!(store.current_deployment(&name).unwrap(), Some(first));
+
+        store
+            .set_current_deployment(&name, &second)
+            .expect("Failed to switch deployment of subgraph name");
+        assert_eq!(store.current_deployment(&name).unwrap(), Some(second));
+    })
+}
+
+#[test]
+fn count_entities_of_subgraph() {
+    run_test(|| {
+        let logger = Logger::root(slog::Discard, o!());
+        let url = postgres_test_url();
+        let store = DieselStore::new(StoreConfig { url }, &logger);
+
+        assert_eq!(store.count_entities("test_subgraph"), Ok(3));
+        assert_eq!(store.count_entities("other_subgraph"), Ok(0));
+    })
+}